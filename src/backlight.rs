@@ -0,0 +1,66 @@
+use image::{GrayImage, Rgb, RgbImage};
+
+use crate::lithophane::{pixel_depth, snap_to_layers, LithophaneOptions};
+
+/// How light passes through a filament, for simulating how a lithophane will look lit from behind
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Material {
+	/// How quickly light is absorbed and scattered going through the filament, per millimeter (the Beer–Lambert attenuation coefficient)
+	pub attenuation: f32,
+	/// The color of the light that makes it through, from 0 to 1 for each channel
+	pub color: [f32; 3],
+}
+
+impl Default for Material {
+	/// Roughly white PLA in front of a warm white light
+	fn default() -> Self {
+		Self {
+			attenuation: 1.5,
+			color: [1.0, 0.96, 0.88],
+		}
+	}
+}
+
+/// Simulate how a lithophane made from `image` will look when it's lit from behind, one pixel for each pixel of the image
+///
+/// The light let through each pixel falls off exponentially with its thickness (Beer–Lambert), including contours, the thickness offset
+/// and snapping to layers. It's scaled so the thinnest a pixel can be comes out at full brightness, then encoded as sRGB, so the result can be
+/// saved and viewed like any other image. The frame, skirt, depth mask, label and back texture aren't included, only the image itself.
+pub fn simulate_backlight(image: &GrayImage, options: &LithophaneOptions, material: &Material) -> RgbImage {
+	let thickness = |gray_value: u8| {
+		let depth = pixel_depth(gray_value, options.white_depth, options.black_depth);
+		let depth = options.contours.map_or(depth, |c| c.apply(depth, options.white_depth, options.black_depth)) + options.thickness_offset;
+		snap_to_layers(depth, options.layer_height)
+	};
+	let thinnest = thickness(0).min(thickness(255));
+
+	// There are only 256 gray values, so work out the color for each once
+	let colors = (0..=u8::MAX)
+		.map(|gray_value| {
+			let transmitted = (-material.attenuation * (thickness(gray_value) - thinnest)).exp();
+			Rgb(material.color.map(|c| (linear_to_srgb(c * transmitted) * 255.0).round() as u8))
+		})
+		.collect::<Vec<_>>();
+	RgbImage::from_fn(image.width(), image.height(), |x, y| colors[image.get_pixel(x, y).0[0] as usize])
+}
+
+/// How much thicker to make each part of a lithophane that's lit by several lights of different brightness (eg one LED panel behind each
+/// piece of a `Puzzle`), so the whole thing looks evenly lit
+///
+/// `light_levels` are the relative brightnesses of the lights, in any unit. Going by Beer–Lambert, a light that's twice as bright needs
+/// `ln(2) / attenuation` more millimeters of material to let the same amount of light through, which is the same for every tone. The
+/// dimmest light gets no extra thickness, so nothing is made thinner than it was designed to be.
+pub fn brightness_compensation(light_levels: &[f32], material: &Material) -> Vec<f32> {
+	let dimmest = light_levels.iter().copied().fold(f32::INFINITY, f32::min);
+	light_levels.iter().map(|&level| (level / dimmest).ln() / material.attenuation).collect()
+}
+
+/// Encode a linear light value from 0 to 1 with the sRGB transfer function
+fn linear_to_srgb(value: f32) -> f32 {
+	let value = value.clamp(0.0, 1.0);
+	if value <= 0.0031308 {
+		value * 12.92
+	} else {
+		1.055 * value.powf(1.0 / 2.4) - 0.055
+	}
+}
@@ -0,0 +1,94 @@
+use std::f32::consts::FRAC_PI_2;
+
+use image::GrayImage;
+
+use crate::{
+	outline::{Outline, Shape},
+	photo_frame::AspectFit,
+};
+
+/// The number of straight edges each rounded corner is made of
+const CORNER_SEGMENTS: u32 = 16;
+
+/// A thin, tall bookmark with a rounded top and a hole near the top for a tassel
+///
+/// The image is fitted to the bookmark's aspect ratio first (see `fit_image`), and the corners and the hole are cut with an outline, so
+/// it's generated by the normal lithophane generator. All sizes are in millimeters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bookmark {
+	pub width: f32,
+	pub height: f32,
+	/// The thickness of the darkest parts, which is used as the black depth
+	pub thickness: f32,
+	/// The radius of the two top corners
+	pub corner_radius: f32,
+	pub hole_diameter: f32,
+	/// How far the center of the hole is below the top edge
+	pub hole_offset: f32,
+	/// The width of the ring around the hole that's made the full thickness, so the tassel doesn't tear through
+	pub hole_reinforcement: f32,
+}
+
+impl Default for Bookmark {
+	fn default() -> Self {
+		Self {
+			width: 50.0,
+			height: 150.0,
+			thickness: 1.5,
+			corner_radius: 10.0,
+			hole_diameter: 5.0,
+			hole_offset: 10.0,
+			hole_reinforcement: 1.5,
+		}
+	}
+}
+
+impl Bookmark {
+	/// The width divided by the height
+	pub fn aspect_ratio(&self) -> f32 {
+		self.width / self.height
+	}
+
+	/// Crop, pad or stretch an image to the bookmark's aspect ratio
+	pub fn fit_image(&self, image: &GrayImage, fit: AspectFit) -> GrayImage {
+		fit.apply(image, self.aspect_ratio())
+	}
+
+	/// The white and black depths for `LithophaneOptions`, which leave a third of the thickness behind the lightest parts since the
+	/// bookmark is so thin
+	pub fn depths(&self) -> (f32, f32) {
+		(self.thickness / 3.0, self.thickness)
+	}
+
+	/// Expressions for the bookmark lying on the XY plane, `width` wide, for an image from `fit_image`
+	pub fn expressions(&self) -> [String; 3] {
+		let scale = format!("{}/(w-1)", self.width);
+		[format!("x*{}", scale), format!("-y*{}", scale), "0".to_string()]
+	}
+
+	/// The outline with the rounded top and the tassel hole, for an image from `fit_image`
+	pub fn outline(&self, image_width: u32, image_height: u32) -> Outline {
+		let millimeters_per_pixel = self.width / (image_width - 1) as f32;
+		let (right, bottom) = ((image_width - 1) as f32, (image_height - 1) as f32);
+		let radius = (self.corner_radius / millimeters_per_pixel).min(right / 2.0).min(bottom);
+
+		let mut points = vec![(0.0, bottom), (right, bottom)];
+		// Each corner is a quarter circle going counterclockwise from the side to the top, with y pointing down
+		for (center_x, start) in [(right - radius, 0.0), (radius, FRAC_PI_2)] {
+			points.extend((0..=CORNER_SEGMENTS).map(|i| {
+				let angle = start + FRAC_PI_2 * i as f32 / CORNER_SEGMENTS as f32;
+				(center_x + radius * angle.cos(), radius - radius * angle.sin())
+			}));
+		}
+
+		Outline {
+			boundary: Some(Shape::Polygon(points)),
+			holes: vec![Shape::Circle {
+				center_x: right / 2.0,
+				center_y: self.hole_offset / millimeters_per_pixel,
+				radius: self.hole_diameter / 2.0 / millimeters_per_pixel,
+			}],
+			hole_reinforcement: self.hole_reinforcement / millimeters_per_pixel,
+		}
+	}
+}
@@ -0,0 +1,78 @@
+use image::GrayImage;
+use pk_stl::StlModel;
+
+use crate::lithophane::{check_image_size, generate_disc_lithophane_with_relief, LithophaneError};
+
+/// A round photo clock face with a hole in the middle for a standard quartz clock movement
+///
+/// The largest circle that fits in the image is used. All sizes are in millimeters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockFace {
+	pub diameter: f32,
+	/// Most quartz movements have a 5/16" (7.9 mm) threaded shaft, so this is 8 mm by default
+	pub hole_diameter: f32,
+	pub hour_markers: Option<HourMarkers>,
+}
+
+impl Default for ClockFace {
+	fn default() -> Self {
+		Self {
+			diameter: 150.0,
+			hole_diameter: 8.0,
+			hour_markers: Some(HourMarkers::default()),
+		}
+	}
+}
+
+/// Bars raised from the front of a clock face at each hour, pointing at the center
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HourMarkers {
+	pub length: f32,
+	pub width: f32,
+	/// How far the markers stick out past the front of the image
+	pub height: f32,
+	/// The distance between the outer ends of the markers and the edge of the face
+	pub margin: f32,
+}
+
+impl Default for HourMarkers {
+	fn default() -> Self {
+		Self {
+			length: 6.0,
+			width: 2.0,
+			height: 0.6,
+			margin: 3.0,
+		}
+	}
+}
+
+impl HourMarkers {
+	/// How far the front is raised at a point relative to the center of a face with this radius
+	fn height(&self, x: f32, y: f32, radius: f32) -> f32 {
+		use std::f32::consts::TAU;
+
+		// Rotate the point back by the angle of the nearest hour, so the marker lies along the x axis
+		let hour_angle = ((y.atan2(x) / (TAU / 12.0)).round()) * TAU / 12.0;
+		let (sin, cos) = hour_angle.sin_cos();
+		let (along, across) = (x * cos + y * sin, y * cos - x * sin);
+
+		let outer_end = radius - self.margin;
+		if along <= outer_end && along >= outer_end - self.length && across.abs() <= self.width / 2.0 {
+			self.height
+		} else {
+			0.0
+		}
+	}
+}
+
+impl ClockFace {
+	pub fn generate(&self, image: GrayImage, white_depth: f32, black_depth: f32) -> Result<StlModel, LithophaneError> {
+		check_image_size(image.width(), image.height())?;
+		let pixel_size = self.diameter / (image.width().min(image.height()) - 1) as f32;
+		let radius = self.diameter / 2.0;
+		let hour_markers = self.hour_markers;
+		generate_disc_lithophane_with_relief(image, pixel_size, self.hole_diameter, white_depth, black_depth, |x, y| {
+			hour_markers.map_or(0.0, |m| m.height(x, y, radius))
+		})
+	}
+}
@@ -0,0 +1,357 @@
+use image::{DynamicImage, ImageBuffer, Rgb, Rgba};
+
+/// An RGB color profile built from a matrix and tone curves, which covers sRGB, Display P3, Adobe RGB and the other profiles cameras and
+/// phones embed in photos
+///
+/// Profiles that describe their colors with lookup tables (mostly printer and scanner profiles) aren't supported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorProfile {
+	/// The XYZ color (adapted to D50, like all ICC profiles) of the red, green and blue primaries at full intensity
+	primaries: [[f32; 3]; 3],
+	/// The red, green and blue tone curves, which turn encoded values into linear light
+	curves: [Curve; 3],
+}
+
+/// A tone curve, stored as the parameters of the most general ICC parametric curve:
+/// `(a * x + b)^g + e` for `x >= d`, and `c * x + f` below that
+#[derive(Debug, Clone, PartialEq)]
+enum Curve {
+	Parametric {
+		g: f32,
+		a: f32,
+		b: f32,
+		c: f32,
+		d: f32,
+		e: f32,
+		f: f32,
+	},
+	/// Evenly spaced samples from 0 to 1, interpolated linearly
+	Table(Vec<f32>),
+}
+
+const SRGB_PRIMARIES: [[f32; 3]; 3] = [[0.4361, 0.2225, 0.0139], [0.3851, 0.7169, 0.0971], [0.1431, 0.0606, 0.7141]];
+const SRGB_CURVE: Curve = Curve::Parametric {
+	g: 2.4,
+	a: 1.0 / 1.055,
+	b: 0.055 / 1.055,
+	c: 1.0 / 12.92,
+	d: 0.04045,
+	e: 0.0,
+	f: 0.0,
+};
+/// Converts XYZ adapted to D50 into linear sRGB
+const XYZ_TO_SRGB: [[f32; 3]; 3] = [
+	[3.133_856, -1.616_867, -0.490_615],
+	[-0.978_768, 1.916_142, 0.033_454],
+	[0.071_945, -0.228_991, 1.405_243],
+];
+
+impl ColorProfile {
+	/// Read an ICC profile, returning `None` if it isn't an RGB profile or doesn't have a matrix and tone curves
+	pub fn parse(bytes: &[u8]) -> Option<Self> {
+		if bytes.get(16..20)? != b"RGB " {
+			return None;
+		}
+		let tag = |signature: &[u8]| {
+			// The count can't be trusted, and a huge one would take a long time to look through
+			let count = (read_u32(bytes, 128)? as usize).min(bytes.len().saturating_sub(132) / 12);
+			(0..count).map(|i| 132 + i * 12).find(|&entry| bytes.get(entry..entry + 4) == Some(signature)).and_then(|entry| {
+				let offset = read_u32(bytes, entry + 4)? as usize;
+				let size = read_u32(bytes, entry + 8)? as usize;
+				bytes.get(offset..offset.checked_add(size)?)
+			})
+		};
+		let primary = |signature: &[u8]| {
+			let data = tag(signature)?;
+			if data.get(0..4)? != b"XYZ " {
+				return None;
+			}
+			Some([read_s15_fixed16(data, 8)?, read_s15_fixed16(data, 12)?, read_s15_fixed16(data, 16)?])
+		};
+
+		Some(ColorProfile {
+			primaries: [primary(b"rXYZ")?, primary(b"gXYZ")?, primary(b"bXYZ")?],
+			curves: [Curve::parse(tag(b"rTRC")?)?, Curve::parse(tag(b"gTRC")?)?, Curve::parse(tag(b"bTRC")?)?],
+		})
+	}
+
+	/// Whether the profile is (close enough to) sRGB, so images using it don't need to be converted
+	pub fn is_srgb(&self) -> bool {
+		let same_primaries = self.primaries.iter().flatten().zip(SRGB_PRIMARIES.iter().flatten()).all(|(a, b)| (a - b).abs() < 0.003);
+		let same_curves =
+			self.curves.iter().all(|curve| (0..=16).map(|i| i as f32 / 16.0).all(|x| (curve.apply(x) - SRGB_CURVE.apply(x)).abs() < 0.003));
+		same_primaries && same_curves
+	}
+
+	/// Convert an image from this profile to sRGB, clipping colors that sRGB can't show
+	///
+	/// The result is a 16 bit image (with alpha if the original had it), so the conversion doesn't add banding to smooth gradients.
+	pub fn to_srgb(&self, image: &DynamicImage) -> DynamicImage {
+		// The 16 bit values are decoded with lookup tables, since there are far fewer possible values than pixels in a photo
+		let tables = self.curves.clone().map(|curve| (0..=u16::MAX).map(|v| curve.apply(v as f32 / u16::MAX as f32)).collect::<Vec<_>>());
+		let mut matrix = [[0.0; 3]; 3];
+		for (row, to_srgb) in matrix.iter_mut().zip(XYZ_TO_SRGB) {
+			for (value, primary) in row.iter_mut().zip(self.primaries) {
+				*value = to_srgb.iter().zip(primary).map(|(a, b)| a * b).sum();
+			}
+		}
+		let convert = |rgb: &[u16]| {
+			let linear = [tables[0][rgb[0] as usize], tables[1][rgb[1] as usize], tables[2][rgb[2] as usize]];
+			matrix.map(|row| {
+				let value = (row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2]).clamp(0.0, 1.0);
+				let encoded = if value <= 0.0031308 {
+					value * 12.92
+				} else {
+					1.055 * value.powf(1.0 / 2.4) - 0.055
+				};
+				(encoded * u16::MAX as f32).round() as u16
+			})
+		};
+
+		if image.color().has_alpha() {
+			let rgba = image.to_rgba16();
+			DynamicImage::ImageRgba16(ImageBuffer::from_fn(rgba.width(), rgba.height(), |x, y| {
+				let pixel = rgba.get_pixel(x, y).0;
+				let [r, g, b] = convert(&pixel);
+				Rgba([r, g, b, pixel[3]])
+			}))
+		} else {
+			let rgb = image.to_rgb16();
+			DynamicImage::ImageRgb16(ImageBuffer::from_fn(rgb.width(), rgb.height(), |x, y| {
+				Rgb(convert(&rgb.get_pixel(x, y).0))
+			}))
+		}
+	}
+}
+
+impl Curve {
+	fn parse(data: &[u8]) -> Option<Self> {
+		match data.get(0..4)? {
+			b"curv" => {
+				let count = read_u32(data, 8)? as usize;
+				let entry = |i: usize| Some(u16::from_be_bytes(data.get(12 + i * 2..14 + i * 2)?.try_into().ok()?));
+				match count {
+					0 => Some(Curve::gamma(1.0)),
+					// A single entry is a gamma value as an 8.8 fixed point number
+					1 => Some(Curve::gamma(entry(0)? as f32 / 256.0)),
+					_ => Some(Curve::Table(
+						(0..count).map(|i| Some(entry(i)? as f32 / u16::MAX as f32)).collect::<Option<_>>()?,
+					)),
+				}
+			},
+			b"para" => {
+				let function = u16::from_be_bytes(data.get(8..10)?.try_into().ok()?);
+				let parameter = |i: usize| read_s15_fixed16(data, 12 + i * 4);
+				// Each function type is a special case of the next, so they're all stored as the most general one (type 4)
+				let g = parameter(0)?;
+				Some(match function {
+					0 => Curve::gamma(g),
+					1 => {
+						let (a, b) = (parameter(1)?, parameter(2)?);
+						Curve::Parametric {
+							g,
+							a,
+							b,
+							c: 0.0,
+							d: -b / a,
+							e: 0.0,
+							f: 0.0,
+						}
+					},
+					2 => {
+						let (a, b, c) = (parameter(1)?, parameter(2)?, parameter(3)?);
+						Curve::Parametric {
+							g,
+							a,
+							b,
+							c: 0.0,
+							d: -b / a,
+							e: c,
+							f: c,
+						}
+					},
+					3 => Curve::Parametric {
+						g,
+						a: parameter(1)?,
+						b: parameter(2)?,
+						c: parameter(3)?,
+						d: parameter(4)?,
+						e: 0.0,
+						f: 0.0,
+					},
+					4 => Curve::Parametric {
+						g,
+						a: parameter(1)?,
+						b: parameter(2)?,
+						c: parameter(3)?,
+						d: parameter(4)?,
+						e: parameter(5)?,
+						f: parameter(6)?,
+					},
+					_ => return None,
+				})
+			},
+			_ => None,
+		}
+	}
+
+	fn gamma(g: f32) -> Self {
+		Curve::Parametric {
+			g,
+			a: 1.0,
+			b: 0.0,
+			c: 0.0,
+			d: 0.0,
+			e: 0.0,
+			f: 0.0,
+		}
+	}
+
+	fn apply(&self, x: f32) -> f32 {
+		match *self {
+			Curve::Parametric { g, a, b, d, e, .. } if x >= d => (a * x + b).max(0.0).powf(g) + e,
+			Curve::Parametric { c, f, .. } => c * x + f,
+			Curve::Table(ref table) => {
+				let position = x.clamp(0.0, 1.0) * (table.len() - 1) as f32;
+				let i = (position as usize).min(table.len() - 2);
+				table[i] + (table[i + 1] - table[i]) * (position - i as f32)
+			},
+		}
+	}
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+	Some(u32::from_be_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn read_s15_fixed16(bytes: &[u8], offset: usize) -> Option<f32> {
+	Some(i32::from_be_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?) as f32 / 65536.0)
+}
+
+#[cfg(test)]
+mod tests {
+	use image::RgbImage;
+
+	use super::*;
+
+	const DISPLAY_P3_PRIMARIES: [[f32; 3]; 3] = [[0.5151, 0.2412, -0.0011], [0.2919, 0.6922, 0.0419], [0.1571, 0.0666, 0.7841]];
+
+	fn s15_fixed16(value: f32) -> [u8; 4] {
+		((value * 65536.0).round() as i32).to_be_bytes()
+	}
+
+	fn xyz_tag(xyz: [f32; 3]) -> Vec<u8> {
+		let mut tag = b"XYZ \0\0\0\0".to_vec();
+		for value in xyz {
+			tag.extend(s15_fixed16(value));
+		}
+		tag
+	}
+
+	/// A type 3 parametric curve, which is how most profiles store the sRGB curve
+	fn srgb_curve_tag() -> Vec<u8> {
+		let mut tag = b"para\0\0\0\0\0\x03\0\0".to_vec();
+		for value in [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045] {
+			tag.extend(s15_fixed16(value));
+		}
+		tag
+	}
+
+	/// A `curv` tag with these 16 bit entries
+	fn curv_tag(entries: &[u16]) -> Vec<u8> {
+		let mut tag = b"curv\0\0\0\0".to_vec();
+		tag.extend((entries.len() as u32).to_be_bytes());
+		tag.extend(entries.iter().flat_map(|e| e.to_be_bytes()));
+		tag
+	}
+
+	/// An ICC profile with just the header fields and tags `ColorProfile::parse` reads
+	fn profile(color_space: &[u8; 4], primaries: [[f32; 3]; 3], curve: &[u8]) -> Vec<u8> {
+		let tags: Vec<(&[u8; 4], Vec<u8>)> = vec![
+			(b"rXYZ", xyz_tag(primaries[0])),
+			(b"gXYZ", xyz_tag(primaries[1])),
+			(b"bXYZ", xyz_tag(primaries[2])),
+			(b"rTRC", curve.to_vec()),
+			(b"gTRC", curve.to_vec()),
+			(b"bTRC", curve.to_vec()),
+		];
+		let mut bytes = vec![0u8; 128];
+		bytes[16..20].copy_from_slice(color_space);
+		bytes.extend((tags.len() as u32).to_be_bytes());
+		let mut offset = 132 + tags.len() * 12;
+		let mut data: Vec<u8> = Vec::new();
+		for (signature, tag) in &tags {
+			bytes.extend(*signature);
+			bytes.extend((offset as u32).to_be_bytes());
+			bytes.extend((tag.len() as u32).to_be_bytes());
+			offset += tag.len();
+			data.extend(tag);
+		}
+		bytes.extend(data);
+		bytes
+	}
+
+	#[test]
+	fn recognizes_srgb() {
+		let srgb = ColorProfile::parse(&profile(b"RGB ", SRGB_PRIMARIES, &srgb_curve_tag())).unwrap();
+		assert!(srgb.is_srgb());
+		let p3 = ColorProfile::parse(&profile(b"RGB ", DISPLAY_P3_PRIMARIES, &srgb_curve_tag())).unwrap();
+		assert!(!p3.is_srgb());
+		let gamma = ColorProfile::parse(&profile(b"RGB ", SRGB_PRIMARIES, &curv_tag(&[563]))).unwrap();
+		assert!(!gamma.is_srgb());
+	}
+
+	#[test]
+	fn reads_curv_curves() {
+		let Some(Curve::Parametric { g, .. }) = Curve::parse(&curv_tag(&[563])) else {
+			panic!("a single entry is a gamma");
+		};
+		assert!((g - 2.2).abs() < 0.01);
+		let linear = Curve::parse(&curv_tag(&[0, u16::MAX])).unwrap();
+		assert!((linear.apply(0.25) - 0.25).abs() < 1e-6);
+		assert_eq!(Curve::parse(&curv_tag(&[])).unwrap().apply(0.5), 0.5);
+		// The count says there are more entries than there are
+		let mut cut_off = curv_tag(&[0, 100, 200]);
+		cut_off.truncate(cut_off.len() - 2);
+		assert_eq!(Curve::parse(&cut_off), None);
+	}
+
+	#[test]
+	fn rejects_other_and_broken_profiles() {
+		assert_eq!(ColorProfile::parse(&profile(b"GRAY", SRGB_PRIMARIES, &srgb_curve_tag())), None);
+		assert_eq!(ColorProfile::parse(&profile(b"RGB ", SRGB_PRIMARIES, b"mAB \0\0\0\0")), None);
+		assert_eq!(ColorProfile::parse(&[]), None);
+
+		let bytes = profile(b"RGB ", SRGB_PRIMARIES, &srgb_curve_tag());
+		assert_eq!(ColorProfile::parse(&bytes[..bytes.len() - 1]), None);
+		// A tag whose offset and size add up to more than a usize can hold
+		let mut overflowing = bytes.clone();
+		overflowing[136..140].copy_from_slice(&u32::MAX.to_be_bytes());
+		overflowing[140..144].copy_from_slice(&u32::MAX.to_be_bytes());
+		assert_eq!(ColorProfile::parse(&overflowing), None);
+		// More tags than there are bytes for, with one missing so they're all looked through
+		let mut too_many_tags = bytes;
+		too_many_tags[128..132].copy_from_slice(&u32::MAX.to_be_bytes());
+		assert!(ColorProfile::parse(&too_many_tags).is_some());
+		too_many_tags[132 + 5 * 12..132 + 5 * 12 + 4].copy_from_slice(b"xxxx");
+		assert_eq!(ColorProfile::parse(&too_many_tags), None);
+	}
+
+	#[test]
+	fn converts_wide_gamut_colors_to_srgb() {
+		let p3 = ColorProfile::parse(&profile(b"RGB ", DISPLAY_P3_PRIMARIES, &srgb_curve_tag())).unwrap();
+		let image = DynamicImage::ImageRgb8(RgbImage::from_fn(3, 1, |x, _| match x {
+			0 => Rgb([255, 0, 0]),
+			1 => Rgb([128, 128, 128]),
+			_ => Rgb([0, 0, 0]),
+		}));
+		let converted = p3.to_srgb(&image).to_rgb8();
+		// P3's red is redder than sRGB can show, so it's clipped to sRGB's red
+		assert_eq!(converted.get_pixel(0, 0).0, [255, 0, 0]);
+		// Grays are the same in both, since they share a white point and a tone curve
+		for channel in converted.get_pixel(1, 0).0 {
+			assert!(channel.abs_diff(128) <= 1, "{:?}", converted.get_pixel(1, 0));
+		}
+		assert_eq!(converted.get_pixel(2, 0).0, [0, 0, 0]);
+	}
+}
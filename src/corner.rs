@@ -0,0 +1,34 @@
+use std::f32::consts::FRAC_PI_2;
+
+/// A lithophane bent 90° around a rounded corner, eg for a lamp that stands in the corner of a room with the light behind it
+///
+/// The image is split evenly between the two flat faces, with a quarter circle between them. It's generated from expressions by the
+/// normal lithophane generator, which are built from `min` and `max` so each part of the path is only used for its own stretch of the
+/// image. The image faces out, toward the room, and stands upright with its bottom edge at z = 0. All sizes are in millimeters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CornerWrap {
+	/// The width of the lithophane measured around the corner, as if it was flattened out
+	pub width: f32,
+	/// The radius of the inside of the bend, which should be larger than the black depth so the pixels don't overlap there
+	pub fillet_radius: f32,
+}
+
+impl CornerWrap {
+	/// The width of each of the flat faces, which is negative if the bend is too big to fit in the width
+	pub fn face_width(&self) -> f32 {
+		(self.width - FRAC_PI_2 * self.fillet_radius) / 2.0
+	}
+
+	/// Expressions for the lithophane around a corner at the origin, with the first face along the x axis and the second along the y axis
+	pub fn expressions(&self) -> [String; 3] {
+		let (face, radius) = (self.face_width(), self.fillet_radius);
+		// The distance along the lithophane, and the angle the bend has turned through so far
+		let distance = format!("(x*{}/(w-1))", self.width);
+		let angle = format!("(min(max({}-{},0),{})/{})", distance, face, FRAC_PI_2 * radius, radius);
+		[
+			format!("{}-min({},{})-{}*sin{}", radius + face, distance, face, radius, angle),
+			format!("{r}-{r}*cos{}+max({}-{},0)", angle, distance, face + FRAC_PI_2 * radius, r = radius),
+			format!("(h-1-y)*{}/(w-1)", self.width),
+		]
+	}
+}
@@ -0,0 +1,154 @@
+use image::{
+	imageops::{self, FilterType},
+	GrayImage, Luma,
+};
+
+use crate::preprocessing::resize_area_average;
+
+/// An estimate of how much of an image's detail will be visible in the printed lithophane, eg to explain why a face disappears on a
+/// 20 mm keychain
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DetailReport {
+	/// The distance between neighbouring pixels on the surface of the lithophane, in millimeters
+	pub pixel_size: f32,
+	/// The size of the smallest feature that can be printed and seen, in millimeters
+	pub smallest_feature: f32,
+	/// The size of the smallest visible feature in pixels of the image
+	pub smallest_feature_pixels: f32,
+	/// The fraction of the image's detail (the contrast between neighbouring pixels) that's still there at the size of the smallest
+	/// visible feature, from 0 to 1
+	pub visible_detail: f32,
+}
+
+/// Estimate how much of an image's detail survives being printed with `pixel_size` millimeters between pixels, when features smaller
+/// than `smallest_feature` millimeters (usually about the nozzle width) can't be printed
+///
+/// The image is shrunk so each pixel is the size of the smallest feature and enlarged again, and the contrast between neighbouring pixels
+/// that's left is compared to the original. Images whose detail is mostly in large areas keep more of it than eg a small group photo.
+pub fn detail_report(image: &GrayImage, pixel_size: f32, smallest_feature: f32) -> DetailReport {
+	let smallest_feature_pixels = smallest_feature / pixel_size;
+	let (width, height) = image.dimensions();
+
+	let visible_detail = if smallest_feature_pixels <= 1.0 || width < 2 || height < 2 {
+		1.0
+	} else {
+		let shrunk = resize_area_average(
+			image,
+			((width as f32 / smallest_feature_pixels).round() as u32).max(1),
+			((height as f32 / smallest_feature_pixels).round() as u32).max(1),
+		);
+		let printed = imageops::resize(&shrunk, width, height, FilterType::Triangle);
+		let original_detail = contrast(image);
+		if original_detail == 0.0 {
+			1.0
+		} else {
+			(contrast(&printed) / original_detail).min(1.0) as f32
+		}
+	};
+
+	DetailReport {
+		pixel_size,
+		smallest_feature,
+		smallest_feature_pixels,
+		visible_detail,
+	}
+}
+
+/// The sum of the squared differences between horizontally and vertically neighbouring pixels
+fn contrast(image: &GrayImage) -> f64 {
+	let value = |x: u32, y: u32| image.get_pixel(x, y).0[0] as f64;
+	let mut sum = 0.0;
+	for y in 0..image.height() {
+		for x in 0..image.width() {
+			if x + 1 < image.width() {
+				sum += (value(x + 1, y) - value(x, y)).powi(2);
+			}
+			if y + 1 < image.height() {
+				sum += (value(x, y + 1) - value(x, y)).powi(2);
+			}
+		}
+	}
+	sum
+}
+
+/// A dark feature (so a thick part of the lithophane) that's too thin to print, like a line in line art, as a bounding box in pixels of the
+/// image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThinFeature {
+	pub x: u32,
+	pub y: u32,
+	pub width: u32,
+	pub height: u32,
+	/// How many pixels of the feature are too thin
+	pub pixels: u32,
+}
+
+/// How much darker than its surroundings a feature has to be to count in `thin_features`, as a gray value difference
+const THIN_FEATURE_CONTRAST: u8 = 64;
+
+/// Find dark features that are narrower than `smallest_feature` millimeters when the lithophane is printed with `pixel_size` millimeters
+/// between pixels, so they can be made thicker before printing
+///
+/// Features are found by filling in everything narrower than the smallest feature (a morphological closing) and looking for the pixels
+/// that got much lighter, which are then grouped into connected areas, largest first.
+pub fn thin_features(image: &GrayImage, pixel_size: f32, smallest_feature: f32) -> Vec<ThinFeature> {
+	let (width, height) = image.dimensions();
+	// A surface with no size (eg every expression is 0) has nothing to check
+	if !(pixel_size > 0.0 && pixel_size.is_finite()) {
+		return Vec::new();
+	}
+	let radius = ((smallest_feature / pixel_size - 1.0) / 2.0).ceil();
+	if !(radius >= 1.0 && radius.is_finite()) {
+		return Vec::new();
+	}
+	// A square bigger than the image covers all of it anyway
+	let radius = (radius as u32).min(width.max(height));
+	let closed = square_filter(&square_filter(image, radius, u8::max), radius, u8::min);
+	let mut thin = image.pixels().zip(closed.pixels()).map(|(p, c)| c.0[0].saturating_sub(p.0[0]) >= THIN_FEATURE_CONTRAST).collect::<Vec<_>>();
+
+	let mut features = Vec::new();
+	let mut stack = Vec::new();
+	for start in 0..thin.len() {
+		if !thin[start] {
+			continue;
+		}
+		thin[start] = false;
+		stack.push(start);
+		let (mut min_x, mut min_y, mut max_x, mut max_y, mut pixels) = (width, height, 0, 0, 0);
+		while let Some(i) = stack.pop() {
+			let (x, y) = ((i % width as usize) as u32, (i / width as usize) as u32);
+			(min_x, min_y, max_x, max_y, pixels) = (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y), pixels + 1);
+			for ny in y.saturating_sub(1)..=(y + 1).min(height - 1) {
+				for nx in x.saturating_sub(1)..=(x + 1).min(width - 1) {
+					let n = (ny * width + nx) as usize;
+					if thin[n] {
+						thin[n] = false;
+						stack.push(n);
+					}
+				}
+			}
+		}
+		features.push(ThinFeature {
+			x: min_x,
+			y: min_y,
+			width: max_x - min_x + 1,
+			height: max_y - min_y + 1,
+			pixels,
+		});
+	}
+	features.sort_by_key(|f| std::cmp::Reverse(f.pixels));
+	features
+}
+
+/// Combine every pixel with its neighbours in a square `radius` pixels out from it, eg with `u8::max` to lighten everything near a light
+/// pixel
+fn square_filter(image: &GrayImage, radius: u32, combine: fn(u8, u8) -> u8) -> GrayImage {
+	let (width, height) = image.dimensions();
+	// The square is separable, so it's done as a row and then a column
+	let rows = GrayImage::from_fn(width, height, |x, y| {
+		Luma([(x.saturating_sub(radius)..=(x + radius).min(width - 1)).map(|x| image.get_pixel(x, y).0[0]).reduce(combine).unwrap()])
+	});
+	GrayImage::from_fn(width, height, |x, y| {
+		Luma([(y.saturating_sub(radius)..=(y + radius).min(height - 1)).map(|y| rows.get_pixel(x, y).0[0]).reduce(combine).unwrap()])
+	})
+}
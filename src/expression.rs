@@ -0,0 +1,143 @@
+use meval::{tokenizer::Token, Context, Expr};
+use thiserror::Error;
+
+/// The unit that the trig functions in expressions take (and the inverse trig functions return) angles in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AngleUnit {
+	#[default]
+	Radians,
+	/// Makes eg `cos(90)` 0, like in OpenSCAD
+	Degrees,
+}
+
+/// The functions and constants available in expressions
+///
+/// This is meval's built in functions and constants, with the trig functions using `angle_unit`, plus `deg()` and `rad()` to convert
+/// radians to degrees and degrees to radians.
+pub fn context(angle_unit: AngleUnit) -> Context<'static> {
+	let mut context = Context::new();
+	context.func("deg", f64::to_degrees).func("rad", f64::to_radians);
+
+	if angle_unit == AngleUnit::Degrees {
+		context
+			.func("sin", |a: f64| a.to_radians().sin())
+			.func("cos", |a: f64| a.to_radians().cos())
+			.func("tan", |a: f64| a.to_radians().tan())
+			.func("asin", |v: f64| v.asin().to_degrees())
+			.func("acos", |v: f64| v.acos().to_degrees())
+			.func("atan", |v: f64| v.atan().to_degrees())
+			.func2("atan2", |y: f64, x: f64| y.atan2(x).to_degrees());
+	}
+
+	context
+}
+
+/// A parsed expression of `x`, `y`, `w` and `h`, which can be cloned and sent to other threads, unlike the closures meval binds
+/// expressions into (its `Context` isn't `Send`), so each thread can bind its own without parsing the expression again
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledExpression {
+	expression: Expr,
+	angle_unit: AngleUnit,
+}
+
+impl CompiledExpression {
+	/// Parse an expression, checking that it only uses `x`, `y`, `w`, `h` and the functions and constants in `context`
+	pub fn new(source: &str, angle_unit: AngleUnit) -> Result<Self, meval::Error> {
+		let expression = source.parse::<Expr>()?;
+		// Binding checks that every variable and function exists
+		let _ = expression.clone().bind4_with_context(context(angle_unit), "x", "y", "w", "h")?;
+		Ok(Self { expression, angle_unit })
+	}
+
+	/// The parsed expression, eg to check it with a `Sandbox`
+	pub fn expression(&self) -> &Expr {
+		&self.expression
+	}
+
+	/// A function that evaluates the expression at a point in image coordinates, like the ones `generate_lithophane` takes
+	pub fn bind(&self) -> impl Fn(f32, f32, f32, f32) -> f32 {
+		let f = self
+			.expression
+			.clone()
+			.bind4_with_context(context(self.angle_unit), "x", "y", "w", "h")
+			.expect("the expression was checked when it was compiled");
+		move |x: f32, y: f32, w: f32, h: f32| -> f32 { f(x as f64, y as f64, w as f64, h as f64) as f32 }
+	}
+}
+
+/// Limits on expressions that come from someone who isn't trusted, eg when a server generates lithophanes for its users
+///
+/// Every function in `context` is a plain math function, so the main risk is expressions that take a very long time to evaluate for every
+/// vertex. Limiting the number of nodes bounds that, and limiting the functions lets a host leave out any it doesn't want to offer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sandbox {
+	/// The names of the functions expressions are allowed to call
+	pub allowed_functions: Vec<String>,
+	/// The most numbers, variables, operators and function calls an expression can have
+	pub max_nodes: usize,
+}
+
+impl Default for Sandbox {
+	/// Every function in `context`, with up to 256 nodes
+	fn default() -> Self {
+		Self {
+			allowed_functions: [
+				"sqrt", "exp", "ln", "abs", "sin", "cos", "tan", "asin", "acos", "atan", "sinh", "cosh", "tanh", "asinh", "acosh", "atanh", "floor",
+				"ceil", "round", "signum", "atan2", "max", "min", "deg", "rad",
+			]
+			.map(String::from)
+			.to_vec(),
+			max_nodes: 256,
+		}
+	}
+}
+
+#[derive(Error, Debug)]
+pub enum SandboxError {
+	#[error("the function \"{0}\" isn't allowed")]
+	FunctionNotAllowed(String),
+	#[error("it has {nodes} parts, but only {max_nodes} are allowed")]
+	TooComplex { nodes: usize, max_nodes: usize },
+}
+
+impl Sandbox {
+	/// Check that a parsed expression stays within the limits, before it's bound and evaluated
+	pub fn check(&self, expression: &Expr) -> Result<(), SandboxError> {
+		// Parentheses are gone after parsing, so every token left is a node
+		if expression.len() > self.max_nodes {
+			return Err(SandboxError::TooComplex {
+				nodes: expression.len(),
+				max_nodes: self.max_nodes,
+			});
+		}
+		for token in expression.iter() {
+			if let Token::Func(name, _) = token {
+				if !self.allowed_functions.contains(name) {
+					return Err(SandboxError::FunctionNotAllowed(name.clone()));
+				}
+			}
+		}
+		Ok(())
+	}
+}
+
+#[derive(Error, Debug)]
+pub enum ExpressionError {
+	#[error("invalid {0} expression: {1}")]
+	Invalid(&'static str, meval::Error),
+	#[error("invalid {0} expression: {1}")]
+	NotAllowed(&'static str, SandboxError),
+}
+
+/// Compile the x, y and z expressions, checking them against `sandbox` if there is one, so the CLI, the wasm and C APIs and the job
+/// queue all compile and check them the same way
+pub fn compile_expressions(sources: [&str; 3], angle_unit: AngleUnit, sandbox: Option<&Sandbox>) -> Result<[CompiledExpression; 3], ExpressionError> {
+	let [x, y, z] = [("x", sources[0]), ("y", sources[1]), ("z", sources[2])].map(|(name, source)| {
+		let compiled = CompiledExpression::new(source, angle_unit).map_err(|e| ExpressionError::Invalid(name, e))?;
+		if let Some(sandbox) = sandbox {
+			sandbox.check(compiled.expression()).map_err(|e| ExpressionError::NotAllowed(name, e))?;
+		}
+		Ok(compiled)
+	});
+	Ok([x?, y?, z?])
+}
@@ -0,0 +1,138 @@
+use std::{
+	cell::RefCell,
+	error::Error,
+	ffi::{c_char, CStr, CString},
+	panic, ptr, slice,
+};
+
+use crate::{
+	expression, input,
+	lithophane::{self, LithophaneOptions},
+	output, preprocessing,
+};
+
+/// Settings for `lithophane_generate`, which `lithophane_default_options` fills in with the defaults
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct LithophaneFfiOptions {
+	pub white_depth: f32,
+	pub black_depth: f32,
+	/// The frame to use from an animated image, 0 for other images
+	pub frame: usize,
+	pub subdivisions: u32,
+	/// Whether to compress the STL with gzip
+	pub compress: bool,
+}
+
+/// Bytes allocated by the library, which have to be given back to `lithophane_free_buffer` to be freed
+#[repr(C)]
+#[derive(Debug)]
+pub struct LithophaneBuffer {
+	pub data: *mut u8,
+	pub len: usize,
+}
+
+thread_local! {
+	static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+#[no_mangle]
+pub extern "C" fn lithophane_default_options() -> LithophaneFfiOptions {
+	let options = LithophaneOptions::default();
+	LithophaneFfiOptions {
+		white_depth: options.white_depth,
+		black_depth: options.black_depth,
+		frame: 0,
+		subdivisions: options.subdivisions,
+		compress: false,
+	}
+}
+
+/// Generate a lithophane from an encoded image (eg the contents of a PNG file) as a binary STL, returning 0 if it worked and -1 if it
+/// didn't, with the reason from `lithophane_last_error`
+///
+/// # Safety
+///
+/// The expressions have to be nul terminated strings, `image` has to point to `image_len` bytes, and `options` and `output` have to be
+/// valid pointers. `output` is only written to if it worked.
+#[no_mangle]
+pub unsafe extern "C" fn lithophane_generate(
+	x_expression: *const c_char,
+	y_expression: *const c_char,
+	z_expression: *const c_char,
+	image: *const u8,
+	image_len: usize,
+	options: *const LithophaneFfiOptions,
+	output: *mut LithophaneBuffer,
+) -> i32 {
+	let expressions = [x_expression, y_expression, z_expression].map(|e| CStr::from_ptr(e).to_string_lossy());
+	let image = slice::from_raw_parts(image, image_len);
+	let options = *options;
+
+	// Panics can't unwind into the caller's code, so they're reported like any other error
+	let result = panic::catch_unwind(|| generate(&expressions[0], &expressions[1], &expressions[2], image, options))
+		.unwrap_or_else(|_| Err("the lithophane generator panicked".into()));
+	match result {
+		Ok(stl) => {
+			let stl = Box::into_raw(stl.into_boxed_slice());
+			*output = LithophaneBuffer {
+				data: stl.cast(),
+				len: stl.len(),
+			};
+			0
+		},
+		Err(e) => {
+			let message = CString::new(e.to_string().replace('\0', " ")).expect("nul bytes were replaced");
+			LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
+			-1
+		},
+	}
+}
+
+/// Free the bytes returned by `lithophane_generate`
+///
+/// # Safety
+///
+/// The buffer has to be one returned by `lithophane_generate` that hasn't been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn lithophane_free_buffer(buffer: LithophaneBuffer) {
+	if !buffer.data.is_null() {
+		drop(Box::from_raw(ptr::slice_from_raw_parts_mut(buffer.data, buffer.len)));
+	}
+}
+
+/// The reason the last call to `lithophane_generate` on this thread failed, or null if none has
+///
+/// The string belongs to the library and stays valid until the next failed call on the same thread.
+#[no_mangle]
+pub extern "C" fn lithophane_last_error() -> *const c_char {
+	LAST_ERROR.with(|last_error| last_error.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+fn generate(
+	x_expression: &str,
+	y_expression: &str,
+	z_expression: &str,
+	image: &[u8],
+	options: LithophaneFfiOptions,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+	let image = input::decode_frame_as_srgb(image, options.frame)?;
+
+	let [x_fn, y_fn, z_fn] = expression::compile_expressions([x_expression, y_expression, z_expression], Default::default(), None)?.map(|e| e.bind());
+
+	let stl = lithophane::generate_lithophane(
+		x_fn,
+		y_fn,
+		z_fn,
+		preprocessing::to_grayscale(image, Default::default(), Default::default()),
+		&LithophaneOptions {
+			white_depth: options.white_depth,
+			black_depth: options.black_depth,
+			subdivisions: options.subdivisions,
+			..Default::default()
+		},
+	)
+	.map(|model| output::as_binary(&model))?;
+
+	Ok(if options.compress { output::compress_gzip(&stl)? } else { stl })
+}
@@ -0,0 +1,164 @@
+use image::{GrayImage, Luma};
+use pk_stl::{error::Error as StlError, StlModel};
+
+use crate::{
+	expression::{AngleUnit, CompiledExpression},
+	lithophane::{generate_lithophane, LithophaneOptions},
+	manifest::hash_input,
+	mesh::{canonicalize, quantize},
+	outline::{Outline, Shape},
+	output::as_binary,
+};
+
+/// The precision models are rounded to before they're hashed, which is coarse enough to hide the differences in the last bits of
+/// floating point math between platforms
+pub const FIXTURE_PRECISION: f32 = 0.0001;
+
+/// A small lithophane that always comes out the same, with the hash of the result, so bindings to this crate (and code that saves or
+/// loads its models) can be checked against known good geometry
+///
+/// The images are generated rather than loaded, and the expressions only use arithmetic, so nothing depends on image decoders or on
+/// how a platform calculates `sin` and friends.
+#[derive(Debug, Clone)]
+pub struct Fixture {
+	/// A short name to tell the fixtures apart, eg in test output
+	pub name: &'static str,
+	pub width: u32,
+	pub height: u32,
+	/// The gray value of each pixel of the image, from its coordinates
+	pub pixel: fn(u32, u32) -> u8,
+	pub x_expression: &'static str,
+	pub y_expression: &'static str,
+	pub z_expression: &'static str,
+	/// Whether the lithophane is cut to the largest circle that fits in the image
+	pub circle: bool,
+	/// The number of triangles in the lithophane
+	pub triangles: usize,
+	/// `manifest::hash_input` of the binary STL from `canonical_stl`
+	pub hash: u64,
+}
+
+impl Fixture {
+	pub fn image(&self) -> GrayImage {
+		GrayImage::from_fn(self.width, self.height, |x, y| Luma([(self.pixel)(x, y)]))
+	}
+
+	pub fn options(&self) -> LithophaneOptions {
+		LithophaneOptions {
+			outline: Outline {
+				boundary: self.circle.then(|| Shape::circle_in_image(self.width, self.height, 0.0, 0.0)),
+				..Default::default()
+			},
+			..Default::default()
+		}
+	}
+
+	/// Generate the lithophane with the default settings, before it's made canonical
+	pub fn generate(&self) -> StlModel {
+		let [x_fn, y_fn, z_fn] = [self.x_expression, self.y_expression, self.z_expression]
+			.map(|source| CompiledExpression::new(source, AngleUnit::Radians).expect("the fixture expressions are valid").bind());
+		generate_lithophane(x_fn, y_fn, z_fn, self.image(), &self.options()).expect("the fixtures make valid lithophanes")
+	}
+
+	/// The lithophane as binary STL, rounded to `FIXTURE_PRECISION` with the triangles in a canonical order and no header, which is what
+	/// `hash` is the hash of
+	pub fn canonical_stl(&self) -> Vec<u8> {
+		canonical_stl(self.generate())
+	}
+
+	/// Check that a binary STL file has the same geometry as this fixture, ignoring its header, the order of its triangles and differences
+	/// smaller than `FIXTURE_PRECISION`
+	pub fn matches(&self, stl: &[u8]) -> Result<bool, StlError> {
+		let stl = canonical_stl(pk_stl::parse_stl(stl)?);
+		Ok(hash_input(&stl) == self.hash)
+	}
+}
+
+fn canonical_stl(mut model: StlModel) -> Vec<u8> {
+	model.header.clear();
+	quantize(&mut model, FIXTURE_PRECISION);
+	canonicalize(&mut model);
+	as_binary(&model)
+}
+
+/// The fixtures, which cover a flat panel, a curved one and one cut to a circle
+pub fn fixtures() -> Vec<Fixture> {
+	vec![
+		Fixture {
+			name: "flat-gradient",
+			width: 16,
+			height: 8,
+			pixel: |x, _| (x * 17) as u8,
+			x_expression: "x",
+			y_expression: "-y",
+			z_expression: "0",
+			circle: false,
+			triangles: 508,
+			hash: 0x2e12_4bb4_6149_008f,
+		},
+		Fixture {
+			name: "curved-checkerboard",
+			width: 12,
+			height: 12,
+			pixel: |x, y| if (x / 3 + y / 3) % 2 == 0 { 255 } else { 0 },
+			x_expression: "x",
+			y_expression: "-y",
+			z_expression: "(x - w / 2) * (x - w / 2) / w",
+			circle: false,
+			triangles: 572,
+			hash: 0xfe6d_3f2d_a209_4d35,
+		},
+		Fixture {
+			name: "circle-rings",
+			width: 16,
+			height: 16,
+			pixel: |x, y| {
+				if ((x as i32 - 8).pow(2) + (y as i32 - 8).pow(2)) % 20 < 10 {
+					64
+				} else {
+					192
+				}
+			},
+			x_expression: "x",
+			y_expression: "-y",
+			z_expression: "0",
+			circle: true,
+			triangles: 908,
+			hash: 0x44a4_a51f_e4e2_6d3e,
+		},
+	]
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn fixtures_match_their_hashes() {
+		for fixture in fixtures() {
+			let model = fixture.generate();
+			assert_eq!(
+				model.triangles.len(),
+				fixture.triangles,
+				"{} has the wrong number of triangles",
+				fixture.name
+			);
+			assert!(fixture.matches(&as_binary(&model)).unwrap(), "{} doesn't match its hash", fixture.name);
+		}
+	}
+
+	#[test]
+	fn fixtures_dont_match_each_other() {
+		let fixtures = fixtures();
+		for fixture in &fixtures {
+			for other in fixtures.iter().filter(|other| other.name != fixture.name) {
+				assert!(
+					!other.matches(&fixture.canonical_stl()).unwrap(),
+					"{} matches {}",
+					fixture.name,
+					other.name
+				);
+			}
+		}
+	}
+}
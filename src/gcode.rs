@@ -0,0 +1,169 @@
+use std::{f32::consts::PI, fmt::Write};
+
+use thiserror::Error;
+
+use crate::{
+	printer::{BuildVolume, PrintSettings},
+	resin::{ResinError, ThicknessMap},
+};
+
+/// Settings for `flat_lithophane_gcode` on top of the slicer settings, in millimeters, millimeters per second and degrees Celsius
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GcodeSettings {
+	pub print: PrintSettings,
+	/// The lithophane is put in the middle of the bed
+	pub bed: BuildVolume,
+	pub nozzle_temperature: u32,
+	pub bed_temperature: u32,
+	pub filament_diameter: f32,
+	pub travel_speed: f32,
+	/// How far the filament is pulled back before travelling, to keep it from oozing
+	pub retraction: f32,
+}
+
+impl Default for GcodeSettings {
+	/// PLA on an Ender 3 sized bed
+	fn default() -> Self {
+		Self {
+			print: PrintSettings::default(),
+			bed: BuildVolume {
+				width: 220.0,
+				depth: 220.0,
+				height: 250.0,
+			},
+			nozzle_temperature: 210,
+			bed_temperature: 60,
+			filament_diameter: 1.75,
+			travel_speed: 150.0,
+			retraction: 0.8,
+		}
+	}
+}
+
+#[derive(Error, Debug)]
+pub enum GcodeError {
+	#[error("the lithophane is {width:.1} × {depth:.1} × {height:.1} mm, which doesn't fit in the build volume")]
+	DoesntFit { width: f32, depth: f32, height: f32 },
+	#[error(transparent)]
+	Resin(#[from] ResinError),
+}
+
+/// Travel moves shorter than this many line widths don't retract, since they're only hopping to the next line
+const RETRACTION_LINE_WIDTHS: f32 = 2.0;
+
+/// Turn a flat lithophane lying on the bed straight into G-code for a filament printer, without making a mesh and slicing it
+///
+/// This is experimental and deliberately simple, for printing many lithophanes of the same format: every layer is filled with straight
+/// lines one `line_width` apart wherever the lithophane reaches that layer, going back and forth along x on even layers and along y on odd
+/// ones, with no perimeters, skirt or supports. `map` has to be made with a pixel size of `line_width`, so each of its pixels is one
+/// stretch of one line. The first layer is printed at half speed. Extrusion is relative (M83) and doesn't include any printer specific
+/// start code beyond homing and heating up, so check the output in a G-code viewer before printing it.
+pub fn flat_lithophane_gcode(map: &ThicknessMap, settings: &GcodeSettings) -> Result<String, GcodeError> {
+	let GcodeSettings {
+		print,
+		bed,
+		nozzle_temperature,
+		bed_temperature,
+		filament_diameter,
+		travel_speed,
+		retraction,
+	} = *settings;
+	let line_width = print.line_width;
+	let (width, depth) = (map.width as f32 * line_width, map.height as f32 * line_width);
+	let layers = map.layer_count(print.layer_height)?;
+	let height = layers as f32 * print.layer_height;
+	if width > bed.width || depth > bed.depth || height > bed.height {
+		return Err(GcodeError::DoesntFit { width, depth, height });
+	}
+
+	// Where the middle of a pixel of the map ends up on the bed, with the top of the image at the back
+	let (left, front) = ((bed.width - width) / 2.0, (bed.depth - depth) / 2.0);
+	let position = |x: u32, y: u32| {
+		(
+			left + (x as f32 + 0.5) * line_width,
+			front + (map.height - y) as f32 * line_width - line_width / 2.0,
+		)
+	};
+	// The length of filament needed per millimeter of line
+	let extrusion = line_width * print.layer_height / (PI * filament_diameter * filament_diameter / 4.0);
+	let per_minute = |speed: f32| (speed * 60.0).round() as u32;
+
+	let mut gcode = String::new();
+	writeln!(
+		gcode,
+		"; Flat lithophane, {:.1} × {:.1} × {:.2} mm in {} layers",
+		width, depth, height, layers
+	)
+	.unwrap();
+	writeln!(
+		gcode,
+		"M140 S{}\nM104 S{}\nG28\nM190 S{}\nM109 S{}",
+		bed_temperature, nozzle_temperature, bed_temperature, nozzle_temperature
+	)
+	.unwrap();
+	writeln!(gcode, "G90\nM83\nG92 E0").unwrap();
+
+	let mut at = (0.0, 0.0);
+	for layer in 0..layers {
+		let middle = (layer as f32 + 0.5) * print.layer_height;
+		let speed = if layer == 0 { print.infill_speed / 2.0 } else { print.infill_speed };
+		writeln!(
+			gcode,
+			"; Layer {}\nG1 Z{:.3} F{}",
+			layer + 1,
+			(layer + 1) as f32 * print.layer_height,
+			per_minute(travel_speed)
+		)
+		.unwrap();
+
+		// Each line is a row (or column on odd layers) of the map, reversed every other line so the nozzle zigzags
+		let along_x = layer % 2 == 0;
+		let (lines, length) = if along_x { (map.height, map.width) } else { (map.width, map.height) };
+		for line in 0..lines {
+			let forwards = line % 2 == 0;
+			let pixel = |i: u32| {
+				let i = if forwards { i } else { length - 1 - i };
+				if along_x {
+					(i, line)
+				} else {
+					(line, i)
+				}
+			};
+			let filled = |i: u32| {
+				let (x, y) = pixel(i);
+				map.thickness[(y * map.width + x) as usize] > middle
+			};
+			let mut i = 0;
+			while i < length {
+				if !filled(i) {
+					i += 1;
+					continue;
+				}
+				let start = i;
+				while i < length && filled(i) {
+					i += 1;
+				}
+				// The line goes from the near edge of its first pixel to the far edge of its last, and the image's y goes the other way to the bed's
+				let ((x0, y0), (x1, y1)) = (position(pixel(start).0, pixel(start).1), position(pixel(i - 1).0, pixel(i - 1).1));
+				let half = if forwards { line_width / 2.0 } else { -line_width / 2.0 };
+				let (dx, dy) = if along_x { (half, 0.0) } else { (0.0, -half) };
+				let (from, to) = ((x0 - dx, y0 - dy), (x1 + dx, y1 + dy));
+				let travel = (from.0 - at.0).hypot(from.1 - at.1);
+				let retract = travel > RETRACTION_LINE_WIDTHS * line_width && retraction > 0.0;
+				if retract {
+					writeln!(gcode, "G1 E{:.4}", -retraction).unwrap();
+				}
+				writeln!(gcode, "G0 X{:.3} Y{:.3} F{}", from.0, from.1, per_minute(travel_speed)).unwrap();
+				if retract {
+					writeln!(gcode, "G1 E{:.4}", retraction).unwrap();
+				}
+				let extruded = (to.0 - from.0).hypot(to.1 - from.1) * extrusion;
+				writeln!(gcode, "G1 X{:.3} Y{:.3} E{:.5} F{}", to.0, to.1, extruded, per_minute(speed)).unwrap();
+				at = to;
+			}
+		}
+	}
+
+	writeln!(gcode, "; End\nG1 E{:.4}\nG91\nG1 Z10 F600\nG90\nM104 S0\nM140 S0\nM84", -retraction).unwrap();
+	Ok(gcode)
+}
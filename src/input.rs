@@ -0,0 +1,153 @@
+use std::io::Cursor;
+
+#[cfg(feature = "tiff")]
+use image::error::{DecodingError, ImageFormatHint, UnsupportedError, UnsupportedErrorKind};
+use image::{
+	codecs::{gif::GifDecoder, jpeg::JpegDecoder, png::PngDecoder},
+	error::{ParameterError, ParameterErrorKind},
+	AnimationDecoder, DynamicImage, ImageDecoder, ImageError, ImageFormat, ImageResult,
+};
+
+use crate::color_profile::ColorProfile;
+
+/// The number of frames (or pages) in an image, which is 1 for formats that don't support more than one
+///
+/// Animated GIFs and PNGs (APNG) have frames, and TIFFs have pages when the tiff feature is on.
+pub fn frame_count(bytes: &[u8]) -> ImageResult<usize> {
+	match image::guess_format(bytes)? {
+		ImageFormat::Gif => Ok(GifDecoder::new(Cursor::new(bytes))?.into_frames().count()),
+		ImageFormat::Png => {
+			let decoder = PngDecoder::new(Cursor::new(bytes))?;
+			Ok(if decoder.is_apng() { decoder.apng().into_frames().count() } else { 1 })
+		},
+		#[cfg(feature = "tiff")]
+		ImageFormat::Tiff => {
+			let mut decoder = tiff::decoder::Decoder::new(Cursor::new(bytes)).map_err(tiff_error)?;
+			let mut count = 1;
+			while decoder.more_images() {
+				decoder.next_image().map_err(tiff_error)?;
+				count += 1;
+			}
+			Ok(count)
+		},
+		_ => Ok(1),
+	}
+}
+
+/// The width and height of an image, read from its header without decoding it, eg to turn away huge images before decoding them
+pub fn dimensions(bytes: &[u8]) -> ImageResult<(u32, u32)> {
+	image::io::Reader::new(Cursor::new(bytes)).with_guessed_format().map_err(ImageError::IoError)?.into_dimensions()
+}
+
+/// Decode one frame (or page) of an image, where `frame` is 0 for the first frame
+pub fn decode_frame(bytes: &[u8], frame: usize) -> ImageResult<DynamicImage> {
+	let no_frame_error = || {
+		ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::Generic(format!(
+			"the image doesn't have a frame {}",
+			frame
+		))))
+	};
+
+	match image::guess_format(bytes) {
+		Ok(ImageFormat::Gif) => {
+			let frame = GifDecoder::new(Cursor::new(bytes))?.into_frames().nth(frame).ok_or_else(no_frame_error)??;
+			Ok(DynamicImage::ImageRgba8(frame.into_buffer()))
+		},
+		Ok(ImageFormat::Png) if PngDecoder::new(Cursor::new(bytes))?.is_apng() => {
+			let frame = PngDecoder::new(Cursor::new(bytes))?.apng().into_frames().nth(frame).ok_or_else(no_frame_error)??;
+			Ok(DynamicImage::ImageRgba8(frame.into_buffer()))
+		},
+		#[cfg(feature = "tiff")]
+		Ok(ImageFormat::Tiff) if frame != 0 => {
+			let mut decoder = tiff::decoder::Decoder::new(Cursor::new(bytes)).map_err(tiff_error)?;
+			decoder.seek_to_image(frame).map_err(|_| no_frame_error())?;
+			tiff_page(&mut decoder)
+		},
+		_ if frame != 0 => Err(no_frame_error()),
+		_ => image::io::Reader::new(Cursor::new(bytes)).with_guessed_format().map_err(ImageError::IoError)?.decode(),
+	}
+}
+
+/// Decode the page of a TIFF the decoder is at, for the pages after the first, which `image` can't decode
+///
+/// This supports the same 8 and 16 bit gray and RGB pages (with or without alpha) as `image` does for the first page.
+#[cfg(feature = "tiff")]
+fn tiff_page(decoder: &mut tiff::decoder::Decoder<Cursor<&[u8]>>) -> ImageResult<DynamicImage> {
+	use image::{ImageBuffer, Luma, LumaA, Rgb, Rgba};
+	use tiff::{decoder::DecodingResult, ColorType};
+
+	let (width, height) = decoder.dimensions().map_err(tiff_error)?;
+	let color_type = decoder.colortype().map_err(tiff_error)?;
+	let image = match (color_type, decoder.read_image().map_err(tiff_error)?) {
+		(ColorType::Gray(8), DecodingResult::U8(data)) => ImageBuffer::<Luma<u8>, _>::from_raw(width, height, data).map(DynamicImage::from),
+		(ColorType::GrayA(8), DecodingResult::U8(data)) => ImageBuffer::<LumaA<u8>, _>::from_raw(width, height, data).map(DynamicImage::from),
+		(ColorType::RGB(8), DecodingResult::U8(data)) => ImageBuffer::<Rgb<u8>, _>::from_raw(width, height, data).map(DynamicImage::from),
+		(ColorType::RGBA(8), DecodingResult::U8(data)) => ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, data).map(DynamicImage::from),
+		(ColorType::Gray(16), DecodingResult::U16(data)) => ImageBuffer::<Luma<u16>, _>::from_raw(width, height, data).map(DynamicImage::from),
+		(ColorType::GrayA(16), DecodingResult::U16(data)) => ImageBuffer::<LumaA<u16>, _>::from_raw(width, height, data).map(DynamicImage::from),
+		(ColorType::RGB(16), DecodingResult::U16(data)) => ImageBuffer::<Rgb<u16>, _>::from_raw(width, height, data).map(DynamicImage::from),
+		(ColorType::RGBA(16), DecodingResult::U16(data)) => ImageBuffer::<Rgba<u16>, _>::from_raw(width, height, data).map(DynamicImage::from),
+		_ => {
+			return Err(ImageError::Unsupported(UnsupportedError::from_format_and_kind(
+				ImageFormatHint::Exact(ImageFormat::Tiff),
+				UnsupportedErrorKind::GenericFeature(format!("TIFF pages with the color type {:?}", color_type)),
+			)))
+		},
+	};
+	image.ok_or_else(|| ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::DimensionMismatch)))
+}
+
+#[cfg(feature = "tiff")]
+pub(crate) fn tiff_error(error: tiff::TiffError) -> ImageError {
+	ImageError::Decoding(DecodingError::new(ImageFormatHint::Exact(ImageFormat::Tiff), error))
+}
+
+/// The ICC color profile embedded in an image, if it has one and it's in a format the profile can be read from (JPEG or PNG)
+pub fn embedded_color_profile(bytes: &[u8]) -> Option<Vec<u8>> {
+	match image::guess_format(bytes).ok()? {
+		ImageFormat::Jpeg => JpegDecoder::new(Cursor::new(bytes)).ok()?.icc_profile(),
+		ImageFormat::Png => PngDecoder::new(Cursor::new(bytes)).ok()?.icc_profile(),
+		_ => None,
+	}
+}
+
+/// Decode one frame of an image like `decode_frame`, converting it to sRGB if it has an embedded color profile
+///
+/// Without this, wide gamut photos (eg Display P3 from phones) come out with shifted tones, since their values are read as if they were
+/// sRGB. Images without a profile, or with one that can't be read, are assumed to be sRGB already, like browsers do.
+pub fn decode_frame_as_srgb(bytes: &[u8], frame: usize) -> ImageResult<DynamicImage> {
+	let image = decode_frame(bytes, frame)?;
+	match embedded_color_profile(bytes).as_deref().and_then(ColorProfile::parse) {
+		Some(profile) if !profile.is_srgb() => Ok(profile.to_srgb(&image)),
+		_ => Ok(image),
+	}
+}
+
+#[cfg(all(test, feature = "tiff"))]
+mod tests {
+	use tiff::encoder::{colortype, TiffEncoder};
+
+	use super::*;
+
+	#[test]
+	fn decodes_every_page_of_a_tiff() {
+		let mut bytes = Cursor::new(Vec::new());
+		let mut encoder = TiffEncoder::new(&mut bytes).unwrap();
+		encoder.write_image::<colortype::Gray8>(4, 3, &[10; 12]).unwrap();
+		encoder.write_image::<colortype::RGB8>(2, 5, &[200; 30]).unwrap();
+		encoder.write_image::<colortype::Gray16>(3, 3, &[40000; 9]).unwrap();
+		let bytes = bytes.into_inner();
+
+		assert_eq!(frame_count(&bytes).unwrap(), 3);
+		assert_eq!(
+			decode_frame(&bytes, 0).unwrap(),
+			DynamicImage::ImageLuma8(image::GrayImage::from_pixel(4, 3, image::Luma([10])))
+		);
+		assert_eq!(
+			decode_frame(&bytes, 1).unwrap(),
+			DynamicImage::ImageRgb8(image::RgbImage::from_pixel(2, 5, image::Rgb([200; 3])))
+		);
+		assert_eq!(decode_frame(&bytes, 2).unwrap().into_luma16().get_pixel(1, 1).0, [40000]);
+		assert!(decode_frame(&bytes, 3).is_err());
+	}
+}
@@ -0,0 +1,682 @@
+use std::{
+	collections::{HashMap, VecDeque},
+	fs, io,
+	panic::{self, AssertUnwindSafe},
+	path::PathBuf,
+	sync::{
+		atomic::{self, AtomicBool},
+		Arc, Condvar, Mutex, MutexGuard,
+	},
+	thread,
+	time::{Duration, Instant},
+};
+
+use thiserror::Error;
+
+use crate::{
+	expression::{compile_expressions, AngleUnit, Sandbox},
+	input,
+	lithophane::{generate_lithophane_with_hooks, lithophane_triangle_count, GenerateError, LithophaneOptions},
+	manifest::{hash_input, Manifest},
+	output, preprocessing,
+};
+
+/// A lithophane to generate in the background: an encoded image (eg the contents of a PNG file) and the settings to generate it with
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobRequest {
+	pub image: Vec<u8>,
+	pub x_expression: String,
+	pub y_expression: String,
+	pub z_expression: String,
+	pub angle_unit: AngleUnit,
+	pub white_depth: f32,
+	pub black_depth: f32,
+	/// The frame to use from an animated image, 0 for other images
+	pub frame: usize,
+	/// Whether to compress the STL with gzip
+	pub compress: bool,
+}
+
+impl Default for JobRequest {
+	fn default() -> Self {
+		let options = LithophaneOptions::default();
+		Self {
+			image: Vec::new(),
+			x_expression: String::new(),
+			y_expression: String::new(),
+			z_expression: String::new(),
+			angle_unit: AngleUnit::Radians,
+			white_depth: options.white_depth,
+			black_depth: options.black_depth,
+			frame: 0,
+			compress: false,
+		}
+	}
+}
+
+/// Limits on the jobs a `JobQueue` takes on and how long it keeps them, since the requests usually come from someone else
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JobLimits {
+	/// The most pixels an image can have, which is checked before it's decoded
+	pub max_pixels: u64,
+	/// The most triangles a lithophane can have
+	pub max_triangles: usize,
+	/// The most jobs that can be waiting for a worker, since each one keeps its image in memory until it starts
+	pub max_queued: usize,
+	/// How long finished jobs are kept when there's no store, since their results would fill up the memory otherwise
+	pub keep_finished: Duration,
+}
+
+impl Default for JobLimits {
+	/// 50 megapixels, 100 million triangles (a 5 GB STL), 100 queued jobs and an hour
+	fn default() -> Self {
+		Self {
+			max_pixels: 50_000_000,
+			max_triangles: 100_000_000,
+			max_queued: 100,
+			keep_finished: Duration::from_secs(60 * 60),
+		}
+	}
+}
+
+impl JobRequest {
+	/// Generate the lithophane as a binary STL, the same way `lithophane_generate` in the C API does, with the expressions checked against
+	/// `sandbox` and the image against `limits` first, along with a manifest of how it was generated
+	pub fn run(&self, sandbox: &Sandbox, limits: &JobLimits, progress: &dyn Fn(f32), cancel: &AtomicBool) -> Result<(Vec<u8>, Manifest), String> {
+		let (width, height) = input::dimensions(&self.image).map_err(|e| e.to_string())?;
+		if width as u64 * height as u64 > limits.max_pixels {
+			return Err(format!(
+				"the image is {}×{} pixels, but it can have at most {} pixels",
+				width, height, limits.max_pixels
+			));
+		}
+		let triangles = lithophane_triangle_count(width, height, 1, 1);
+		if triangles > limits.max_triangles {
+			return Err(format!(
+				"the lithophane would have {} triangles, but it can have at most {}",
+				triangles, limits.max_triangles
+			));
+		}
+		let image = input::decode_frame_as_srgb(&self.image, self.frame).map_err(|e| e.to_string())?;
+		let sources = [&self.x_expression, &self.y_expression, &self.z_expression].map(String::as_str);
+		let [x_fn, y_fn, z_fn] = compile_expressions(sources, self.angle_unit, Some(sandbox)).map_err(|e| e.to_string())?.map(|e| e.bind());
+		let options = LithophaneOptions {
+			white_depth: self.white_depth,
+			black_depth: self.black_depth,
+			..Default::default()
+		};
+
+		let model = generate_lithophane_with_hooks(
+			x_fn,
+			y_fn,
+			z_fn,
+			preprocessing::to_grayscale(image, Default::default(), Default::default()),
+			&options,
+			&progress,
+			cancel,
+		)
+		.map_err(|e| match e {
+			GenerateError::Cancelled => "cancelled".to_string(),
+			e => e.to_string(),
+		})?;
+		let mut stl = output::as_binary(&model);
+		if self.compress {
+			stl = output::compress_gzip(&stl).map_err(|e| e.to_string())?;
+		}
+		let manifest = Manifest {
+			input_hash: hash_input(&self.image),
+			parameters: self.settings(),
+			triangles: model.triangles.len(),
+			bytes: stl.len(),
+			..Default::default()
+		};
+		Ok((stl, manifest))
+	}
+
+	/// Everything but the image as names and values, which is how the settings are saved in a `JobStore`
+	fn settings(&self) -> Vec<(String, String)> {
+		[
+			("x", self.x_expression.clone()),
+			("y", self.y_expression.clone()),
+			("z", self.z_expression.clone()),
+			("degrees", (self.angle_unit == AngleUnit::Degrees).to_string()),
+			("white_depth", self.white_depth.to_string()),
+			("black_depth", self.black_depth.to_string()),
+			("frame", self.frame.to_string()),
+			("compress", self.compress.to_string()),
+		]
+		.into_iter()
+		.map(|(name, value)| (name.to_string(), value))
+		.collect()
+	}
+}
+
+#[derive(Error, Debug)]
+pub enum SubmitError {
+	#[error("there are already {0} jobs waiting")]
+	QueueFull(usize),
+	#[error("couldn't save the job: {0}")]
+	Store(#[from] io::Error),
+}
+
+/// How far along a job is
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobStatus {
+	/// Waiting for a worker, behind this many other jobs
+	Queued { position: usize },
+	/// Being generated, with the fraction that's done so far
+	Running { progress: f32 },
+	/// Finished, with the result ready to be taken with `JobQueue::result`
+	Done,
+	/// Stopped with this error (or "cancelled")
+	Failed(String),
+}
+
+struct Job {
+	status: JobStatus,
+	/// Taken by the worker when it starts the job
+	request: Option<JobRequest>,
+	/// When the job was done or failed, so it can be forgotten a while later when there's no store
+	finished: Option<Instant>,
+	/// Only kept here when there's no store, otherwise they're read from it when they're asked for
+	result: Option<Arc<Vec<u8>>>,
+	manifest: Option<Arc<String>>,
+	cancel: Arc<AtomicBool>,
+}
+
+impl Job {
+	fn new(status: JobStatus, request: Option<JobRequest>) -> Self {
+		Self {
+			status,
+			request,
+			finished: None,
+			result: None,
+			manifest: None,
+			cancel: Arc::new(AtomicBool::new(false)),
+		}
+	}
+}
+
+#[derive(Default)]
+struct State {
+	jobs: HashMap<u64, Job>,
+	queue: VecDeque<u64>,
+	next_id: u64,
+}
+
+/// Lithophanes waiting to be generated and the worker threads that generate them, one job per worker at a time in the order they were
+/// submitted
+///
+/// Jobs and their results are kept until they're removed with `remove`, so a client can poll for the status and download the result
+/// whenever it likes, except that without a store finished jobs are forgotten once they're older than `JobLimits::keep_finished`.
+/// Cloning a queue gives another handle to the same jobs.
+#[derive(Clone)]
+pub struct JobQueue {
+	shared: Arc<(Mutex<State>, Condvar)>,
+	sandbox: Arc<Sandbox>,
+	limits: JobLimits,
+	store: Option<JobStore>,
+}
+
+impl JobQueue {
+	/// Start a queue with `workers` threads (at least one), which run until the program exits, checking every job's expressions against
+	/// `sandbox` and its image against `limits` since they usually come from someone else
+	///
+	/// The jobs are only kept in memory, so they're lost when the program exits.
+	pub fn new(workers: usize, sandbox: Sandbox, limits: JobLimits) -> Self {
+		Self::start(workers, sandbox, limits, None, State::default())
+	}
+
+	/// Start a queue like `new` that keeps its jobs in `store`, picking up the jobs that are already there
+	///
+	/// Jobs that were queued or running when the program last stopped are queued again (from the start, in the order they were submitted),
+	/// and finished ones can be polled and downloaded as if there hadn't been a restart.
+	pub fn with_store(workers: usize, sandbox: Sandbox, limits: JobLimits, store: JobStore) -> io::Result<Self> {
+		let mut state = State::default();
+		for (id, status, request) in store.load()? {
+			if request.is_some() {
+				state.queue.push_back(id);
+			}
+			state.jobs.insert(id, Job::new(status, request));
+			state.next_id = state.next_id.max(id);
+		}
+		Ok(Self::start(workers, sandbox, limits, Some(store), state))
+	}
+
+	fn start(workers: usize, sandbox: Sandbox, limits: JobLimits, store: Option<JobStore>, state: State) -> Self {
+		let queue = Self {
+			shared: Arc::new((Mutex::new(state), Condvar::new())),
+			sandbox: Arc::new(sandbox),
+			limits,
+			store,
+		};
+		for _ in 0..workers.max(1) {
+			let worker = queue.clone();
+			thread::spawn(move || worker.work());
+		}
+		queue
+	}
+
+	/// Add a job to the end of the queue, returning its id
+	///
+	/// This fails when `JobLimits::max_queued` jobs are already waiting, so the caller can ask to try again later, or when the job can't be
+	/// saved in the queue's store.
+	pub fn submit(&self, request: JobRequest) -> Result<u64, SubmitError> {
+		let mut state = self.state();
+		if state.queue.len() >= self.limits.max_queued {
+			return Err(SubmitError::QueueFull(self.limits.max_queued));
+		}
+		// Only submitting adds to what's kept in memory, so this is enough to keep it from growing forever
+		if self.store.is_none() {
+			let keep = self.limits.keep_finished;
+			state.jobs.retain(|_, job| job.finished.is_none_or(|finished| finished.elapsed() < keep));
+		}
+		state.next_id += 1;
+		let id = state.next_id;
+		let status = JobStatus::Queued { position: state.queue.len() };
+		if let Some(store) = &self.store {
+			store.save(id, &status, &request)?;
+		}
+		state.jobs.insert(id, Job::new(status, Some(request)));
+		state.queue.push_back(id);
+		self.shared.1.notify_one();
+		Ok(id)
+	}
+
+	/// The status of a job, or None if there's no job with that id
+	pub fn status(&self, id: u64) -> Option<JobStatus> {
+		let state = self.state();
+		let job = state.jobs.get(&id)?;
+		Some(match job.status {
+			// The position is worked out when it's asked for, since it changes as the jobs in front of it start
+			JobStatus::Queued { .. } => JobStatus::Queued {
+				position: state.queue.iter().position(|&queued| queued == id).unwrap_or_default(),
+			},
+			ref status => status.clone(),
+		})
+	}
+
+	/// The binary STL (gzipped if the request asked for it) of a finished job
+	pub fn result(&self, id: u64) -> Option<Arc<Vec<u8>>> {
+		let state = self.state();
+		let job = state.jobs.get(&id)?;
+		match &self.store {
+			Some(store) if job.status == JobStatus::Done => store.result(id).map(Arc::new),
+			_ => job.result.clone(),
+		}
+	}
+
+	/// The manifest (see `Manifest::to_json`) of a finished job, with the settings it was generated with and the size of the STL
+	pub fn manifest(&self, id: u64) -> Option<Arc<String>> {
+		let state = self.state();
+		let job = state.jobs.get(&id)?;
+		match &self.store {
+			Some(store) if job.status == JobStatus::Done => store.manifest(id).map(Arc::new),
+			_ => job.manifest.clone(),
+		}
+	}
+
+	/// Stop a job that's queued or running, returning whether there was one to stop
+	pub fn cancel(&self, id: u64) -> bool {
+		let mut state = self.state();
+		let Some(job) = state.jobs.get_mut(&id) else { return false };
+		job.cancel.store(true, atomic::Ordering::Relaxed);
+		match job.status {
+			JobStatus::Queued { .. } => {
+				job.status = JobStatus::Failed("cancelled".to_string());
+				job.finished = Some(Instant::now());
+				if let (Some(store), Some(request)) = (&self.store, &job.request) {
+					// If this doesn't get saved the job is queued again after a restart, which is harmless
+					let _ = store.save(id, &job.status, request);
+				}
+				job.request = None;
+				state.queue.retain(|&queued| queued != id);
+				true
+			},
+			JobStatus::Running { .. } => true,
+			_ => false,
+		}
+	}
+
+	/// Forget a job and its result, cancelling it first if it hasn't finished, returning whether there was one
+	pub fn remove(&self, id: u64) -> bool {
+		self.cancel(id);
+		let mut state = self.state();
+		if let Some(store) = &self.store {
+			store.remove(id);
+		}
+		state.jobs.remove(&id).is_some()
+	}
+
+	fn state(&self) -> MutexGuard<'_, State> {
+		// A worker that panicked can't have left a job half updated, since every update is a single assignment
+		self.shared.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+	}
+
+	fn work(&self) {
+		loop {
+			let (id, request, cancel) = {
+				let mut state = self.state();
+				let id = loop {
+					match state.queue.pop_front() {
+						Some(id) => break id,
+						None => state = self.shared.1.wait(state).unwrap_or_else(|poisoned| poisoned.into_inner()),
+					}
+				};
+				let Some(job) = state.jobs.get_mut(&id) else { continue };
+				let Some(request) = job.request.take() else { continue };
+				job.status = JobStatus::Running { progress: 0.0 };
+				(id, request, job.cancel.clone())
+			};
+
+			let progress = |fraction: f32| {
+				if let Some(job) = self.state().jobs.get_mut(&id) {
+					job.status = JobStatus::Running { progress: fraction };
+				}
+			};
+			// A panic only fails its own job, so the worker carries on with the next one
+			let mut result = panic::catch_unwind(AssertUnwindSafe(|| request.run(&self.sandbox, &self.limits, &progress, &cancel)))
+				.unwrap_or_else(|_| Err("the generator crashed".to_string()))
+				.map(|(stl, manifest)| (stl, manifest.to_json()));
+
+			// The STL is saved before taking the lock, so polling isn't held up by writing a big file
+			if let Some(store) = &self.store {
+				if let Ok((stl, manifest)) = &result {
+					if let Err(e) = store.save_result(id, stl, manifest) {
+						result = Err(format!("couldn't save the result: {}", e));
+					}
+				}
+				let status = match &result {
+					Ok(_) => JobStatus::Done,
+					Err(e) => JobStatus::Failed(e.clone()),
+				};
+				if let Err(e) = store.save(id, &status, &request) {
+					result = Err(format!("couldn't save the job: {}", e));
+				}
+			}
+
+			let mut state = self.state();
+			match state.jobs.get_mut(&id) {
+				Some(job) => {
+					job.finished = Some(Instant::now());
+					match result {
+						Ok((stl, manifest)) => {
+							job.status = JobStatus::Done;
+							if self.store.is_none() {
+								job.result = Some(Arc::new(stl));
+								job.manifest = Some(Arc::new(manifest));
+							}
+						},
+						Err(e) => job.status = JobStatus::Failed(e),
+					}
+				},
+				// It was removed while it was running, after its files were deleted
+				None => {
+					if let Some(store) = &self.store {
+						store.remove(id);
+					}
+				},
+			}
+		}
+	}
+}
+
+/// Where a `JobQueue` keeps its jobs, so they survive the program restarting and can be looked at later
+///
+/// Each job is a few records named after its id: the settings and status (eg `1.job`), the image (`1.image`) and, once it's done, the
+/// STL (`1.stl`) and its manifest (`1.json`). In a directory (`open`) each record is a file, which is written to a temporary name and then
+/// renamed, so a crash can't leave half of one. With the `sled` feature they can be kept in a sled database instead (`open_sled`), which
+/// is one file that's quicker with lots of small jobs.
+#[derive(Debug, Clone)]
+pub struct JobStore {
+	backend: Backend,
+}
+
+#[derive(Debug, Clone)]
+enum Backend {
+	Directory(Arc<PathBuf>),
+	#[cfg(feature = "sled")]
+	Sled(sled::Db),
+}
+
+impl JobStore {
+	/// Use `dir` as a store, creating it if it doesn't exist
+	pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+		let dir = dir.into();
+		fs::create_dir_all(&dir)?;
+		Ok(Self {
+			backend: Backend::Directory(Arc::new(dir)),
+		})
+	}
+
+	/// Use the sled database at `path` as a store, creating it if it doesn't exist
+	#[cfg(feature = "sled")]
+	pub fn open_sled(path: impl AsRef<std::path::Path>) -> io::Result<Self> {
+		Ok(Self {
+			backend: Backend::Sled(sled::open(path)?),
+		})
+	}
+
+	fn name(id: u64, extension: &str) -> String {
+		format!("{}.{}", id, extension)
+	}
+
+	fn write(&self, id: u64, extension: &str, contents: &[u8]) -> io::Result<()> {
+		match &self.backend {
+			Backend::Directory(dir) => {
+				let temporary = dir.join(Self::name(id, &format!("{}.tmp", extension)));
+				fs::write(&temporary, contents)?;
+				fs::rename(temporary, dir.join(Self::name(id, extension)))
+			},
+			#[cfg(feature = "sled")]
+			Backend::Sled(db) => {
+				db.insert(Self::name(id, extension), contents)?;
+				db.flush()?;
+				Ok(())
+			},
+		}
+	}
+
+	/// A record of a job, or None if there isn't one
+	fn read(&self, id: u64, extension: &str) -> io::Result<Option<Vec<u8>>> {
+		match &self.backend {
+			Backend::Directory(dir) => match fs::read(dir.join(Self::name(id, extension))) {
+				Ok(contents) => Ok(Some(contents)),
+				Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+				Err(e) => Err(e),
+			},
+			#[cfg(feature = "sled")]
+			Backend::Sled(db) => Ok(db.get(Self::name(id, extension))?.map(|contents| contents.to_vec())),
+		}
+	}
+
+	fn contains(&self, id: u64, extension: &str) -> io::Result<bool> {
+		match &self.backend {
+			Backend::Directory(dir) => Ok(dir.join(Self::name(id, extension)).exists()),
+			#[cfg(feature = "sled")]
+			Backend::Sled(db) => Ok(db.contains_key(Self::name(id, extension))?),
+		}
+	}
+
+	/// The ids of the jobs in the store, in no particular order
+	fn ids(&self) -> io::Result<Vec<u64>> {
+		let id = |name: &str| name.strip_suffix(".job")?.parse::<u64>().ok();
+		match &self.backend {
+			Backend::Directory(dir) => {
+				let mut ids = Vec::new();
+				for entry in fs::read_dir(&**dir)? {
+					ids.extend(entry?.file_name().to_str().and_then(id));
+				}
+				Ok(ids)
+			},
+			#[cfg(feature = "sled")]
+			Backend::Sled(db) => {
+				let mut ids = Vec::new();
+				for key in db.iter().keys() {
+					ids.extend(std::str::from_utf8(&key?).ok().and_then(id));
+				}
+				Ok(ids)
+			},
+		}
+	}
+
+	/// Save a job's status and settings, and its image if it hasn't been saved yet
+	fn save(&self, id: u64, status: &JobStatus, request: &JobRequest) -> io::Result<()> {
+		if !self.contains(id, "image")? {
+			self.write(id, "image", &request.image)?;
+		}
+		let (status, error) = match status {
+			JobStatus::Done => ("done", ""),
+			JobStatus::Failed(e) => ("failed", e.as_str()),
+			// A job that was running when the program stopped has to start again
+			JobStatus::Queued { .. } | JobStatus::Running { .. } => ("queued", ""),
+		};
+		let mut contents = format!("status={}\nerror={}\n", status, escape(error));
+		for (name, value) in request.settings() {
+			contents.push_str(&format!("{}={}\n", name, escape(&value)));
+		}
+		self.write(id, "job", contents.as_bytes())
+	}
+
+	fn save_result(&self, id: u64, stl: &[u8], manifest: &str) -> io::Result<()> {
+		self.write(id, "stl", stl)?;
+		self.write(id, "json", manifest.as_bytes())
+	}
+
+	fn result(&self, id: u64) -> Option<Vec<u8>> {
+		self.read(id, "stl").ok().flatten()
+	}
+
+	fn manifest(&self, id: u64) -> Option<String> {
+		String::from_utf8(self.read(id, "json").ok().flatten()?).ok()
+	}
+
+	fn remove(&self, id: u64) {
+		// Records that are already gone (or can't be deleted) don't stop the job from being forgotten
+		for extension in ["job", "image", "stl", "json"] {
+			match &self.backend {
+				Backend::Directory(dir) => {
+					let _ = fs::remove_file(dir.join(Self::name(id, extension)));
+				},
+				#[cfg(feature = "sled")]
+				Backend::Sled(db) => {
+					let _ = db.remove(Self::name(id, extension));
+				},
+			}
+		}
+	}
+
+	/// Every job in the store with its status, and its request (with the image) if it's still to be done, in order of id
+	fn load(&self) -> io::Result<Vec<(u64, JobStatus, Option<JobRequest>)>> {
+		let mut jobs = Vec::new();
+		for id in self.ids()? {
+			let name = Self::name(id, "job");
+			let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", name, message));
+			let contents = self.read(id, "job")?.ok_or_else(|| invalid("it was removed while loading"))?;
+			let contents = String::from_utf8(contents).map_err(|_| invalid("it isn't UTF-8"))?;
+			let mut request = JobRequest::default();
+			let (mut status, mut error) = (String::new(), String::new());
+			for line in contents.lines() {
+				let (name, value) = line.split_once('=').ok_or_else(|| invalid("a line is missing its \"=\""))?;
+				let value = unescape(value);
+				let number = |value: &str| value.parse::<f32>().map_err(|_| invalid(&format!("\"{}\" isn't a number", name)));
+				match name {
+					"status" => status = value,
+					"error" => error = value,
+					"x" => request.x_expression = value,
+					"y" => request.y_expression = value,
+					"z" => request.z_expression = value,
+					"degrees" => request.angle_unit = if value == "true" { AngleUnit::Degrees } else { AngleUnit::Radians },
+					"white_depth" => request.white_depth = number(&value)?,
+					"black_depth" => request.black_depth = number(&value)?,
+					"frame" => request.frame = value.parse().map_err(|_| invalid("\"frame\" isn't a whole number"))?,
+					"compress" => request.compress = value == "true",
+					_ => return Err(invalid(&format!("unknown setting \"{}\"", name))),
+				}
+			}
+			jobs.push(match status.as_str() {
+				"queued" => {
+					request.image = self.read(id, "image")?.ok_or_else(|| invalid("its image is missing"))?;
+					(id, JobStatus::Queued { position: 0 }, Some(request))
+				},
+				"done" => (id, JobStatus::Done, None),
+				"failed" => (id, JobStatus::Failed(error), None),
+				_ => return Err(invalid("the status is invalid")),
+			});
+		}
+		jobs.sort_by_key(|&(id, ..)| id);
+		Ok(jobs)
+	}
+}
+
+/// Escape backslashes and line breaks, so a value fits on one line of a job file
+fn escape(value: &str) -> String {
+	value.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+fn unescape(value: &str) -> String {
+	let mut unescaped = String::with_capacity(value.len());
+	let mut chars = value.chars();
+	while let Some(c) = chars.next() {
+		unescaped.push(match c {
+			'\\' => match chars.next() {
+				Some('n') => '\n',
+				Some('r') => '\r',
+				Some(c) => c,
+				None => break,
+			},
+			c => c,
+		});
+	}
+	unescaped
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn round_trips_jobs(store: JobStore) {
+		let request = JobRequest {
+			x_expression: "x\ny".to_string(),
+			image: vec![1, 2, 3],
+			frame: 2,
+			..Default::default()
+		};
+		store.save(3, &JobStatus::Running { progress: 0.5 }, &request).unwrap();
+		store.save(1, &JobStatus::Failed("bad\\input".to_string()), &request).unwrap();
+		store.save(2, &JobStatus::Done, &request).unwrap();
+		store.save_result(2, b"solid", "{}").unwrap();
+
+		let jobs = store.load().unwrap();
+		assert_eq!(
+			jobs,
+			vec![
+				(1, JobStatus::Failed("bad\\input".to_string()), None),
+				(2, JobStatus::Done, None),
+				(3, JobStatus::Queued { position: 0 }, Some(request)),
+			]
+		);
+		assert_eq!(
+			(store.result(2).as_deref(), store.manifest(2).as_deref()),
+			(Some(&b"solid"[..]), Some("{}"))
+		);
+
+		store.remove(2);
+		assert_eq!(store.result(2), None);
+		assert_eq!(store.load().unwrap().len(), 2);
+	}
+
+	#[test]
+	fn directory_store_round_trips_jobs() {
+		let dir = std::env::temp_dir().join(format!("lithophane-jobs-{}", std::process::id()));
+		round_trips_jobs(JobStore::open(&dir).unwrap());
+		fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[cfg(feature = "sled")]
+	#[test]
+	fn sled_store_round_trips_jobs() {
+		let db = sled::Config::new().temporary(true).open().unwrap();
+		round_trips_jobs(JobStore { backend: Backend::Sled(db) });
+	}
+}
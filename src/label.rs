@@ -0,0 +1,129 @@
+use thiserror::Error;
+
+/// Text engraved into the back of the lithophane, eg a date, an order number or a short hash of the settings, to tell which settings a
+/// print was made with
+///
+/// The text is mirrored so it reads correctly from behind. Only the characters allowed by `check_label_text` can be engraved, others are
+/// left blank. Each character is drawn from a 5×7 grid, so the label should be at least 14 pixels high (or use subdivisions) to be readable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+	pub text: String,
+	/// The left edge of the label in pixels, as seen from the front
+	pub x: f32,
+	/// The top edge of the label in pixels
+	pub y: f32,
+	/// The height of the characters in pixels
+	pub height: f32,
+	/// How deep the text is engraved
+	/// It's never engraved deeper than half of the thickness of the lithophane, so it can't cut through thin areas.
+	pub depth: f32,
+}
+
+pub(crate) const GLYPH_WIDTH: usize = 5;
+pub(crate) const GLYPH_HEIGHT: usize = 7;
+
+impl Label {
+	/// Place a label in the bottom right corner as seen from the front (so the bottom left as seen from behind), `margin` pixels from the
+	/// edges of an image
+	pub fn in_corner(text: String, image_width: u32, image_height: u32, height: f32, depth: f32, margin: f32) -> Self {
+		let mut label = Label {
+			text,
+			x: 0.0,
+			y: image_height as f32 - 1.0 - margin - height,
+			height,
+			depth,
+		};
+		label.x = image_width as f32 - 1.0 - margin - label.width();
+		label
+	}
+
+	/// The width of the label in pixels, with a column of space between characters
+	pub fn width(&self) -> f32 {
+		let columns = (self.text.chars().count() * (GLYPH_WIDTH + 1)).saturating_sub(1);
+		columns as f32 * self.height / GLYPH_HEIGHT as f32
+	}
+
+	/// How deep the label is engraved at a point in image coordinates
+	pub(crate) fn depth(&self, x: f32, y: f32) -> f32 {
+		let cell = self.height / GLYPH_HEIGHT as f32;
+		// Columns are counted from the right edge, since that's the left edge when reading from behind
+		let column = ((self.x + self.width() - x) / cell).floor();
+		let row = ((y - self.y) / cell).floor();
+		if column < 0.0 || row < 0.0 || row >= GLYPH_HEIGHT as f32 {
+			return 0.0;
+		}
+		let (column, row) = (column as usize, row as usize);
+
+		let Some(c) = self.text.chars().nth(column / (GLYPH_WIDTH + 1)) else {
+			return 0.0;
+		};
+		let glyph_column = column % (GLYPH_WIDTH + 1);
+		match glyph(c) {
+			Some(rows) if glyph_column < GLYPH_WIDTH && rows[row] >> (GLYPH_WIDTH - 1 - glyph_column) & 1 == 1 => self.depth,
+			_ => 0.0,
+		}
+	}
+}
+
+#[derive(Error, Debug)]
+#[error("labels can only contain A-Z, 0-9, spaces and - . : / # _, not '{0}'")]
+pub struct UnsupportedCharacterError(pub char);
+
+/// Check that text can be engraved as a label, since the built in font only has a few characters
+/// Lowercase letters are engraved as uppercase.
+pub fn check_label_text(text: &str) -> Result<(), UnsupportedCharacterError> {
+	match text.chars().find(|&c| glyph(c).is_none()) {
+		Some(c) => Err(UnsupportedCharacterError(c)),
+		None => Ok(()),
+	}
+}
+
+/// The rows of a character in the built in 5×7 font, from the top, with the leftmost column in the highest bit
+pub(crate) fn glyph(c: char) -> Option<[u8; GLYPH_HEIGHT]> {
+	Some(match c.to_ascii_uppercase() {
+		'0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+		'1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+		'2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+		'3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+		'4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+		'5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+		'6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+		'7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+		'8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+		'9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+		'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+		'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+		'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+		'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+		'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+		'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+		'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+		'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+		'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+		'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+		'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+		'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+		'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+		'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+		'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+		'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+		'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+		'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+		'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+		'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+		'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+		'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+		'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+		'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+		'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+		'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+		' ' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+		'-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+		'.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+		':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+		'/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+		'#' => [0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A],
+		'_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+		_ => return None,
+	})
+}
@@ -1,17 +1,72 @@
-use std::{io::Cursor, panic};
+use std::panic;
 
-use image::ImageError;
+use expression::AngleUnit;
+use image::DynamicImage;
+use lithophane::LithophaneOptions;
 use thiserror::Error;
-use wasm_bindgen::{prelude::wasm_bindgen, JsError};
+use wasm_bindgen::{prelude::wasm_bindgen, JsError, JsValue};
 
+pub mod backlight;
+pub mod bookmark;
+pub mod clock;
+pub mod color_profile;
+pub mod corner;
+pub mod detail;
+pub mod expression;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fixtures;
+pub mod gcode;
+pub mod input;
+#[cfg(feature = "server")]
+pub mod jobs;
+pub mod label;
 pub mod lithophane;
+pub mod low_poly;
+pub mod manifest;
+pub mod mesh;
+pub mod nightlight;
+pub mod outline;
+pub mod output;
+pub mod pendant;
+pub mod photo_frame;
+pub mod preprocessing;
+pub mod presets;
+pub mod printer;
+pub mod puzzle;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod resin;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod settings;
+pub mod shadow_box;
+pub mod spiral;
+pub mod suggest;
+pub mod tactile;
+pub mod terrain;
+pub mod warnings;
 
 #[wasm_bindgen]
 pub fn init() {
 	panic::set_hook(Box::new(console_error_panic_hook::hook));
 }
 
+/// Compile the x, y and z expressions the functions here take, which use radians like the CLI does by default
+///
+/// They're checked against the default `Sandbox`, which keeps an expression from being so long that evaluating it for every vertex
+/// freezes the page.
+fn compile_expressions(x_expression: &str, y_expression: &str, z_expression: &str) -> Result<[expression::CompiledExpression; 3], Error> {
+	let sources = [x_expression, y_expression, z_expression];
+	Ok(expression::compile_expressions(
+		sources,
+		AngleUnit::Radians,
+		Some(&expression::Sandbox::default()),
+	)?)
+}
+
 #[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
 pub fn generate_lithophane(
 	x_expression: &str,
 	y_expression: &str,
@@ -19,70 +74,804 @@ pub fn generate_lithophane(
 	image: Vec<u8>,
 	white_depth: f32,
 	black_depth: f32,
+	frame: usize,
+	compress: bool,
+	header: String,
 ) -> Result<Vec<u8>, JsError> {
-	let image = image::io::Reader::new(Cursor::new(image)).with_guessed_format().map_err(ImageError::IoError)?.decode()?;
+	let image = input::decode_frame_as_srgb(&image, frame)?;
+	output::check_stl_header(&header)?;
 
-	let x_expression =
-		x_expression.parse::<meval::Expr>().and_then(|e| e.bind4("x", "y", "w", "h")).map_err(|e| Error::MevalError("x".to_string(), e))?;
-	let y_expression =
-		y_expression.parse::<meval::Expr>().and_then(|e| e.bind4("x", "y", "w", "h")).map_err(|e| Error::MevalError("y".to_string(), e))?;
-	let z_expression =
-		z_expression.parse::<meval::Expr>().and_then(|e| e.bind4("x", "y", "w", "h")).map_err(|e| Error::MevalError("z".to_string(), e))?;
+	let [x_fn, y_fn, z_fn] = compile_expressions(x_expression, y_expression, z_expression)?.map(|e| e.bind());
 
-	fn meval_f32_wrapper(f: impl Fn(f64, f64, f64, f64) -> f64) -> impl Fn(f32, f32, f32, f32) -> f32 {
-		move |x: f32, y: f32, w: f32, h: f32| -> f32 { f(x as f64, y as f64, w as f64, h as f64) as f32 }
-	}
+	let stl = lithophane::generate_lithophane(
+		x_fn,
+		y_fn,
+		z_fn,
+		preprocessing::to_grayscale(image, Default::default(), Default::default()),
+		&LithophaneOptions {
+			white_depth,
+			black_depth,
+			header,
+			..Default::default()
+		},
+	)
+	.map(|model| output::as_binary(&model))?;
 
-	Ok(lithophane::generate_lithophane(
-		meval_f32_wrapper(x_expression),
-		meval_f32_wrapper(y_expression),
-		meval_f32_wrapper(z_expression),
-		image.into_luma8(),
-		white_depth,
-		black_depth,
-	)?
-	.as_binary())
+	Ok(if compress { output::compress_gzip(&stl)? } else { stl })
 }
 
+/// Generate a lithophane (which is compressed if `compress` is set) along with a preview stepping by `step` pixels, only evaluating the
+/// expressions once
 #[wasm_bindgen]
-pub fn generate_preview(x_expression: &str, y_expression: &str, z_expression: &str, width: u32, height: u32, step: u32) -> Result<Vec<u8>, JsError> {
-	let x_expression =
-		x_expression.parse::<meval::Expr>().and_then(|e| e.bind4("x", "y", "w", "h")).map_err(|e| Error::MevalError("x".to_string(), e))?;
-	let y_expression =
-		y_expression.parse::<meval::Expr>().and_then(|e| e.bind4("x", "y", "w", "h")).map_err(|e| Error::MevalError("y".to_string(), e))?;
-	let z_expression =
-		z_expression.parse::<meval::Expr>().and_then(|e| e.bind4("x", "y", "w", "h")).map_err(|e| Error::MevalError("z".to_string(), e))?;
+#[allow(clippy::too_many_arguments)]
+pub fn generate_lithophane_with_preview(
+	x_expression: &str,
+	y_expression: &str,
+	z_expression: &str,
+	image: Vec<u8>,
+	white_depth: f32,
+	black_depth: f32,
+	frame: usize,
+	compress: bool,
+	header: String,
+	step: u32,
+) -> Result<LithophaneWithPreview, JsError> {
+	let image = input::decode_frame_as_srgb(&image, frame)?;
+	output::check_stl_header(&header)?;
 
-	fn meval_f32_wrapper(f: impl Fn(f64, f64, f64, f64) -> f64) -> impl Fn(f32, f32, f32, f32) -> f32 {
-		move |x: f32, y: f32, w: f32, h: f32| -> f32 { f(x as f64, y as f64, w as f64, h as f64) as f32 }
-	}
+	let [x_fn, y_fn, z_fn] = compile_expressions(x_expression, y_expression, z_expression)?.map(|e| e.bind());
+
+	let (lithophane, preview) = lithophane::generate_lithophane_with_preview(
+		x_fn,
+		y_fn,
+		z_fn,
+		preprocessing::to_grayscale(image, Default::default(), Default::default()),
+		&LithophaneOptions {
+			white_depth,
+			black_depth,
+			header,
+			..Default::default()
+		},
+		step,
+	)?;
+
+	let lithophane = output::as_binary(&lithophane);
+	Ok(LithophaneWithPreview {
+		lithophane: if compress { output::compress_gzip(&lithophane)? } else { lithophane },
+		preview: output::as_binary(&preview),
+	})
+}
+
+/// Generate a preview (as binary STL) sampling every `step_x` pixels horizontally and `step_y` pixels vertically
+/// The steps are increased as needed to stay under `max_samples` samples and `max_triangles` triangles, unless they're 0.
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn generate_preview(
+	x_expression: &str,
+	y_expression: &str,
+	z_expression: &str,
+	width: u32,
+	height: u32,
+	step_x: u32,
+	step_y: u32,
+	max_samples: usize,
+	max_triangles: usize,
+) -> Result<Vec<u8>, JsError> {
+	lithophane::check_image_size(width, height)?;
+	let [x_fn, y_fn, z_fn] = compile_expressions(x_expression, y_expression, z_expression)?.map(|e| e.bind());
 
 	Ok(lithophane::generate_preview(
-		meval_f32_wrapper(x_expression),
-		meval_f32_wrapper(y_expression),
-		meval_f32_wrapper(z_expression),
+		x_fn,
+		y_fn,
+		z_fn,
 		width,
 		height,
+		lithophane::PreviewSampling {
+			step_x,
+			step_y,
+			max_samples: if max_samples == 0 { None } else { Some(max_samples) },
+		},
+		if max_triangles == 0 { None } else { Some(max_triangles) },
+	)
+	.map(|model| output::as_binary(&model))?)
+}
+
+#[wasm_bindgen]
+extern "C" {
+	/// A JavaScript function that's called with each preview from `generate_progressive_preview`
+	#[wasm_bindgen(typescript_type = "(preview: Uint8Array, step: number) => boolean | void")]
+	pub type PreviewCallback;
+
+	#[wasm_bindgen(method, catch, js_name = call)]
+	fn call(this: &PreviewCallback, context: &JsValue, preview: Vec<u8>, step: u32) -> Result<JsValue, JsValue>;
+}
+
+/// Generate previews from coarse to fine, calling `callback` with each one (as binary STL) and the step it was generated with, ending with
+/// a preview stepping by `step`
+/// The callback can return false to stop early. This runs synchronously, so it should be run in a worker that passes each preview on to
+/// the page as it arrives.
+#[wasm_bindgen]
+pub fn generate_progressive_preview(
+	x_expression: &str,
+	y_expression: &str,
+	z_expression: &str,
+	width: u32,
+	height: u32,
+	step: u32,
+	callback: &PreviewCallback,
+) -> Result<(), JsError> {
+	lithophane::check_image_size(width, height)?;
+	let [x_fn, y_fn, z_fn] = compile_expressions(x_expression, y_expression, z_expression)?.map(|e| e.bind());
+
+	let mut callback_error = None;
+	lithophane::generate_progressive_preview(x_fn, y_fn, z_fn, width, height, step, |preview, step| {
+		match callback.call(&JsValue::NULL, output::as_binary(&preview), step) {
+			Ok(result) => result.as_bool() != Some(false),
+			Err(e) => {
+				callback_error = Some(e);
+				false
+			},
+		}
+	})?;
+
+	match callback_error {
+		Some(e) => Err(JsError::new(&format!(
+			"the preview callback threw an error: {}",
+			e.as_string().unwrap_or_default()
+		))),
+		None => Ok(()),
+	}
+}
+
+/// Generate a preview of part of the image, stepping by `step` pixels, to show that area in more detail on top of a coarser preview
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn generate_preview_region(
+	x_expression: &str,
+	y_expression: &str,
+	z_expression: &str,
+	width: u32,
+	height: u32,
+	left: u32,
+	top: u32,
+	region_width: u32,
+	region_height: u32,
+	step: u32,
+) -> Result<Vec<u8>, JsError> {
+	lithophane::check_image_size(width, height)?;
+	let [x_fn, y_fn, z_fn] = compile_expressions(x_expression, y_expression, z_expression)?.map(|e| e.bind());
+
+	Ok(lithophane::generate_preview_region(
+		x_fn,
+		y_fn,
+		z_fn,
+		width,
+		height,
+		image::math::Rect {
+			x: left,
+			y: top,
+			width: region_width,
+			height: region_height,
+		},
 		step,
-	)?
-	.as_binary())
+	)
+	.map(|model| output::as_binary(&model))?)
+}
+
+/// Get the estimated curvature at every vertex of every triangle of the preview generated with the same arguments, in the same order
+#[wasm_bindgen]
+pub fn get_preview_curvature(
+	x_expression: &str,
+	y_expression: &str,
+	z_expression: &str,
+	width: u32,
+	height: u32,
+	step: u32,
+	max_triangles: usize,
+) -> Result<Vec<f32>, JsError> {
+	lithophane::check_image_size(width, height)?;
+	let [x_fn, y_fn, z_fn] = compile_expressions(x_expression, y_expression, z_expression)?.map(|e| e.bind());
+
+	Ok(lithophane::preview_vertex_curvature(
+		x_fn,
+		y_fn,
+		z_fn,
+		width,
+		height,
+		step,
+		if max_triangles == 0 { None } else { Some(max_triangles) },
+	)?)
+}
+
+/// Get the size of the surface the expressions describe in millimeters, before adding the thickness, and the average distance between
+/// neighbouring vertices when stepping by `step` pixels, as [width, depth, height, spacing]
+#[wasm_bindgen]
+pub fn get_surface_size(x_expression: &str, y_expression: &str, z_expression: &str, width: u32, height: u32, step: u32) -> Result<Vec<f32>, JsError> {
+	lithophane::check_image_size(width, height)?;
+	let [x_fn, y_fn, z_fn] = compile_expressions(x_expression, y_expression, z_expression)?.map(|e| e.bind());
+
+	let size = lithophane::surface_size(&x_fn, &y_fn, &z_fn, width, height);
+	let pixel_size = lithophane::pixel_size(&x_fn, &y_fn, &z_fn, width, height);
+	Ok(vec![size.x, size.y, size.z, pixel_size * step.max(1) as f32])
+}
+
+#[wasm_bindgen]
+pub fn generate_vase_lithophane(
+	image: Vec<u8>,
+	pixel_size: f32,
+	white_depth: f32,
+	black_depth: f32,
+	frame: usize,
+	compress: bool,
+) -> Result<Vec<u8>, JsError> {
+	let image = input::decode_frame_as_srgb(&image, frame)?;
+
+	let stl = lithophane::generate_vase_lithophane(
+		lithophane::extend_to_strip(preprocessing::to_grayscale(image, Default::default(), Default::default()))?,
+		pixel_size,
+		white_depth,
+		black_depth,
+	)
+	.map(|model| output::as_binary(&model))?;
+
+	Ok(if compress { output::compress_gzip(&stl)? } else { stl })
+}
+
+/// Generate a tactile graphic to read by touch (see `tactile::Tactile`), with `levels` heights up to `relief` millimeters above the base,
+/// which is compressed if `compress` is set
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn generate_tactile_graphic(
+	x_expression: &str,
+	y_expression: &str,
+	z_expression: &str,
+	image: Vec<u8>,
+	levels: u32,
+	relief: f32,
+	frame: usize,
+	compress: bool,
+) -> Result<Vec<u8>, JsError> {
+	let image = input::decode_frame_as_srgb(&image, frame)?;
+	let [x_fn, y_fn, z_fn] = compile_expressions(x_expression, y_expression, z_expression)?.map(|e| e.bind());
+
+	let image = lithophane::extend_to_strip(preprocessing::to_grayscale(image, Default::default(), Default::default()))?;
+	let tactile = tactile::Tactile {
+		levels,
+		relief,
+		..Default::default()
+	};
+	let pixel_size = lithophane::pixel_size(&x_fn, &y_fn, &z_fn, image.width(), image.height());
+	let (white_depth, black_depth) = tactile.depths();
+	let stl = lithophane::generate_lithophane(
+		x_fn,
+		y_fn,
+		z_fn,
+		tactile.simplify(&image, pixel_size),
+		&LithophaneOptions {
+			white_depth,
+			black_depth,
+			max_slope: Some(tactile.max_slope(pixel_size)),
+			..Default::default()
+		},
+	)
+	.map(|model| output::as_binary(&model))?;
+
+	Ok(if compress { output::compress_gzip(&stl)? } else { stl })
+}
+
+/// Generate a cylindrical lithophane with a hollow wall, with the top and bottom each "open", "flat" or "dome"
+/// Flat caps are `cap_thickness` thick, and domes stick out `dome_height` past the end of the wall and are `cap_thickness` thick at the tip.
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn generate_tube_lithophane(
+	image: Vec<u8>,
+	pixel_size: f32,
+	white_depth: f32,
+	black_depth: f32,
+	top_cap: &str,
+	bottom_cap: &str,
+	cap_thickness: f32,
+	dome_height: f32,
+	frame: usize,
+	compress: bool,
+) -> Result<Vec<u8>, JsError> {
+	let image = input::decode_frame_as_srgb(&image, frame)?;
+	let cap = |cap: &str| match cap {
+		"" | "open" => Ok(lithophane::Cap::Open),
+		"flat" => Ok(lithophane::Cap::Flat { thickness: cap_thickness }),
+		"dome" => Ok(lithophane::Cap::Dome {
+			height: dome_height,
+			thickness: cap_thickness,
+		}),
+		_ => Err(Error::UnknownCap(cap.to_string())),
+	};
+
+	let stl = lithophane::generate_tube_lithophane(
+		lithophane::extend_to_strip(preprocessing::to_grayscale(image, Default::default(), Default::default()))?,
+		pixel_size,
+		white_depth,
+		black_depth,
+		cap(top_cap)?,
+		cap(bottom_cap)?,
+	)
+	.map(|model| output::as_binary(&model))?;
+
+	Ok(if compress { output::compress_gzip(&stl)? } else { stl })
+}
+
+/// Generate a lampshade with the image wound around it in a helical band `turns` times, with a dark separator between the turns
+#[wasm_bindgen]
+pub fn generate_spiral_lithophane(
+	image: Vec<u8>,
+	pixel_size: f32,
+	turns: f32,
+	white_depth: f32,
+	black_depth: f32,
+	frame: usize,
+	compress: bool,
+) -> Result<Vec<u8>, JsError> {
+	let image = input::decode_frame_as_srgb(&image, frame)?;
+	let spiral = spiral::SpiralLampshade { turns, ..Default::default() };
+
+	let stl = lithophane::generate_vase_lithophane(
+		lithophane::extend_to_strip(spiral.unroll(&preprocessing::to_grayscale(image, Default::default(), Default::default())))?,
+		pixel_size,
+		white_depth,
+		black_depth,
+	)
+	.map(|model| output::as_binary(&model))?;
+
+	Ok(if compress { output::compress_gzip(&stl)? } else { stl })
+}
+
+/// Generate a round lithophane from the largest circle that fits in the image, with a hole in the middle (or none if `hole_diameter` is 0)
+#[wasm_bindgen]
+pub fn generate_disc_lithophane(
+	image: Vec<u8>,
+	pixel_size: f32,
+	hole_diameter: f32,
+	white_depth: f32,
+	black_depth: f32,
+	frame: usize,
+	compress: bool,
+) -> Result<Vec<u8>, JsError> {
+	let image = input::decode_frame_as_srgb(&image, frame)?;
+
+	let stl = lithophane::generate_disc_lithophane(
+		lithophane::extend_to_strip(preprocessing::to_grayscale(image, Default::default(), Default::default()))?,
+		pixel_size,
+		hole_diameter,
+		white_depth,
+		black_depth,
+	)
+	.map(|model| output::as_binary(&model))?;
+
+	Ok(if compress { output::compress_gzip(&stl)? } else { stl })
 }
 
 #[derive(Error, Debug)]
 pub enum Error {
-	#[error("invalid {0} expression: {1}")]
-	MevalError(String, meval::Error),
+	#[error(transparent)]
+	Expression(#[from] expression::ExpressionError),
+	#[error("unknown crop \"{0}\", expected circle, ellipse or nothing")]
+	UnknownCrop(String),
+	#[error("unknown cap \"{0}\", expected open, flat or dome")]
+	UnknownCap(String),
+	#[error("unknown edges \"{0}\", expected clamp, repeat or mirror")]
+	UnknownEdges(String),
+	#[error("there's no {0} named \"{1}\"")]
+	NotInCatalog(&'static str, String),
+	#[error("the image lengths add up to {0} bytes, but there are {1}")]
+	ImageLengths(usize, usize),
+	#[error("there are {0} shape setting names but {1} values")]
+	ShapeLengths(usize, usize),
+	#[error("the width, height and step have to be at least 1")]
+	ZeroSize,
+	#[error("the {0} has to be a positive number, not {1}")]
+	NotPositive(&'static str, f32),
 }
 
 #[wasm_bindgen]
 pub fn get_image_dimensions(image: Vec<u8>) -> Result<ImageDimensions, JsError> {
-	let image = image::io::Reader::new(Cursor::new(image)).with_guessed_format().map_err(ImageError::IoError)?.decode()?;
+	let image = input::decode_frame(&image, 0)?;
 	Ok(ImageDimensions {
 		width: image.width(),
 		height: image.height(),
 	})
 }
 
+/// Get warnings about the image, as a list of strings
+#[wasm_bindgen]
+pub fn get_image_warnings(image: Vec<u8>, frame: usize) -> Result<Vec<JsValue>, JsError> {
+	let image = input::decode_frame_as_srgb(&image, frame)?;
+	Ok(
+		warnings::clipping_warnings(&preprocessing::to_grayscale(image, Default::default(), Default::default()))
+			.iter()
+			.map(|w| JsValue::from_str(&w.to_string()))
+			.collect(),
+	)
+}
+
+/// Get warnings about images in a set that look the same (see `warnings::duplicate_warnings`), as a list of strings
+///
+/// The image files are passed one after another in `images`, with the length of each in bytes in `lengths`.
+#[wasm_bindgen]
+pub fn get_duplicate_warnings(images: Vec<u8>, lengths: Vec<usize>) -> Result<Vec<JsValue>, JsError> {
+	let total = lengths.iter().sum();
+	if total != images.len() {
+		return Err(Error::ImageLengths(total, images.len()).into());
+	}
+	let mut start = 0;
+	let mut decoded = Vec::with_capacity(lengths.len());
+	for length in lengths {
+		let image = input::decode_frame_as_srgb(&images[start..start + length], 0)?;
+		decoded.push(preprocessing::to_grayscale(image, Default::default(), Default::default()));
+		start += length;
+	}
+	Ok(warnings::duplicate_warnings(&decoded).iter().map(|w| JsValue::from_str(&w.to_string())).collect())
+}
+
+/// Get the grayscale image a lithophane is generated from and a simulation of how the lithophane will look lit from behind (see
+/// `backlight::simulate_backlight`), both as RGBA pixels at the same size, eg to compare them with a slider
+#[wasm_bindgen]
+pub fn get_backlight_comparison(
+	image: Vec<u8>,
+	white_depth: f32,
+	black_depth: f32,
+	frame: usize,
+	attenuation: f32,
+) -> Result<BacklightComparison, JsError> {
+	let image = input::decode_frame_as_srgb(&image, frame)?;
+	let grayscale = preprocessing::to_grayscale(image, Default::default(), Default::default());
+	let lit = backlight::simulate_backlight(
+		&grayscale,
+		&LithophaneOptions {
+			white_depth,
+			black_depth,
+			..Default::default()
+		},
+		&backlight::Material {
+			attenuation,
+			..Default::default()
+		},
+	);
+
+	Ok(BacklightComparison {
+		width: grayscale.width(),
+		height: grayscale.height(),
+		grayscale: DynamicImage::ImageLuma8(grayscale).into_rgba8().into_raw(),
+		lit: DynamicImage::ImageRgb8(lit).into_rgba8().into_raw(),
+	})
+}
+
+/// Move and zoom the image on the surface without changing the shape (see `preprocessing::ImageMapping`), as a PNG file to generate from
+///
+/// `edges` is what fills the parts the image no longer covers: "clamp", "repeat" or "mirror".
+#[wasm_bindgen]
+pub fn get_mapped_image(image: Vec<u8>, frame: usize, offset_x: f32, offset_y: f32, scale: f32, edges: &str) -> Result<Vec<u8>, JsError> {
+	let image = input::decode_frame_as_srgb(&image, frame)?;
+	let edges = match edges {
+		"clamp" => preprocessing::EdgeMode::Clamp,
+		"repeat" => preprocessing::EdgeMode::Repeat,
+		"mirror" => preprocessing::EdgeMode::Mirror,
+		_ => return Err(Error::UnknownEdges(edges.to_string()).into()),
+	};
+	let mapped = preprocessing::map_image(
+		&preprocessing::to_grayscale(image, Default::default(), Default::default()),
+		&preprocessing::ImageMapping {
+			offset: (offset_x, offset_y),
+			scale,
+			edges,
+		},
+	)?;
+
+	let mut png = std::io::Cursor::new(Vec::new());
+	mapped.write_to(&mut png, image::ImageFormat::Png)?;
+	Ok(png.into_inner())
+}
+
+/// Get shapes and sizes that suit the image (see `suggest::suggest_shapes`), best first, as a JSON array of objects with the expressions to
+/// make each one
+#[wasm_bindgen]
+pub fn get_shape_suggestions(image: Vec<u8>, frame: usize) -> Result<String, JsError> {
+	let image = input::decode_frame_as_srgb(&image, frame)?;
+	let suggestions = suggest::suggest_shapes(&preprocessing::to_grayscale(image, Default::default(), Default::default()));
+	Ok(format!("[{}]", suggestions.iter().map(|s| s.to_json()).collect::<Vec<_>>().join(", ")))
+}
+
+// The catalog functions take the user's definitions as JSON every time (see `presets::Catalog::parse_json`), since nothing is kept between
+// calls, so the page can keep them in local storage or wherever it likes
+fn import_catalog(catalog: &str) -> Result<presets::Catalog, presets::CatalogError> {
+	let mut built_in = presets::Catalog::default();
+	built_in.extend(presets::Catalog::parse_json(catalog)?);
+	Ok(built_in)
+}
+
+/// Get the names of the presets, materials or curves (`kind` is "preset", "material" or "curve"), the built in ones and the ones in
+/// `catalog`, which also checks `catalog` can be read
+#[wasm_bindgen]
+pub fn get_catalog_names(catalog: &str, kind: &str) -> Result<Vec<JsValue>, JsError> {
+	let catalog = import_catalog(catalog)?;
+	let names: Vec<&String> = match kind {
+		"preset" => catalog.presets.iter().map(|(name, _)| name).collect(),
+		"material" => catalog.materials.iter().map(|(name, _)| name).collect(),
+		"curve" => catalog.curves.iter().map(|(name, _)| name).collect(),
+		_ => return Err(presets::CatalogError::UnknownKind(kind.to_string()).into()),
+	};
+	Ok(names.into_iter().map(|name| JsValue::from_str(name)).collect())
+}
+
+/// Get the x, y and z expressions of a preset
+#[wasm_bindgen]
+pub fn get_catalog_preset(catalog: &str, name: &str) -> Result<Vec<JsValue>, JsError> {
+	let catalog = import_catalog(catalog)?;
+	let preset = catalog.preset(name).ok_or_else(|| Error::NotInCatalog("preset", name.to_string()))?;
+	Ok([&preset.x_expression, &preset.y_expression, &preset.z_expression].map(|e| JsValue::from_str(e)).to_vec())
+}
+
+/// Get a material's attenuation followed by the red, green and blue of its color, eg for `get_backlight_comparison`
+#[wasm_bindgen]
+pub fn get_catalog_material(catalog: &str, name: &str) -> Result<Vec<f32>, JsError> {
+	let catalog = import_catalog(catalog)?;
+	let material = catalog.material(name).ok_or_else(|| Error::NotInCatalog("material", name.to_string()))?;
+	Ok([material.attenuation].into_iter().chain(material.color).collect())
+}
+
+/// Get a curve as the output gray value for each of the 256 input gray values
+#[wasm_bindgen]
+pub fn get_catalog_curve(catalog: &str, name: &str) -> Result<Vec<u8>, JsError> {
+	let catalog = import_catalog(catalog)?;
+	Ok(catalog.curve(name).ok_or_else(|| Error::NotInCatalog("curve", name.to_string()))?.lookup_table())
+}
+
+/// Save everything set up in the web app as JSON (see `settings::Settings`), eg for a shareable project link or autosave, where an empty
+/// `preset`, `curve` or `material` means none is used and `shape_names` and `shape_values` are the settings of the generators that don't
+/// use expressions (eg "pixel_size")
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn save_settings(
+	preset: &str,
+	x_expression: &str,
+	y_expression: &str,
+	z_expression: &str,
+	white_depth: f32,
+	black_depth: f32,
+	frame: usize,
+	step: u32,
+	curve: &str,
+	material: &str,
+	shape_names: Vec<JsValue>,
+	shape_values: Vec<f32>,
+) -> Result<String, JsError> {
+	if shape_names.len() != shape_values.len() {
+		return Err(Error::ShapeLengths(shape_names.len(), shape_values.len()).into());
+	}
+	let name = |name: &str| (!name.is_empty()).then(|| name.to_string());
+	let settings = settings::Settings {
+		preset: name(preset),
+		x_expression: x_expression.to_string(),
+		y_expression: y_expression.to_string(),
+		z_expression: z_expression.to_string(),
+		white_depth,
+		black_depth,
+		frame,
+		step,
+		curve: name(curve),
+		material: name(material),
+		shape: shape_names.iter().map(|name| name.as_string().unwrap_or_default()).zip(shape_values).collect(),
+	};
+	Ok(settings.to_json()?)
+}
+
+/// Load settings saved by `save_settings`
+#[wasm_bindgen]
+pub fn load_settings(json: &str) -> Result<SavedSettings, JsError> {
+	let settings = settings::Settings::parse_json(json)?;
+	Ok(SavedSettings {
+		preset: settings.preset.unwrap_or_default(),
+		x_expression: settings.x_expression,
+		y_expression: settings.y_expression,
+		z_expression: settings.z_expression,
+		white_depth: settings.white_depth,
+		black_depth: settings.black_depth,
+		frame: settings.frame,
+		step: settings.step,
+		curve: settings.curve.unwrap_or_default(),
+		material: settings.material.unwrap_or_default(),
+		shape_names: settings.shape.iter().map(|(name, _)| JsValue::from_str(name)).collect(),
+		shape_values: settings.shape.iter().map(|&(_, value)| value).collect(),
+	})
+}
+
+/// Get a JSON manifest recording how `model` (the output of `generate_lithophane`) was generated from `image`, to save alongside it
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn get_manifest(
+	x_expression: &str,
+	y_expression: &str,
+	z_expression: &str,
+	image: Vec<u8>,
+	white_depth: f32,
+	black_depth: f32,
+	frame: usize,
+	model: Vec<u8>,
+) -> Result<String, JsError> {
+	let input_hash = manifest::hash_input(&image);
+	let decoded = input::decode_frame_as_srgb(&image, frame)?;
+	// The model might be compressed
+	let stl = if model.starts_with(&[0x1f, 0x8b]) {
+		let mut decompressed = Vec::new();
+		std::io::Read::read_to_end(&mut flate2::read::GzDecoder::new(&model[..]), &mut decompressed)?;
+		decompressed
+	} else {
+		model.clone()
+	};
+
+	Ok(manifest::Manifest {
+		input_hash,
+		parameters: vec![
+			("x_expression".to_string(), x_expression.to_string()),
+			("y_expression".to_string(), y_expression.to_string()),
+			("z_expression".to_string(), z_expression.to_string()),
+			("white_depth".to_string(), white_depth.to_string()),
+			("black_depth".to_string(), black_depth.to_string()),
+			("frame".to_string(), frame.to_string()),
+		],
+		metadata: Vec::new(),
+		triangles: pk_stl::parse_stl(&stl)?.triangles.len(),
+		bytes: model.len(),
+		print_time: None,
+		warnings: warnings::clipping_warnings(&preprocessing::to_grayscale(decoded, Default::default(), Default::default()))
+			.iter()
+			.map(|w| w.to_string())
+			.collect(),
+	}
+	.to_json())
+}
+
+/// Get the center of mass of an STL model as [x, y, z], or an empty list if it doesn't have any volume
+#[wasm_bindgen]
+pub fn get_center_of_mass(stl: Vec<u8>) -> Result<Vec<f32>, JsError> {
+	let model = pk_stl::parse_stl(&stl)?;
+	Ok(mesh::center_of_mass(&model).map(|c| vec![c.x, c.y, c.z]).unwrap_or_default())
+}
+
+/// Check whether an STL model will stand on its lowest points (with z up) without tipping over
+#[wasm_bindgen]
+pub fn is_stable(stl: Vec<u8>) -> Result<bool, JsError> {
+	let model = pk_stl::parse_stl(&stl)?;
+	Ok(mesh::is_stable(&model, 0.01))
+}
+
+/// Get the open edges of an STL model (where it isn't watertight), as a flat list of x,y,z coordinates with two points per edge
+#[wasm_bindgen]
+pub fn get_boundary_edges(stl: Vec<u8>) -> Result<Vec<f32>, JsError> {
+	let model = pk_stl::parse_stl(&stl)?;
+	Ok(mesh::boundary_edges(&model).into_iter().flat_map(|[a, b]| [a.x, a.y, a.z, b.x, b.y, b.z]).collect())
+}
+
+/// Roughly estimate how many seconds an STL model takes to print, with sizes in millimeters and speeds in millimeters per second
+#[wasm_bindgen]
+pub fn estimate_print_time(stl: Vec<u8>, layer_height: f32, line_width: f32, perimeter_speed: f32, infill_speed: f32) -> Result<f32, JsError> {
+	for (name, value) in [
+		("layer height", layer_height),
+		("line width", line_width),
+		("perimeter speed", perimeter_speed),
+		("infill speed", infill_speed),
+	] {
+		if !(value > 0.0 && value.is_finite()) {
+			return Err(Error::NotPositive(name, value).into());
+		}
+	}
+	Ok(printer::estimate_print_time(
+		&pk_stl::parse_stl(&stl)?,
+		&printer::PrintSettings {
+			layer_height,
+			line_width,
+			perimeter_speed,
+			infill_speed,
+			..Default::default()
+		},
+	))
+}
+
+/// Predict the number of triangles and binary STL size in bytes for a lithophane (or a preview if `preview` is set), as [triangles, bytes]
+#[wasm_bindgen]
+pub fn estimate_output(width: u32, height: u32, step: u32, preview: bool) -> Result<Vec<f64>, JsError> {
+	if width == 0 || height == 0 || (preview && step == 0) {
+		return Err(Error::ZeroSize.into());
+	}
+	let triangles = if preview {
+		lithophane::preview_triangle_count(width, height, step)
+	} else {
+		lithophane::lithophane_triangle_count(width, height, 1, 1)
+	};
+	Ok(vec![
+		triangles as f64,
+		output::estimated_size(output::OutputFormat::BinaryStl, triangles) as f64,
+	])
+}
+
+/// Get the positions of the vertices on the front of a lithophane stepping by `step` pixels with `subdivisions` vertices per pixel (see
+/// `lithophane::sampling_grid`), as x,y pairs, leaving out those outside of `crop` ("circle", "ellipse" or "" for none)
+///
+/// The positions are scaled from the `width` × `height` image the lithophane is generated from to the `display_width` × `display_height`
+/// photo they're drawn on (keeping the centers of the corner pixels on each other), eg when the photo was resized to a lower resolution
+/// first.
+#[wasm_bindgen]
+pub fn get_sampling_grid(
+	width: u32,
+	height: u32,
+	subdivisions: u32,
+	step: u32,
+	crop: &str,
+	display_width: u32,
+	display_height: u32,
+) -> Result<Vec<f32>, JsError> {
+	if width == 0 || height == 0 {
+		return Err(Error::ZeroSize.into());
+	}
+	let boundary = match crop {
+		"" => None,
+		"circle" => Some(outline::Shape::circle_in_image(width, height, 0.0, 0.0)),
+		"ellipse" => Some(outline::Shape::ellipse_in_image(width, height, 0.0, 0.0)),
+		_ => return Err(Error::UnknownCrop(crop.to_string()).into()),
+	};
+	let options = LithophaneOptions {
+		outline: outline::Outline {
+			boundary,
+			..Default::default()
+		},
+		subdivisions,
+		sample_step: step,
+		..Default::default()
+	};
+	let (scale_x, scale_y) = (display_width as f32 / width as f32, display_height as f32 / height as f32);
+	Ok(lithophane::sampling_grid(width, height, &options)
+		.into_iter()
+		.flat_map(|(x, y)| [(x + 0.5) * scale_x - 0.5, (y + 0.5) * scale_y - 0.5])
+		.collect())
+}
+
+/// Get the number of frames in an animated image, or 1 for still images
+#[wasm_bindgen]
+pub fn get_frame_count(image: Vec<u8>) -> Result<usize, JsError> {
+	Ok(input::frame_count(&image)?)
+}
+
+/// A lithophane and its preview as binary STL files
+#[wasm_bindgen(getter_with_clone)]
+pub struct LithophaneWithPreview {
+	pub lithophane: Vec<u8>,
+	pub preview: Vec<u8>,
+}
+
+/// The grayscale image and the simulated backlit lithophane from `get_backlight_comparison`, as RGBA pixels (eg for `ImageData`)
+#[wasm_bindgen(getter_with_clone)]
+pub struct BacklightComparison {
+	pub width: u32,
+	pub height: u32,
+	pub grayscale: Vec<u8>,
+	pub lit: Vec<u8>,
+}
+
+/// Settings from `load_settings`, with an empty string for a preset, curve or material that isn't used
+#[wasm_bindgen(getter_with_clone)]
+pub struct SavedSettings {
+	pub preset: String,
+	pub x_expression: String,
+	pub y_expression: String,
+	pub z_expression: String,
+	pub white_depth: f32,
+	pub black_depth: f32,
+	pub frame: usize,
+	pub step: u32,
+	pub curve: String,
+	pub material: String,
+	pub shape_names: Vec<JsValue>,
+	pub shape_values: Vec<f32>,
+}
+
 #[wasm_bindgen]
 pub struct ImageDimensions {
 	pub width: u32,
@@ -1,61 +1,2098 @@
-use image::GrayImage;
+use std::{
+	borrow::Cow,
+	mem,
+	sync::atomic::{self, AtomicBool},
+};
+
+use image::{
+	imageops::{self, FilterType},
+	math::Rect,
+	GrayImage, Luma,
+};
 use pk_stl::{
 	geometry::{Triangle, Vec3},
 	StlModel,
 };
 use thiserror::Error;
 
+use crate::{
+	label::Label,
+	outline::{Outline, Shape},
+	preprocessing::sample_bilinear,
+	puzzle::Puzzle,
+};
+
+/// Settings for generating a lithophane, other than the expressions and the image
+#[derive(Debug, Clone, PartialEq)]
+pub struct LithophaneOptions {
+	/// The distance between the backing mesh and the surface for white pixels
+	pub white_depth: f32,
+	/// The distance between the backing mesh and the surface for black pixels
+	pub black_depth: f32,
+	/// Only the parts of the image inside the outline are used, with walls generated along its edges
+	pub outline: Outline,
+	pub back_texture: Option<BackTexture>,
+	/// Ribs on the back to keep large, thin lithophanes from warping
+	pub back_ribs: Option<BackRibs>,
+	/// A ridge around the edges of the back to hold a sheet of acrylic or glass against it
+	pub sheet_ridge: Option<SheetRidge>,
+	/// How many pixels the backing extends past the edges of the image, so the walls around the edges slope outwards towards the back
+	/// instead of being exactly vertical, which gives slicers a more solid join between the walls and the backing
+	pub backing_margin: f32,
+	/// How many degrees the walls around the edges of the image lean outwards towards the back, on top of the backing margin, so thicker
+	/// parts of the edges get wider walls
+	pub wall_draft: f32,
+	/// A second grayscale image that changes the depth range locally, eg to emphasize a face and flatten the background
+	pub depth_mask: Option<DepthMask>,
+	/// Fade the thickness to an even depth towards the edge of a round lithophane, so it blends smoothly into its rim
+	pub radial_fade: Option<RadialFade>,
+	/// The number of vertices per pixel along each axis, with the depths between pixels interpolated bilinearly, to smooth out the blocky
+	/// look of low resolution images printed large
+	pub subdivisions: u32,
+	/// Only make a vertex for about every `sample_step`-th pixel along each axis, from the average of the pixels around it, for a lower
+	/// resolution mesh with fewer triangles (eg a quick test print). Subdividing then works on the averaged pixels.
+	pub sample_step: u32,
+	/// Limit the thickness near the edges of the image so the lithophane fits into the rabbet of a photo frame
+	pub frame_fit: Option<FrameFit>,
+	/// A thick band below the image so curved lithophanes can stand on their own
+	pub skirt: Option<Skirt>,
+	/// Text engraved into the back, eg to trace a print back to the settings it was made with
+	pub label: Option<Label>,
+	/// Flatten the tones into a few plateaus, like the contour lines of a topographic map
+	pub contours: Option<Contours>,
+	/// Show the tones with a pattern of dots instead of the thickness
+	pub stipple: Option<Stipple>,
+	/// Show the tones with raised lines of different widths instead of the thickness, like an engraving
+	pub hatching: Option<Hatching>,
+	/// A small image (eg a maker's logo) embossed into the surface at a fixed thickness, whatever the tones of the photo underneath are
+	pub emboss: Option<Emboss>,
+	/// The largest difference in depth between neighbouring pixels, so steep edges (eg black text on white) become short ramps, which
+	/// print better than vertical walls
+	pub max_slope: Option<f32>,
+	/// Round every depth to a multiple of the slicer's layer height, so each tone is a whole number of layers instead of shimmering
+	/// between two
+	pub layer_height: Option<f32>,
+	/// Added to every depth (or subtracted, if negative) to compensate for the first layer being squished thinner or wider than it should
+	/// be, so a calibrated set of depths works the same with a different printer or filament
+	pub thickness_offset: f32,
+	/// Only generate one part of the lithophane, so the image and the frame around it can be printed in different filaments
+	pub part: Option<Part>,
+	/// Repeat the image across the surface as a pattern
+	pub tiling: Option<Tiling>,
+	/// The text saved in the header of binary STL files, eg to identify the order in a print farm (see `output::check_stl_header`)
+	pub header: String,
+	/// Which neighbouring points the direction the surface is pushed out in is averaged from
+	pub normal_averaging: NormalAveraging,
+}
+
+impl Default for LithophaneOptions {
+	fn default() -> Self {
+		Self {
+			white_depth: 0.5,
+			black_depth: 3.0,
+			outline: Outline::default(),
+			back_texture: None,
+			back_ribs: None,
+			sheet_ridge: None,
+			backing_margin: 0.0,
+			wall_draft: 0.0,
+			depth_mask: None,
+			radial_fade: None,
+			subdivisions: 1,
+			sample_step: 1,
+			frame_fit: None,
+			skirt: None,
+			label: None,
+			contours: None,
+			stipple: None,
+			hatching: None,
+			emboss: None,
+			max_slope: None,
+			layer_height: None,
+			thickness_offset: 0.0,
+			part: None,
+			tiling: None,
+			header: String::new(),
+			normal_averaging: NormalAveraging::default(),
+		}
+	}
+}
+
+/// Which of a point's neighbours its normal (the direction the thickness is added in) is averaged from
+///
+/// The default only uses two of the four triangles around the point, which leans the normals slightly towards one diagonal on surfaces
+/// that curve in both directions. The others are more even, but they move the points slightly, so they aren't the default to keep
+/// existing models the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NormalAveraging {
+	/// The triangles to the lower right and upper left of the point
+	#[default]
+	Pair,
+	/// All four triangles between the point and its upper, lower, left and right neighbours, equally
+	Four,
+	/// All eight triangles between the point and its neighbours including the diagonal ones, weighted by the angle each covers at the point
+	EightWeighted,
+}
+
+impl NormalAveraging {
+	/// The normal of the point at `x_i` in `current`, with the rows above and below it
+	fn normal(self, above: &[Vec3], current: &[Vec3], below: &[Vec3], x_i: usize) -> Result<Vec3, InvalidPointsError> {
+		let v = current[x_i];
+		let face = |a: Vec3, b: Vec3| normalize_to_unit_vector(cross_product(a - v, b - v));
+		match self {
+			NormalAveraging::Pair => normalize_to_unit_vector(face(below[x_i], current[x_i + 1])? + face(above[x_i], current[x_i - 1])?),
+			NormalAveraging::Four => normalize_to_unit_vector(
+				face(below[x_i], current[x_i + 1])?
+					+ face(current[x_i + 1], above[x_i])?
+					+ face(above[x_i], current[x_i - 1])?
+					+ face(current[x_i - 1], below[x_i])?,
+			),
+			NormalAveraging::EightWeighted => {
+				// Going around the point in the same direction as the pairs above
+				let ring = [
+					below[x_i],
+					below[x_i + 1],
+					current[x_i + 1],
+					above[x_i + 1],
+					above[x_i],
+					above[x_i - 1],
+					current[x_i - 1],
+					below[x_i - 1],
+				];
+				let mut sum = Vec3::from([0.0; 3]);
+				for (i, &a) in ring.iter().enumerate() {
+					let (a, b) = (a - v, ring[(i + 1) % ring.len()] - v);
+					let cross = cross_product(a, b);
+					let length = (cross.x * cross.x + cross.y * cross.y + cross.z * cross.z).sqrt();
+					// Triangles with no area don't point anywhere, and they cover no angle either
+					if length > 0.0 {
+						let angle = length.atan2(a.x * b.x + a.y * b.y + a.z * b.z);
+						sum = sum + cross * (angle / length);
+					}
+				}
+				normalize_to_unit_vector(sum)
+			},
+		}
+	}
+}
+
+/// A fine pattern embossed into the back of the lithophane to diffuse light from point sources like LEDs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BackTexture {
+	/// Random bumps at every vertex, similar to frosted glass
+	Stipple { amplitude: f32, seed: u32 },
+	/// A grid of small pyramids, `period` pixels wide
+	MicroPrisms { amplitude: f32, period: f32 },
+	/// Smooth random bumps for a stucco or canvas look, where `frequency` is the number of bumps per pixel (eg 0.1 for bumps about 10
+	/// pixels apart), with a few finer layers of bumps added on top
+	Noise { amplitude: f32, frequency: f32, seed: u32 },
+}
+
+impl BackTexture {
+	/// How far the back surface is raised at a point in image coordinates
+	fn height(&self, x: f32, y: f32) -> f32 {
+		match *self {
+			BackTexture::Stipple { amplitude, seed } => amplitude * random(x.to_bits(), y.to_bits(), seed),
+			BackTexture::MicroPrisms { amplitude, period } => {
+				let triangle_wave = |v: f32| 1.0 - ((v / period).rem_euclid(1.0) - 0.5).abs() * 2.0;
+				amplitude * triangle_wave(x).min(triangle_wave(y))
+			},
+			BackTexture::Noise { amplitude, frequency, seed } => {
+				// Value noise: random values on a grid, smoothly interpolated between grid points
+				let value_noise = |x: f32, y: f32, seed: u32| {
+					let (x0, y0) = (x.floor(), y.floor());
+					let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+					let (tx, ty) = (smooth(x - x0), smooth(y - y0));
+					let corner = |dx: f32, dy: f32| random((x0 + dx) as i32 as u32, (y0 + dy) as i32 as u32, seed);
+					let top = corner(0.0, 0.0) + (corner(1.0, 0.0) - corner(0.0, 0.0)) * tx;
+					let bottom = corner(0.0, 1.0) + (corner(1.0, 1.0) - corner(0.0, 1.0)) * tx;
+					top + (bottom - top) * ty
+				};
+
+				// Three octaves, each twice as fine and half as high as the last, scaled so the total stays between 0 and amplitude
+				let mut total = 0.0;
+				for octave in 0..3 {
+					let scale = (1 << octave) as f32;
+					total += value_noise(x * frequency * scale, y * frequency * scale, seed.wrapping_add(octave)) / scale;
+				}
+				amplitude * total / 1.75
+			},
+		}
+	}
+}
+
+/// A pseudorandom value from 0 to 1 that's always the same for the same arguments
+pub(crate) fn random(a: u32, b: u32, seed: u32) -> f32 {
+	let mut h = a.wrapping_mul(0x9E37_79B1) ^ b.wrapping_mul(0x85EB_CA77) ^ seed.wrapping_mul(0xC2B2_AE3D);
+	h ^= h >> 15;
+	h = h.wrapping_mul(0x2C1B_3C6D);
+	h ^= h >> 12;
+	h = h.wrapping_mul(0x297A_2D39);
+	h ^= h >> 15;
+	h as f32 / u32::MAX as f32
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepthMask {
+	/// This is stretched to the size of the lithophane's image if it isn't already the same size
+	pub image: GrayImage,
+	pub mode: DepthMaskMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DepthMaskMode {
+	/// Scale the range between white and black depth, so white in the mask keeps the full range and black flattens it to the white depth
+	Multiply,
+	/// Add to the depth, where white in the mask adds `amount` and black adds nothing
+	Offset { amount: f32 },
+}
+
+impl DepthMask {
+	fn apply(&self, depth: f32, mask_value: u8, white_depth: f32) -> f32 {
+		let mask_value = mask_value as f32 / 255.0;
+		match self.mode {
+			DepthMaskMode::Multiply => white_depth + (depth - white_depth) * mask_value,
+			DepthMaskMode::Offset { amount } => depth + amount * mask_value,
+		}
+	}
+}
+
+/// Tones flattened into evenly spaced plateaus from the white depth to the black depth, for a stepped, topographic look that suits matte
+/// filaments
+///
+/// The walls between plateaus are as steep as neighbouring pixels allow, unless `max_slope` turns them into ramps.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Contours {
+	/// The number of plateaus, including the ones at the white and black depths
+	pub levels: u32,
+}
+
+impl Contours {
+	pub(crate) fn apply(&self, depth: f32, white_depth: f32, black_depth: f32) -> f32 {
+		let steps = self.levels.max(2) as f32 - 1.0;
+		let level = ((depth - white_depth) / (black_depth - white_depth) * steps).round().clamp(0.0, steps);
+		white_depth + (black_depth - white_depth) * level / steps
+	}
+}
+
+/// Tones shown as a pattern of dots, where the chance of a dot at each spot depends on the tone there, eg for a star map lit through pinholes
+///
+/// The dots are placed in a grid of cells `spacing` apart, each with at most one dot somewhere inside it, so they never overlap. All sizes are
+/// in pixels, and the dots need to be a few pixels across (or subdivided) to come out round.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stipple {
+	pub kind: StippleKind,
+	pub spacing: f32,
+	pub radius: f32,
+	columns: usize,
+	/// The dot in each cell, row by row, if there is one
+	dots: Vec<Option<(f32, f32)>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StippleKind {
+	/// Holes through a lithophane that's black depth thick everywhere else, with more holes where the image is lighter
+	Holes,
+	/// Round bumps `height` millimeters high on a lithophane that's white depth thick everywhere else, with more bumps where the image is
+	/// darker
+	Bumps { height: f32 },
+}
+
+impl Stipple {
+	/// Place the dots for an image, which are always in the same places for the same image and `seed`
+	pub fn new(kind: StippleKind, spacing: f32, radius: f32, seed: u32, image: &GrayImage) -> Self {
+		let spacing = spacing.max(radius * 2.0).max(1.0);
+		let (columns, rows) = (
+			((image.width() - 1) as f32 / spacing).ceil() as usize + 1,
+			((image.height() - 1) as f32 / spacing).ceil() as usize + 1,
+		);
+
+		// Each dot is moved randomly around its cell as far as it can without reaching into the next one
+		let jitter = spacing - radius * 2.0;
+		let dots = (0..rows)
+			.flat_map(|row| (0..columns).map(move |column| (column, row)))
+			.map(|(column, row)| {
+				let (cell_x, cell_y) = (column as u32, row as u32);
+				let x = column as f32 * spacing + (random(cell_x, cell_y, seed) - 0.5) * jitter;
+				let y = row as f32 * spacing + (random(cell_x, cell_y, seed.wrapping_add(1)) - 0.5) * jitter;
+				let pixel = image.get_pixel(
+					(x.round().max(0.0) as u32).min(image.width() - 1),
+					(y.round().max(0.0) as u32).min(image.height() - 1),
+				);
+				let lightness = pixel.0[0] as f32 / 255.0;
+				let chance = match kind {
+					StippleKind::Holes => lightness,
+					StippleKind::Bumps { .. } => 1.0 - lightness,
+				};
+				(random(cell_x, cell_y, seed.wrapping_add(2)) < chance).then_some((x, y))
+			})
+			.collect();
+
+		Self {
+			kind,
+			spacing,
+			radius,
+			columns,
+			dots,
+		}
+	}
+
+	/// The distance from a point to the center of the closest dot, if there's one close enough to matter
+	fn distance(&self, x: f32, y: f32) -> Option<f32> {
+		let (column, row) = ((x / self.spacing).round() as i64, (y / self.spacing).round() as i64);
+		let rows = (self.dots.len() / self.columns) as i64;
+		(row - 1..=row + 1)
+			.flat_map(|r| (column - 1..=column + 1).map(move |c| (c, r)))
+			.filter(|&(c, r)| c >= 0 && r >= 0 && c < self.columns as i64 && r < rows)
+			.filter_map(|(c, r)| self.dots[r as usize * self.columns + c as usize])
+			.map(|(dot_x, dot_y)| ((x - dot_x).powi(2) + (y - dot_y).powi(2)).sqrt())
+			.reduce(f32::min)
+	}
+
+	/// Whether a point is inside a hole
+	fn is_hole(&self, x: f32, y: f32) -> bool {
+		self.kind == StippleKind::Holes && self.distance(x, y).is_some_and(|d| d < self.radius)
+	}
+
+	/// The depth at a point, in place of the depth from the image
+	fn depth(&self, x: f32, y: f32, white_depth: f32, black_depth: f32) -> f32 {
+		match self.kind {
+			StippleKind::Holes => black_depth,
+			StippleKind::Bumps { height } => {
+				let bump = self.distance(x, y).filter(|&d| d < self.radius).map_or(0.0, |d| (1.0 - (d / self.radius).powi(2)).sqrt());
+				white_depth + height * bump
+			},
+		}
+	}
+}
+
+/// Tones shown as parallel raised lines on a lithophane that's white depth thick everywhere else, where the lines are wider where the image
+/// is darker, like an engraving
+///
+/// Since most of the lithophane is thin and flat, it also prints faster than the whole relief.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hatching {
+	/// The direction of the lines in degrees counterclockwise from horizontal
+	pub angle: f32,
+	/// The distance between the middles of neighbouring lines in pixels
+	pub spacing: f32,
+	/// How far the lines are raised in millimeters
+	pub height: f32,
+	/// Add lines across the first ones for the darker half of the tones, so the first lines can stay thinner
+	pub crosshatch: bool,
+}
+
+impl Hatching {
+	/// The depth at a point with this gray value, in place of the depth from the image
+	fn depth(&self, x: f32, y: f32, gray_value: u8, white_depth: f32) -> f32 {
+		let darkness = 1.0 - gray_value as f32 / 255.0;
+		let (sin, cos) = self.angle.to_radians().sin_cos();
+
+		// How much of a point is covered by the nearest line that's `half_width` wide, with the edges blended over a pixel so they aren't
+		// jagged
+		let coverage = |across: f32, half_width: f32| {
+			let distance = (across - (across / self.spacing).round() * self.spacing).abs();
+			if half_width > 0.0 {
+				(half_width - distance + 0.5).clamp(0.0, 1.0)
+			} else {
+				0.0
+			}
+		};
+		// The distances across the lines and along them, which is across the crosshatching. The image's y points down, so the lines go
+		// along (cos, -sin).
+		let (across, along) = (x * sin + y * cos, x * cos - y * sin);
+		let lines = if self.crosshatch {
+			coverage(across, darkness.min(0.5) * self.spacing / 2.0).max(coverage(along, (darkness - 0.5).max(0.0) * self.spacing / 2.0))
+		} else {
+			coverage(across, darkness * self.spacing / 2.0)
+		};
+		white_depth + self.height * lines
+	}
+}
+
+/// A fade from the depths of the image in the middle of a round (or elliptical) lithophane to an even depth at its edge, eg so a round
+/// nightlight blends into its rim instead of the photo stopping abruptly
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RadialFade {
+	pub center_x: f32,
+	pub center_y: f32,
+	pub radius_x: f32,
+	pub radius_y: f32,
+	/// How far out the fade starts, as a fraction of the radius (eg 0.7 leaves the middle 70% alone)
+	pub start: f32,
+	/// The depth at the edge
+	pub depth: f32,
+	pub easing: Easing,
+}
+
+/// How a fade speeds up and slows down between its start and end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Easing {
+	Linear,
+	/// Starts slowly and speeds up towards the end
+	EaseIn,
+	/// Starts quickly and slows down towards the end
+	EaseOut,
+	/// Starts and ends slowly, so there's no visible line at either end
+	Smooth,
+}
+
+impl Easing {
+	/// How far along the fade is, from 0 to 1, at a fraction `t` of the way from its start to its end
+	pub fn apply(self, t: f32) -> f32 {
+		let t = t.clamp(0.0, 1.0);
+		match self {
+			Easing::Linear => t,
+			Easing::EaseIn => t * t,
+			Easing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+			Easing::Smooth => t * t * (3.0 - 2.0 * t),
+		}
+	}
+}
+
+impl RadialFade {
+	/// A fade to the edge of a circle or ellipse, or `None` for other shapes
+	pub fn in_shape(shape: &Shape, start: f32, depth: f32, easing: Easing) -> Option<Self> {
+		let (center_x, center_y, radius_x, radius_y) = match *shape {
+			Shape::Circle { center_x, center_y, radius } => (center_x, center_y, radius, radius),
+			Shape::Ellipse {
+				center_x,
+				center_y,
+				radius_x,
+				radius_y,
+			} => (center_x, center_y, radius_x, radius_y),
+			_ => return None,
+		};
+		Some(Self {
+			center_x,
+			center_y,
+			radius_x,
+			radius_y,
+			start,
+			depth,
+			easing,
+		})
+	}
+
+	/// Blend a depth with the fade's depth at a point in image coordinates
+	fn apply(&self, x: f32, y: f32, depth: f32) -> f32 {
+		let distance = (((x - self.center_x) / self.radius_x).powi(2) + ((y - self.center_y) / self.radius_y).powi(2)).sqrt();
+		let t = (distance - self.start) / (1.0 - self.start).max(f32::EPSILON);
+		depth + (self.depth - depth) * self.easing.apply(t)
+	}
+}
+
+/// A small image embossed into the lithophane, where black is made exactly `depth` thick, white is left alone, and grays blend between the
+/// two
+///
+/// This happens after the photo has been turned into depths, so unlike an overlay it isn't changed by any of the preprocessing or tone
+/// curves, and a logo always comes out the same.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Emboss {
+	pub image: GrayImage,
+	/// The left edge, in image coordinates
+	pub x: f32,
+	/// The top edge, in image coordinates
+	pub y: f32,
+	/// The width in image coordinates, with the height following the emboss image's aspect ratio
+	pub width: f32,
+	pub depth: f32,
+}
+
+impl Emboss {
+	/// The height in image coordinates
+	pub fn height(&self) -> f32 {
+		self.width * self.image.height() as f32 / self.image.width() as f32
+	}
+
+	/// Blend a depth with the emboss at a point in image coordinates
+	fn apply(&self, x: f32, y: f32, depth: f32) -> f32 {
+		let scale = (self.image.width() - 1).max(1) as f32 / self.width;
+		let (u, v) = ((x - self.x) * scale, (y - self.y) * scale);
+		let (max_u, max_v) = ((self.image.width() - 1) as f32, (self.image.height() - 1) as f32);
+		if !(0.0..=max_u).contains(&u) || !(0.0..=max_v).contains(&v) {
+			return depth;
+		}
+
+		let (u0, v0) = (u.floor().min((max_u - 1.0).max(0.0)), v.floor().min((max_v - 1.0).max(0.0)));
+		let (tu, tv) = (u - u0, v - v0);
+		let value = |u: f32, v: f32| self.image.get_pixel((u as u32).min(max_u as u32), (v as u32).min(max_v as u32)).0[0] as f32;
+		let top = value(u0, v0) * (1.0 - tu) + value(u0 + 1.0, v0) * tu;
+		let bottom = value(u0, v0 + 1.0) * (1.0 - tu) + value(u0 + 1.0, v0 + 1.0) * tu;
+		let coverage = 1.0 - (top * (1.0 - tv) + bottom * tv) / 255.0;
+		depth + (self.depth - depth) * coverage
+	}
+}
+
+/// A limit on the thickness near the edges of the image
+///
+/// Any back texture or ribs are flattened there, so the edges fit between two flat surfaces `max_thickness` apart.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameFit {
+	/// How far from the edges of the image the limit applies, in pixels
+	pub border: f32,
+	pub max_thickness: f32,
+	/// The distance after the border, in pixels, over which the thickness smoothly goes back to normal so there's no sharp step
+	pub transition: f32,
+}
+
+impl FrameFit {
+	/// Limit the texture height and pixel depth at a point `distance` pixels from the nearest edge of the image
+	fn apply(&self, distance: f32, texture_height: f32, depth: f32) -> (f32, f32) {
+		let clamped_depth = depth.min(self.max_thickness);
+
+		let t = if self.transition > 0.0 {
+			((distance - self.border) / self.transition).clamp(0.0, 1.0)
+		} else if distance > self.border {
+			1.0
+		} else {
+			0.0
+		};
+		let blend = t * t * (3.0 - 2.0 * t);
+		(texture_height * blend, clamped_depth + (depth - clamped_depth) * blend)
+	}
+}
+
+/// A solid band added below the bottom edge of the image, as part of the same solid as the rest of the lithophane
+///
+/// The expressions are evaluated for the rows below the image (with y past the bottom of the image, but the same w and h), so the band
+/// follows the shape, and it's `depth` thick instead of following the pixels. For an arc this makes a wide, flat footprint to stand on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Skirt {
+	/// How far the skirt extends below the image, in pixels
+	pub height: f32,
+	pub depth: f32,
+	/// Make the back of the skirt a wedge with a flat bottom, so a flat lithophane can stand leaning back
+	pub wedge: Option<Wedge>,
+}
+
+/// A wedge behind the skirt of a flat lithophane, so it stands on its own leaning back by `tilt` degrees once it's stood up with
+/// `mesh::stand_up`
+///
+/// The back of the skirt slopes from the top of the skirt down to the back of a flat bottom, which is level with the front of the skirt's
+/// bottom edge when the lithophane leans back by `tilt`. The wedge follows the surface like the rest of the skirt, so it only makes sense
+/// for flat lithophanes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Wedge {
+	pub tilt: f32,
+	/// How long the flat bottom is from the front of the skirt to the back of the wedge, in the same units as the expressions, or None to
+	/// reach as far back as the top of the lithophane leans so it can't tip over. It's limited so the back of the wedge stays behind the
+	/// lithophane and below the top of the skirt.
+	pub length: Option<f32>,
+}
+
+/// A part of the lithophane that can be generated on its own, eg to print the frame in an opaque filament and the image in white, and glue
+/// them together
+///
+/// The image and the frame meet in walls, so each one is a closed solid. A lithophane without a frame fit, skirt or reinforced holes has
+/// no frame. The base and the relief split the lithophane at a depth instead, for dual extruder printers (see `generate_base_and_relief`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Part {
+	/// Everything except the frame
+	Image,
+	/// The border limited by `LithophaneOptions::frame_fit`, the skirt and the reinforcement around holes
+	Frame,
+	/// Everything up to `depth` from the backing mesh, which is a slab following the surface except where the lithophane is thinner
+	Base { depth: f32 },
+	/// Everything further than `base_depth` from the backing mesh, which leaves out the areas where the lithophane is thinner
+	Relief { base_depth: f32 },
+}
+
+/// The thinnest the relief gets where it ends, so the walls there aren't flat
+const MIN_RELIEF_DEPTH: f32 = 0.01;
+
+/// Repeat the image as a texture across the surface, eg for lace or other pattern panels, instead of stretching one copy over it
+///
+/// The tiles are sampled from the image while generating the surface, so large repeat counts don't need a huge image. Everything else
+/// (the expressions' `w` and `h`, outlines, labels, etc) uses the size of the whole tiled surface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tiling {
+	pub columns: u32,
+	pub rows: u32,
+	/// Flip every other tile, so images that don't tile seamlessly on their own still line up at the seams
+	pub mirror: bool,
+}
+
+impl Default for Tiling {
+	fn default() -> Self {
+		Self {
+			columns: 1,
+			rows: 1,
+			mirror: false,
+		}
+	}
+}
+
+impl Tiling {
+	/// The size of the tiled surface in pixels, for an image of the given size
+	pub fn size(&self, width: u32, height: u32) -> (u32, u32) {
+		(width * self.columns.max(1), height * self.rows.max(1))
+	}
+
+	/// The pixel of the image that a pixel of the tiled surface comes from
+	fn source_pixel(&self, x: u32, y: u32, width: u32, height: u32) -> (u32, u32) {
+		let wrap = |v: u32, length: u32| {
+			let (tile, offset) = (v / length, v % length);
+			if self.mirror && tile % 2 == 1 {
+				length - 1 - offset
+			} else {
+				offset
+			}
+		};
+		(wrap(x, width), wrap(y, height))
+	}
+}
+
+/// Ribs raised from the back of the lithophane, as part of the same solid as the backing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BackRibs {
+	pub pattern: RibPattern,
+	pub height: f32,
+	/// The width of each rib, in pixels
+	pub width: f32,
+	/// The distance between ribs, in pixels
+	pub spacing: f32,
+}
+
+/// A shallow ridge raised from the back of the lithophane along its edges, as part of the same solid as the backing, so a sheet of acrylic
+/// or glass (eg a diffuser panel) fits into the pocket it makes and stays lined up with the image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SheetRidge {
+	/// How far the ridge stands out from the back, usually the sheet's thickness (eg 2 or 3 mm for acrylic) so the sheet sits flush
+	pub height: f32,
+	/// The width of the ridge, in pixels from the edges of the image
+	pub width: f32,
+}
+
+impl SheetRidge {
+	/// How far the back surface is raised at a point `distance` pixels from the nearest edge of the image
+	fn height(&self, distance: f32) -> f32 {
+		if distance <= self.width {
+			self.height
+		} else {
+			0.0
+		}
+	}
+}
+
+/// The percentiles `ThicknessStats` reports the thickness at
+pub const THICKNESS_PERCENTILES: [f32; 5] = [5.0, 25.0, 50.0, 75.0, 95.0];
+
+/// The number of bars in the histogram `generate_lithophane_with_thickness_stats` makes
+pub const THICKNESS_HISTOGRAM_BINS: usize = 10;
+
+/// How the final thicknesses of a lithophane are spread out, in millimeters from the back to the front, to check it actually uses the range
+/// of thicknesses it was meant to after preprocessing, clamping, snapping to layers, etc
+///
+/// Each point of the mesh that's part of the lithophane counts once, so subdividing doesn't change the proportions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThicknessStats {
+	pub min: f32,
+	pub max: f32,
+	/// The thickness at each of `THICKNESS_PERCENTILES`
+	pub percentiles: [f32; THICKNESS_PERCENTILES.len()],
+	/// How many points are in each of a number of equal ranges from `min` to `max`
+	pub histogram: Vec<usize>,
+}
+
+impl ThicknessStats {
+	/// Returns `None` if there aren't any thicknesses
+	pub fn new(mut thicknesses: Vec<f32>, bins: usize) -> Option<Self> {
+		if thicknesses.is_empty() {
+			return None;
+		}
+		thicknesses.sort_by(f32::total_cmp);
+		let (min, max) = (thicknesses[0], thicknesses[thicknesses.len() - 1]);
+		let percentiles = THICKNESS_PERCENTILES.map(|p| thicknesses[((thicknesses.len() - 1) as f32 * p / 100.0).round() as usize]);
+
+		let bins = bins.max(1);
+		let mut histogram = vec![0; bins];
+		for thickness in thicknesses {
+			let bin = if max > min {
+				((thickness - min) / (max - min) * bins as f32) as usize
+			} else {
+				0
+			};
+			histogram[bin.min(bins - 1)] += 1;
+		}
+		Some(Self {
+			min,
+			max,
+			percentiles,
+			histogram,
+		})
+	}
+
+	/// The range of thicknesses in a bar of the histogram
+	pub fn bin_range(&self, bin: usize) -> (f32, f32) {
+		let width = (self.max - self.min) / self.histogram.len() as f32;
+		(self.min + width * bin as f32, self.min + width * (bin + 1) as f32)
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RibPattern {
+	Grid,
+	Honeycomb,
+}
+
+impl BackRibs {
+	/// How far the back surface is raised at a point in image coordinates
+	fn height(&self, x: f32, y: f32) -> f32 {
+		let distance_from_rib = match self.pattern {
+			RibPattern::Grid => {
+				let distance_from_line = |v: f32| (v - (v / self.spacing).round() * self.spacing).abs();
+				distance_from_line(x).min(distance_from_line(y))
+			},
+			RibPattern::Honeycomb => {
+				// Find the nearest center of a hexagon, checking the closest column in the two nearest rows
+				let row_height = self.spacing * 3f32.sqrt() / 2.0;
+				let row = (y / row_height).floor();
+				let (dx, dy) = [row, row + 1.0]
+					.into_iter()
+					.map(|r| {
+						let offset = if r.rem_euclid(2.0) == 1.0 { self.spacing / 2.0 } else { 0.0 };
+						let center_x = ((x - offset) / self.spacing).round() * self.spacing + offset;
+						(x - center_x, y - r * row_height)
+					})
+					.min_by(|a, b| (a.0 * a.0 + a.1 * a.1).total_cmp(&(b.0 * b.0 + b.1 * b.1)))
+					.unwrap();
+				// The ribs are the edges of the hexagons, which are half of the spacing away from the center
+				let hexagon_distance = dx.abs().max(dx.abs() / 2.0 + dy.abs() * 3f32.sqrt() / 2.0);
+				self.spacing / 2.0 - hexagon_distance
+			},
+		};
+
+		if distance_from_rib <= self.width / 2.0 {
+			self.height
+		} else {
+			0.0
+		}
+	}
+}
+
+/// The fewest pixels an image can have in each direction to be made into a lithophane, since it takes two rows and two columns of pixels
+/// to make any triangles
+pub const MIN_IMAGE_SIZE: u32 = 2;
+
+#[derive(Error, Debug)]
+#[error("the image is {width}×{height} pixels, but it needs to be at least {MIN_IMAGE_SIZE}×{MIN_IMAGE_SIZE} pixels")]
+pub struct ImageTooSmallError {
+	pub width: u32,
+	pub height: u32,
+}
+
+/// Check that an image is big enough to make a lithophane or preview from, since smaller images would make an empty model
+pub fn check_image_size(width: u32, height: u32) -> Result<(), ImageTooSmallError> {
+	if width < MIN_IMAGE_SIZE || height < MIN_IMAGE_SIZE {
+		return Err(ImageTooSmallError { width, height });
+	}
+	Ok(())
+}
+
+/// Repeat the only row or column of an image that's 1 pixel tall or wide, so it makes a lithophane shaped like a thin bar instead of an
+/// empty model
+/// Images that are already big enough are returned unchanged, and empty images can't be made into anything, so they're an error.
+pub fn extend_to_strip(image: GrayImage) -> Result<GrayImage, ImageTooSmallError> {
+	let (width, height) = image.dimensions();
+	if width == 0 || height == 0 {
+		return Err(ImageTooSmallError { width, height });
+	}
+	if width >= MIN_IMAGE_SIZE && height >= MIN_IMAGE_SIZE {
+		return Ok(image);
+	}
+	Ok(GrayImage::from_fn(width.max(MIN_IMAGE_SIZE), height.max(MIN_IMAGE_SIZE), |x, y| {
+		*image.get_pixel(x.min(width - 1), y.min(height - 1))
+	}))
+}
+
+/// Receives updates on how far along generating a lithophane or preview is, eg to show a progress bar
+pub trait ProgressSink {
+	/// Called every so often with the fraction of the work that's done, going from 0 to 1
+	fn progress(&self, fraction: f32);
+}
+
+impl<F: Fn(f32)> ProgressSink for F {
+	fn progress(&self, fraction: f32) {
+		self(fraction)
+	}
+}
+
+/// Lets a lithophane or preview stop being generated part way through, eg when the settings in a GUI have changed or a server's client
+/// has gone away
+pub trait CancelToken {
+	/// Checked every so often while generating, which stops with `GenerateError::Cancelled` once it's true
+	fn is_cancelled(&self) -> bool;
+}
+
+impl CancelToken for AtomicBool {
+	fn is_cancelled(&self) -> bool {
+		self.load(atomic::Ordering::Relaxed)
+	}
+}
+
+/// Why a lithophane or preview couldn't be generated
+#[derive(Error, Debug)]
+pub enum LithophaneError {
+	#[error(transparent)]
+	InvalidPoints(#[from] InvalidPointsError),
+	#[error(transparent)]
+	ImageTooSmall(#[from] ImageTooSmallError),
+	#[error("the layer height has to be a positive number of millimeters, not {0}")]
+	InvalidLayerHeight(f32),
+	#[error("the channels have to be the same size, but they're {0:?} pixels")]
+	MismatchedChannels([(u32, u32); 3]),
+}
+
+#[derive(Error, Debug)]
+pub enum GenerateError {
+	#[error(transparent)]
+	Lithophane(#[from] LithophaneError),
+	#[error("generating was cancelled")]
+	Cancelled,
+}
+
+impl From<InvalidPointsError> for GenerateError {
+	fn from(error: InvalidPointsError) -> Self {
+		GenerateError::Lithophane(error.into())
+	}
+}
+
+impl From<ImageTooSmallError> for GenerateError {
+	fn from(error: ImageTooSmallError) -> Self {
+		GenerateError::Lithophane(error.into())
+	}
+}
+
+/// The progress and cancellation hooks for one generation, along with the part of the whole progress (from 0 to 1) the current step covers
+#[derive(Clone, Copy)]
+struct Hooks<'a> {
+	progress: Option<&'a dyn ProgressSink>,
+	cancel: Option<&'a dyn CancelToken>,
+	start: f32,
+	end: f32,
+}
+
+impl<'a> Hooks<'a> {
+	const NONE: Hooks<'static> = Hooks {
+		progress: None,
+		cancel: None,
+		start: 0.0,
+		end: 1.0,
+	};
+
+	fn new(progress: &'a dyn ProgressSink, cancel: &'a dyn CancelToken) -> Self {
+		Self {
+			progress: Some(progress),
+			cancel: Some(cancel),
+			..Hooks::NONE
+		}
+	}
+
+	/// The hooks for a step covering this part (from 0 to 1) of the current step
+	fn part(&self, start: f32, end: f32) -> Self {
+		let length = self.end - self.start;
+		Self {
+			start: self.start + start * length,
+			end: self.start + end * length,
+			..*self
+		}
+	}
+
+	/// Report that this fraction of the current step is done, and stop if it's been cancelled
+	fn check(&self, fraction: f32) -> Result<(), GenerateError> {
+		if self.cancel.is_some_and(|cancel| cancel.is_cancelled()) {
+			return Err(GenerateError::Cancelled);
+		}
+		if let Some(progress) = self.progress {
+			progress.progress(self.start + fraction * (self.end - self.start));
+		}
+		Ok(())
+	}
+}
+
+/// Unwrap the error from a generator that was given `Hooks::NONE`, which can't be cancelled
+fn uncancelled(error: GenerateError) -> LithophaneError {
+	match error {
+		GenerateError::Lithophane(e) => e,
+		GenerateError::Cancelled => unreachable!("generating can't be cancelled without a cancel token"),
+	}
+}
+
 /// Create a lithophane using three functions to translate x and y coordinates from an image into x,y,z coordinates for a mesh
+/// Images 1 pixel tall or wide make a thin bar (see `extend_to_strip`), and empty images are an error.
 pub fn generate_lithophane<F: Fn(f32, f32, f32, f32) -> f32>(
 	x_fn: F,
 	y_fn: F,
 	z_fn: F,
 	image: GrayImage,
-	white_depth: f32,
-	black_depth: f32,
-) -> Result<StlModel, InvalidPointsError> {
-	let point_cloud = generate_point_cloud(x_fn, y_fn, z_fn, image.width(), image.height(), 1)?;
-	let mesh = generate_lithophane_mesh(point_cloud, image, white_depth, black_depth)?;
-	Ok(StlModel {
-		header: String::new(),
-		triangles: mesh,
+	options: &LithophaneOptions,
+) -> Result<StlModel, LithophaneError> {
+	Ok(generate_lithophane_and_maybe_preview(x_fn, y_fn, z_fn, image, options, None, Hooks::NONE).map_err(uncancelled)?.0)
+}
+
+/// Create a lithophane like `generate_lithophane`, reporting how far along it is to `progress` and stopping early if `cancel` is cancelled
+pub fn generate_lithophane_with_hooks<F: Fn(f32, f32, f32, f32) -> f32>(
+	x_fn: F,
+	y_fn: F,
+	z_fn: F,
+	image: GrayImage,
+	options: &LithophaneOptions,
+	progress: &dyn ProgressSink,
+	cancel: &dyn CancelToken,
+) -> Result<StlModel, GenerateError> {
+	Ok(generate_lithophane_and_maybe_preview(x_fn, y_fn, z_fn, image, options, None, Hooks::new(progress, cancel))?.0)
+}
+
+/// Create a lithophane and find how thick it ended up, after every option that changes the thickness has been applied
+///
+/// The stats are `None` when nothing is left inside the outline.
+pub fn generate_lithophane_with_thickness_stats<F: Fn(f32, f32, f32, f32) -> f32>(
+	x_fn: F,
+	y_fn: F,
+	z_fn: F,
+	image: GrayImage,
+	options: &LithophaneOptions,
+) -> Result<(StlModel, Option<ThicknessStats>), LithophaneError> {
+	let (lithophane, _, thicknesses) =
+		generate_lithophane_and_maybe_preview(x_fn, y_fn, z_fn, image, options, None, Hooks::NONE).map_err(uncancelled)?;
+	Ok((lithophane, ThicknessStats::new(thicknesses, THICKNESS_HISTOGRAM_BINS)))
+}
+
+/// Create a lithophane and the same preview `generate_preview` would create with `step`, while only evaluating the expressions once
+pub fn generate_lithophane_with_preview<F: Fn(f32, f32, f32, f32) -> f32>(
+	x_fn: F,
+	y_fn: F,
+	z_fn: F,
+	image: GrayImage,
+	options: &LithophaneOptions,
+	step: u32,
+) -> Result<(StlModel, StlModel), LithophaneError> {
+	let (lithophane, preview, _) =
+		generate_lithophane_and_maybe_preview(x_fn, y_fn, z_fn, image, options, Some(step), Hooks::NONE).map_err(uncancelled)?;
+	Ok((lithophane, preview.expect("a preview is always generated when there's a step")))
+}
+
+fn generate_lithophane_and_maybe_preview<F: Fn(f32, f32, f32, f32) -> f32>(
+	x_fn: F,
+	y_fn: F,
+	z_fn: F,
+	image: GrayImage,
+	options: &LithophaneOptions,
+	preview_step: Option<u32>,
+	hooks: Hooks,
+) -> Result<(StlModel, Option<StlModel>, Vec<f32>), GenerateError> {
+	let image = extend_to_strip(image)?;
+	if let Some(layer_height) = options.layer_height.filter(|&h| !(h > 0.0 && h.is_finite())) {
+		return Err(LithophaneError::InvalidLayerHeight(layer_height).into());
+	}
+	let tiling = options.tiling.unwrap_or_default();
+	let (tiled_width, tiled_height) = tiling.size(image.width(), image.height());
+	let width_f32 = tiled_width as f32;
+	let height_f32 = tiled_height as f32;
+
+	// The skirt is made of extra rows of pixels below the image, which get their depth from the skirt instead
+	let skirt_rows = skirt_rows(options.skirt);
+	let pixel = |x: u32, y: u32| -> u8 {
+		if y >= tiled_height {
+			return 0;
+		}
+		let (x, y) = tiling.source_pixel(x, y, image.width(), image.height());
+		image.get_pixel(x, y).0[0]
+	};
+
+	// When sampling or subdividing, the point cloud is generated for the resampled image, but the expressions still get the original image
+	// coordinates. Samples are spread evenly over the image, so they're a little less than `sample_step` pixels apart when it doesn't divide
+	// the image's size, and there's always one on the last pixel.
+	let (subdivisions, sample_step) = (options.subdivisions.max(1), options.sample_step.max(1));
+	fn resampled<F: Fn(f32, f32, f32, f32) -> f32>(f: &F, scale: (f32, f32), width: f32, height: f32) -> impl Fn(f32, f32, f32, f32) -> f32 + '_ {
+		move |x: f32, y: f32, _: f32, _: f32| -> f32 { f(x / scale.0, y / scale.1, width, height) }
+	}
+	let (width, height) = (tiled_width, tiled_height + skirt_rows);
+	let ((samples_x, spacing_x), (samples_y, spacing_y)) = (samples(width, sample_step), samples(height, sample_step));
+	let sampled_pixel = |x: u32, y: u32| -> u8 {
+		if sample_step == 1 {
+			return pixel(x, y);
+		}
+		// Average the pixels closer to this sample than to any other, without mixing the bottom of the image into the skirt
+		let (center_x, center_y) = (x as f32 * spacing_x, y as f32 * spacing_y);
+		if center_y.round() as u32 >= tiled_height {
+			return 0;
+		}
+		let window = |center: f32, spacing: f32, length: u32| {
+			let start = (center - spacing / 2.0).ceil().max(0.0) as u32;
+			start..((center + spacing / 2.0).ceil() as u32).clamp(start + 1, length)
+		};
+		let (columns, rows) = (window(center_x, spacing_x, width), window(center_y, spacing_y, tiled_height));
+		let count = columns.len() * rows.len();
+		let total = rows.flat_map(|y| columns.clone().map(move |x| (x, y))).map(|(x, y)| pixel(x, y) as usize).sum::<usize>();
+		((total + count / 2) / count) as u8
+	};
+	let gray_value = |x: u32, y: u32| -> u8 {
+		if subdivisions > 1 {
+			subdivided_pixel(sampled_pixel, samples_x, samples_y, subdivisions, x, y)
+		} else {
+			sampled_pixel(x, y)
+		}
+	};
+	let scale = (subdivisions as f32 / spacing_x, subdivisions as f32 / spacing_y);
+	let point_cloud = generate_point_cloud(
+		resampled(&x_fn, scale, width_f32, height_f32),
+		resampled(&y_fn, scale, width_f32, height_f32),
+		resampled(&z_fn, scale, width_f32, height_f32),
+		(samples_x - 1) * subdivisions + 1,
+		(samples_y - 1) * subdivisions + 1,
+		1,
+		1,
+		options.normal_averaging,
+		hooks.part(0.0, 0.5),
+	)?;
+
+	let position = |x: f32, y: f32| Vec3 {
+		x: (x_fn)(x, y, width_f32, height_f32),
+		y: (y_fn)(x, y, width_f32, height_f32),
+		z: (z_fn)(x, y, width_f32, height_f32),
+	};
+
+	// The preview is made from every step-th point, which are the same points generate_preview would evaluate the expressions for. When
+	// sampling, the lithophane's points aren't on whole pixels, so the preview's points are evaluated separately.
+	let preview = match preview_step {
+		Some(step) => {
+			let preview_cloud = if sample_step == 1 {
+				point_cloud.subsampled(step.max(1) * subdivisions)
+			} else {
+				generate_point_cloud(
+					resampled(&x_fn, (1.0, 1.0), width_f32, height_f32),
+					resampled(&y_fn, (1.0, 1.0), width_f32, height_f32),
+					resampled(&z_fn, (1.0, 1.0), width_f32, height_f32),
+					width,
+					height,
+					step.max(1),
+					step.max(1),
+					NormalAveraging::default(),
+					Hooks::NONE,
+				)?
+			};
+			let triangles = preview_triangle_indices(&preview_cloud)
+				.map(|[a, b, c]| three_points_to_triangle([preview_cloud.vertices[a], preview_cloud.vertices[b], preview_cloud.vertices[c]]))
+				.collect::<Result<Vec<_>, _>>()?;
+			Some(StlModel {
+				header: String::new(),
+				triangles,
+			})
+		},
+		None => None,
+	};
+
+	let (mesh, thicknesses) = generate_lithophane_mesh(point_cloud, gray_value, options, scale, position, hooks.part(0.5, 1.0))?;
+	hooks.check(1.0)?;
+	Ok((
+		StlModel {
+			header: options.header.clone(),
+			triangles: mesh,
+		},
+		preview,
+		thicknesses,
+	))
+}
+
+/// The gray value of a pixel of an image enlarged so there are `subdivisions` pixels for every original pixel along each axis, interpolating
+/// bilinearly between them
+/// The original pixels are kept at every `subdivisions`th pixel, so the image is (width - 1) * subdivisions + 1 pixels wide, and similarly tall
+fn subdivided_pixel(pixel: impl Fn(u32, u32) -> u8, width: u32, height: u32, subdivisions: u32, x: u32, y: u32) -> u8 {
+	let (x0, y0) = ((x / subdivisions).min(width - 2), (y / subdivisions).min(height - 2));
+	let tx = (x - x0 * subdivisions) as f32 / subdivisions as f32;
+	let ty = (y - y0 * subdivisions) as f32 / subdivisions as f32;
+	let value = |x: u32, y: u32| pixel(x, y) as f32;
+	let top = value(x0, y0) * (1.0 - tx) + value(x0 + 1, y0) * tx;
+	let bottom = value(x0, y0 + 1) * (1.0 - tx) + value(x0 + 1, y0 + 1) * tx;
+	(top * (1.0 - ty) + bottom * ty).round() as u8
+}
+
+/// The number of vertices along one side of a point cloud, when stepping through `length` pixels `step` at a time and always including
+/// the last pixel
+fn sample_count(length: u32, step: u32) -> usize {
+	(length.saturating_sub(1).div_ceil(step.max(1)) + 1) as usize
+}
+
+/// The number of samples along an axis `length` pixels long, and how many pixels apart they are
+fn samples(length: u32, step: u32) -> (u32, f32) {
+	let samples = sample_count(length, step) as u32;
+	(samples, if samples > 1 { (length - 1) as f32 / (samples - 1) as f32 } else { 1.0 })
+}
+
+/// The number of rows of pixels added below the image for a skirt
+fn skirt_rows(skirt: Option<Skirt>) -> u32 {
+	match skirt {
+		Some(skirt) if skirt.height > 0.0 => skirt.height.ceil() as u32,
+		_ => 0,
+	}
+}
+
+/// The image coordinates of the vertices on the front of a lithophane of an image this size, row by row from the top, eg to draw the grid
+/// over the photo and show what resolution it will really be printed at
+///
+/// This takes the sample step, subdivisions and tiling of the options into account, and leaves out vertices outside of the outline or in
+/// the skirt below the image. Empty images don't have any vertices.
+pub fn sampling_grid(image_width: u32, image_height: u32, options: &LithophaneOptions) -> Vec<(f32, f32)> {
+	let (width, height) = options.tiling.unwrap_or_default().size(image_width, image_height);
+	if width == 0 || height == 0 {
+		return Vec::new();
+	}
+	let (subdivisions, sample_step) = (options.subdivisions.max(1), options.sample_step.max(1));
+	let ((samples_x, spacing_x), (samples_y, spacing_y)) = (samples(width, sample_step), samples(height + skirt_rows(options.skirt), sample_step));
+	let positions = |samples: u32, spacing: f32| (0..(samples - 1) * subdivisions + 1).map(move |i| i as f32 * spacing / subdivisions as f32);
+	positions(samples_y, spacing_y)
+		.take_while(|&y| y <= (height - 1) as f32)
+		.flat_map(|y| positions(samples_x, spacing_x).map(move |x| (x, y)))
+		.filter(|&(x, y)| options.outline.contains(x, y))
+		.collect()
+}
+
+/// Predict the number of triangles `generate_preview` will generate, without generating anything
+pub fn preview_triangle_count(width: u32, height: u32, step: u32) -> usize {
+	(sample_count(width, step) - 1) * (sample_count(height, step) - 1) * 2
+}
+
+/// Predict the number of triangles `generate_lithophane` will generate for an image without an outline, without generating anything
+/// Outlines can only remove triangles, so this is an upper bound when there is one (except for some small holes, which add walls).
+pub fn lithophane_triangle_count(width: u32, height: u32, subdivisions: u32, sample_step: u32) -> usize {
+	let (subdivisions, sample_step) = (subdivisions.max(1) as usize, sample_step.max(1));
+	let width = (sample_count(width, sample_step) - 1) * subdivisions + 1;
+	let height = (sample_count(height, sample_step) - 1) * subdivisions + 1;
+	(width - 1) * (height - 1) * 4 + 4 * (width - 1) + 4 * (height - 1)
+}
+
+/// The smallest step (but at least `min_step`) that keeps a preview of an image at or under `max_triangles` triangles
+/// Tiny budgets can't always be met, since the preview always has at least 2 triangles.
+pub fn preview_step_for_budget(width: u32, height: u32, min_step: u32, max_triangles: usize) -> u32 {
+	let mut step = min_step.max(1);
+	while preview_triangle_count(width, height, step) > max_triangles && step < width.max(height) {
+		step += 1;
+	}
+	step
+}
+
+/// How densely `generate_preview` samples the image
+///
+/// The steps can be different for each axis, so a wide panorama doesn't have to be over-sampled vertically to get enough samples
+/// horizontally (or the other way around).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PreviewSampling {
+	/// How many pixels apart the samples are horizontally
+	pub step_x: u32,
+	/// How many pixels apart the samples are vertically
+	pub step_y: u32,
+	/// If set, both steps are increased, keeping about the same ratio between them, until there are at most this many samples
+	/// This makes the amount of work independent of the image size, eg for benchmarks.
+	pub max_samples: Option<usize>,
+}
+
+impl PreviewSampling {
+	/// Sample every `step` pixels in both directions
+	pub fn uniform(step: u32) -> Self {
+		Self {
+			step_x: step,
+			step_y: step,
+			max_samples: None,
+		}
+	}
+
+	/// The steps to use for an image, after applying `max_samples`
+	/// Tiny limits can't always be met, since there are always at least 2 samples in each direction.
+	pub fn steps(&self, width: u32, height: u32) -> (u32, u32) {
+		self.steps_within(width, height, None)
+	}
+
+	/// The steps to use for an image, after applying `max_samples` and keeping the preview at or under `max_triangles` triangles
+	fn steps_within(&self, width: u32, height: u32, max_triangles: Option<usize>) -> (u32, u32) {
+		let (base_x, base_y) = (self.step_x.max(1), self.step_y.max(1));
+		let (mut step_x, mut step_y) = (base_x, base_y);
+		let too_many = |step_x: u32, step_y: u32| {
+			let (samples_x, samples_y) = (sample_count(width, step_x), sample_count(height, step_y));
+			self.max_samples.is_some_and(|max_samples| samples_x * samples_y > max_samples)
+				|| max_triangles.is_some_and(|max_triangles| (samples_x - 1) * (samples_y - 1) * 2 > max_triangles)
+		};
+		while too_many(step_x, step_y) {
+			let (x_done, y_done) = (step_x >= width, step_y >= height);
+			if x_done && y_done {
+				break;
+			}
+			// Grow whichever step has grown the least compared to where it started, so the ratio between them stays about the same
+			if y_done || (!x_done && step_x as u64 * base_y as u64 <= step_y as u64 * base_x as u64) {
+				step_x += 1;
+			} else {
+				step_y += 1;
+			}
+		}
+		(step_x, step_y)
+	}
+}
+
+/// Create a flat preview mesh using three functions to translate x and y coordinates from an image into x,y,z coordinates for the mesh
+/// `sampling` sets how many pixels apart the vertices are, generating a lower resolution preview in a shorter amount of time
+/// If `max_triangles` is set, the steps are increased as much as needed to stay under that many triangles.
+pub fn generate_preview<F: Fn(f32, f32, f32, f32) -> f32>(
+	x_fn: F,
+	y_fn: F,
+	z_fn: F,
+	width: u32,
+	height: u32,
+	sampling: PreviewSampling,
+	max_triangles: Option<usize>,
+) -> Result<StlModel, LithophaneError> {
+	generate_preview_and_check_hooks(x_fn, y_fn, z_fn, width, height, sampling, max_triangles, Hooks::NONE).map_err(uncancelled)
+}
+
+/// Create a preview like `generate_preview`, reporting how far along it is to `progress` and stopping early if `cancel` is cancelled
+#[allow(clippy::too_many_arguments)]
+pub fn generate_preview_with_hooks<F: Fn(f32, f32, f32, f32) -> f32>(
+	x_fn: F,
+	y_fn: F,
+	z_fn: F,
+	width: u32,
+	height: u32,
+	sampling: PreviewSampling,
+	max_triangles: Option<usize>,
+	progress: &dyn ProgressSink,
+	cancel: &dyn CancelToken,
+) -> Result<StlModel, GenerateError> {
+	generate_preview_and_check_hooks(x_fn, y_fn, z_fn, width, height, sampling, max_triangles, Hooks::new(progress, cancel))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn generate_preview_and_check_hooks<F: Fn(f32, f32, f32, f32) -> f32>(
+	x_fn: F,
+	y_fn: F,
+	z_fn: F,
+	width: u32,
+	height: u32,
+	sampling: PreviewSampling,
+	max_triangles: Option<usize>,
+	hooks: Hooks,
+) -> Result<StlModel, GenerateError> {
+	let (step_x, step_y) = sampling.steps_within(width, height, max_triangles);
+	// Evaluating the expressions is most of the work, so turning the points into triangles is left as the last tenth
+	let point_cloud = generate_point_cloud(
+		x_fn,
+		y_fn,
+		z_fn,
+		width,
+		height,
+		step_x,
+		step_y,
+		NormalAveraging::default(),
+		hooks.part(0.0, 0.9),
+	)?;
+	hooks.part(0.9, 1.0).check(0.0)?;
+
+	let triangles = preview_triangle_indices(&point_cloud)
+		.map(|[a, b, c]| three_points_to_triangle([point_cloud.vertices[a], point_cloud.vertices[b], point_cloud.vertices[c]]))
+		.collect::<Result<Vec<_>, _>>()?;
+	hooks.check(1.0)?;
+
+	Ok(StlModel {
+		header: String::new(),
+		triangles,
+	})
+}
+
+/// The most triangles the first preview from `generate_progressive_preview` can have, so it can be shown almost right away
+const FIRST_PROGRESSIVE_PREVIEW_TRIANGLES: usize = 2000;
+
+/// Generate previews from coarse to fine, ending with a preview stepping by `step`, so the surface can be shown taking shape instead of
+/// only appearing once the detailed preview is done
+///
+/// `on_preview` is called with each preview and the step it was generated with, and can return false to stop early (eg when the
+/// expressions have changed). The first preview has at most about 2000 triangles, and the step is halved for each one after that.
+pub fn generate_progressive_preview<F: Fn(f32, f32, f32, f32) -> f32>(
+	x_fn: F,
+	y_fn: F,
+	z_fn: F,
+	width: u32,
+	height: u32,
+	step: u32,
+	mut on_preview: impl FnMut(StlModel, u32) -> bool,
+) -> Result<(), LithophaneError> {
+	let mut steps = vec![step.max(1)];
+	while let Some(&last) = steps.last().filter(|&&last| last < width.max(height)) {
+		if preview_triangle_count(width, height, last) <= FIRST_PROGRESSIVE_PREVIEW_TRIANGLES {
+			break;
+		}
+		steps.push(last * 2);
+	}
+
+	for &step in steps.iter().rev() {
+		let preview = generate_preview(&x_fn, &y_fn, &z_fn, width, height, PreviewSampling::uniform(step), None)?;
+		if !on_preview(preview, step) {
+			break;
+		}
+	}
+	Ok(())
+}
+
+/// Create a preview of just part of the image, eg to show one area (like a face) in more detail on top of a coarse preview of the whole image
+///
+/// `region` is in pixels and is clamped to the image. The expressions still get the coordinates and size of the whole image, so the patch
+/// lines up with the full preview; if the region's edges are multiples of the coarse preview's step, they follow its grid lines exactly,
+/// so the patch can replace the coarse triangles in that area. A step of 0 is treated as 1.
+pub fn generate_preview_region<F: Fn(f32, f32, f32, f32) -> f32>(
+	x_fn: F,
+	y_fn: F,
+	z_fn: F,
+	width: u32,
+	height: u32,
+	region: Rect,
+	step: u32,
+) -> Result<StlModel, LithophaneError> {
+	check_image_size(width, height)?;
+	let left = region.x.min(width - 1);
+	let top = region.y.min(height - 1);
+	let region_width = region.width.max(2).min(width - left);
+	let region_height = region.height.max(2).min(height - top);
+
+	let point_cloud = generate_point_cloud(
+		offset(&x_fn, left, top, width, height),
+		offset(&y_fn, left, top, width, height),
+		offset(&z_fn, left, top, width, height),
+		region_width,
+		region_height,
+		step.max(1),
+		step.max(1),
+		NormalAveraging::default(),
+		Hooks::NONE,
+	)
+	.map_err(uncancelled)?;
+
+	let triangles = preview_triangle_indices(&point_cloud)
+		.map(|[a, b, c]| three_points_to_triangle([point_cloud.vertices[a], point_cloud.vertices[b], point_cloud.vertices[c]]))
+		.collect::<Result<Vec<_>, _>>()?;
+
+	Ok(StlModel {
+		header: String::new(),
+		triangles,
+	})
+}
+
+/// Evaluate an expression function for part of an image as if it was the whole `width` × `height` image, where `left` and `top` are
+/// the position of the part
+fn offset<F: Fn(f32, f32, f32, f32) -> f32>(f: &F, left: u32, top: u32, width: u32, height: u32) -> impl Fn(f32, f32, f32, f32) -> f32 + '_ {
+	move |x: f32, y: f32, _: f32, _: f32| -> f32 { f(x + left as f32, y + top as f32, width as f32, height as f32) }
+}
+
+/// Create three lithophanes from the red, green and blue channels of an image, in interleaved vertical stripes (experimental)
+///
+/// Each lithophane only covers every third stripe, starting with the first, second and third stripe, so they fit together into one
+/// panel that can be printed with a different translucent filament for each channel, eg for a tri-color LED backlight. The stripes are
+/// `stripe_width` pixels wide and share their edge columns with their neighbors. The outline, frame fit and label aren't used, since
+/// they're meant for the whole image rather than separate stripes.
+/// The channels have to be the same size, and ones 1 pixel wide or tall are extended to a strip (see `extend_to_strip`).
+pub fn generate_rgb_stripes<F: Fn(f32, f32, f32, f32) -> f32>(
+	x_fn: F,
+	y_fn: F,
+	z_fn: F,
+	channels: [GrayImage; 3],
+	stripe_width: u32,
+	options: &LithophaneOptions,
+) -> Result<[StlModel; 3], LithophaneError> {
+	let sizes = channels.each_ref().map(|channel| channel.dimensions());
+	if sizes[1] != sizes[0] || sizes[2] != sizes[0] {
+		return Err(LithophaneError::MismatchedChannels(sizes));
+	}
+	let [red, green, blue] = channels.map(extend_to_strip);
+	let channels = [red?, green?, blue?];
+	let (width, height) = channels[0].dimensions();
+	let stripe_width = stripe_width.max(1);
+	let options = LithophaneOptions {
+		outline: Outline::default(),
+		frame_fit: None,
+		label: None,
+		tiling: None,
+		..options.clone()
+	};
+
+	let mut models = [0, 1, 2].map(|_| StlModel {
+		header: options.header.clone(),
+		triangles: Vec::new(),
+	});
+	for (stripe, left) in (0..width - 1).step_by(stripe_width as usize).enumerate() {
+		let stripe_right = (left + stripe_width).min(width - 1);
+		let channel = stripe % 3;
+		let image = imageops::crop_imm(&channels[channel], left, 0, stripe_right - left + 1, height).to_image();
+		let model = generate_lithophane(
+			offset(&x_fn, left, 0, width, height),
+			offset(&y_fn, left, 0, width, height),
+			offset(&z_fn, left, 0, width, height),
+			image,
+			&options,
+		)?;
+		models[channel].triangles.extend(model.triangles);
+	}
+	Ok(models)
+}
+
+/// Split a lithophane into a base `base_depth` thick and the relief on top of it, to print with two filaments, eg a colored base and a
+/// white relief
+///
+/// The parts are generated with `Part::Base` and `Part::Relief`, so `options.part` is ignored. Where the lithophane is thinner than the
+/// base, there's only the base.
+pub fn generate_base_and_relief<F: Fn(f32, f32, f32, f32) -> f32>(
+	x_fn: F,
+	y_fn: F,
+	z_fn: F,
+	image: GrayImage,
+	base_depth: f32,
+	options: &LithophaneOptions,
+) -> Result<[StlModel; 2], LithophaneError> {
+	let base = LithophaneOptions {
+		part: Some(Part::Base { depth: base_depth }),
+		..options.clone()
+	};
+	let relief = LithophaneOptions {
+		part: Some(Part::Relief { base_depth }),
+		..options.clone()
+	};
+	Ok([
+		generate_lithophane(&x_fn, &y_fn, &z_fn, image.clone(), &base)?,
+		generate_lithophane(&x_fn, &y_fn, &z_fn, image, &relief)?,
+	])
+}
+
+/// Cut a lithophane into interlocking jigsaw pieces, row by row from the top left, each of which is a closed solid
+///
+/// Each piece is generated from the whole image with `options.outline.boundary` replaced by the piece's shape, so everything else (eg the
+/// frame fit, label and holes) stays where it would be on the whole lithophane. That means the expressions are evaluated for the whole
+/// image once for every piece. Each piece's entry in `puzzle.thickness_offsets` is added to `options.thickness_offset`.
+pub fn generate_puzzle_pieces<F: Fn(f32, f32, f32, f32) -> f32>(
+	x_fn: F,
+	y_fn: F,
+	z_fn: F,
+	image: GrayImage,
+	puzzle: &Puzzle,
+	options: &LithophaneOptions,
+) -> Result<Vec<StlModel>, LithophaneError> {
+	let (width, height) = options.tiling.unwrap_or_default().size(image.width(), image.height());
+	puzzle
+		.pieces(width, height)
+		.into_iter()
+		.enumerate()
+		.map(|(i, piece)| {
+			let options = LithophaneOptions {
+				outline: Outline {
+					boundary: Some(piece),
+					..options.outline.clone()
+				},
+				thickness_offset: options.thickness_offset + puzzle.thickness_offsets.get(i).copied().unwrap_or(0.0),
+				..options.clone()
+			};
+			generate_lithophane(&x_fn, &y_fn, &z_fn, image.clone(), &options)
+		})
+		.collect()
+}
+
+/// The average distance between neighbouring pixels on the surface the expressions describe, sampled at a grid of points across the image
+pub fn pixel_size<F: Fn(f32, f32, f32, f32) -> f32>(x_fn: F, y_fn: F, z_fn: F, width: u32, height: u32) -> f32 {
+	const SAMPLES: u32 = 5;
+	let (width_f32, height_f32) = (width as f32, height as f32);
+	let position = |x: f32, y: f32| Vec3 {
+		x: x_fn(x, y, width_f32, height_f32),
+		y: y_fn(x, y, width_f32, height_f32),
+		z: z_fn(x, y, width_f32, height_f32),
+	};
+	let length = |v: Vec3| (v.x * v.x + v.y * v.y + v.z * v.z).sqrt();
+
+	let mut total = 0.0;
+	for i in 0..SAMPLES {
+		for j in 0..SAMPLES {
+			let x = (width.saturating_sub(2) * i / (SAMPLES - 1)) as f32;
+			let y = (height.saturating_sub(2) * j / (SAMPLES - 1)) as f32;
+			let here = position(x, y);
+			total += (length(position(x + 1.0, y) - here) + length(position(x, y + 1.0) - here)) / 2.0;
+		}
+	}
+	total / (SAMPLES * SAMPLES) as f32
+}
+
+/// The size of the box around the surface the expressions describe, sampled at up to 256 points along each axis (always including the
+/// edges), eg to tell how big a lithophane will be before generating it
+///
+/// The thickness of the lithophane isn't included, so flat lithophanes have no height.
+pub fn surface_size<F: Fn(f32, f32, f32, f32) -> f32>(x_fn: F, y_fn: F, z_fn: F, width: u32, height: u32) -> Vec3 {
+	const SAMPLES: u32 = 256;
+	let (width_f32, height_f32) = (width as f32, height as f32);
+	let positions = |length: u32| {
+		let count = SAMPLES.min(length).max(2);
+		(0..count).map(move |i| (length - 1) as f32 * i as f32 / (count - 1) as f32)
+	};
+
+	let mut min = Vec3::new([f32::INFINITY; 3]);
+	let mut max = Vec3::new([f32::NEG_INFINITY; 3]);
+	for y in positions(height) {
+		for x in positions(width) {
+			let point = [
+				x_fn(x, y, width_f32, height_f32),
+				y_fn(x, y, width_f32, height_f32),
+				z_fn(x, y, width_f32, height_f32),
+			];
+			min = Vec3::new([min.x.min(point[0]), min.y.min(point[1]), min.z.min(point[2])]);
+			max = Vec3::new([max.x.max(point[0]), max.y.max(point[1]), max.z.max(point[2])]);
+		}
+	}
+	max - min
+}
+
+/// The most rows and columns `arc_length_resample` measures distances along
+const ARC_LENGTH_LINES: u32 = 64;
+
+/// Resample an image so its pixels are spread out evenly by distance along the surface the expressions describe, instead of evenly in x
+/// and y, so the image doesn't look stretched where the surface is steep (eg near the sides of a half cylinder)
+///
+/// The distances are averaged over several rows and columns, so columns of the image stay columns and rows stay rows. The image keeps its
+/// size and the mesh doesn't change, only which part of the image ends up where.
+pub fn arc_length_resample<F: Fn(f32, f32, f32, f32) -> f32>(image: &GrayImage, x_fn: F, y_fn: F, z_fn: F) -> GrayImage {
+	let (width, height) = image.dimensions();
+	let (width_f32, height_f32) = (width as f32, height as f32);
+	let point = |x: f32, y: f32| Vec3 {
+		x: x_fn(x, y, width_f32, height_f32),
+		y: y_fn(x, y, width_f32, height_f32),
+		z: z_fn(x, y, width_f32, height_f32),
+	};
+	let distance = |a: Vec3, b: Vec3| {
+		let d = a - b;
+		(d.x * d.x + d.y * d.y + d.z * d.z).sqrt()
+	};
+
+	// Where each column (or row) of the new image comes from in the old one, from how far along the surface it is
+	let positions = |length: u32, across: u32, step: &dyn Fn(f32, f32) -> f32| -> Vec<f32> {
+		let lines = ARC_LENGTH_LINES.min(across).max(1);
+		let line = |i: u32| {
+			if lines > 1 {
+				(across - 1) as f32 * i as f32 / (lines - 1) as f32
+			} else {
+				0.0
+			}
+		};
+		let mut along = vec![0.0f32; length as usize];
+		for i in 1..length as usize {
+			along[i] = along[i - 1] + (0..lines).map(|l| step(i as f32, line(l))).sum::<f32>() / lines as f32;
+		}
+		let total = along.last().copied().unwrap_or(0.0);
+		if total.is_finite() && total > 0.0 {
+			along.iter().map(|a| a / total * (length - 1) as f32).collect()
+		} else {
+			(0..length).map(|i| i as f32).collect()
+		}
+	};
+	let columns = positions(width, height, &|x, y| distance(point(x, y), point(x - 1.0, y)));
+	let rows = positions(height, width, &|y, x| distance(point(x, y), point(x, y - 1.0)));
+
+	GrayImage::from_fn(width, height, |x, y| {
+		Luma([sample_bilinear(image, columns[x as usize], rows[y as usize])])
 	})
 }
 
-/// Create a flat preview mesh using three functions to translate x and y coordinates from an image into x,y,z coordinates for the mesh
-/// The step argument allows stepping by that many vertices at a time, generating a lower resolution preview in a shorter amount of time
-pub fn generate_preview<F: Fn(f32, f32, f32, f32) -> f32>(
+/// A part of the image where the surface stretches the pixels much more in one direction than the other, which shows up as banding
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StretchedRegion {
+	/// The bounding box of the stretched pixels
+	pub x: u32,
+	pub y: u32,
+	pub width: u32,
+	pub height: u32,
+	/// The fraction of the image that's stretched
+	pub fraction: f32,
+	/// How many times longer the longer side of the most stretched pixel is than its shorter side, up to `MAX_STRETCH`
+	pub ratio: f32,
+}
+
+/// The largest ratio `stretched_region` reports, since a pixel whose shorter side has no length at all (eg at the pole of a sphere) is
+/// infinitely stretched
+pub const MAX_STRETCH: f32 = 100.0;
+
+/// Find where the surface the expressions describe makes pixels more than `min_ratio` times longer in one direction than the other,
+/// sampled at a grid of points across the image
+pub fn stretched_region<F: Fn(f32, f32, f32, f32) -> f32>(
+	x_fn: F,
+	y_fn: F,
+	z_fn: F,
+	width: u32,
+	height: u32,
+	min_ratio: f32,
+) -> Option<StretchedRegion> {
+	const SAMPLES: u32 = 32;
+	let (width_f32, height_f32) = (width as f32, height as f32);
+	let position = |x: f32, y: f32| Vec3 {
+		x: x_fn(x, y, width_f32, height_f32),
+		y: y_fn(x, y, width_f32, height_f32),
+		z: z_fn(x, y, width_f32, height_f32),
+	};
+	let length = |v: Vec3| (v.x * v.x + v.y * v.y + v.z * v.z).sqrt();
+
+	let (columns, rows) = (SAMPLES.min(width.saturating_sub(1)).max(1), SAMPLES.min(height.saturating_sub(1)).max(1));
+	let (mut left, mut top, mut right, mut bottom) = (u32::MAX, u32::MAX, 0, 0);
+	let (mut stretched, mut max_ratio) = (0, 0.0f32);
+	for j in 0..rows {
+		for i in 0..columns {
+			let x = width.saturating_sub(2) * i / (columns - 1).max(1);
+			let y = height.saturating_sub(2) * j / (rows - 1).max(1);
+			let here = position(x as f32, y as f32);
+			let (across, down) = (
+				length(position(x as f32 + 1.0, y as f32) - here),
+				length(position(x as f32, y as f32 + 1.0) - here),
+			);
+			let ratio = across.max(down) / across.min(down);
+			// Both sides having no length (eg outside the domain of the expressions) isn't stretching
+			if ratio.is_nan() || ratio <= min_ratio {
+				continue;
+			}
+			stretched += 1;
+			max_ratio = max_ratio.max(ratio.min(MAX_STRETCH));
+			(left, top, right, bottom) = (left.min(x), top.min(y), right.max(x + 1), bottom.max(y + 1));
+		}
+	}
+
+	(stretched > 0).then(|| StretchedRegion {
+		x: left,
+		y: top,
+		width: right - left + 1,
+		height: bottom - top + 1,
+		fraction: stretched as f32 / (columns * rows) as f32,
+		ratio: max_ratio,
+	})
+}
+
+/// Estimate the curvature of the surface `generate_preview` would generate with the same arguments, so problem areas can be highlighted
+///
+/// There's one value for every vertex of every triangle, in the same order as the triangles and vertices in the preview. The curvature is
+/// the average angle between a vertex's normal and its neighbors' normals, divided by the distance to them, so it's larger where the surface
+/// bends more sharply and 0 for flat areas. A step of 0 is treated as 1.
+pub fn preview_vertex_curvature<F: Fn(f32, f32, f32, f32) -> f32>(
 	x_fn: F,
 	y_fn: F,
 	z_fn: F,
 	width: u32,
 	height: u32,
 	step: u32,
-) -> Result<StlModel, InvalidPointsError> {
-	let point_cloud = generate_point_cloud(x_fn, y_fn, z_fn, width, height, step)?;
+	max_triangles: Option<usize>,
+) -> Result<Vec<f32>, LithophaneError> {
+	let step = match max_triangles {
+		Some(max_triangles) => preview_step_for_budget(width, height, step, max_triangles),
+		None => step.max(1),
+	};
+	let point_cloud =
+		generate_point_cloud(x_fn, y_fn, z_fn, width, height, step, step, NormalAveraging::default(), Hooks::NONE).map_err(uncancelled)?;
 
 	let width_usize = point_cloud.width as usize;
 	let height_usize = point_cloud.height as usize;
 
-	let num_triangles = (width_usize - 1) * (height_usize - 1) * 2;
-	let mut triangles = Vec::with_capacity(num_triangles);
+	let mut curvature = Vec::with_capacity(point_cloud.vertices.len());
+	for y_i in 0..height_usize {
+		for x_i in 0..width_usize {
+			let i = y_i * width_usize + x_i;
+			let neighbors = [
+				(x_i > 0).then(|| i - 1),
+				(x_i + 1 < width_usize).then(|| i + 1),
+				(y_i > 0).then(|| i - width_usize),
+				(y_i + 1 < height_usize).then(|| i + width_usize),
+			];
+			let (mut total, mut count) = (0.0, 0);
+			for j in neighbors.into_iter().flatten() {
+				let (a, b) = (point_cloud.vertex_normals[i], point_cloud.vertex_normals[j]);
+				let angle = (a.x * b.x + a.y * b.y + a.z * b.z).clamp(-1.0, 1.0).acos();
+				let d = point_cloud.vertices[j] - point_cloud.vertices[i];
+				let distance = (d.x * d.x + d.y * d.y + d.z * d.z).sqrt();
+				if distance > 0.0 {
+					total += angle / distance;
+					count += 1;
+				}
+			}
+			curvature.push(if count > 0 { total / count as f32 } else { 0.0 });
+		}
+	}
+
+	Ok(preview_triangle_indices(&point_cloud).flat_map(|t| t.map(|i| curvature[i])).collect())
+}
+
+/// The indices into a point cloud's vertices of the triangles making up a preview
+fn preview_triangle_indices(point_cloud: &PointCloud) -> impl Iterator<Item = [usize; 3]> {
+	let width_usize = point_cloud.width as usize;
+	let height_usize = point_cloud.height as usize;
 
 	// Remember that the image origin is top left, so y_i = 0, x_i = 0 is the top left of the image
 
-	for y_i in 0..point_cloud.height as usize - 1 {
-		for x_i in 0..point_cloud.width as usize - 1 {
+	(0..height_usize - 1).flat_map(move |y_i| {
+		(0..width_usize - 1).flat_map(move |x_i| {
+			[
+				[y_i * width_usize + x_i, (y_i + 1) * width_usize + x_i, (y_i + 1) * width_usize + x_i + 1],
+				[y_i * width_usize + x_i, (y_i + 1) * width_usize + x_i + 1, y_i * width_usize + x_i + 1],
+			]
+		})
+	})
+}
+
+/// Create a cylindrical lithophane meant to be printed in spiral/vase mode
+///
+/// The image is wrapped once around a cylinder, with the pixel depths applied purely radially to the outside surface, and the cylinder is
+/// closed with a flat top and bottom so it's a single solid. Since every row of the image is a closed loop with exactly one radius per
+/// column, every horizontal slice of the model is a single simple loop, which is what slicers need for spiral/vase mode.
+/// The radius is calculated so that pixels are `pixel_size` wide on the inside of the wall, and the rows are also `pixel_size` apart.
+/// Images smaller than 2×2 pixels are an error, so extend thin ones first (see `extend_to_strip`).
+pub fn generate_vase_lithophane(image: GrayImage, pixel_size: f32, white_depth: f32, black_depth: f32) -> Result<StlModel, LithophaneError> {
+	check_image_size(image.width(), image.height())?;
+	let width = image.width() as usize;
+	let height = image.height() as usize;
+
+	let radius = width as f32 * pixel_size / (2.0 * std::f32::consts::PI);
+	let top = (height - 1) as f32 * pixel_size;
+
+	// Every row goes around at the same angles, so only work out the direction of each column once
+	let directions = (0..width).map(|x_i| (x_i as f32 / width as f32 * 2.0 * std::f32::consts::PI).sin_cos()).collect::<Vec<_>>();
+
+	// Remember that the image origin is top left, so the top row of the image becomes the top of the cylinder
+	let mut vertices = Vec::with_capacity(width * height);
+	for y_i in 0..height {
+		for (x_i, &(sin, cos)) in directions.iter().enumerate() {
+			let r = radius + pixel_depth(image.get_pixel(x_i as u32, y_i as u32).0[0], white_depth, black_depth);
+			vertices.push(Vec3 {
+				x: r * cos,
+				y: r * sin,
+				z: top - y_i as f32 * pixel_size,
+			});
+		}
+	}
+
+	let mut triangles = Vec::with_capacity(width * (height - 1) * 2 + width * 2);
+
+	// Generate triangles for the wall, wrapping the last column around to the first one
+	for y_i in 0..height - 1 {
+		for x_i in 0..width {
+			let next_x_i = (x_i + 1) % width;
 			triangles.push(three_points_to_triangle([
-				point_cloud.vertices[y_i * width_usize + x_i],
-				point_cloud.vertices[(y_i + 1) * width_usize + x_i],
-				point_cloud.vertices[(y_i + 1) * width_usize + x_i + 1],
+				vertices[y_i * width + x_i],
+				vertices[(y_i + 1) * width + x_i],
+				vertices[(y_i + 1) * width + next_x_i],
 			])?);
 			triangles.push(three_points_to_triangle([
-				point_cloud.vertices[y_i * width_usize + x_i],
-				point_cloud.vertices[(y_i + 1) * width_usize + x_i + 1],
-				point_cloud.vertices[y_i * width_usize + x_i + 1],
+				vertices[y_i * width + x_i],
+				vertices[(y_i + 1) * width + next_x_i],
+				vertices[y_i * width + next_x_i],
 			])?);
 		}
 	}
+
+	// Generate triangles for the top and bottom caps
+	let top_center = Vec3 { x: 0.0, y: 0.0, z: top };
+	let bottom_center = Vec3 { x: 0.0, y: 0.0, z: 0.0 };
+	for x_i in 0..width {
+		let next_x_i = (x_i + 1) % width;
+		triangles.push(three_points_to_triangle([top_center, vertices[x_i], vertices[next_x_i]])?);
+		triangles.push(three_points_to_triangle([
+			bottom_center,
+			vertices[(height - 1) * width + next_x_i],
+			vertices[(height - 1) * width + x_i],
+		])?);
+	}
+
+	Ok(StlModel {
+		header: String::new(),
+		triangles,
+	})
+}
+
+/// How one end of a tube from `generate_tube_lithophane` is finished
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Cap {
+	/// Leave the end open, eg for the top of a lampshade
+	#[default]
+	Open,
+	/// Close the end with a flat disc this thick
+	Flat { thickness: f32 },
+	/// Close the end with a dome that sticks out this far past the end of the wall and is this thick at its tip, eg for an ornament
+	Dome { height: f32, thickness: f32 },
+}
+
+impl Cap {
+	/// How far the outside of the cap sticks out past the end of the wall, and how far the inside does (which is negative when the
+	/// inside is below the end of the wall), or None for open ends
+	fn heights(&self) -> Option<(f32, f32)> {
+		match *self {
+			Cap::Open => None,
+			Cap::Flat { thickness } => Some((0.0, -thickness)),
+			Cap::Dome { height, thickness } => Some((height, height - thickness)),
+		}
+	}
+}
+
+/// Create a cylindrical lithophane with a hollow wall, with each end left open or closed by a cap
+///
+/// Unlike `generate_vase_lithophane`, which is a solid meant for spiral/vase mode, the inside of the wall is part of the model, so it can
+/// be printed normally and the ends can be finished differently: open ends make a lampshade, and flat or domed ends make a closed
+/// ornament. The inside of the wall is a smooth cylinder whose circumference is the image's width in pixels, and the pixel depths make
+/// the wall thicker outwards. Sizes are in the same units as `pixel_size`, with the bottom of the wall at z = 0 (so a domed bottom cap
+/// reaches below that). Flat caps and the thickness of domes have to leave some of the inside open between the caps, and images smaller
+/// than 2×2 pixels are an error.
+pub fn generate_tube_lithophane(
+	image: GrayImage,
+	pixel_size: f32,
+	white_depth: f32,
+	black_depth: f32,
+	top_cap: Cap,
+	bottom_cap: Cap,
+) -> Result<StlModel, LithophaneError> {
+	check_image_size(image.width(), image.height())?;
+	let width = image.width() as usize;
+	let height = image.height() as usize;
+
+	let radius = width as f32 * pixel_size / (2.0 * std::f32::consts::PI);
+	let top = (height - 1) as f32 * pixel_size;
+	let directions = (0..width).map(|x_i| (x_i as f32 / width as f32 * 2.0 * std::f32::consts::PI).sin_cos()).collect::<Vec<_>>();
+	let ring = |radii: &dyn Fn(usize) -> f32, z: f32| -> Vec<Vec3> {
+		directions
+			.iter()
+			.enumerate()
+			.map(|(x_i, &(sin, cos))| Vec3 {
+				x: radii(x_i) * cos,
+				y: radii(x_i) * sin,
+				z,
+			})
+			.collect()
+	};
+
+	// The rings of a dome over `base` (which sticks out `sign` × `dome_height` past it), from the base outwards, and the point at its tip
+	let dome = |base: &[Vec3], dome_height: f32, sign: f32| -> (Vec<Vec<Vec3>>, Vec3) {
+		let z = base[0].z;
+		let tip = Vec3 {
+			x: 0.0,
+			y: 0.0,
+			z: z + sign * dome_height.max(0.0),
+		};
+		if dome_height <= 0.0 {
+			return (Vec::new(), tip);
+		}
+		let rings = ((dome_height.max(radius) * std::f32::consts::FRAC_PI_2 / pixel_size).ceil() as usize).max(2);
+		let rings = (1..rings)
+			.map(|k| {
+				let (sin, cos) = (k as f32 / rings as f32 * std::f32::consts::FRAC_PI_2).sin_cos();
+				base.iter()
+					.map(|v| Vec3 {
+						x: v.x * cos,
+						y: v.y * cos,
+						z: z + sign * dome_height * sin,
+					})
+					.collect()
+			})
+			.collect();
+		(rings, tip)
+	};
+
+	// The outside goes from the top down, and the inside from the bottom up, so going through the outside and then the inside (or the
+	// other way around) always goes the same way around the wall's cross section, and the triangles between each pair of rings face out
+	let mut outside = (0..height)
+		.map(|y_i| {
+			ring(
+				&|x_i| radius + pixel_depth(image.get_pixel(x_i as u32, y_i as u32).0[0], white_depth, black_depth),
+				top - y_i as f32 * pixel_size,
+			)
+		})
+		.collect::<Vec<_>>();
+	let (top_heights, bottom_heights) = (top_cap.heights(), bottom_cap.heights());
+	let inside_top = top + top_heights.map_or(0.0, |(_, inside)| inside.min(0.0));
+	let inside_bottom = -bottom_heights.map_or(0.0, |(_, inside)| inside.min(0.0));
+	let mut inside = vec![ring(&|_| radius, inside_bottom), ring(&|_| radius, inside_top)];
+
+	let mut tips = [None; 4];
+	if let Some((outside_height, inside_height)) = top_heights {
+		let (rings, tip) = dome(&outside[0], outside_height, 1.0);
+		outside.splice(0..0, rings.into_iter().rev());
+		tips[0] = Some(tip);
+		let (rings, tip) = dome(&inside[inside.len() - 1], inside_height, 1.0);
+		inside.extend(rings);
+		tips[3] = Some(tip);
+	}
+	if let Some((outside_height, inside_height)) = bottom_heights {
+		let (rings, tip) = dome(&outside[outside.len() - 1], outside_height, -1.0);
+		outside.extend(rings);
+		tips[1] = Some(tip);
+		let (rings, tip) = dome(&inside[0], inside_height, -1.0);
+		inside.splice(0..0, rings.into_iter().rev());
+		tips[2] = Some(tip);
+	}
+
+	let mut triangles = Vec::new();
+	match tips {
+		[None, None, None, None] => {
+			outside.append(&mut inside);
+			stitch_rings(&outside, None, None, true, &mut triangles)?;
+		},
+		[Some(top_tip), None, None, Some(inside_top_tip)] => {
+			outside.append(&mut inside);
+			stitch_rings(&outside, Some(top_tip), Some(inside_top_tip), false, &mut triangles)?;
+		},
+		[None, Some(bottom_tip), Some(inside_bottom_tip), None] => {
+			inside.append(&mut outside);
+			stitch_rings(&inside, Some(inside_bottom_tip), Some(bottom_tip), false, &mut triangles)?;
+		},
+		[top_tip, bottom_tip, inside_bottom_tip, inside_top_tip] => {
+			stitch_rings(&outside, top_tip, bottom_tip, false, &mut triangles)?;
+			stitch_rings(&inside, inside_bottom_tip, inside_top_tip, false, &mut triangles)?;
+		},
+	}
+
+	Ok(StlModel {
+		header: String::new(),
+		triangles,
+	})
+}
+
+/// Join a sequence of rings (which all go counterclockwise seen from above, with the same number of points) into a surface, closing it
+/// with a fan of triangles from `start` to the first ring and from the last ring to `end`, or joining the last ring back to the first
+/// one if `closed` is set
+fn stitch_rings(
+	rings: &[Vec<Vec3>],
+	start: Option<Vec3>,
+	end: Option<Vec3>,
+	closed: bool,
+	triangles: &mut Vec<Triangle>,
+) -> Result<(), InvalidPointsError> {
+	let width = rings[0].len();
+	let pairs = rings.windows(2).map(|pair| (&pair[0], &pair[1])).chain(closed.then(|| (&rings[rings.len() - 1], &rings[0])));
+	for (from, to) in pairs {
+		for x_i in 0..width {
+			let next_x_i = (x_i + 1) % width;
+			triangles.push(three_points_to_triangle([from[x_i], to[x_i], to[next_x_i]])?);
+			triangles.push(three_points_to_triangle([from[x_i], to[next_x_i], from[next_x_i]])?);
+		}
+	}
+	for x_i in 0..width {
+		let next_x_i = (x_i + 1) % width;
+		if let Some(start) = start {
+			triangles.push(three_points_to_triangle([start, rings[0][x_i], rings[0][next_x_i]])?);
+		}
+		if let Some(end) = end {
+			let last = &rings[rings.len() - 1];
+			triangles.push(three_points_to_triangle([end, last[next_x_i], last[x_i]])?);
+		}
+	}
+	Ok(())
+}
+
+/// Create a flat, round lithophane from the largest circle that fits in the image, with a hole in the middle, eg for a clock movement or
+/// to display it like a record
+///
+/// Instead of a square grid cut off at the edge, the mesh is made of concentric rings one pixel apart, so the edges are smooth circles.
+/// Each ring has about one vertex per pixel of its circumference, so the triangles stay close to the same size and shape all the way in
+/// to the middle instead of turning into slivers around the center. Without a hole (`hole_diameter` of 0) the innermost ring is a single
+/// point in the center. Sizes are in the same units as `pixel_size`, with the back of the disc at z = 0. The hole has to leave at least a
+/// pixel wide ring, and images smaller than 2×2 pixels are an error.
+pub fn generate_disc_lithophane(
+	image: GrayImage,
+	pixel_size: f32,
+	hole_diameter: f32,
+	white_depth: f32,
+	black_depth: f32,
+) -> Result<StlModel, LithophaneError> {
+	generate_disc_lithophane_with_relief(image, pixel_size, hole_diameter, white_depth, black_depth, |_, _| 0.0)
+}
+
+/// Create a round lithophane like `generate_disc_lithophane`, with the front raised by `relief` at each point (in the same units as
+/// `pixel_size`, relative to the center), eg for markings on a clock face
+pub(crate) fn generate_disc_lithophane_with_relief(
+	image: GrayImage,
+	pixel_size: f32,
+	hole_diameter: f32,
+	white_depth: f32,
+	black_depth: f32,
+	relief: impl Fn(f32, f32) -> f32,
+) -> Result<StlModel, LithophaneError> {
+	use std::f32::consts::TAU;
+
+	check_image_size(image.width(), image.height())?;
+	let (center_x, center_y) = ((image.width() - 1) as f32 / 2.0, (image.height() - 1) as f32 / 2.0);
+	let outer_radius = center_x.min(center_y);
+	let inner_radius = hole_diameter / 2.0 / pixel_size;
+	if inner_radius >= outer_radius - 1.0 {
+		return Err(InvalidPointsError {}.into());
+	}
+
+	// Interpolate between pixels, since the rings don't line up with them
+	let depth_at = |x: f32, y: f32| -> f32 {
+		let (x0, y0) = (x.floor().min(image.width() as f32 - 2.0), y.floor().min(image.height() as f32 - 2.0));
+		let (tx, ty) = (x - x0, y - y0);
+		let value = |x: f32, y: f32| image.get_pixel(x as u32, y as u32).0[0] as f32;
+		let top = value(x0, y0) * (1.0 - tx) + value(x0 + 1.0, y0) * tx;
+		let bottom = value(x0, y0 + 1.0) * (1.0 - tx) + value(x0 + 1.0, y0 + 1.0) * tx;
+		pixel_depth((top * (1.0 - ty) + bottom * ty).round() as u8, white_depth, black_depth)
+	};
+
+	// The front and back vertices of each ring from the inside out, going counterclockwise (seen from the front) from the positive x axis
+	let ring_count = (outer_radius - inner_radius).ceil() as usize + 1;
+	let mut front = Vec::with_capacity(ring_count);
+	let mut back = Vec::with_capacity(ring_count);
+	for ring in 0..ring_count {
+		let radius = inner_radius + (outer_radius - inner_radius) * ring as f32 / (ring_count - 1) as f32;
+		let segments = if radius == 0.0 { 1 } else { ((TAU * radius).ceil() as usize).max(8) };
+		let (ring_front, ring_back) = (0..segments)
+			.map(|i| {
+				let angle = i as f32 / segments as f32 * TAU;
+				let (x, y) = (radius * angle.cos(), radius * angle.sin());
+				let back = Vec3::new([x * pixel_size, y * pixel_size, 0.0]);
+				// Remember that the image origin is top left, so y is flipped
+				let depth = depth_at(center_x + x, center_y - y) + relief(back.x, back.y);
+				(back + Vec3::new([0.0, 0.0, depth]), back)
+			})
+			.unzip::<_, _, Vec<_>, Vec<_>>();
+		front.push(ring_front);
+		back.push(ring_back);
+	}
+
+	let mut triangles = Vec::new();
+
+	// Stitch each pair of neighboring rings together, always advancing along whichever ring's next vertex comes first, since neighboring
+	// rings usually have different numbers of vertices
+	let mut stitch = |inner: &[Vec3], outer: &[Vec3], facing_front: bool| -> Result<(), InvalidPointsError> {
+		let (a, b) = (inner.len(), outer.len());
+		let (mut i, mut j) = (0, 0);
+		while i < a || j < b {
+			let points = if j == b || (i < a && (i + 1) * b < (j + 1) * a) {
+				i += 1;
+				// A single point in the center has nothing to advance to
+				if a == 1 {
+					continue;
+				}
+				[inner[i - 1], outer[j % b], inner[i % a]]
+			} else {
+				j += 1;
+				[inner[i % a], outer[j - 1], outer[j % b]]
+			};
+			let [p0, p1, p2] = points;
+			triangles.push(three_points_to_triangle(if facing_front { [p0, p1, p2] } else { [p0, p2, p1] })?);
+		}
+		Ok(())
+	};
+	for ring in 0..ring_count - 1 {
+		stitch(&front[ring], &front[ring + 1], true)?;
+		stitch(&back[ring], &back[ring + 1], false)?;
+	}
+
+	// Walls around the outside edge and the hole, facing away from the disc
+	let mut wall = |front: &[Vec3], back: &[Vec3], facing_out: bool| -> Result<(), InvalidPointsError> {
+		for i in 0..front.len() {
+			let next = (i + 1) % front.len();
+			let (a, b) = if facing_out {
+				([back[i], back[next], front[next]], [back[i], front[next], front[i]])
+			} else {
+				([back[i], front[next], back[next]], [back[i], front[i], front[next]])
+			};
+			triangles.push(three_points_to_triangle(a)?);
+			triangles.push(three_points_to_triangle(b)?);
+		}
+		Ok(())
+	};
+	wall(&front[ring_count - 1], &back[ring_count - 1], true)?;
+	if inner_radius > 0.0 {
+		wall(&front[0], &back[0], false)?;
+	}
+
 	Ok(StlModel {
 		header: String::new(),
 		triangles,
@@ -70,18 +2107,42 @@ struct PointCloud {
 	pub height: u32,
 }
 
+impl PointCloud {
+	/// Keep every `step`-th point in each direction, plus the last row and column, like `generate_point_cloud` does when stepping
+	fn subsampled(&self, step: u32) -> PointCloud {
+		let indices = |length: u32| -> Vec<usize> {
+			let mut v = (0..length as usize).step_by(step as usize).collect::<Vec<_>>();
+			if !(length - 1).is_multiple_of(step) {
+				v.push(length as usize - 1);
+			}
+			v
+		};
+		let (xs, ys) = (indices(self.width), indices(self.height));
+
+		let points = ys.iter().flat_map(|&y| xs.iter().map(move |&x| y * self.width as usize + x));
+		PointCloud {
+			vertices: points.clone().map(|i| self.vertices[i]).collect(),
+			vertex_normals: points.map(|i| self.vertex_normals[i]).collect(),
+			width: xs.len() as u32,
+			height: ys.len() as u32,
+		}
+	}
+}
+
 /// Generates a point cloud from a set of equations
+#[allow(clippy::too_many_arguments)]
 fn generate_point_cloud<F: Fn(f32, f32, f32, f32) -> f32>(
 	x_fn: F,
 	y_fn: F,
 	z_fn: F,
 	width: u32,
 	height: u32,
-	step: u32,
-) -> Result<PointCloud, InvalidPointsError> {
-	// Generate vertices with an extra border that will be used to calculate normals
-	let mut vertices = Vec::with_capacity((width as usize + 2) * (height as usize + 2));
-
+	step_x: u32,
+	step_y: u32,
+	normal_averaging: NormalAveraging,
+	hooks: Hooks,
+) -> Result<PointCloud, GenerateError> {
+	check_image_size(width, height)?;
 	let width_f32 = width as f32;
 	let height_f32 = height as f32;
 
@@ -92,14 +2153,14 @@ fn generate_point_cloud<F: Fn(f32, f32, f32, f32) -> f32>(
 	/// Create a Vec<i64> from -step to length-1 inclusive, stepping by step, but with an extra element at the end to reach exactly length if
 	/// necessary, and with another element after that with the same difference (eg length=15 step=4 results in -4,0,4,8,12,14,16).
 	fn step_iter_with_size(length: u32, step: u32) -> Vec<i64> {
-		let mut v = Vec::with_capacity(((length - 1 + step - 1) / step + 3) as usize);
+		let mut v = Vec::with_capacity(((length - 1).div_ceil(step) + 3) as usize);
 
 		let length_i64 = length as i64;
 		let step_i64 = step as i64;
 
 		v.extend((-step_i64..length_i64).step_by(step as usize));
 
-		if (length - 1) % step != 0 {
+		if !(length - 1).is_multiple_of(step) {
 			v.push(length_i64 - 1);
 		}
 		v.push((length_i64 - 1) * 2 - v[v.len() - 2]);
@@ -107,52 +2168,45 @@ fn generate_point_cloud<F: Fn(f32, f32, f32, f32) -> f32>(
 		v
 	}
 
-	let width_range = step_iter_with_size(width, step);
+	let width_range = step_iter_with_size(width, step_x);
 	let ewc = width_range.len(); // Extended width count
-	let height_range = step_iter_with_size(height, step);
+	let height_range = step_iter_with_size(height, step_y);
 	let ehc = height_range.len(); // Extended height count
 
-	for y_i in height_range.iter().copied() {
-		for x_i in width_range.iter().copied() {
-			vertices.push(Vec3 {
-				x: (x_fn)(x_i as f32, y_i as f32, width_f32, height_f32),
-				y: (y_fn)(x_i as f32, y_i as f32, width_f32, height_f32),
-				z: (z_fn)(x_i as f32, y_i as f32, width_f32, height_f32),
-			});
-		}
-	}
-
 	let wc = ewc - 2; // Actual width count
 	let hc = ehc - 2; // Actual height count
+	let mut vertices = Vec::with_capacity(wc * hc);
 	let mut normals = Vec::with_capacity(wc * hc);
 
-	for y_i in 0..hc {
-		for x_i in 0..wc {
-			let v = vertices[(y_i + 1) * ewc + 1 + x_i];
-			// lower and right vectors
-			let norm1 = normalize_to_unit_vector(cross_product(
-				vertices[(y_i + 2) * ewc + 1 + x_i] - v,
-				vertices[(y_i + 1) * ewc + 2 + x_i] - v,
-			))?;
-			// upper and left vectors
-			let norm2 = normalize_to_unit_vector(cross_product(vertices[y_i * ewc + 1 + x_i] - v, vertices[(y_i + 1) * ewc + x_i] - v))?;
+	// The vertices are generated with an extra border that's used to calculate normals, but only three rows of them are kept at a time, so
+	// there's never a second copy of the whole grid
+	let fill_row = |row: &mut Vec<Vec3>, y_i: i64| {
+		row.clear();
+		row.extend(width_range.iter().map(|&x_i| Vec3 {
+			x: (x_fn)(x_i as f32, y_i as f32, width_f32, height_f32),
+			y: (y_fn)(x_i as f32, y_i as f32, width_f32, height_f32),
+			z: (z_fn)(x_i as f32, y_i as f32, width_f32, height_f32),
+		}));
+	};
+	let (mut above, mut current, mut below) = (Vec::with_capacity(ewc), Vec::with_capacity(ewc), Vec::with_capacity(ewc));
+	fill_row(&mut above, height_range[0]);
+	fill_row(&mut current, height_range[1]);
 
-			normals.push(normalize_to_unit_vector(norm1 + norm2)?);
+	for (row, &y_i) in height_range[2..].iter().enumerate() {
+		hooks.check(row as f32 / hc as f32)?;
+		fill_row(&mut below, y_i);
+		for x_i in 1..ewc - 1 {
+			normals.push(normal_averaging.normal(&above, &current, &below, x_i)?);
 		}
+		vertices.extend_from_slice(&current[1..ewc - 1]);
+
+		// Move each row up one, reusing the top row's buffer for the next row
+		mem::swap(&mut above, &mut current);
+		mem::swap(&mut current, &mut below);
 	}
 
 	Ok(PointCloud {
-		vertices: vertices
-			.into_iter()
-			.enumerate()
-			.filter(|&(i, _)| {
-				i >= ewc // exclude extra bottom row
-				&& i < ewc * (ehc - 1) // exclude extra top row
-				&& i % ewc != 0 // exclude extra left row
-				&& i % ewc != ewc - 1 // exclude extra right row
-			})
-			.map(|(_, v)| v)
-			.collect::<Vec<_>>(),
+		vertices,
 		vertex_normals: normals,
 		width: wc as u32,
 		height: hc as u32,
@@ -161,10 +2215,41 @@ fn generate_point_cloud<F: Fn(f32, f32, f32, f32) -> f32>(
 
 fn generate_lithophane_mesh(
 	point_cloud: PointCloud,
-	image: GrayImage,
-	white_depth: f32,
-	black_depth: f32,
-) -> Result<Vec<Triangle>, InvalidPointsError> {
+	gray_value: impl Fn(u32, u32) -> u8,
+	options: &LithophaneOptions,
+	(scale_x, scale_y): (f32, f32),
+	position: impl Fn(f32, f32) -> Vec3,
+	hooks: Hooks,
+) -> Result<(Vec<Triangle>, Vec<f32>), GenerateError> {
+	let LithophaneOptions {
+		white_depth,
+		black_depth,
+		ref outline,
+		back_texture,
+		back_ribs,
+		sheet_ridge,
+		backing_margin,
+		wall_draft,
+		ref depth_mask,
+		radial_fade,
+		subdivisions: _,
+		sample_step: _,
+		frame_fit,
+		skirt,
+		ref label,
+		contours,
+		ref stipple,
+		hatching,
+		ref emboss,
+		max_slope,
+		layer_height,
+		thickness_offset,
+		part,
+		tiling: _,
+		header: _,
+		normal_averaging: _,
+	} = *options;
+
 	let width = point_cloud.width as usize;
 	let height = point_cloud.height as usize;
 
@@ -175,109 +2260,337 @@ fn generate_lithophane_mesh(
 
 	let mut triangles = Vec::with_capacity(num_triangles);
 
+	// The outline, textures, etc are all in original image coordinates, which aren't the same as the vertex indices when sampling or
+	// subdividing, so this finds the image coordinates of a vertex
+	let coordinates = |i: usize| ((i % width) as f32 / scale_x, (i / width) as f32 / scale_y);
+
 	// Remember that the image origin is top left, so y_i = 0, x_i = 0 is the top left of the image
 
-	// Generate triangles for backing mesh
-	for y_i in 0..height - 1 {
-		for x_i in 0..width - 1 {
-			triangles.push(three_points_to_triangle([
-				point_cloud.vertices[y_i * width + x_i],
-				point_cloud.vertices[(y_i + 1) * width + x_i + 1],
-				point_cloud.vertices[(y_i + 1) * width + x_i],
-			])?);
-			triangles.push(three_points_to_triangle([
-				point_cloud.vertices[y_i * width + x_i],
-				point_cloud.vertices[y_i * width + x_i + 1],
-				point_cloud.vertices[(y_i + 1) * width + x_i + 1],
-			])?);
+	let reinforce = |x: f32, y: f32, depth: f32| -> f32 {
+		if outline.is_reinforced(x, y) {
+			black_depth
+		} else {
+			depth
+		}
+	};
+
+	let (max_x, max_y) = coordinates(width * height - 1);
+	let skirt_top = skirt.map_or(f32::INFINITY, |s| max_y - s.height.ceil());
+
+	// The backing mesh is pushed out away from the pixels by the texture, ribs and sheet ridge, if there are any. The ridge follows the
+	// edges of the image, so it goes along the top of the skirt rather than its bottom.
+	let texture_height = |x: f32, y: f32| -> f32 {
+		let ridge_height = sheet_ridge.map_or(0.0, |r| r.height(x.min(y).min(max_x - x).min(skirt_top.min(max_y) - y)));
+		back_texture.map_or(0.0, |t| t.height(x, y)) + back_ribs.map_or(0.0, |r| r.height(x, y)) + ridge_height
+	};
+
+	// The final texture height and pixel depth at a point, after reinforcing holes, fitting into a frame, offsetting, snapping to layers
+	// and engraving the label
+	let thickness = |x: f32, y: f32, depth: f32| -> (f32, f32) {
+		let (texture_height, depth) = if let Some(skirt) = skirt.filter(|_| y > skirt_top) {
+			(0.0, skirt.depth)
+		} else {
+			let depth = reinforce(x, y, depth);
+			match frame_fit {
+				Some(f) => f.apply(x.min(y).min(max_x - x).min(max_y - y), texture_height(x, y), depth),
+				None => (texture_height(x, y), depth),
+			}
+		};
+		let depth = depth + thickness_offset;
+		let depth = snap_to_layers(depth, layer_height);
+		match label {
+			Some(label) => (texture_height - label.depth(x, y).min((texture_height + depth) / 2.0), depth),
+			None => (texture_height, depth),
+		}
+	};
+
+	let part_thickness = |x: f32, y: f32, depth: f32| -> (f32, f32) {
+		let (texture_height, depth) = thickness(x, y, depth);
+		match part {
+			Some(Part::Base { depth: base_depth }) => (texture_height, depth.min(base_depth)),
+			Some(Part::Relief { base_depth }) => (-base_depth, depth.max(base_depth + MIN_RELIEF_DEPTH)),
+			_ => (texture_height, depth),
 		}
+	};
+
+	// Whether a point with this (interpolated) pixel depth is part of the outline and the part being generated
+	let is_frame =
+		|x: f32, y: f32| y > skirt_top || outline.is_reinforced(x, y) || frame_fit.is_some_and(|f| x.min(y).min(max_x - x).min(max_y - y) < f.border);
+	let contains = |x: f32, y: f32, depth: f32| {
+		outline.contains(x, y)
+			&& !stipple.as_ref().is_some_and(|s| s.is_hole(x, y))
+			&& match part {
+				None | Some(Part::Base { .. }) => true,
+				Some(Part::Image) => !is_frame(x, y),
+				Some(Part::Frame) => is_frame(x, y),
+				Some(Part::Relief { base_depth }) => thickness(x, y, depth).1 > base_depth,
+			}
+	};
+
+	let depth_mask = depth_mask.as_ref().map(|m| {
+		let image = if m.image.dimensions() == (width as u32, height as u32) {
+			Cow::Borrowed(&m.image)
+		} else {
+			Cow::Owned(imageops::resize(&m.image, width as u32, height as u32, FilterType::Triangle))
+		};
+		(m, image)
+	});
+
+	let mut depths = (0..width * height)
+		.map(|i| {
+			let gray_value = gray_value((i % width) as u32, (i / width) as u32);
+			let depth = pixel_depth(gray_value, white_depth, black_depth);
+			let depth = contours.map_or(depth, |c| c.apply(depth, white_depth, black_depth));
+			let depth = match stipple {
+				Some(stipple) => {
+					let (x, y) = coordinates(i);
+					stipple.depth(x, y, white_depth, black_depth)
+				},
+				None => depth,
+			};
+			let depth = match hatching {
+				Some(hatching) => {
+					let (x, y) = coordinates(i);
+					hatching.depth(x, y, gray_value, white_depth)
+				},
+				None => depth,
+			};
+			let depth = match &depth_mask {
+				Some((mask, mask_image)) => mask.apply(depth, mask_image.get_pixel((i % width) as u32, (i / width) as u32).0[0], white_depth),
+				None => depth,
+			};
+			let depth = match radial_fade {
+				Some(fade) => {
+					let (x, y) = coordinates(i);
+					fade.apply(x, y, depth)
+				},
+				None => depth,
+			};
+			match emboss {
+				Some(emboss) => {
+					let (x, y) = coordinates(i);
+					emboss.apply(x, y, depth)
+				},
+				None => depth,
+			}
+		})
+		.collect::<Vec<_>>();
+	if let Some(max_slope) = max_slope {
+		// The skirt rows get their depth from the skirt, so they're left out
+		let image_rows = skirt.map_or(height, |_| (skirt_top * scale_y).floor() as usize + 1);
+		limit_slope(&mut depths[..image_rows * width], width, max_slope / scale_x.max(scale_y));
 	}
 
-	// Calculate vertices for pixels
-	let get_px_depth = |gray_value: u8| -> f32 { white_depth + (255 - gray_value) as f32 / 255.0 * (black_depth - white_depth) };
+	// The backing of the skirt rows under a wedge is a straight line (seen from the side) from the top of the skirt to the back of the flat
+	// bottom, so each row's backing is moved up along the surface and away from it. Distances along the surface are measured in a straight
+	// line, since the wedge is only meant for flat lithophanes. `wedge_backing` gives the row and the distance behind the surface.
+	let wedge_backing = |x: f32, y: f32, depth: f32| -> Option<(f32, f32)> {
+		let wedge = skirt.and_then(|s| s.wedge).filter(|_| y > skirt_top)?;
+		let length = |a: Vec3, b: Vec3| {
+			let d = b - a;
+			(d.x * d.x + d.y * d.y + d.z * d.z).sqrt()
+		};
+		let skirt_height = length(position(x, skirt_top), position(x, max_y));
+		let (sin, cos) = wedge.tilt.to_radians().sin_cos();
+		// Leaning back by the tilt, the bottom is level with the front of the bottom edge, which is `depth` in front of the surface
+		let bottom_length = wedge.length.unwrap_or(length(position(x, 0.0), position(x, max_y)) * sin + depth * cos);
+		let bottom_length = bottom_length.min(skirt_height / sin).max(depth / cos);
+		let (back_height, back_distance) = (bottom_length * sin, bottom_length * cos - depth);
+
+		let t = (y - skirt_top) / (max_y - skirt_top);
+		let height = skirt_height * (1.0 - t) + back_height * t;
+		Some((max_y - height / skirt_height * (max_y - skirt_top), back_distance * t))
+	};
+
+	// Along the edges of the image, the backing is moved out past them by the margin, plus however far the draft leans the wall over its
+	// thickness. Corners are moved out along both edges. The bottom of a wedge is where the wedge puts it instead.
+	let draft = wall_draft.to_radians().tan();
+	let backing_vertex = |x: f32, y: f32, surface: Vec3, normal: Vec3, texture_height: f32, depth: f32| -> Vec3 {
+		let outward = |v: f32, max: f32| match v {
+			_ if v == 0.0 => -1.0,
+			_ if v == max => 1.0,
+			_ => 0.0,
+		};
+		let (dx, dy) = (outward(x, max_x), outward(y, max_y));
+		let (y, surface, texture_height, dy) = match wedge_backing(x, y, depth) {
+			Some((wedge_y, back_distance)) => (wedge_y, position(x, wedge_y), back_distance, 0.0),
+			None => (y, surface, texture_height, dy),
+		};
+		if dx == 0.0 && dy == 0.0 {
+			return surface - normal * texture_height;
+		}
+
+		let mut margin = backing_margin;
+		if draft > 0.0 {
+			// The draft is in millimeters, so it's turned into pixels using how far apart the pixels are on the surface here
+			let step = position(x + dx, y + dy) - surface;
+			let pixel_size = (step.x * step.x + step.y * step.y + step.z * step.z).sqrt() / (dx * dx + dy * dy).sqrt();
+			if pixel_size > 0.0 {
+				margin += (texture_height + depth) * draft / pixel_size;
+			}
+		}
+		let surface = if margin > 0.0 {
+			position(x + dx * margin, y + dy * margin)
+		} else {
+			surface
+		};
+		surface - normal * texture_height
+	};
+
+	// Calculate vertices for pixels, keeping the final thickness of the ones that are included for the stats
+	let mut thicknesses = Vec::with_capacity(width * height);
+	let mut inside = Vec::with_capacity(width * height);
+	let mut backing_vertices = Vec::with_capacity(width * height);
 	let mut px_vertices = Vec::with_capacity(width * height);
-	for i in 0..width * height {
-		let depth = get_px_depth(image.get_pixel(i as u32 % image.width(), i as u32 / image.width()).0[0]);
+	for (i, &depth) in depths.iter().enumerate() {
+		let (x, y) = coordinates(i);
+		inside.push(contains(x, y, depth));
+		let (texture_height, depth) = part_thickness(x, y, depth);
+		if inside[i] {
+			thicknesses.push(texture_height + depth);
+		}
+		backing_vertices.push(backing_vertex(
+			x,
+			y,
+			point_cloud.vertices[i],
+			point_cloud.vertex_normals[i],
+			texture_height,
+			depth,
+		));
 		px_vertices.push(point_cloud.vertices[i] + point_cloud.vertex_normals[i] * depth);
 	}
 
-	// Generate triangles for pixels
-	for y_i in 0..height - 1 {
-		for x_i in 0..width - 1 {
-			triangles.push(three_points_to_triangle([
-				px_vertices[y_i * width + x_i],
-				px_vertices[(y_i + 1) * width + x_i],
-				px_vertices[(y_i + 1) * width + x_i + 1],
-			])?);
-			triangles.push(three_points_to_triangle([
-				px_vertices[y_i * width + x_i],
-				px_vertices[(y_i + 1) * width + x_i + 1],
-				px_vertices[y_i * width + x_i + 1],
-			])?);
+	// Find where the edge of the outline crosses between an included and an excluded vertex, returning the backing and pixel vertices there.
+	// This always searches from the included vertex so that both cells sharing the crossing end up with exactly the same vertices.
+	let crossing = |inside_i: usize, outside_i: usize| -> Result<(Vec3, Vec3), InvalidPointsError> {
+		let ((ax, ay), (bx, by)) = (coordinates(inside_i), coordinates(outside_i));
+		let depth = |t: f32| depths[inside_i] * (1.0 - t) + depths[outside_i] * t;
+		let (mut low, mut high) = (0.0f32, 1.0f32);
+		for _ in 0..12 {
+			let mid = (low + high) / 2.0;
+			if contains(ax + (bx - ax) * mid, ay + (by - ay) * mid, depth(mid)) {
+				low = mid;
+			} else {
+				high = mid;
+			}
 		}
-	}
+		// Keep the crossing away from the grid vertices so the triangles around it can't degenerate
+		let t = low.clamp(0.02, 0.98);
 
-	// Generate triangles to connect the top of the image to backing mesh
-	for x_i in 0..width - 1 {
-		triangles.push(three_points_to_triangle([
-			point_cloud.vertices[x_i],
-			px_vertices[x_i],
-			px_vertices[x_i + 1],
-		])?);
-		triangles.push(three_points_to_triangle([
-			point_cloud.vertices[x_i],
-			px_vertices[x_i + 1],
-			point_cloud.vertices[x_i + 1],
-		])?);
-	}
+		let (x, y) = (ax + (bx - ax) * t, ay + (by - ay) * t);
+		let surface = position(x, y);
+		let normal = normalize_to_unit_vector(point_cloud.vertex_normals[inside_i] * (1.0 - t) + point_cloud.vertex_normals[outside_i] * t)?;
+		let (texture_height, depth) = part_thickness(x, y, depth(t));
+		Ok((backing_vertex(x, y, surface, normal, texture_height, depth), surface + normal * depth))
+	};
 
-	// Generate triangles to connect the bottom of the image to backing mesh
-	for x_i in (height - 1) * width..height * width - 1 {
-		triangles.push(three_points_to_triangle([
-			point_cloud.vertices[x_i],
-			px_vertices[x_i + 1],
-			px_vertices[x_i],
-		])?);
-		triangles.push(three_points_to_triangle([
-			point_cloud.vertices[x_i],
-			point_cloud.vertices[x_i + 1],
-			px_vertices[x_i + 1],
-		])?);
+	// Each cell is turned into a convex polygon of the parts of it that are inside the outline (marching squares), going counterclockwise
+	// from the top left corner. Sides are numbered by the corner they start at, so side 0 is the left side, 1 is the bottom, 2 is the right,
+	// and 3 is the top.
+	struct PolygonPoint {
+		backing: Vec3,
+		pixel: Vec3,
+		side: usize,
+		is_crossing: bool,
 	}
+	let mut polygon = Vec::with_capacity(6);
 
-	// Generate triangles to connect the left side of the image to backing mesh
 	for y_i in 0..height - 1 {
-		let current_index = y_i * width;
-		let lower_index = (y_i + 1) * width;
-		triangles.push(three_points_to_triangle([
-			point_cloud.vertices[current_index],
-			point_cloud.vertices[lower_index],
-			px_vertices[lower_index],
-		])?);
-		triangles.push(three_points_to_triangle([
-			point_cloud.vertices[current_index],
-			px_vertices[lower_index],
-			px_vertices[current_index],
-		])?);
+		hooks.check(y_i as f32 / (height - 1) as f32)?;
+		for x_i in 0..width - 1 {
+			let corners = [
+				y_i * width + x_i,
+				(y_i + 1) * width + x_i,
+				(y_i + 1) * width + x_i + 1,
+				y_i * width + x_i + 1,
+			];
+			let on_border = [x_i == 0, y_i + 1 == height - 1, x_i + 1 == width - 1, y_i == 0];
+
+			polygon.clear();
+			for side in 0..4 {
+				let (a, b) = (corners[side], corners[(side + 1) % 4]);
+				if inside[a] {
+					polygon.push(PolygonPoint {
+						backing: backing_vertices[a],
+						pixel: px_vertices[a],
+						side,
+						is_crossing: false,
+					});
+				}
+				if inside[a] != inside[b] {
+					let (backing, pixel) = if inside[a] { crossing(a, b)? } else { crossing(b, a)? };
+					polygon.push(PolygonPoint {
+						backing,
+						pixel,
+						side,
+						is_crossing: true,
+					});
+				}
+			}
+			if polygon.len() < 3 {
+				continue;
+			}
+
+			// Generate triangles for pixels and backing mesh
+			for i in 1..polygon.len() - 1 {
+				triangles.push(three_points_to_triangle([polygon[0].pixel, polygon[i].pixel, polygon[i + 1].pixel])?);
+				triangles.push(three_points_to_triangle([
+					polygon[0].backing,
+					polygon[i + 1].backing,
+					polygon[i].backing,
+				])?);
+			}
+
+			// Generate triangles to connect the pixels to the backing mesh, both along the edges of the image and wherever the outline cuts
+			// through the cell
+			for i in 0..polygon.len() {
+				let a = &polygon[i];
+				let b = &polygon[(i + 1) % polygon.len()];
+				if (a.is_crossing && b.is_crossing) || on_border[a.side] {
+					triangles.push(three_points_to_triangle([a.backing, b.backing, b.pixel])?);
+					triangles.push(three_points_to_triangle([a.backing, b.pixel, a.pixel])?);
+				}
+			}
+		}
 	}
 
-	// Generate triangles to connect the right side of the image to backing mesh
-	for y_i in 0..height - 1 {
-		let current_index = (y_i + 1) * width - 1;
-		let lower_index = (y_i + 2) * width - 1;
-		triangles.push(three_points_to_triangle([
-			point_cloud.vertices[current_index],
-			px_vertices[lower_index],
-			point_cloud.vertices[lower_index],
-		])?);
-		triangles.push(three_points_to_triangle([
-			point_cloud.vertices[current_index],
-			px_vertices[current_index],
-			px_vertices[lower_index],
-		])?);
+	Ok((triangles, thicknesses))
+}
+
+/// Raise depths next to much deeper ones, so no two neighbouring vertices (in a `width` wide grid) differ by more than `max_step`
+///
+/// Each depth is raised to the highest of the other depths minus `max_step` for every step between them, so steep edges turn into ramps
+/// that are as short as possible, and the deeper side (eg black text) keeps its full depth.
+fn limit_slope(depths: &mut [f32], width: usize, max_step: f32) {
+	// Two passes are enough, since every path between two vertices can be taken as steps down and right followed by steps up and left
+	for i in 0..depths.len() {
+		if i % width != 0 {
+			depths[i] = depths[i].max(depths[i - 1] - max_step);
+		}
+		if i >= width {
+			depths[i] = depths[i].max(depths[i - width] - max_step);
+		}
+	}
+	for i in (0..depths.len()).rev() {
+		if i % width != width - 1 {
+			depths[i] = depths[i].max(depths[i + 1] - max_step);
+		}
+		if i + width < depths.len() {
+			depths[i] = depths[i].max(depths[i + width] - max_step);
+		}
 	}
+}
+
+/// Linearly map a gray value to a depth, where white is `white_depth` and black is `black_depth`
+pub(crate) fn pixel_depth(gray_value: u8, white_depth: f32, black_depth: f32) -> f32 {
+	white_depth + (255 - gray_value) as f32 / 255.0 * (black_depth - white_depth)
+}
 
-	Ok(triangles)
+/// Round a depth to a whole number of layers, if there's a layer height, but never less than one layer so the thinnest areas don't disappear
+///
+/// Layer heights that aren't positive are left out, since they'd make every depth NaN or turn it inside out.
+pub(crate) fn snap_to_layers(depth: f32, layer_height: Option<f32>) -> f32 {
+	layer_height.filter(|&h| h > 0.0 && h.is_finite()).map_or(depth, |h| (depth / h).round().max(1.0) * h)
 }
 
 #[derive(Error, Debug)]
@@ -285,19 +2598,19 @@ fn generate_lithophane_mesh(
 pub struct InvalidPointsError {}
 
 /// Turn three points into a triangle, calculating the normal by counterclockwise ordering.
-fn three_points_to_triangle(points: [Vec3; 3]) -> Result<Triangle, InvalidPointsError> {
+pub(crate) fn three_points_to_triangle(points: [Vec3; 3]) -> Result<Triangle, InvalidPointsError> {
 	Ok(Triangle {
 		normal: normalize_to_unit_vector(cross_product(points[1] - points[0], points[2] - points[0]))?,
 		vertices: [points[0], points[1], points[2]],
 	})
 }
 
-fn cross_product(a: Vec3, b: Vec3) -> Vec3 {
+pub(crate) fn cross_product(a: Vec3, b: Vec3) -> Vec3 {
 	[a.y * b.z - b.y * a.z, a.z * b.x - b.z * a.x, a.x * b.y - b.x * a.y].into()
 }
 
 /// Will return Err if the vector has no length
-fn normalize_to_unit_vector(v: Vec3) -> Result<Vec3, InvalidPointsError> {
+pub(crate) fn normalize_to_unit_vector(v: Vec3) -> Result<Vec3, InvalidPointsError> {
 	let length = (v.x * v.x + v.y * v.y + v.z * v.z).sqrt();
 	if length == 0.0 {
 		return Err(InvalidPointsError {});
@@ -305,3 +2618,238 @@ fn normalize_to_unit_vector(v: Vec3) -> Result<Vec3, InvalidPointsError> {
 
 	Ok([v.x / length, v.y / length, v.z / length].into())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	type Expression = fn(f32, f32, f32, f32) -> f32;
+
+	fn plane() -> [Expression; 3] {
+		[|x, _, _, _| x, |_, y, _, _| y, |_, _, _, _| 0.0]
+	}
+
+	#[test]
+	fn preview_region_treats_a_step_of_0_as_1() {
+		let [x_fn, y_fn, z_fn] = plane();
+		let region = Rect {
+			x: 2,
+			y: 3,
+			width: 4,
+			height: 5,
+		};
+		let unstepped = generate_preview_region(x_fn, y_fn, z_fn, 10, 10, region, 0).unwrap();
+		let stepped = generate_preview_region(x_fn, y_fn, z_fn, 10, 10, region, 1).unwrap();
+		assert_eq!(unstepped.triangles.len(), (4 - 1) * (5 - 1) * 2);
+		assert_eq!(unstepped.triangles.len(), stepped.triangles.len());
+	}
+
+	#[test]
+	fn curvature_treats_a_step_of_0_as_1() {
+		let [x_fn, y_fn, z_fn] = plane();
+		let curvature = preview_vertex_curvature(x_fn, y_fn, z_fn, 6, 4, 0, None).unwrap();
+		assert_eq!(curvature.len(), (6 - 1) * (4 - 1) * 2 * 3);
+		assert!(curvature.iter().all(|&c| c == 0.0), "a plane isn't curved");
+	}
+
+	#[test]
+	fn sampling_grid_is_empty_for_empty_images() {
+		for (width, height) in [(0, 0), (0, 5), (5, 0)] {
+			assert!(sampling_grid(width, height, &Default::default()).is_empty(), "{}×{}", width, height);
+		}
+		assert_eq!(sampling_grid(1, 1, &Default::default()), vec![(0.0, 0.0)]);
+	}
+
+	#[test]
+	fn vase_rejects_empty_images() {
+		for (width, height) in [(0, 0), (0, 4), (4, 0), (1, 4)] {
+			assert!(
+				matches!(
+					generate_vase_lithophane(GrayImage::new(width, height), 0.5, 0.8, 3.0),
+					Err(LithophaneError::ImageTooSmall(_))
+				),
+				"{}×{}",
+				width,
+				height
+			);
+		}
+	}
+
+	#[test]
+	fn tube_rejects_empty_images() {
+		for (width, height) in [(0, 0), (0, 4), (4, 0), (4, 1)] {
+			assert!(
+				matches!(
+					generate_tube_lithophane(GrayImage::new(width, height), 0.5, 0.8, 3.0, Cap::Open, Cap::Flat { thickness: 1.0 }),
+					Err(LithophaneError::ImageTooSmall(_))
+				),
+				"{}×{}",
+				width,
+				height
+			);
+		}
+	}
+
+	#[test]
+	fn disc_rejects_empty_images() {
+		for (width, height) in [(0, 0), (0, 4), (4, 0), (1, 1)] {
+			assert!(
+				matches!(
+					generate_disc_lithophane(GrayImage::new(width, height), 0.5, 0.0, 0.8, 3.0),
+					Err(LithophaneError::ImageTooSmall(_))
+				),
+				"{}×{}",
+				width,
+				height
+			);
+		}
+	}
+
+	/// Check every edge is used by exactly two triangles, once in each direction, so the model is a closed solid with consistent
+	/// winding, and return its volume (which is positive when the triangles face out)
+	fn closed_volume(model: &StlModel) -> f32 {
+		let key = |v: Vec3| [v.x.to_bits(), v.y.to_bits(), v.z.to_bits()];
+		let mut edges = std::collections::HashMap::<_, usize>::new();
+		for triangle in &model.triangles {
+			for i in 0..3 {
+				*edges.entry([key(triangle.vertices[i]), key(triangle.vertices[(i + 1) % 3])]).or_default() += 1;
+			}
+		}
+		for (&[a, b], &count) in &edges {
+			assert_eq!((count, edges.get(&[b, a])), (1, Some(&1)), "edge from {:?} to {:?}", a, b);
+		}
+		model
+			.triangles
+			.iter()
+			.map(|t| {
+				let [a, b, c] = t.vertices;
+				a.x * (b.y * c.z - b.z * c.y) + a.y * (b.z * c.x - b.x * c.z) + a.z * (b.x * c.y - b.y * c.x)
+			})
+			.sum::<f32>()
+			/ 6.0
+	}
+
+	fn assert_close(actual: f32, expected: f32, what: &str) {
+		assert!((actual - expected).abs() < 1e-3, "{} is {} instead of {}", what, actual, expected);
+	}
+
+	#[test]
+	fn vase_is_a_closed_cylinder() {
+		let white = generate_vase_lithophane(GrayImage::from_pixel(16, 5, Luma([255])), 0.5, 0.8, 3.0).unwrap();
+		// Two triangles for each pixel of the wall between rows, and one for each column in the top and bottom
+		assert_eq!(white.triangles.len(), 16 * 4 * 2 + 16 * 2);
+		let volume = closed_volume(&white);
+		let radius = 16.0 * 0.5 / std::f32::consts::TAU + 0.8;
+		let (min, max) = crate::mesh::bounding_box(&white).unwrap();
+		assert_close(max.x, radius, "the radius");
+		assert_close(min.x, -radius, "the radius");
+		assert_close(min.z, 0.0, "the bottom");
+		assert_close(max.z, 4.0 * 0.5, "the top");
+		// The cross section is a regular polygon with a vertex for each column
+		let area = 16.0 / 2.0 * radius * radius * (std::f32::consts::TAU / 16.0).sin();
+		assert_close(volume, area * 2.0, "the volume");
+
+		// Darker pixels stick out further, making the vase bigger
+		let black = generate_vase_lithophane(GrayImage::from_pixel(16, 5, Luma([0])), 0.5, 0.8, 3.0).unwrap();
+		assert!(closed_volume(&black) > volume);
+		assert_close(crate::mesh::bounding_box(&black).unwrap().1.x, radius - 0.8 + 3.0, "the black radius");
+	}
+
+	#[test]
+	fn tubes_are_closed_with_any_caps() {
+		let image = GrayImage::from_fn(16, 6, |x, y| Luma([(x * 16 + y * 8) as u8]));
+		let caps = [Cap::Open, Cap::Flat { thickness: 0.5 }, Cap::Dome { height: 1.0, thickness: 0.5 }];
+		let mut volumes = Vec::new();
+		for top_cap in caps {
+			for bottom_cap in caps {
+				let tube = generate_tube_lithophane(image.clone(), 0.5, 0.8, 3.0, top_cap, bottom_cap).unwrap();
+				let volume = closed_volume(&tube);
+				assert!(volume > 0.0, "{:?} and {:?} face in", top_cap, bottom_cap);
+				volumes.push(volume);
+
+				let (min, max) = crate::mesh::bounding_box(&tube).unwrap();
+				let dome = |cap| if let Cap::Dome { height, .. } = cap { height } else { 0.0 };
+				assert_close(min.z, -dome(bottom_cap), "the bottom");
+				assert_close(max.z, 5.0 * 0.5 + dome(top_cap), "the top");
+			}
+		}
+		// Caps add to the wall, and flat ones fill the ends (domes are thinner, but stick out)
+		let open = volumes[0];
+		assert!(volumes[1..].iter().all(|&v| v > open), "{:?}", volumes);
+		assert!(volumes[4] > volumes[1]);
+	}
+
+	#[test]
+	fn open_tube_has_a_smooth_inside() {
+		let tube = generate_tube_lithophane(GrayImage::from_pixel(16, 3, Luma([0])), 0.5, 0.8, 3.0, Cap::Open, Cap::Open).unwrap();
+		// Two triangles for each column between the outside's three rows, the inside's top and bottom, and the ends joining them
+		assert_eq!(tube.triangles.len(), (2 + 1 + 2) * 16 * 2);
+		let radius = 16.0 * 0.5 / std::f32::consts::TAU;
+		let polygon_area = |r: f32| 16.0 / 2.0 * r * r * (std::f32::consts::TAU / 16.0).sin();
+		assert_close(
+			closed_volume(&tube),
+			(polygon_area(radius + 3.0) - polygon_area(radius)) * 2.0 * 0.5,
+			"the volume",
+		);
+	}
+
+	#[test]
+	fn disc_is_a_closed_round_solid() {
+		let image = GrayImage::from_pixel(21, 21, Luma([255]));
+		for hole_diameter in [0.0, 2.0] {
+			let disc = generate_disc_lithophane(image.clone(), 0.5, hole_diameter, 0.8, 3.0).unwrap();
+			let volume = closed_volume(&disc);
+			let (min, max) = crate::mesh::bounding_box(&disc).unwrap();
+			let radii = disc.triangles.iter().flat_map(|t| t.vertices).map(|v| v.x.hypot(v.y));
+			let (hole_radius, radius) = radii.fold((f32::INFINITY, 0.0f32), |(low, high), r| (low.min(r), high.max(r)));
+			// The largest circle that fits is 20 pixels across
+			assert_close(radius, 5.0, "the radius");
+			assert_close(hole_radius, hole_diameter / 2.0, "the hole's radius");
+			assert_close(min.z, 0.0, "the back");
+			assert_close(max.z, 0.8, "the front");
+			let hole_area = std::f32::consts::PI * (hole_diameter / 2.0).powi(2);
+			let expected = (std::f32::consts::PI * 25.0 - hole_area) * 0.8;
+			assert!(
+				(volume - expected).abs() < expected * 0.02,
+				"the volume is {} instead of about {}",
+				volume,
+				expected
+			);
+		}
+	}
+
+	#[test]
+	fn wedge_stands_a_tilted_lithophane_up() {
+		use crate::mesh::{bounding_box, is_stable, stand_up};
+
+		let [x_fn, y_fn, z_fn]: [Expression; 3] = [|x, _, _, _| x * 0.5, |_, y, _, _| -y * 0.5, |_, _, _, _| 0.0];
+		let image = GrayImage::from_fn(20, 30, |x, y| Luma([((x + y) * 6) as u8]));
+		let generate = |wedge| {
+			let options = LithophaneOptions {
+				skirt: Some(Skirt {
+					height: 8.0,
+					depth: 3.0,
+					wedge,
+				}),
+				..Default::default()
+			};
+			let mut lithophane = generate_lithophane(x_fn, y_fn, z_fn, image.clone(), &options).unwrap();
+			assert!(closed_volume(&lithophane) > 0.0);
+			stand_up(&mut lithophane, 15.0);
+			lithophane
+		};
+		assert!(!is_stable(&generate(None), 0.01), "a thin panel leaning back tips over");
+
+		for length in [None, Some(12.0)] {
+			let lithophane = generate(Some(Wedge { tilt: 15.0, length }));
+			assert!(is_stable(&lithophane, 0.01), "{:?} long", length);
+			// The whole flat bottom is resting on the ground, not just an edge
+			let (min, max) = bounding_box(&lithophane).unwrap();
+			let bottom =
+				lithophane.triangles.iter().filter(|t| t.vertices.iter().all(|v| v.z < min.z + 0.01)).map(|t| t.vertices).collect::<Vec<_>>();
+			let depth = bottom.iter().flatten().map(|v| v.y).fold(f32::NEG_INFINITY, f32::max)
+				- bottom.iter().flatten().map(|v| v.y).fold(f32::INFINITY, f32::min);
+			assert_close(depth, length.unwrap_or(max.y - min.y), "the length of the bottom");
+		}
+	}
+}
@@ -0,0 +1,326 @@
+use std::collections::HashSet;
+
+use image::GrayImage;
+use pk_stl::{geometry::Vec3, StlModel};
+
+use crate::lithophane::{cross_product, normalize_to_unit_vector, pixel_depth, three_points_to_triangle, InvalidPointsError};
+
+/// A faceted, low-poly lithophane, where the image is grouped into regions of similar tones (superpixels) and the front is made of a small
+/// number of large flat triangles between them
+///
+/// Each region becomes a vertex at its center, as deep as the region's average tone, and the vertices are joined by a Delaunay
+/// triangulation, with more vertices along the edges of the image to keep it rectangular. Besides the look, it makes very small files.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LowPoly {
+	/// Roughly how many triangles the front is made of (the back has as many, plus a few for the walls around the edges)
+	pub triangles: u32,
+	/// How much the regions keep to a regular grid rather than following the tones of the image, in gray values per region width, where
+	/// lower values (eg 5) follow edges in the image more closely and higher ones (eg 40) give more even facets
+	pub compactness: f32,
+}
+
+impl Default for LowPoly {
+	fn default() -> Self {
+		Self {
+			triangles: 1000,
+			compactness: 20.0,
+		}
+	}
+}
+
+/// The number of times the regions are moved to the center of the pixels closest to them
+const ITERATIONS: u32 = 10;
+
+impl LowPoly {
+	/// Create a low-poly lithophane, using three functions to translate x and y coordinates from the image into x,y,z coordinates like
+	/// `generate_lithophane`
+	pub fn generate<F: Fn(f32, f32, f32, f32) -> f32>(
+		&self,
+		x_fn: F,
+		y_fn: F,
+		z_fn: F,
+		image: &GrayImage,
+		white_depth: f32,
+		black_depth: f32,
+	) -> Result<StlModel, InvalidPointsError> {
+		let (width, height) = image.dimensions();
+		let (right, bottom) = ((width - 1) as f32, (height - 1) as f32);
+
+		// Every triangulated point makes about two triangles
+		let (centers, labels) = superpixels(image, (self.triangles / 2).max(1), self.compactness);
+		let spacing = ((width * height) as f32 / centers.len() as f32).sqrt();
+
+		// Points along the edges take the tone of the region they're in, and centers too close to an edge or another point are left out so
+		// there aren't any slivers
+		let mut points = Vec::<(f32, f32, f32)>::new();
+		let edge_points = |length: f32| {
+			let count = (length / spacing).ceil().max(1.0) as u32;
+			(0..=count).map(move |i| length * i as f32 / count as f32)
+		};
+		let edge_gray = |x: f32, y: f32| centers[labels[(y.round() as u32 * width + x.round() as u32) as usize]].2;
+		for x in edge_points(right) {
+			points.push((x, 0.0, edge_gray(x, 0.0)));
+			points.push((x, bottom, edge_gray(x, bottom)));
+		}
+		for y in edge_points(bottom).filter(|&y| y > 0.0 && y < bottom) {
+			points.push((0.0, y, edge_gray(0.0, y)));
+			points.push((right, y, edge_gray(right, y)));
+		}
+		let margin = (spacing / 4.0).max(0.5);
+		for &(x, y, gray) in &centers {
+			let near_edge = x.min(y).min(right - x).min(bottom - y) < margin;
+			if !near_edge && points.iter().all(|p| (p.0 - x).powi(2) + (p.1 - y).powi(2) >= margin * margin) {
+				points.push((x, y, gray));
+			}
+		}
+
+		let (width_f32, height_f32) = (width as f32, height as f32);
+		let position = |x: f32, y: f32| Vec3 {
+			x: x_fn(x, y, width_f32, height_f32),
+			y: y_fn(x, y, width_f32, height_f32),
+			z: z_fn(x, y, width_f32, height_f32),
+		};
+		let mut backs = Vec::with_capacity(points.len());
+		let mut fronts = Vec::with_capacity(points.len());
+		for &(x, y, gray) in &points {
+			// The normal is worked out the same way as for the point clouds, from the points below and to the right
+			let surface = position(x, y);
+			let normal = normalize_to_unit_vector(cross_product(position(x, y + 1.0) - surface, position(x + 1.0, y) - surface))?;
+			let depth = pixel_depth(gray.round().clamp(0.0, 255.0) as u8, white_depth, black_depth);
+			backs.push(surface);
+			fronts.push(surface + normal * depth);
+		}
+
+		let faces = delaunay(&points.iter().map(|&(x, y, _)| (x as f64, y as f64)).collect::<Vec<_>>());
+		let mut triangles = Vec::with_capacity(faces.len() * 2 + points.len() * 2);
+		for &[a, b, c] in &faces {
+			triangles.push(three_points_to_triangle([fronts[a], fronts[b], fronts[c]])?);
+			triangles.push(three_points_to_triangle([backs[a], backs[c], backs[b]])?);
+		}
+
+		// Edges that only one face goes along (in its direction) are around the outside, so they get walls between the front and back
+		let edges = faces.iter().flat_map(|&[a, b, c]| [(a, b), (b, c), (c, a)]).collect::<HashSet<_>>();
+		for &(a, b) in edges.iter().filter(|&&(a, b)| !edges.contains(&(b, a))) {
+			triangles.push(three_points_to_triangle([backs[a], backs[b], fronts[b]])?);
+			triangles.push(three_points_to_triangle([backs[a], fronts[b], fronts[a]])?);
+		}
+
+		Ok(StlModel {
+			header: String::new(),
+			triangles,
+		})
+	}
+}
+
+/// Group the pixels of an image into about `count` regions of similar tones (simple linear iterative clustering), returning the center
+/// and average gray value of each region, and the region each pixel is in
+fn superpixels(image: &GrayImage, count: u32, compactness: f32) -> (Vec<(f32, f32, f32)>, Vec<usize>) {
+	let (width, height) = image.dimensions();
+	let spacing = ((width * height) as f32 / count as f32).sqrt().max(1.0);
+	let (columns, rows) = (
+		((width as f32 / spacing).round() as u32).max(1),
+		((height as f32 / spacing).round() as u32).max(1),
+	);
+
+	// Start with the regions in a grid
+	let mut centers = (0..rows)
+		.flat_map(|row| (0..columns).map(move |column| (column, row)))
+		.map(|(column, row)| {
+			let x = ((column as f32 + 0.5) * width as f32 / columns as f32).floor().min((width - 1) as f32);
+			let y = ((row as f32 + 0.5) * height as f32 / rows as f32).floor().min((height - 1) as f32);
+			(x, y, image.get_pixel(x as u32, y as u32).0[0] as f32)
+		})
+		.collect::<Vec<_>>();
+
+	let mut labels = vec![0; (width * height) as usize];
+	let mut distances = vec![f32::INFINITY; (width * height) as usize];
+	for _ in 0..ITERATIONS {
+		// Each pixel goes to the closest region within twice the spacing, counting differences in tone as distance too
+		distances.fill(f32::INFINITY);
+		for (i, &(cx, cy, gray)) in centers.iter().enumerate() {
+			let window =
+				|center: f32, length: u32| (center - spacing * 2.0).floor().max(0.0) as u32..((center + spacing * 2.0).ceil() as u32 + 1).min(length);
+			for y in window(cy, height) {
+				for x in window(cx, width) {
+					let tone = (image.get_pixel(x, y).0[0] as f32 - gray) / compactness;
+					let distance = ((x as f32 - cx).powi(2) + (y as f32 - cy).powi(2)) / (spacing * spacing) + tone * tone;
+					let pixel = (y * width + x) as usize;
+					if distance < distances[pixel] {
+						distances[pixel] = distance;
+						labels[pixel] = i;
+					}
+				}
+			}
+		}
+
+		// Then each region moves to the middle of its pixels. Regions that didn't get any stay where they are.
+		let mut totals = vec![(0.0f64, 0.0f64, 0.0f64, 0u32); centers.len()];
+		for (pixel, &label) in labels.iter().enumerate() {
+			let (x, y) = (pixel as u32 % width, pixel as u32 / width);
+			let total = &mut totals[label];
+			total.0 += x as f64;
+			total.1 += y as f64;
+			total.2 += image.get_pixel(x, y).0[0] as f64;
+			total.3 += 1;
+		}
+		for (center, &(x, y, gray, count)) in centers.iter_mut().zip(&totals) {
+			if count > 0 {
+				let count = count as f64;
+				*center = ((x / count) as f32, (y / count) as f32, (gray / count) as f32);
+			}
+		}
+	}
+	(centers, labels)
+}
+
+/// Triangulate points so no point is inside the circle through the corners of any triangle (Bowyer–Watson), returning the corners of each
+/// triangle in the same order as the squares of a lithophane's mesh are split (counterclockwise on screen, with y pointing down)
+fn delaunay(points: &[(f64, f64)]) -> Vec<[usize; 3]> {
+	// Start with a triangle around all the points, whose corners are removed again at the end
+	let (min_x, max_x) = points.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), p| (lo.min(p.0), hi.max(p.0)));
+	let (min_y, max_y) = points.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), p| (lo.min(p.1), hi.max(p.1)));
+	let size = (max_x - min_x).max(max_y - min_y).max(1.0) * 20.0;
+	let (mid_x, mid_y) = ((min_x + max_x) / 2.0, (min_y + max_y) / 2.0);
+	let mut all = points.to_vec();
+	all.extend([(mid_x - size, mid_y - size), (mid_x, mid_y + size), (mid_x + size, mid_y - size)]);
+	let n = points.len();
+
+	let circumcircle = |[a, b, c]: [usize; 3], all: &[(f64, f64)]| {
+		let ((ax, ay), (bx, by), (cx, cy)) = (all[a], all[b], all[c]);
+		let d = 2.0 * (ax * (by - cy) + bx * (cy - ay) + cx * (ay - by));
+		let (a2, b2, c2) = (ax * ax + ay * ay, bx * bx + by * by, cx * cx + cy * cy);
+		let ux = (a2 * (by - cy) + b2 * (cy - ay) + c2 * (ay - by)) / d;
+		let uy = (a2 * (cx - bx) + b2 * (ax - cx) + c2 * (bx - ax)) / d;
+		(ux, uy, (ax - ux).powi(2) + (ay - uy).powi(2))
+	};
+
+	let mut triangles = vec![([n, n + 1, n + 2], circumcircle([n, n + 1, n + 2], &all))];
+	let mut edges = Vec::new();
+	for p in 0..n {
+		let (px, py) = all[p];
+		// Remove every triangle whose circle the point is in, and fill the hole with triangles from its edges to the point
+		edges.clear();
+		triangles.retain(|&([a, b, c], (ux, uy, r2))| {
+			if (px - ux).powi(2) + (py - uy).powi(2) < r2 {
+				edges.extend([(a, b), (b, c), (c, a)]);
+				false
+			} else {
+				true
+			}
+		});
+		for &(a, b) in &edges {
+			// Edges between two removed triangles are inside the hole
+			if edges.iter().filter(|&&(c, d)| (c, d) == (a, b) || (c, d) == (b, a)).count() == 1 {
+				triangles.push(([a, b, p], circumcircle([a, b, p], &all)));
+			}
+		}
+	}
+
+	triangles
+		.into_iter()
+		.map(|(corners, _)| corners)
+		.filter(|corners| corners.iter().all(|&i| i < n))
+		.map(|[a, b, c]| {
+			let ((ax, ay), (bx, by), (cx, cy)) = (all[a], all[b], all[c]);
+			if (bx - ax) * (cy - ay) - (by - ay) * (cx - ax) < 0.0 {
+				[a, b, c]
+			} else {
+				[a, c, b]
+			}
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use image::Luma;
+
+	use super::*;
+	use crate::{lithophane::random, mesh::boundary_edges};
+
+	type Expression = fn(f32, f32, f32, f32) -> f32;
+
+	/// A square's corners with points scattered inside it
+	fn scattered_points(count: u32) -> Vec<(f64, f64)> {
+		let mut points = vec![(0.0, 0.0), (100.0, 0.0), (100.0, 100.0), (0.0, 100.0)];
+		points.extend((0..count).map(|i| (1.0 + random(i, 0, 7) as f64 * 98.0, 1.0 + random(i, 1, 7) as f64 * 98.0)));
+		points
+	}
+
+	#[test]
+	fn delaunay_covers_the_points_with_the_expected_number_of_triangles() {
+		for count in [0, 1, 10, 200] {
+			let points = scattered_points(count);
+			let triangles = delaunay(&points);
+			// Any triangulation of n points with h of them on the convex hull has 2n - h - 2 triangles, and the 4 corners are the hull here
+			assert_eq!(triangles.len(), 2 * points.len() - 4 - 2, "{} points", points.len());
+			// The corners go clockwise with y pointing up, which is counterclockwise on screen
+			let areas: Vec<f64> = triangles
+				.iter()
+				.map(|&[a, b, c]| {
+					let ((ax, ay), (bx, by), (cx, cy)) = (points[a], points[b], points[c]);
+					((bx - ax) * (cy - ay) - (by - ay) * (cx - ax)) / 2.0
+				})
+				.collect();
+			assert!(areas.iter().all(|&area| area < 0.0), "the triangles don't all go the same way around");
+			let area = -areas.iter().sum::<f64>();
+			assert!((area - 100.0 * 100.0).abs() < 1e-6, "the triangles cover {} instead of the square", area);
+		}
+	}
+
+	#[test]
+	fn delaunay_leaves_every_circumcircle_empty() {
+		let points = scattered_points(100);
+		for [a, b, c] in delaunay(&points) {
+			let ((ax, ay), (bx, by), (cx, cy)) = (points[a], points[b], points[c]);
+			let d = 2.0 * (ax * (by - cy) + bx * (cy - ay) + cx * (ay - by));
+			let (a2, b2, c2) = (ax * ax + ay * ay, bx * bx + by * by, cx * cx + cy * cy);
+			let ux = (a2 * (by - cy) + b2 * (cy - ay) + c2 * (ay - by)) / d;
+			let uy = (a2 * (cx - bx) + b2 * (ax - cx) + c2 * (bx - ax)) / d;
+			let r2 = (ax - ux).powi(2) + (ay - uy).powi(2);
+			for (i, &(px, py)) in points.iter().enumerate().filter(|&(i, _)| ![a, b, c].contains(&i)) {
+				assert!(
+					(px - ux).powi(2) + (py - uy).powi(2) >= r2 * (1.0 - 1e-9),
+					"point {} is inside the circle of {:?}",
+					i,
+					[a, b, c]
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn superpixels_label_every_pixel() {
+		let image = GrayImage::from_fn(40, 30, |x, y| Luma([if x < 20 { 30 } else { 220 } + (y % 3) as u8]));
+		let (centers, labels) = superpixels(&image, 12, 20.0);
+		assert_eq!(labels.len(), 40 * 30);
+		assert!(labels.iter().all(|&label| label < centers.len()));
+		assert!((6..=24).contains(&centers.len()), "{} regions instead of about 12", centers.len());
+		assert!(centers.iter().all(|&(x, y, gray)| (0.0..40.0).contains(&x) && (0.0..30.0).contains(&y) && (0.0..=255.0).contains(&gray)));
+	}
+
+	#[test]
+	fn low_poly_lithophanes_are_watertight() {
+		let image = GrayImage::from_fn(60, 40, |x, y| Luma([((x * 4 + y * 2) % 256) as u8]));
+		for triangles in [20, 200, 1000] {
+			let low_poly = LowPoly {
+				triangles,
+				..Default::default()
+			};
+			let [x_fn, y_fn, z_fn]: [Expression; 3] = [|x, _, _, _| x, |_, y, _, _| -y, |_, _, _, _| 0.0];
+			let flat = low_poly.generate(x_fn, y_fn, z_fn, &image, 0.5, 3.0).unwrap();
+			assert!(boundary_edges(&flat).is_empty(), "{} triangles has holes", triangles);
+			// The flat back has a triangle for each one on the front, and the walls have two for each edge around the outside
+			let back = flat.triangles.iter().filter(|t| t.normal.z < -0.999).count();
+			let walls = flat.triangles.iter().filter(|t| t.normal.z.abs() < 1e-4).count();
+			assert_eq!(flat.triangles.len(), back * 2 + walls);
+			assert!(walls.is_multiple_of(2));
+			assert!(
+				back as u32 >= triangles / 2 && back as u32 <= triangles * 2,
+				"{} front triangles instead of about {}",
+				back,
+				triangles
+			);
+		}
+	}
+}
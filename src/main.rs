@@ -1,25 +1,952 @@
-use std::{fs::OpenOptions, io::Write, process::ExitCode};
+use std::{
+	fs::{File, OpenOptions},
+	io::Write,
+	path::Path,
+	process::{Command, ExitCode, ExitStatus, Stdio},
+};
+#[cfg(feature = "server")]
+use std::{net::TcpListener, time::Duration};
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 
-use lithophane_generator::lithophane::generate_lithophane;
+use image::{DynamicImage, GrayImage, ImageFormat};
+use lithophane_generator::{
+	backlight::{brightness_compensation, simulate_backlight, Material},
+	bookmark::Bookmark,
+	clock::{ClockFace, HourMarkers},
+	corner::CornerWrap,
+	detail::{detail_report, thin_features},
+	expression::{compile_expressions, AngleUnit, CompiledExpression, Sandbox},
+	gcode::{flat_lithophane_gcode, GcodeError, GcodeSettings},
+	input::{decode_frame, decode_frame_as_srgb},
+	label::{check_label_text, Label},
+	lithophane::{
+		arc_length_resample, extend_to_strip, generate_base_and_relief, generate_lithophane, generate_lithophane_with_thickness_stats,
+		generate_puzzle_pieces, generate_rgb_stripes, lithophane_triangle_count, pixel_size, stretched_region, surface_size, Contours, DepthMask,
+		DepthMaskMode, Easing, Emboss, FrameFit, Hatching, LithophaneError, LithophaneOptions, NormalAveraging, Part, RadialFade, SheetRidge, Skirt,
+		Stipple, StippleKind, ThicknessStats, Tiling, Wedge, THICKNESS_PERCENTILES,
+	},
+	low_poly::LowPoly,
+	manifest::{hash_input, json_string, Manifest},
+	mesh::{bounding_box, canonicalize, quantize, repair, scale, split, stand_up, IndexedMesh},
+	nightlight::NightlightPanel,
+	outline::{Outline, Shape},
+	output::{
+		as_binary, as_obj, as_obj_objects, check_stl_header, compress_gzip, estimated_size, max_triangles_for_size, metadata_header, OutputFormat,
+		Shading,
+	},
+	pendant::Pendant,
+	photo_frame::{AspectFit, PhotoFrameInsert, PhotoSize},
+	preprocessing::{
+		add_matte, composite, correct_perspective, dodge_and_burn, flatten_background, map_image, resize_area_average, rotate, split_channels,
+		to_grayscale, white_balance, BlendMode, EdgeMode, Filter, Grayscale, ImageFilter, ImageMapping, Overlay, Pipeline, ToneMapping, WhiteBalance,
+	},
+	presets::{load_user_catalog, Catalog},
+	printer::{estimate_print_time, BuildVolume, PrintSettings, Printer},
+	puzzle::Puzzle,
+	resin::{ResinLayers, ThicknessMap},
+	shadow_box::ShadowBox,
+	suggest::suggest_shapes,
+	tactile::Tactile,
+	terrain::{ElevationGrid, Terrain},
+	warnings::{
+		aspect_warning, clipping_warnings, detail_warning, quality_warnings, stretched_pixels_warning, strip_warning, tactile_warning,
+		thin_feature_warning, QualityChecks, STRETCHED_PIXEL_RATIO,
+	},
+};
+#[cfg(feature = "server")]
+use lithophane_generator::{
+	jobs::{JobLimits, JobQueue, JobStore},
+	server::{self, ServerLimits},
+};
+use pk_stl::StlModel;
 
-#[derive(Parser, Debug)]
+/// How deep the surface can be, in millimeters, for --resin-layers and --gcode to treat it as flat
+const FLATNESS: f32 = 0.001;
+
+/// What coordinates are rounded to for --canonical when --precision isn't set, in millimeters
+const CANONICAL_PRECISION: f32 = 0.0001;
+
+#[derive(Parser, Debug, Clone)]
 #[command(author, version, about)]
 struct Cli {
+	/// The image to make a lithophane of (PNG, JPEG, GIF, WebP, Radiance HDR, or AVIF, OpenEXR and TIFF with those features), which can
+	/// also be an http:// or https:// URL when built with the url-input feature
 	#[arg(short, long)]
 	input: String,
+	/// Images to composite onto the input image in order, given as "path", "path:mode" or "path:mode:opacity", where the mode is
+	/// multiply (the default), screen or average and the opacity is from 0 to 1 (eg "watermark.png:multiply:0.5")
+	#[arg(long, value_parser = parse_overlay)]
+	overlay: Vec<OverlayArg>,
+	/// The file to save, which is saved as OBJ if the name ends with ".obj" and STL otherwise, and compressed with gzip if it ends with ".gz"
 	#[arg(short, long)]
 	output: String,
-	x_expression: String,
-	y_expression: String,
-	z_expression: String,
+	/// Crop the image to the largest circle or ellipse that fits inside it
+	#[arg(long, value_enum, conflicts_with = "crop_polygon")]
+	crop: Option<Crop>,
+	/// Crop the image to a polygon, given as points in normalized image coordinates (eg "0,0 1,0 0.5,1")
+	#[arg(long, num_args = 3.., value_parser = parse_point)]
+	crop_polygon: Vec<(f32, f32)>,
+	/// Fade the thickness of a --crop circle or ellipse to --radial-fade-depth towards its edge, starting this far out as a fraction of the
+	/// radius (eg 0.7), so a round nightlight blends smoothly into its rim
+	#[arg(long, requires = "crop")]
+	radial_fade: Option<f32>,
+	/// The thickness at the edge of the --radial-fade in millimeters
+	#[arg(long, default_value_t = LithophaneOptions::default().black_depth, requires = "radial_fade")]
+	radial_fade_depth: f32,
+	/// How the --radial-fade eases from the image to the edge
+	#[arg(long, value_enum, default_value_t = EasingArg::Smooth, requires = "radial_fade")]
+	radial_fade_easing: EasingArg,
+	/// A grayscale image that scales the depth range, where white keeps the full range and black flattens it
+	#[arg(long)]
+	depth_mask: Option<String>,
+	/// A grayscale image to locally brighten (white) or darken (black) the image with before it's turned into depths, where mid gray leaves
+	/// it unchanged, eg to lift a face out of the shadows
+	#[arg(long)]
+	dodge_burn: Option<String>,
+	/// How strongly the --dodge-burn mask is applied, from 0 to 1
+	#[arg(long, default_value_t = 0.5, requires = "dodge_burn")]
+	dodge_burn_strength: f32,
+	/// Turn near-uniform background regions white, where the value is the maximum local standard deviation (0-255) of the background
+	#[arg(long)]
+	flatten_background: Option<f32>,
+	/// Extra preprocessing steps, applied in order after the other preprocessing (eg "gamma:1.5 denoise:2:20")
+	#[arg(long, num_args = 1..)]
+	filter: Vec<Filter>,
+	/// Apply a tone curve from the catalog after the other preprocessing, either built in ("linear") or defined in the user's config
+	/// directory (see `presets::Catalog`)
+	#[arg(long)]
+	curve: Option<String>,
+	/// Rotate the image clockwise by this many degrees as soon as it's made grayscale, before the overlays, masks and any other
+	/// preprocessing, eg to straighten a crooked scan, cropping off the empty corners
+	#[arg(long, allow_negative_numbers = true)]
+	rotate: Option<f32>,
+	/// Correct the perspective of a photo taken at an angle by stretching the area between four corners (top left, top right, bottom right,
+	/// bottom left) into a rectangle, given in normalized image coordinates (eg "0.1,0.05 0.9,0 1,1 0,0.95")
+	#[arg(long, num_args = 4, value_parser = parse_point)]
+	perspective: Vec<(f32, f32)>,
+	/// Shrink the image so neither side is longer than this many pixels
+	#[arg(long)]
+	max_size: Option<u32>,
+	/// The number of vertices per pixel along each axis, interpolating between pixels to smooth out low resolution images
+	#[arg(long, default_value_t = 1)]
+	subdivisions: u32,
+	/// Only make a vertex for about every this many pixels along each axis, averaging the pixels in between, for a quicker, lower
+	/// resolution print without resizing the image
+	#[arg(long, default_value_t = 1)]
+	sample_step: u32,
+	/// The largest difference in thickness between neighbouring pixels, so sharp edges (eg text) become short ramps instead of walls
+	#[arg(long)]
+	max_slope: Option<f32>,
+	/// Flatten the tones into this many plateaus, for a stepped look like a topographic map
+	#[arg(long)]
+	contours: Option<u32>,
+	/// How many degrees the walls between --contours plateaus lean away from vertical, turning them into ramps (eg 45)
+	#[arg(long, requires = "contours", conflicts_with = "max_slope")]
+	contour_wall_angle: Option<f32>,
+	/// Make a tactile graphic to read by touch instead of a lithophane to hold up to the light, with a few clearly raised levels (dark
+	/// areas highest) and details too small to feel smoothed away
+	#[arg(long, conflicts_with_all = ["contours", "max_slope", "stipple", "hatching", "terrain", "clock", "shadow_box", "rgb_stripes"])]
+	tactile: bool,
+	/// The number of heights of a --tactile graphic, including the base
+	#[arg(long, default_value_t = Tactile::default().levels, requires = "tactile")]
+	tactile_levels: u32,
+	/// How far the highest level of a --tactile graphic stands above the base, in millimeters
+	#[arg(long, default_value_t = Tactile::default().relief, requires = "tactile")]
+	tactile_relief: f32,
+	/// Show the tones with a pattern of dots instead of the thickness: holes through an opaque panel, more where the image is lighter (eg
+	/// for a star map), or round bumps on a thin panel, more where it's darker
+	#[arg(long, value_enum, conflicts_with_all = ["contours", "tile"])]
+	stipple: Option<StippleArg>,
+	/// The distance between neighbouring --stipple dots where they're densest, in millimeters
+	#[arg(long, default_value_t = 2.0, requires = "stipple")]
+	stipple_spacing: f32,
+	/// The radius of the --stipple dots in millimeters, which is also how high the bumps are
+	#[arg(long, default_value_t = 0.4, requires = "stipple")]
+	stipple_radius: f32,
+	/// Where the --stipple dots go is random, but always the same for the same seed
+	#[arg(long, default_value_t = 0, requires = "stipple")]
+	stipple_seed: u32,
+	/// Show the tones with raised lines this many millimeters apart instead of the thickness, wider where the image is darker, like an
+	/// engraving
+	#[arg(long, conflicts_with_all = ["contours", "stipple"])]
+	hatching: Option<f32>,
+	/// The direction of the --hatching lines in degrees counterclockwise from horizontal
+	#[arg(long, default_value_t = 45.0, requires = "hatching")]
+	hatching_angle: f32,
+	/// How high the --hatching lines are in millimeters, above the white depth
+	#[arg(long, default_value_t = 1.0, requires = "hatching")]
+	hatching_height: f32,
+	/// Add --hatching lines across the first ones for the darker half of the tones
+	#[arg(long, requires = "hatching")]
+	crosshatch: bool,
+	/// Round all thicknesses to multiples of the layer height the lithophane will be sliced with, in millimeters
+	#[arg(long, value_parser = parse_positive)]
+	layer_height: Option<f32>,
+	/// Add this many millimeters to all thicknesses (or remove them, if negative) to compensate for first layer squish, eg so depths
+	/// calibrated on one printer or filament carry over to another
+	#[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+	thickness_offset: f32,
+	/// Only generate the image or only the frame (the --frame-border, --skirt-height and reinforcement around holes), to print them in
+	/// different filaments
+	#[arg(long, value_enum)]
+	part: Option<PartArg>,
+	/// Split the lithophane this many millimeters from the back into a base and the relief on top of it, to print with two filaments (eg a
+	/// colored base and a white relief), saved as objects in one OBJ file or as separate STL files (eg "out.base.stl")
+	#[arg(long, conflicts_with_all = [
+		"part", "clock", "rgb_stripes", "shadow_box", "stand", "printer", "bed", "max_part_triangles", "max_part_mb", "precision", "repair",
+		"manifest", "post_cmd", "dry_run",
+	])]
+	base_depth: Option<f32>,
+	/// Cut the lithophane into interlocking jigsaw pieces, this many columns by this many rows (eg "3x2"), to print one that's bigger than
+	/// the bed, saved as objects in one OBJ file or as separate STL files (eg "out.1-2.stl" for the second piece in the first row)
+	#[arg(long, value_parser = parse_tiles, conflicts_with_all = [
+		"crop", "crop_polygon", "nightlight", "clock", "rgb_stripes", "shadow_box", "base_depth", "stand", "printer", "bed",
+		"max_part_triangles", "max_part_mb", "precision", "repair", "manifest", "post_cmd", "dry_run",
+	])]
+	puzzle: Option<(u32, u32)>,
+	/// Choose a different random layout for the tabs of the --puzzle pieces
+	#[arg(long, default_value_t = 0, requires = "puzzle")]
+	puzzle_seed: u32,
+	/// The gap between --puzzle pieces in millimeters, so they can be pushed together after printing
+	#[arg(long, default_value_t = 0.2, requires = "puzzle")]
+	puzzle_clearance: f32,
+	/// The relative brightness of the light behind each --puzzle piece, row by row (eg "1,0.8,1,0.9"), when each is lit by its own LED
+	/// panel, so the brighter ones are made thicker to match (see --attenuation)
+	#[arg(long, value_delimiter = ',', requires = "puzzle")]
+	piece_brightness: Vec<f32>,
+	/// How to tone map high dynamic range images
+	#[arg(long, value_enum, default_value_t = ToneMappingArg::Reinhard)]
+	tone_mapping: ToneMappingArg,
+	/// How to turn colors into gray values: luma like most image editors, or CIELAB lightness, which tracks how bright colors look more
+	/// closely
+	#[arg(long, value_enum, default_value_t = GrayscaleArg::Luma)]
+	grayscale: GrayscaleArg,
+	/// Remove a color cast (eg from indoor tungsten light) before turning the colors into gray values, by assuming the image averages out
+	/// to gray or that its lightest parts (see --white-balance-percentile) are white
+	#[arg(long, value_enum)]
+	white_balance: Option<WhiteBalanceArg>,
+	/// The percentile of each channel that's made the same by --white-balance percentile, below 100 so a few highlights don't decide it
+	#[arg(long, default_value_t = 99.0, requires = "white_balance")]
+	white_balance_percentile: f32,
+	/// The frame to use from animated images (GIF or APNG), or the page of a multi-page TIFF, starting at 0
+	#[arg(long, default_value_t = 0)]
+	frame: usize,
+	/// Read the image's values as sRGB even if it has an embedded color profile, like versions before profiles were supported did
+	#[arg(long)]
+	ignore_color_profile: bool,
+	/// Use smooth shading (averaged vertex normals) instead of flat shading for OBJ output
+	#[arg(long)]
+	smooth: bool,
+	/// Weld nearly coincident vertices, remove degenerate and duplicate triangles, and fill small holes before saving
+	#[arg(long)]
+	repair: bool,
+	/// Merge vertices closer together than this when saving as OBJ or repairing, trading exactness for smaller files
+	#[arg(long, default_value_t = 0.0)]
+	weld_tolerance: f32,
+	/// Round all coordinates to a multiple of this (eg 0.001) to make the output smaller
+	#[arg(long, value_parser = parse_positive)]
+	precision: Option<f32>,
+	/// Save the triangles in a canonical order, with the coordinates rounded to --precision (or 0.0001 mm), so the same inputs give the
+	/// same bytes on every platform, eg for golden file tests or caching
+	#[arg(long)]
+	canonical: bool,
+	/// Use degrees instead of radians for the trig functions in the expressions
+	#[arg(long)]
+	degrees: bool,
+	/// Only allow these functions in the expressions (separated by commas), eg when the expressions come from users of a web service
+	#[arg(long, value_delimiter = ',')]
+	allowed_functions: Vec<String>,
+	/// The most numbers, variables, operators and function calls each expression can have, eg when the expressions come from users of a
+	/// web service
+	#[arg(long)]
+	max_expression_nodes: Option<usize>,
+	/// A shell command to run after saving, with the output path as its last argument and a JSON object of stats about the lithophane
+	/// (path, width, height, triangles, bytes) on its standard input. The path is also in the LITHOPHANE_OUTPUT environment variable.
+	#[arg(long)]
+	post_cmd: Option<String>,
+	/// Limit the thickness within this many pixels of the edges, so the lithophane fits into a photo frame (see --frame-max-thickness)
+	#[arg(long, requires = "frame_max_thickness", conflicts_with = "photo_frame")]
+	frame_border: Option<f32>,
+	/// The maximum thickness near the edges when using --frame-border or --photo-frame
+	#[arg(long)]
+	frame_max_thickness: Option<f32>,
+	/// Raise a ridge this many millimeters high around the edges of the back, to hold a sheet of acrylic or glass that thick (eg a
+	/// diffuser) against it
+	#[arg(long, conflicts_with_all = ["frame_border", "photo_frame", "crop", "crop_polygon", "nightlight", "clock", "shadow_box"])]
+	sheet_ridge: Option<f32>,
+	/// The width of the --sheet-ridge, in pixels
+	#[arg(long, default_value_t = 5.0, requires = "sheet_ridge")]
+	sheet_ridge_width: f32,
+	/// Extend the backing this many millimeters past the edges of the image, so the walls around the edges slope outwards instead of
+	/// being vertical, for a stronger join between the walls and the backing
+	#[arg(long, conflicts_with_all = ["clock", "shadow_box", "rgb_stripes"])]
+	backing_margin: Option<f32>,
+	/// Lean the walls around the edges of the image outwards towards the back by this many degrees (eg 10), so slicers see a wall with
+	/// some thickness to it instead of a vertical one, and thicker parts of the edges get wider walls
+	#[arg(long, default_value_t = 0.0, conflicts_with_all = ["clock", "shadow_box", "rgb_stripes"])]
+	wall_draft: f32,
+	/// Which neighbouring points the direction the thickness is added in is averaged from: the pair of triangles that's always been used,
+	/// or all four or eight around each point, which avoids a slight lean towards one diagonal on surfaces curved in both directions
+	#[arg(long, value_enum, default_value_t = NormalAveragingArg::Pair)]
+	normal_averaging: NormalAveragingArg,
+	/// Scale the surface the expressions describe so the longest side of the box around it is this many millimeters, without changing the
+	/// thickness, so the same expressions work for any image resolution
+	#[arg(long, value_parser = parse_positive, conflicts_with_all = ["size", "nightlight", "clock", "corner", "shadow_box", "terrain"])]
+	target_size: Option<f32>,
+	/// Spread the image out evenly by distance along the surface instead of evenly in x and y, so it doesn't look stretched where the
+	/// surface is steep (this changes which part of the image ends up where, so it's off by default to keep existing output the same)
+	#[arg(long, conflicts_with_all = ["clock", "shadow_box", "tile", "rgb_stripes"])]
+	arc_length: bool,
+	/// Move the image on the surface by this fraction of its width and height (eg "0.1,-0.05" to move it right and up a little), without
+	/// changing the shape
+	#[arg(long, value_parser = parse_point, allow_hyphen_values = true)]
+	image_offset: Option<(f32, f32)>,
+	/// Zoom the image on the surface around its center, without changing the shape
+	#[arg(long, default_value_t = 1.0, value_parser = parse_positive)]
+	image_scale: f32,
+	/// What fills the surface where --image-offset or --image-scale leave no image
+	#[arg(long, value_enum, default_value_t = EdgeModeArg::Clamp)]
+	image_edges: EdgeModeArg,
+	/// Fit the image to the aspect ratio of this print size, and if no expressions are given, make a flat lithophane exactly this size
+	#[arg(long, value_enum)]
+	size: Option<PhotoSizeArg>,
+	/// How to fit the image to --size or --bookmark
+	#[arg(long, value_enum, default_value_t = FitArg::Crop)]
+	fit: FitArg,
+	/// The gray value (0-255) of the border added with "--fit pad", where white is the thinnest
+	#[arg(long, default_value_t = 255)]
+	pad_gray: u8,
+	/// Add a uniform border this many pixels wide around the image (after fitting it to --size), like the matte around a framed photo
+	#[arg(long)]
+	matte: Option<u32>,
+	/// The gray value (0-255) of the --matte border, where white is the thinnest
+	#[arg(long, default_value_t = 255)]
+	matte_gray: u8,
+	/// Make the flat lithophane for --size fit into a store-bought photo frame, by limiting the thickness under the frame's lip
+	#[arg(long, requires_all = ["size", "frame_max_thickness"])]
+	photo_frame: bool,
+	/// How far the lip of the frame covers the edges of the photo in millimeters, when using --photo-frame
+	#[arg(long, default_value_t = 6.0)]
+	frame_lip: f32,
+	/// Information to pass through to the outputs, given as "name=value" (eg "order=1234 customer=Smith"), which is saved in the
+	/// --manifest and, if there's no --header, as much of it as fits in the STL header
+	#[arg(long, num_args = 1.., value_parser = parse_metadata)]
+	metadata: Vec<(String, String)>,
+	/// Text to save in the header of STL files (up to 80 bytes), eg a shop name or order ID
+	#[arg(long, default_value = "", value_parser = parse_header)]
+	header: String,
+	/// Text to engrave into the back (eg a date or order number) in the bottom right corner, where "{hash}" is replaced with a short hash
+	/// of the images and settings, which doesn't depend on where the files are or the order of the arguments
+	#[arg(long, value_parser = parse_label)]
+	label: Option<String>,
+	/// The height of the label's characters in pixels, a twentieth of the image height by default
+	#[arg(long, requires = "label")]
+	label_height: Option<f32>,
+	/// How deep the label is engraved
+	#[arg(long, default_value_t = 0.3)]
+	label_depth: f32,
+	/// A small image (eg a maker's logo) to emboss into the bottom left corner, where black is made --emboss-depth thick whatever the tones
+	/// of the photo are, and white or transparent leaves the photo alone
+	#[arg(long)]
+	emboss: Option<String>,
+	/// The width of the --emboss image in pixels, an eighth of the image width by default
+	#[arg(long, requires = "emboss")]
+	emboss_width: Option<f32>,
+	/// Where the top left of the --emboss image goes, in normalized image coordinates (eg "0.8,0.05" for the top right corner)
+	#[arg(long, value_parser = parse_point, requires = "emboss")]
+	emboss_position: Option<(f32, f32)>,
+	/// The thickness of the black parts of the --emboss image
+	#[arg(long, default_value_t = LithophaneOptions::default().black_depth, requires = "emboss")]
+	emboss_depth: f32,
+	/// Cut a window with no material through the lithophane, eg for an LED display behind it, given as a rectangle in normalized image
+	/// coordinates as x,y,width,height from its top left corner (eg "0.3,0.4,0.4,0.2")
+	#[arg(long, value_parser = parse_rectangle, conflicts_with_all = ["clock", "shadow_box", "rgb_stripes"])]
+	window: Vec<(f32, f32, f32, f32)>,
+	/// Cut text through the lithophane as windows, one for each dot of the label font, eg for a clock display lit by LEDs behind it
+	#[arg(long, value_parser = parse_label, conflicts_with_all = ["clock", "shadow_box", "rgb_stripes"])]
+	window_text: Option<String>,
+	/// The height of the --window-text characters in millimeters, a quarter of the image height by default
+	#[arg(long, requires = "window_text")]
+	window_text_height: Option<f32>,
+	/// Where the top left of the --window-text goes, in normalized image coordinates, centered by default
+	#[arg(long, value_parser = parse_point, requires = "window_text")]
+	window_text_position: Option<(f32, f32)>,
+	/// The width of the bars left between the dots of the --window-text in millimeters
+	#[arg(long, default_value_t = 0.6, requires = "window_text")]
+	window_text_gap: f32,
+	/// The width of the solid frame around each --window or --window-text dot in millimeters, which is made the full (black) depth
+	#[arg(long, default_value_t = 1.0)]
+	window_border: f32,
+	/// Add a solid band this many pixels tall below the image, so curved lithophanes can stand on their own (see --skirt-depth)
+	#[arg(long, requires = "skirt_depth")]
+	skirt_height: Option<f32>,
+	/// The thickness of the --skirt-height band, which is how wide its footprint is
+	#[arg(long, requires = "skirt_height")]
+	skirt_depth: Option<f32>,
+	/// Stand a flat lithophane (lying on the XY plane) up on its bottom edge, leaning back by this many degrees, with the --skirt-height
+	/// band made into a wedge behind it with a flat bottom so it doesn't tip over
+	#[arg(long, requires = "skirt_height")]
+	stand: Option<f32>,
+	/// How long the flat bottom of the --stand wedge is in millimeters, from the front of the skirt to the back of the wedge (by default
+	/// it reaches as far back as the top of the lithophane leans)
+	#[arg(long, requires = "stand")]
+	stand_base: Option<f32>,
+	/// Check that the lithophane fits on this printer
+	#[arg(long, value_enum, conflicts_with = "bed")]
+	printer: Option<PrinterArg>,
+	/// Check that the lithophane fits in this build volume in millimeters, given as width,depth,height (eg "220,220,250")
+	#[arg(long, value_parser = parse_build_volume)]
+	bed: Option<BuildVolume>,
+	/// Shrink the lithophane to fit on the --printer or --bed if it's too big, instead of only warning about it
+	#[arg(long)]
+	scale_to_fit: bool,
+	/// Roughly estimate how long the lithophane takes to print from its cross section in each layer, without slicing it
+	#[arg(long)]
+	estimate_print_time: bool,
+	/// The layer height for --estimate-print-time and --gcode in millimeters, if it isn't the same as --layer-height (or 0.2 mm without it)
+	#[arg(long, value_parser = parse_positive)]
+	print_layer_height: Option<f32>,
+	/// The extrusion width for --estimate-print-time and --gcode in millimeters
+	#[arg(long, default_value_t = 0.45, value_parser = parse_positive)]
+	line_width: f32,
+	/// The speed of the perimeters for --estimate-print-time in millimeters per second
+	#[arg(long, default_value_t = 40.0, value_parser = parse_positive, requires = "estimate_print_time")]
+	perimeter_speed: f32,
+	/// The speed of the (solid) infill for --estimate-print-time and --gcode in millimeters per second
+	#[arg(long, default_value_t = 60.0, value_parser = parse_positive)]
+	infill_speed: f32,
+	/// Also save G-code for a flat lithophane (eg "out.gcode"), made straight from the thicknesses with simple back and forth lines on
+	/// the middle of the --printer or --bed, for printing many of the same format without a slicer (experimental, so check it in a viewer)
+	#[arg(long, conflicts_with_all = [
+		"clock", "shadow_box", "rgb_stripes", "tile", "dry_run", "crop", "crop_polygon", "window", "frame_border", "photo_frame", "skirt_height",
+		"depth_mask", "label", "sheet_ridge", "stipple", "hatching", "emboss", "max_slope", "cache",
+	])]
+	gcode: Option<String>,
+	/// The nozzle temperature for --gcode, in degrees Celsius
+	#[arg(long, default_value_t = GcodeSettings::default().nozzle_temperature, requires = "gcode")]
+	nozzle_temperature: u32,
+	/// The bed temperature for --gcode, in degrees Celsius
+	#[arg(long, default_value_t = GcodeSettings::default().bed_temperature, requires = "gcode")]
+	bed_temperature: u32,
+	/// Split the output into numbered files (eg "out.part1.stl") with at most this many triangles each
+	#[arg(long)]
+	max_part_triangles: Option<usize>,
+	/// Split the output into numbered files (eg "out.part1.stl") of at most this many megabytes each before compression
+	#[arg(long)]
+	max_part_mb: Option<f64>,
+	/// Make a 60×80 mm panel with a clip at the bottom for a plug-in LED nightlight base, instead of using expressions
+	#[arg(long, conflicts_with_all = ["size", "crop", "crop_polygon"])]
+	nightlight: bool,
+	/// The width of the --nightlight clip in millimeters
+	#[arg(long, default_value_t = 20.0)]
+	clip_width: f32,
+	/// The thickness of the --nightlight clip in millimeters, to match the slot in the base
+	#[arg(long, default_value_t = 2.4)]
+	clip_thickness: f32,
+	/// Make a 50×150 mm bookmark with a rounded top and a hole for a tassel, instead of using expressions
+	#[arg(long, conflicts_with_all = [
+		"size", "nightlight", "crop", "crop_polygon", "tile", "window", "window_text", "target_size", "terrain", "rgb_stripes",
+	])]
+	bookmark: bool,
+	/// Make a round pendant or earring this many millimeters across (eg 30) with a jump ring loop at the top, instead of using expressions
+	///
+	/// The image is shrunk to about 0.2 mm per pixel in a way that keeps the fine detail.
+	#[arg(long, value_parser = parse_positive, conflicts_with_all = [
+		"size", "nightlight", "bookmark", "crop", "crop_polygon", "tile", "window", "window_text", "target_size", "terrain", "rgb_stripes",
+	])]
+	pendant: Option<f32>,
+	/// The diameter of the hole in the --pendant loop in millimeters, which has to fit the jump ring's wire
+	#[arg(long, default_value_t = Pendant::default().hole_diameter, requires = "pendant")]
+	pendant_hole: f32,
+	/// Make a round photo clock face this many millimeters across, with a hole for a quartz clock movement, instead of using expressions
+	#[arg(long, conflicts_with_all = [
+		"size", "nightlight", "bookmark", "pendant", "crop", "crop_polygon", "skirt_height", "frame_border", "label", "depth_mask",
+		"dry_run",
+	])]
+	clock: Option<f32>,
+	/// The diameter of the hole for the --clock movement's shaft in millimeters
+	#[arg(long, default_value_t = 8.0)]
+	clock_hole: f32,
+	/// Leave the hour markers off the --clock face
+	#[arg(long)]
+	no_hour_markers: bool,
+	/// Bend the lithophane 90° around a rounded corner (eg for a corner lamp), where this is its width in millimeters measured around the
+	/// corner, instead of using expressions
+	#[arg(long, conflicts_with_all = ["size", "nightlight", "bookmark", "pendant", "clock"])]
+	corner: Option<f32>,
+	/// The radius of the inside of the --corner bend in millimeters
+	#[arg(long, default_value_t = 10.0)]
+	fillet_radius: f32,
+	/// Make a layered shadow box this many millimeters wide instead of a lithophane, with the image split into flat layers by brightness
+	/// that are saved as separate files (eg "out.layer1.stl" for the back layer) or objects in one OBJ file
+	#[arg(long, conflicts_with_all = [
+		"size", "nightlight", "bookmark", "pendant", "clock", "corner", "tile", "crop", "crop_polygon", "skirt_height", "frame_border", "label",
+		"depth_mask", "stand", "printer", "bed", "max_part_triangles", "max_part_mb", "precision", "repair", "manifest", "post_cmd", "dry_run",
+	])]
+	shadow_box: Option<f32>,
+	/// Treat the input as elevations (an ESRI ASCII grid, a GeoTIFF with the tiff feature, or a grayscale image like a 16 bit PNG
+	/// heightmap) and make a terrain relief this many millimeters wide, with the thickness following the elevations at the same scale
+	#[arg(long, conflicts_with_all = [
+		"size", "nightlight", "clock", "corner", "shadow_box", "rgb_stripes", "tone_mapping", "grayscale", "ignore_color_profile", "overlay",
+		"dodge_burn", "white_balance",
+	])]
+	terrain: Option<f32>,
+	/// The distance between neighbouring --terrain elevations in meters, if the file doesn't say (like images) or says something else
+	#[arg(long, requires = "terrain")]
+	cell_size: Option<f32>,
+	/// The meters of elevation for each gray value of a --terrain image, or each value of a GeoTIFF
+	#[arg(long, default_value_t = 1.0, requires = "terrain")]
+	elevation_scale: f32,
+	/// Make the --terrain relief this many times higher than it would be at the map's scale
+	#[arg(long, default_value_t = Terrain::default().exaggeration, requires = "terrain")]
+	exaggeration: f32,
+	/// The thickness of the lowest point of the --terrain relief in millimeters
+	#[arg(long, default_value_t = Terrain::default().base, requires = "terrain")]
+	terrain_base: f32,
+	/// The number of --shadow-box layers
+	#[arg(long, default_value_t = 4)]
+	layers: u32,
+	/// The thickness of each --shadow-box layer in millimeters
+	#[arg(long, default_value_t = 1.2)]
+	layer_thickness: f32,
+	/// The gap between --shadow-box layers in millimeters
+	#[arg(long, default_value_t = 5.0)]
+	layer_spacing: f32,
+	/// Repeat the image as a pattern this many times across and down the surface, eg "4x3"
+	#[arg(long, value_parser = parse_tiles, conflicts_with_all = ["clock", "nightlight", "size"])]
+	tile: Option<(u32, u32)>,
+	/// Flip every other --tile, so images that don't tile seamlessly line up at the seams
+	#[arg(long, requires = "tile")]
+	mirror_tiles: bool,
+	/// Experimental: make three lithophanes from the red, green and blue channels in interleaved vertical stripes this many pixels wide,
+	/// eg to print each in a different translucent filament, saved as objects in one OBJ file or as separate STL files (eg "out.red.stl")
+	#[arg(long, conflicts_with_all = [
+		"size", "nightlight", "clock", "corner", "tile", "crop", "crop_polygon", "matte", "overlay", "dodge_burn", "depth_mask", "frame_border",
+		"label", "stand", "printer", "bed", "max_part_triangles", "max_part_mb", "precision", "repair", "manifest", "post_cmd", "dry_run",
+		"shadow_box",
+	])]
+	rgb_stripes: Option<u32>,
+	/// Make a faceted low-poly lithophane with about this many triangles on the front, where the image is grouped into regions of similar
+	/// tones that each become a corner of the facets
+	#[arg(long, conflicts_with_all = [
+		"clock", "shadow_box", "rgb_stripes", "puzzle", "base_depth", "part", "nightlight", "crop", "crop_polygon", "tile", "skirt_height",
+		"frame_border", "photo_frame", "sheet_ridge", "label", "emboss", "depth_mask", "contours", "stipple", "hatching", "max_slope", "layer_height",
+		"thickness_offset", "subdivisions", "sample_step", "backing_margin", "wall_draft", "thickness_stats", "window", "window_text",
+	])]
+	low_poly: Option<u32>,
+	/// How evenly the --low-poly facets are spread instead of following edges in the image, eg 5 to follow edges closely or 40 for even
+	/// facets
+	#[arg(long, default_value_t = LowPoly::default().compactness, requires = "low_poly")]
+	low_poly_compactness: f32,
+	/// The smallest feature the printer can print in millimeters (usually about the nozzle width), to estimate how much of the image's
+	/// detail will be visible
+	#[arg(long, default_value_t = 0.4)]
+	resolution: f32,
+	/// Save a JSON file with the input image's hash, the settings, stats, and warnings, to archive with the model
+	#[arg(long)]
+	manifest: Option<String>,
+	/// Print the predicted number of triangles and file size without generating anything
+	#[arg(long)]
+	dry_run: bool,
+	/// Warn about images whose shorter side has fewer pixels than this
+	#[arg(long)]
+	min_resolution: Option<u32>,
+	/// Warn about images that look blurry, with a sharpness (the variance of the Laplacian) below this, eg 100
+	#[arg(long)]
+	min_sharpness: Option<f32>,
+	/// Warn about images that look under or overexposed, with an average gray value outside this range, eg "40:215"
+	#[arg(long, value_parser = parse_brightness_range)]
+	brightness_range: Option<(u8, u8)>,
+	/// Stop without generating anything if the image fails any of the --min-resolution, --min-sharpness or --brightness-range checks,
+	/// instead of only warning about it
+	#[arg(long)]
+	reject_low_quality: bool,
+	/// Save an image of how the lithophane will look lit from behind (eg "lit.png"), to check the tones before printing it
+	#[arg(long, conflicts_with = "cache")]
+	backlight_preview: Option<String>,
+	/// Save a mask image for each layer of a flat lithophane for an mSLA resin printer, numbered after this path (eg "layers.png" makes
+	/// "layers.layer0001.png" and so on), made straight from the thicknesses instead of by slicing the mesh
+	#[arg(long, conflicts_with_all = [
+		"clock", "shadow_box", "rgb_stripes", "tile", "dry_run", "crop", "crop_polygon", "window", "frame_border", "photo_frame", "skirt_height",
+		"depth_mask", "label", "sheet_ridge", "stipple", "hatching", "emboss", "max_slope", "cache",
+	])]
+	resin_layers: Option<String>,
+	/// The layer height for --resin-layers, in millimeters
+	#[arg(long, default_value_t = ResinLayers::default().layer_height, value_parser = parse_positive, requires = "resin_layers")]
+	resin_layer_height: f32,
+	/// The size of a pixel of the resin printer's screen for --resin-layers, in millimeters
+	#[arg(long, default_value_t = ResinLayers::default().pixel_size, value_parser = parse_positive, requires = "resin_layers")]
+	resin_pixel_size: f32,
+	/// How quickly light fades going through the filament for --backlight-preview and --piece-brightness, per millimeter of thickness
+	#[arg(long, default_value_t = Material::default().attenuation)]
+	attenuation: f32,
+	/// Use a filament from the catalog for --backlight-preview and --piece-brightness instead of --attenuation, either built in
+	/// ("white-pla") or defined in the user's config directory (see `presets::Catalog`)
+	#[arg(long, conflicts_with = "attenuation")]
+	material: Option<String>,
+	/// Read the user's presets, materials and curves from this directory instead of ~/.config/lithophane
+	#[arg(long)]
+	config_dir: Option<String>,
+	/// Print how thick the lithophane ended up (the thinnest and thickest points, percentiles and a histogram), to check it uses the range
+	/// of thicknesses it was meant to after all the adjustments to the image and depths
+	#[arg(long, conflicts_with_all = ["clock", "rgb_stripes", "shadow_box", "base_depth", "puzzle", "cache", "dry_run"])]
+	thickness_stats: bool,
+	/// Keep a copy of every lithophane generated in this directory, and copy it from there instead of generating it again when the same
+	/// images (by their contents) are used with the same settings
+	#[arg(long, conflicts_with_all = [
+		"rgb_stripes", "shadow_box", "base_depth", "puzzle", "max_part_triangles", "max_part_mb", "manifest", "post_cmd", "dry_run",
+	])]
+	cache: Option<String>,
+	/// Use the expressions of a shape from the catalog instead of giving them, either built in ("flat") or defined in the user's config
+	/// directory (see `presets::Catalog`)
+	#[arg(long, conflicts_with_all = ["x_expression", "nightlight", "bookmark", "pendant", "clock", "corner", "shadow_box"])]
+	preset: Option<String>,
+	/// Pick a shape and size from the image's proportions (see `suggest::suggest_shapes`) instead of giving expressions, and print the
+	/// alternatives
+	#[arg(long, conflicts_with_all = ["x_expression", "preset", "size", "nightlight", "bookmark", "pendant", "clock", "corner", "shadow_box", "terrain"])]
+	auto_shape: bool,
+	#[arg(
+		required_unless_present_any = ["size", "nightlight", "bookmark", "pendant", "clock", "corner", "shadow_box", "terrain", "preset", "auto_shape"],
+		conflicts_with_all = ["photo_frame", "nightlight", "bookmark", "pendant", "clock", "corner", "shadow_box"]
+	)]
+	x_expression: Option<String>,
+	#[arg(required_unless_present_any = ["size", "nightlight", "bookmark", "pendant", "clock", "corner", "shadow_box", "terrain", "preset", "auto_shape"])]
+	y_expression: Option<String>,
+	#[arg(required_unless_present_any = ["size", "nightlight", "bookmark", "pendant", "clock", "corner", "shadow_box", "terrain", "preset", "auto_shape"])]
+	z_expression: Option<String>,
+}
+
+/// Run an HTTP service that generates lithophanes in the background, with `lithophane-generator serve` (see `server::serve` for the API)
+#[cfg(feature = "server")]
+#[derive(Parser, Debug, Clone)]
+#[command(name = "lithophane-generator serve", version)]
+struct ServeCli {
+	/// The address and port to listen on, eg "0.0.0.0:8080" to accept connections from other computers
+	#[arg(long, default_value = "127.0.0.1:8080")]
+	address: String,
+	/// How many lithophanes to generate at once
+	#[arg(long, default_value_t = 2)]
+	workers: usize,
+	/// A directory to keep the jobs and their results in, so they're picked up again after a restart
+	#[arg(long)]
+	store: Option<String>,
+	/// Keep the jobs in a sled database at --store rather than a file for each
+	#[cfg(feature = "sled")]
+	#[arg(long, requires = "store")]
+	sled: bool,
+	/// Only allow these functions in the expressions (separated by commas)
+	#[arg(long, value_delimiter = ',')]
+	allowed_functions: Vec<String>,
+	/// The most numbers, variables, operators and function calls each expression can have
+	#[arg(long)]
+	max_expression_nodes: Option<usize>,
+	/// The most pixels an image can have
+	#[arg(long, default_value_t = JobLimits::default().max_pixels)]
+	max_pixels: u64,
+	/// The most triangles a lithophane can have
+	#[arg(long, default_value_t = JobLimits::default().max_triangles)]
+	max_triangles: usize,
+	/// The most jobs that can be waiting for a worker, with any more turned away until some have started
+	#[arg(long, default_value_t = JobLimits::default().max_queued)]
+	max_queued: usize,
+	/// How many minutes to keep finished jobs and their results for without --store, after which they're forgotten
+	#[arg(long, default_value_t = JobLimits::default().keep_finished.as_secs_f32() / 60.0, value_parser = parse_positive, conflicts_with = "store")]
+	keep_finished: f32,
+	/// The most connections to handle at once, with any more turned away until one finishes
+	#[arg(long, default_value_t = ServerLimits::default().max_connections)]
+	max_connections: usize,
+	/// How many seconds a client can go without sending or receiving anything before it's disconnected
+	#[arg(long, default_value_t = ServerLimits::default().timeout.as_secs_f32(), value_parser = parse_positive)]
+	timeout: f32,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Crop {
+	Circle,
+	Ellipse,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum BlendModeArg {
+	Multiply,
+	Screen,
+	Average,
+}
+
+#[derive(Clone, Debug)]
+struct OverlayArg {
+	path: String,
+	mode: BlendModeArg,
+	opacity: f32,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum PrinterArg {
+	PrusaMk4,
+	PrusaMini,
+	BambuLabX1,
+	BambuLabA1Mini,
+	Ender3,
+	Voron350,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum FitArg {
+	Crop,
+	Pad,
+	Stretch,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum PhotoSizeArg {
+	Wallet,
+	#[value(name = "4x6")]
+	FourBySix,
+	#[value(name = "5x7")]
+	FiveBySeven,
+	#[value(name = "8x10")]
+	EightByTen,
+	#[value(name = "10x15cm")]
+	TenByFifteen,
+	#[value(name = "13x18cm")]
+	ThirteenByEighteen,
+	A5,
+	A4,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ToneMappingArg {
+	Reinhard,
+	Aces,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum StippleArg {
+	Holes,
+	Bumps,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum WhiteBalanceArg {
+	GrayWorld,
+	Percentile,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum EasingArg {
+	Linear,
+	EaseIn,
+	EaseOut,
+	Smooth,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum NormalAveragingArg {
+	Pair,
+	Four,
+	EightWeighted,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum EdgeModeArg {
+	Clamp,
+	Repeat,
+	Mirror,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum PartArg {
+	Image,
+	Frame,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum GrayscaleArg {
+	Luma,
+	Lightness,
+}
+
+fn parse_label(s: &str) -> Result<String, String> {
+	check_label_text(&s.replace("{hash}", "")).map_err(|e| e.to_string())?;
+	Ok(s.to_string())
+}
+
+fn parse_overlay(s: &str) -> Result<OverlayArg, String> {
+	// The options are taken from the end, since paths can contain colons (eg "C:\\watermark.png")
+	let mut overlay = OverlayArg {
+		path: s.to_string(),
+		mode: BlendModeArg::Multiply,
+		opacity: 1.0,
+	};
+	if let Some((rest, opacity)) = overlay.path.rsplit_once(':').and_then(|(rest, o)| Some((rest, o.parse::<f32>().ok()?))) {
+		if !(0.0..=1.0).contains(&opacity) {
+			return Err(format!("the opacity must be from 0 to 1, found {}", opacity));
+		}
+		overlay.opacity = opacity;
+		overlay.path = rest.to_string();
+	}
+	if let Some((rest, mode)) = overlay.path.rsplit_once(':').and_then(|(rest, m)| Some((rest, BlendModeArg::from_str(m, true).ok()?))) {
+		overlay.mode = mode;
+		overlay.path = rest.to_string();
+	}
+	Ok(overlay)
+}
+
+fn parse_metadata(s: &str) -> Result<(String, String), String> {
+	match s.split_once('=') {
+		Some((name, value)) if !name.trim().is_empty() => Ok((name.trim().to_string(), value.to_string())),
+		_ => Err(format!("expected metadata like \"order=1234\", found \"{}\"", s)),
+	}
+}
+
+fn parse_header(s: &str) -> Result<String, String> {
+	check_stl_header(s).map_err(|e| e.to_string())?;
+	Ok(s.to_string())
+}
+
+fn parse_build_volume(s: &str) -> Result<BuildVolume, String> {
+	let sizes = s.split(',').map(|v| v.trim().parse::<f32>().map_err(|e| format!("invalid size \"{}\": {}", v, e))).collect::<Result<Vec<_>, _>>()?;
+	match sizes[..] {
+		[width, depth, height] if sizes.iter().all(|&s| s > 0.0) => Ok(BuildVolume { width, depth, height }),
+		_ => Err(format!("expected a build volume like \"220,220,250\", found \"{}\"", s)),
+	}
+}
+
+fn parse_tiles(s: &str) -> Result<(u32, u32), String> {
+	let (columns, rows) = s.split_once('x').ok_or_else(|| format!("expected a number of tiles like \"4x3\", found \"{}\"", s))?;
+	let parse = |v: &str| match v.trim().parse::<u32>() {
+		Ok(n) if n > 0 => Ok(n),
+		Ok(_) => Err("the number of tiles must be at least 1".to_string()),
+		Err(e) => Err(format!("invalid number of tiles \"{}\": {}", v, e)),
+	};
+	Ok((parse(columns)?, parse(rows)?))
+}
+
+fn parse_rectangle(s: &str) -> Result<(f32, f32, f32, f32), String> {
+	let values =
+		s.split(',').map(|v| v.trim().parse::<f32>().map_err(|e| format!("invalid rectangle \"{}\": {}", s, e))).collect::<Result<Vec<_>, _>>()?;
+	match values[..] {
+		[x, y, width, height] if width > 0.0 && height > 0.0 => Ok((x, y, width, height)),
+		[_, _, _, _] => Err(format!("the width and height of a rectangle must be positive, not \"{}\"", s)),
+		_ => Err(format!("expected a rectangle like \"0.3,0.4,0.4,0.2\", found \"{}\"", s)),
+	}
+}
+
+fn parse_brightness_range(s: &str) -> Result<(u8, u8), String> {
+	let (low, high) = s.split_once(':').ok_or_else(|| format!("expected a range of gray values like \"40:215\", found \"{}\"", s))?;
+	let parse = |v: &str| v.trim().parse::<u8>().map_err(|e| format!("invalid gray value \"{}\": {}", v, e));
+	Ok((parse(low)?, parse(high)?))
+}
+
+fn parse_positive(s: &str) -> Result<f32, String> {
+	match s.trim().parse::<f32>() {
+		Ok(v) if v > 0.0 && v.is_finite() => Ok(v),
+		Ok(v) => Err(format!("must be a positive number, found {}", v)),
+		Err(e) => Err(format!("invalid number \"{}\": {}", s, e)),
+	}
+}
+
+fn parse_point(s: &str) -> Result<(f32, f32), String> {
+	let (x, y) = s.split_once(',').ok_or_else(|| format!("expected a point like \"0.5,1\", found \"{}\"", s))?;
+	Ok((
+		x.trim().parse().map_err(|e| format!("invalid x coordinate: {}", e))?,
+		y.trim().parse().map_err(|e| format!("invalid y coordinate: {}", e))?,
+	))
+}
+
+/// The default `Sandbox` with the functions and number of nodes replaced by the ones given, if any
+fn sandbox(allowed_functions: &[String], max_nodes: Option<usize>) -> Sandbox {
+	let default = Sandbox::default();
+	Sandbox {
+		allowed_functions: if allowed_functions.is_empty() {
+			default.allowed_functions
+		} else {
+			allowed_functions.to_vec()
+		},
+		max_nodes: max_nodes.unwrap_or(default.max_nodes),
+	}
+}
+
+#[cfg(feature = "server")]
+fn serve(cli: ServeCli) -> ExitCode {
+	let sandbox = sandbox(&cli.allowed_functions, cli.max_expression_nodes);
+	let listener = match TcpListener::bind(&cli.address) {
+		Ok(listener) => listener,
+		Err(e) => {
+			eprintln!("Couldn't listen on {}: {}", cli.address, e);
+			return ExitCode::FAILURE;
+		},
+	};
+	println!(
+		"Listening on http://{}",
+		listener.local_addr().map_or(cli.address, |address| address.to_string())
+	);
+	let job_limits = JobLimits {
+		max_pixels: cli.max_pixels,
+		max_triangles: cli.max_triangles,
+		max_queued: cli.max_queued,
+		keep_finished: Duration::from_secs_f32(cli.keep_finished * 60.0),
+	};
+	let queue = match cli.store {
+		Some(dir) => {
+			#[cfg(feature = "sled")]
+			let store = if cli.sled { JobStore::open_sled(&dir) } else { JobStore::open(&dir) };
+			#[cfg(not(feature = "sled"))]
+			let store = JobStore::open(&dir);
+			match store.and_then(|store| JobQueue::with_store(cli.workers, sandbox, job_limits, store)) {
+				Ok(queue) => queue,
+				Err(e) => {
+					eprintln!("Error loading the jobs in {}: {}", dir, e);
+					return ExitCode::FAILURE;
+				},
+			}
+		},
+		None => JobQueue::new(cli.workers, sandbox, job_limits),
+	};
+	let limits = ServerLimits {
+		max_connections: cli.max_connections,
+		timeout: Duration::from_secs_f32(cli.timeout),
+	};
+	server::serve(listener, queue, limits, |e| eprintln!("Error accepting a connection: {}", e))
 }
 
 fn main() -> ExitCode {
+	// The serve subcommand is picked out before parsing, since it takes none of the arguments for generating a lithophane
+	if std::env::args().nth(1).as_deref() == Some("serve") {
+		#[cfg(feature = "server")]
+		return serve(ServeCli::parse_from(std::env::args().skip(1)));
+		#[cfg(not(feature = "server"))]
+		{
+			eprintln!(
+				"This build can't run a server, since it was built without the \"server\" feature (eg cargo build --release --features server)"
+			);
+			return ExitCode::FAILURE;
+		}
+	}
 	let cli = Cli::parse();
 
-	let image = match image::open(&cli.input) {
+	let terrain = cli.terrain.map(|width| Terrain {
+		width,
+		cell_size: cli.cell_size,
+		exaggeration: cli.exaggeration,
+		base: cli.terrain_base,
+	});
+
+	// Elevations are turned into a grayscale image and the depths that go with it, so they can go through the same steps as a photo
+	let decode = if cli.ignore_color_profile { decode_frame } else { decode_frame_as_srgb };
+	let read_image = read_input(&cli.input).and_then(|bytes| {
+		let (image, depths) = match &terrain {
+			Some(terrain) => {
+				let (image, white_depth, black_depth) =
+					ElevationGrid::read(&bytes, cli.elevation_scale).and_then(|grid| terrain.prepare(&grid)).map_err(|e| e.to_string())?;
+				(DynamicImage::ImageLuma8(image), Some((white_depth, black_depth)))
+			},
+			None => (decode(&bytes, cli.frame).map_err(|e| e.to_string())?, None),
+		};
+		Ok((hash_input(&bytes), image, depths))
+	});
+	let (input_hash, image, terrain_depths) = match read_image {
 		Ok(i) => i,
 		Err(e) => {
 			eprintln!("Error opening image file \"{}\": {}", cli.input, e);
@@ -27,48 +954,871 @@ fn main() -> ExitCode {
 		},
 	};
 
-	let mut output_file = match OpenOptions::new().create_new(true).write(true).open(&cli.output) {
-		Ok(f) => f,
-		Err(e) => {
-			eprintln!("Error opening output file \"{}\": {}", cli.output, e);
+	let depth_mask = match &cli.depth_mask {
+		Some(path) => match image::open(path) {
+			Ok(i) => Some(DepthMask {
+				image: i.into_luma8(),
+				mode: DepthMaskMode::Multiply,
+			}),
+			Err(e) => {
+				eprintln!("Error opening depth mask file \"{}\": {}", path, e);
+				return ExitCode::FAILURE;
+			},
+		},
+		None => None,
+	};
+
+	// Transparent parts of the emboss image are treated as white, so they don't change the photo
+	let emboss_image = match &cli.emboss {
+		Some(path) => match image::open(path) {
+			Ok(i) => {
+				let image = i.into_luma_alpha8();
+				Some(GrayImage::from_fn(image.width(), image.height(), |x, y| {
+					let [luma, alpha] = image.get_pixel(x, y).0;
+					image::Luma([255 - ((255 - luma) as u32 * alpha as u32 / 255) as u8])
+				}))
+			},
+			Err(e) => {
+				eprintln!("Error opening emboss file \"{}\": {}", path, e);
+				return ExitCode::FAILURE;
+			},
+		},
+		None => None,
+	};
+
+	let size = cli.size.map(|size| match size {
+		PhotoSizeArg::Wallet => PhotoSize::Wallet,
+		PhotoSizeArg::FourBySix => PhotoSize::FourBySix,
+		PhotoSizeArg::FiveBySeven => PhotoSize::FiveBySeven,
+		PhotoSizeArg::EightByTen => PhotoSize::EightByTen,
+		PhotoSizeArg::TenByFifteen => PhotoSize::TenByFifteen,
+		PhotoSizeArg::ThirteenByEighteen => PhotoSize::ThirteenByEighteen,
+		PhotoSizeArg::A5 => PhotoSize::A5,
+		PhotoSizeArg::A4 => PhotoSize::A4,
+	});
+	let fit = match cli.fit {
+		FitArg::Crop => AspectFit::Crop,
+		FitArg::Pad => AspectFit::Pad { gray: cli.pad_gray },
+		FitArg::Stretch => AspectFit::Stretch,
+	};
+	let photo_frame_insert = size.filter(|_| cli.photo_frame).map(|size| PhotoFrameInsert {
+		size,
+		lip: cli.frame_lip,
+		max_thickness: cli.frame_max_thickness.unwrap_or_default(),
+	});
+
+	let tactile = cli.tactile.then(|| Tactile {
+		levels: cli.tactile_levels,
+		relief: cli.tactile_relief,
+		..Default::default()
+	});
+
+	let nightlight_panel = cli.nightlight.then(|| NightlightPanel {
+		clip_width: cli.clip_width,
+		clip_thickness: cli.clip_thickness,
+		..Default::default()
+	});
+
+	let bookmark = cli.bookmark.then(Bookmark::default);
+
+	let pendant = cli.pendant.map(|diameter| Pendant {
+		diameter,
+		hole_diameter: cli.pendant_hole,
+		..Default::default()
+	});
+
+	let clock_face = cli.clock.map(|diameter| ClockFace {
+		diameter,
+		hole_diameter: cli.clock_hole,
+		hour_markers: (!cli.no_hour_markers).then(HourMarkers::default),
+	});
+
+	let shadow_box = cli.shadow_box.map(|width| ShadowBox {
+		width,
+		layers: cli.layers,
+		layer_thickness: cli.layer_thickness,
+		spacing: cli.layer_spacing,
+		..Default::default()
+	});
+
+	let corner_wrap = cli.corner.map(|width| CornerWrap {
+		width,
+		fillet_radius: cli.fillet_radius,
+	});
+	if let Some(corner) = corner_wrap.filter(|c| c.face_width() < 0.0) {
+		eprintln!(
+			"The --corner bend is {:.1} mm around, which doesn't fit in the width",
+			corner.width - corner.face_width() * 2.0
+		);
+		return ExitCode::FAILURE;
+	}
+
+	// The user's definitions are only read when they're needed, so a broken file doesn't get in the way of everything else
+	let catalog = if cli.preset.is_some() || cli.material.is_some() || cli.curve.is_some() {
+		let loaded = match &cli.config_dir {
+			Some(dir) => Catalog::load_dir(Path::new(dir)).map(|user| {
+				let mut catalog = Catalog::default();
+				catalog.extend(user);
+				catalog
+			}),
+			None => load_user_catalog(),
+		};
+		match loaded {
+			Ok(catalog) => catalog,
+			Err(e) => {
+				eprintln!("Error reading the user's presets: {}", e);
+				return ExitCode::FAILURE;
+			},
+		}
+	} else {
+		Catalog::default()
+	};
+	let preset = match cli.preset.as_deref().map(|name| (name, catalog.preset(name))) {
+		Some((_, Some(preset))) => Some(preset),
+		Some((name, None)) => {
+			eprintln!("There's no preset named \"{}\"", name);
 			return ExitCode::FAILURE;
 		},
+		None => None,
 	};
+	let material = match cli.material.as_deref().map(|name| (name, catalog.material(name))) {
+		Some((_, Some(&material))) => material,
+		Some((name, None)) => {
+			eprintln!("There's no material named \"{}\"", name);
+			return ExitCode::FAILURE;
+		},
+		None => Material {
+			attenuation: cli.attenuation,
+			..Default::default()
+		},
+	};
+	let curve = match cli.curve.as_deref().map(|name| (name, catalog.curve(name))) {
+		Some((_, Some(curve))) => Some(curve),
+		Some((name, None)) => {
+			eprintln!("There's no curve named \"{}\"", name);
+			return ExitCode::FAILURE;
+		},
+		None => None,
+	};
+
+	let suggestion = cli.auto_shape.then(|| {
+		let mut suggestions = suggest_shapes(&image.to_luma8()).into_iter();
+		let best = suggestions.next().unwrap();
+		println!(
+			"Shape: {}, {:.1} × {:.1} mm ({})",
+			best.shape.name(),
+			best.width,
+			best.height,
+			best.reason
+		);
+		for other in suggestions {
+			println!(
+				"Alternative: {}, {:.1} × {:.1} mm ({})",
+				other.shape.name(),
+				other.width,
+				other.height,
+				other.reason
+			);
+		}
+		best
+	});
 
-	let x_expression = match cli.x_expression.parse::<meval::Expr>().and_then(|e| e.bind4("x", "y", "w", "h")) {
-		Ok(e) => e,
+	// clap makes sure the expressions are there unless there's a preset or suggestion to generate them for, or a clock face or shadow box
+	let [x_source, y_source, z_source] = match (suggestion, corner_wrap, &nightlight_panel, bookmark, pendant, preset) {
+		(Some(suggestion), ..) => suggestion.expressions,
+		(None, Some(corner), ..) => corner.expressions(),
+		(None, None, Some(panel), ..) => panel.expressions(),
+		(None, None, None, Some(bookmark), ..) => bookmark.expressions(),
+		(None, None, None, None, Some(pendant), _) => pendant.expressions(),
+		(None, None, None, None, None, Some(preset)) => [&preset.x_expression, &preset.y_expression, &preset.z_expression].map(String::clone),
+		(None, None, None, None, None, None) => match (size, terrain) {
+			(Some(size), _) if cli.x_expression.is_none() => size.flat_expressions(image.width(), image.height()),
+			(None, Some(terrain)) if cli.x_expression.is_none() => terrain.flat_expressions(image.width(), image.height()),
+			_ => [&cli.x_expression, &cli.y_expression, &cli.z_expression].map(|e| e.clone().unwrap_or_default()),
+		},
+	};
+
+	// Clock faces and shadow boxes have their own meshes, so they don't use expressions
+	let angle_unit = if cli.degrees { AngleUnit::Degrees } else { AngleUnit::Radians };
+	let mut expressions = if clock_face.is_some() || shadow_box.is_some() {
+		None
+	} else {
+		// Expressions are only sandboxed when asked to, since they come from whoever's running the CLI
+		let sandbox = (!cli.allowed_functions.is_empty() || cli.max_expression_nodes.is_some())
+			.then(|| sandbox(&cli.allowed_functions, cli.max_expression_nodes));
+		match compile_expressions([&x_source, &y_source, &z_source], angle_unit, sandbox.as_ref()) {
+			Ok(compiled) => {
+				let [x_expression, y_expression, z_expression] = compiled.map(|e| e.bind());
+				Some((x_expression, y_expression, z_expression))
+			},
+			Err(e) => {
+				eprintln!("Error: {}", e);
+				return ExitCode::FAILURE;
+			},
+		}
+	};
+
+	let image = match cli.white_balance {
+		Some(WhiteBalanceArg::GrayWorld) => white_balance(image, WhiteBalance::GrayWorld),
+		Some(WhiteBalanceArg::Percentile) => white_balance(image, WhiteBalance::Percentile(cli.white_balance_percentile)),
+		None => image,
+	};
+	// The image is straightened first, so the quality checks, overlays and masks are of the part that ends up in the lithophane. The same
+	// is done to each channel with --rgb-stripes.
+	fn straighten(mut image: GrayImage, cli: &Cli) -> GrayImage {
+		if let Some(degrees) = cli.rotate {
+			image = rotate(&image, degrees);
+		}
+		if let &[top_left, top_right, bottom_right, bottom_left] = &cli.perspective[..] {
+			let pixels = |(x, y): (f32, f32)| (x * (image.width() - 1) as f32, y * (image.height() - 1) as f32);
+			image = correct_perspective(&image, [top_left, top_right, bottom_right, bottom_left].map(pixels));
+		}
+		image
+	}
+	let channels = cli.rgb_stripes.map(|_| split_channels(&image).map(|channel| straighten(channel, &cli)));
+	let image = to_grayscale(
+		image,
+		match cli.tone_mapping {
+			ToneMappingArg::Reinhard => ToneMapping::Reinhard,
+			ToneMappingArg::Aces => ToneMapping::Aces,
+		},
+		match cli.grayscale {
+			GrayscaleArg::Luma => Grayscale::Luma,
+			GrayscaleArg::Lightness => Grayscale::Lightness,
+		},
+	);
+	let mut image = straighten(image, &cli);
+	let quality_warnings = quality_warnings(
+		&image,
+		&QualityChecks {
+			min_resolution: cli.min_resolution,
+			min_sharpness: cli.min_sharpness,
+			brightness: cli.brightness_range,
+		},
+	);
+	if cli.reject_low_quality && !quality_warnings.is_empty() {
+		for warning in &quality_warnings {
+			eprintln!("Error: {}", warning);
+		}
+		return ExitCode::FAILURE;
+	}
+	if !cli.overlay.is_empty() {
+		let mut overlays = Vec::with_capacity(cli.overlay.len());
+		for overlay in &cli.overlay {
+			match image::open(&overlay.path) {
+				Ok(i) => overlays.push(Overlay {
+					image: i.into_luma_alpha8(),
+					mode: match overlay.mode {
+						BlendModeArg::Multiply => BlendMode::Multiply,
+						BlendModeArg::Screen => BlendMode::Screen,
+						BlendModeArg::Average => BlendMode::Average,
+					},
+					opacity: overlay.opacity,
+				}),
+				Err(e) => {
+					eprintln!("Error opening overlay file \"{}\": {}", overlay.path, e);
+					return ExitCode::FAILURE;
+				},
+			}
+		}
+		image = composite(&image, &overlays);
+	}
+	if let Some(path) = &cli.dodge_burn {
+		match image::open(path) {
+			Ok(mask) => image = dodge_and_burn(&image, &mask.into_luma8(), cli.dodge_burn_strength),
+			Err(e) => {
+				eprintln!("Error opening dodge and burn mask file \"{}\": {}", path, e);
+				return ExitCode::FAILURE;
+			},
+		}
+	}
+	// The same steps are used for each channel with --rgb-stripes
+	fn preprocess(mut image: GrayImage, cli: &Cli) -> GrayImage {
+		if let Some(max_size) = cli.max_size {
+			if image.width() > max_size || image.height() > max_size {
+				let scale = max_size as f32 / image.width().max(image.height()) as f32;
+				let width = ((image.width() as f32 * scale).round() as u32).max(1);
+				let height = ((image.height() as f32 * scale).round() as u32).max(1);
+				image = resize_area_average(&image, width, height);
+			}
+		}
+		if let Some(threshold) = cli.flatten_background {
+			image = flatten_background(&image, threshold);
+		}
+		if !cli.filter.is_empty() {
+			image = cli.filter.iter().copied().collect::<Pipeline>().apply(&image);
+		}
+		image
+	}
+	image = preprocess(image, &cli);
+	let mut channels = channels.map(|channels| channels.map(|channel| preprocess(channel, &cli)));
+	if let Some(curve) = curve {
+		image = curve.apply(&image);
+		channels = channels.map(|channels| channels.map(|channel| curve.apply(&channel)));
+	}
+	// Check before fitting the image to a size, since a padded border is meant to be all one gray value
+	let mut warnings = quality_warnings;
+	// Terrain is all the tones from its lowest to highest point, so the lowest point is always pure white
+	if terrain.is_none() {
+		warnings.extend(clipping_warnings(&image));
+	}
+	if let Some(size) = size {
+		warnings.extend(aspect_warning(
+			image.width(),
+			image.height(),
+			size.aspect_ratio(image.width(), image.height()),
+			fit,
+		));
+		image = size.fit_image(&image, fit);
+	}
+	if let Some(bookmark) = &bookmark {
+		warnings.extend(aspect_warning(image.width(), image.height(), bookmark.aspect_ratio(), fit));
+		image = bookmark.fit_image(&image, fit);
+	}
+	if let Some(width) = cli.matte {
+		image = add_matte(&image, width, cli.matte_gray);
+	}
+	if cli.image_offset.is_some() || cli.image_scale != 1.0 {
+		image = match map_image(
+			&image,
+			&ImageMapping {
+				offset: cli.image_offset.unwrap_or_default(),
+				scale: cli.image_scale,
+				edges: match cli.image_edges {
+					EdgeModeArg::Clamp => EdgeMode::Clamp,
+					EdgeModeArg::Repeat => EdgeMode::Repeat,
+					EdgeModeArg::Mirror => EdgeMode::Mirror,
+				},
+			},
+		) {
+			Ok(mapped) => mapped,
+			Err(e) => {
+				eprintln!("Error moving the image: {}", e);
+				return ExitCode::FAILURE;
+			},
+		};
+	}
+	if let Some(panel) = &nightlight_panel {
+		let LithophaneOptions {
+			white_depth, black_depth, ..
+		} = LithophaneOptions::default();
+		image = panel.prepare_image(&image, white_depth, black_depth);
+	}
+	if let Some(pendant) = &pendant {
+		image = pendant.prepare_image(&image);
+	}
+	// A single row or column can't make any triangles, so it's repeated to make a thin bar instead of silently making an empty model
+	warnings.extend(strip_warning(image.width(), image.height()));
+	let image = match extend_to_strip(image) {
+		Ok(image) => image,
 		Err(e) => {
-			eprintln!("Invalid x expression: {}", e);
+			eprintln!("Error: {}", e);
 			return ExitCode::FAILURE;
 		},
 	};
-	let y_expression = match cli.y_expression.parse::<meval::Expr>().and_then(|e| e.bind4("x", "y", "w", "h")) {
-		Ok(e) => e,
-		Err(e) => {
-			eprintln!("Invalid y expression: {}", e);
+	let channels = match channels.map(|channels| channels.map(extend_to_strip)) {
+		Some([Ok(red), Ok(green), Ok(blue)]) => Some([red, green, blue]),
+		Some(_) => {
+			eprintln!("Error: the image is empty");
 			return ExitCode::FAILURE;
 		},
+		None => None,
 	};
-	let z_expression = match cli.z_expression.parse::<meval::Expr>().and_then(|e| e.bind4("x", "y", "w", "h")) {
-		Ok(e) => e,
-		Err(e) => {
-			eprintln!("Invalid z expression: {}", e);
+
+	let tiling = cli.tile.map(|(columns, rows)| Tiling {
+		columns,
+		rows,
+		mirror: cli.mirror_tiles,
+	});
+	let (image_width, image_height) = tiling.map_or(image.dimensions(), |t| t.size(image.width(), image.height()));
+
+	let boundary = match cli.crop {
+		Some(Crop::Circle) => Some(Shape::circle_in_image(image_width, image_height, 0.0, 0.0)),
+		Some(Crop::Ellipse) => Some(Shape::ellipse_in_image(image_width, image_height, 0.0, 0.0)),
+		None if !cli.crop_polygon.is_empty() => Some(Shape::polygon_in_image(image_width, image_height, &cli.crop_polygon)),
+		None => nightlight_panel.map(|p| p.boundary(image_width, image_height)),
+	};
+
+	// The expressions are scaled rather than the mesh, so the depths stay the same
+	if let (Some(target_size), Some((x_expression, y_expression, z_expression))) = (cli.target_size, &expressions) {
+		let size = surface_size(x_expression, y_expression, z_expression, image_width, image_height);
+		let factor = target_size / size.x.max(size.y).max(size.z);
+		if !factor.is_finite() {
+			eprintln!("The surface the expressions describe has no size to scale to --target-size");
+			return ExitCode::FAILURE;
+		}
+		let scaled = |source: &str| CompiledExpression::new(&format!("({})*{}", source, factor), angle_unit).map(|e| e.bind());
+		// These parsed before being scaled, so they still do
+		if let (Ok(x_expression), Ok(y_expression), Ok(z_expression)) = (scaled(&x_source), scaled(&y_source), scaled(&z_source)) {
+			expressions = Some((x_expression, y_expression, z_expression));
+		}
+	}
+
+	// Only the image is resampled, so the mesh stays the same
+	let image = match (&expressions, cli.arc_length) {
+		(Some((x_expression, y_expression, z_expression)), true) => arc_length_resample(&image, x_expression, y_expression, z_expression),
+		_ => image,
+	};
+
+	let detail = expressions.as_ref().map(|(x_expression, y_expression, z_expression)| {
+		let pixel_size = pixel_size(x_expression, y_expression, z_expression, image_width, image_height);
+		detail_report(&image, pixel_size, cli.resolution)
+	});
+	let image = match &tactile {
+		Some(tactile) => tactile.simplify(&image, detail.as_ref().map_or(1.0, |d| d.pixel_size)),
+		None => image,
+	};
+	warnings.extend(tactile.as_ref().and_then(tactile_warning));
+	warnings.extend(detail.as_ref().and_then(detail_warning));
+	warnings.extend(expressions.as_ref().and_then(|(x_expression, y_expression, z_expression)| {
+		stretched_pixels_warning(stretched_region(
+			x_expression,
+			y_expression,
+			z_expression,
+			image_width,
+			image_height,
+			STRETCHED_PIXEL_RATIO,
+		))
+	}));
+	warnings.extend(detail.as_ref().and_then(|d| thin_feature_warning(thin_features(&image, d.pixel_size, d.smallest_feature), d.smallest_feature)));
+
+	for warning in &warnings {
+		eprintln!("Warning: {}", warning);
+	}
+
+	let output_name = cli.output.strip_suffix(".gz").unwrap_or(&cli.output);
+	let format = if output_name.ends_with(".obj") {
+		OutputFormat::Obj
+	} else {
+		OutputFormat::BinaryStl
+	};
+	let max_part_triangles = match (cli.max_part_triangles, cli.max_part_mb) {
+		(None, None) => None,
+		(triangles, mb) => {
+			let for_size = mb.map(|mb| max_triangles_for_size(format, (mb * 1_000_000.0) as usize));
+			Some(triangles.unwrap_or(usize::MAX).min(for_size.unwrap_or(usize::MAX)).max(1))
+		},
+	};
+
+	if cli.dry_run {
+		let triangles = lithophane_triangle_count(image_width, image_height, cli.subdivisions, cli.sample_step);
+		print!("{} triangles, about {} bytes uncompressed", triangles, estimated_size(format, triangles));
+		match max_part_triangles {
+			Some(max) if triangles > max => println!(", split into {} files", triangles.div_ceil(max)),
+			_ => println!(),
+		}
+		if let (Some((x_expression, y_expression, z_expression)), Some(detail)) = (&expressions, &detail) {
+			let size = surface_size(x_expression, y_expression, z_expression, image_width, image_height);
+			println!(
+				"The surface is {:.1} × {:.1} × {:.1} mm (before adding the thickness), with about {:.2} mm between vertices",
+				size.x,
+				size.y,
+				size.z,
+				vertex_spacing(detail.pixel_size, &cli)
+			);
+		}
+		return ExitCode::SUCCESS;
+	}
+
+	// The cache and the label's hash both identify the lithophane by its images and settings, without the paths of the files
+	let settings_key = if cli.cache.is_some() || cli.label.is_some() {
+		match settings_key(&cli, input_hash, &format!("{:?} {:?} {:?}", preset, material, curve)) {
+			Ok(key) => Some(key),
+			Err(e) => {
+				eprintln!("Error reading files to hash the settings: {}", e);
+				return ExitCode::FAILURE;
+			},
+		}
+	} else {
+		None
+	};
+	let cache_path =
+		cli.cache.as_ref().zip(settings_key.as_ref()).map(|(directory, key)| Path::new(directory).join(cache_file_name(&cli, key, format)));
+	if let Some(cached) = cache_path.as_ref().and_then(|path| std::fs::read(path).ok()) {
+		let Some(mut file) = create_output_file(&cli.output) else {
+			return ExitCode::FAILURE;
+		};
+		if let Err(e) = file.write_all(&cached) {
+			eprintln!("Error saving lithophane to \"{}\": {}", cli.output, e);
 			return ExitCode::FAILURE;
+		}
+		eprintln!("Copied the lithophane from the cache");
+		return ExitCode::SUCCESS;
+	}
+
+	// When splitting (or generating several parts), the number of files isn't known until the lithophane has been generated
+	let several_parts = channels.is_some() || shadow_box.is_some() || cli.base_depth.is_some() || cli.puzzle.is_some();
+	let mut output_file = match (max_part_triangles, several_parts) {
+		(None, false) => match create_output_file(&cli.output) {
+			Some(f) => Some(f),
+			None => return ExitCode::FAILURE,
+		},
+		_ => None,
+	};
+
+	let label = cli.label.as_ref().zip(settings_key.as_ref()).map(|(text, key)| {
+		let hash = format!("{:06X}", hash_input(key.as_bytes()) >> 40);
+		let height = cli.label_height.unwrap_or(image_height as f32 / 20.0);
+		Label::in_corner(
+			text.replace("{hash}", &hash),
+			image_width,
+			image_height,
+			height,
+			cli.label_depth,
+			height / 2.0,
+		)
+	});
+	let emboss = emboss_image.map(|image| {
+		let mut emboss = Emboss {
+			image,
+			x: 0.0,
+			y: 0.0,
+			width: cli.emboss_width.unwrap_or(image_width as f32 / 8.0),
+			depth: cli.emboss_depth,
+		};
+		(emboss.x, emboss.y) = match cli.emboss_position {
+			Some((x, y)) => (x * (image_width - 1) as f32, y * (image_height - 1) as f32),
+			None => {
+				let margin = emboss.height() / 2.0;
+				(margin, image_height as f32 - 1.0 - margin - emboss.height())
+			},
+		};
+		emboss
+	});
+	let pixel_size = detail.as_ref().map_or(1.0, |d| d.pixel_size);
+	let mut outline = match (&bookmark, &pendant) {
+		(Some(bookmark), _) => bookmark.outline(image_width, image_height),
+		(None, Some(pendant)) => pendant.outline(image_width, image_height),
+		(None, None) => Outline {
+			boundary,
+			holes: cli
+				.window
+				.iter()
+				.map(|&(x, y, width, height)| Shape::rectangle_in_image(image_width, image_height, x, y, width, height))
+				.collect(),
+			hole_reinforcement: cli.window_border / pixel_size,
+		},
+	};
+	if let Some(text) = &cli.window_text {
+		let height = cli.window_text_height.map_or(image_height as f32 / 4.0, |height| height / pixel_size);
+		let (x, y) = match cli.window_text_position {
+			Some((x, y)) => (x * (image_width - 1) as f32, y * (image_height - 1) as f32),
+			None => (
+				((image_width - 1) as f32 - Outline::text_window_width(text, height)) / 2.0,
+				((image_height - 1) as f32 - height) / 2.0,
+			),
+		};
+		outline = outline.with_text_window(text, x, y, height, cli.window_text_gap / pixel_size);
+	}
+	let LithophaneOptions {
+		white_depth, black_depth, ..
+	} = LithophaneOptions::default();
+	let (white_depth, black_depth) =
+		terrain_depths.or(tactile.map(|t| t.depths())).or(bookmark.map(|b| b.depths())).unwrap_or((white_depth, black_depth));
+	let radial_fade = cli.radial_fade.and_then(|start| {
+		let easing = match cli.radial_fade_easing {
+			EasingArg::Linear => Easing::Linear,
+			EasingArg::EaseIn => Easing::EaseIn,
+			EasingArg::EaseOut => Easing::EaseOut,
+			EasingArg::Smooth => Easing::Smooth,
+		};
+		RadialFade::in_shape(outline.boundary.as_ref()?, start, cli.radial_fade_depth, easing)
+	});
+	let header = if cli.header.is_empty() {
+		metadata_header(&cli.metadata)
+	} else {
+		cli.header.clone()
+	};
+	let options = LithophaneOptions {
+		white_depth,
+		black_depth,
+		outline,
+		depth_mask,
+		radial_fade,
+		subdivisions: cli.subdivisions,
+		sample_step: cli.sample_step,
+		frame_fit: match &photo_frame_insert {
+			Some(insert) => Some(insert.frame_fit(image_width, image_height)),
+			None => cli.frame_border.zip(cli.frame_max_thickness).map(|(border, max_thickness)| FrameFit {
+				border,
+				max_thickness,
+				transition: border / 2.0,
+			}),
+		},
+		sheet_ridge: cli.sheet_ridge.map(|height| SheetRidge {
+			height,
+			width: cli.sheet_ridge_width,
+		}),
+		backing_margin: cli.backing_margin.map_or(0.0, |margin| margin / detail.as_ref().map_or(1.0, |d| d.pixel_size)),
+		wall_draft: cli.wall_draft,
+		skirt: cli.skirt_height.zip(cli.skirt_depth).map(|(height, depth)| Skirt {
+			height,
+			depth,
+			wedge: cli.stand.map(|tilt| Wedge {
+				tilt,
+				length: cli.stand_base,
+			}),
+		}),
+		label,
+		contours: cli.contours.map(|levels| Contours { levels }),
+		stipple: cli.stipple.map(|kind| {
+			let pixel_size = detail.as_ref().map_or(1.0, |d| d.pixel_size);
+			let kind = match kind {
+				StippleArg::Holes => StippleKind::Holes,
+				StippleArg::Bumps => StippleKind::Bumps { height: cli.stipple_radius },
+			};
+			Stipple::new(
+				kind,
+				cli.stipple_spacing / pixel_size,
+				cli.stipple_radius / pixel_size,
+				cli.stipple_seed,
+				&image,
+			)
+		}),
+		hatching: cli.hatching.map(|spacing| Hatching {
+			angle: cli.hatching_angle,
+			spacing: spacing / detail.as_ref().map_or(1.0, |d| d.pixel_size),
+			height: cli.hatching_height,
+			crosshatch: cli.crosshatch,
+		}),
+		emboss,
+		// A wall leaning by the angle covers its height times the tangent of the angle horizontally, so that's how steep it can be
+		max_slope: cli
+			.max_slope
+			.or(cli
+				.contour_wall_angle
+				.filter(|&angle| angle > 0.0)
+				.map(|angle| detail.as_ref().map_or(1.0, |d| d.pixel_size) / angle.to_radians().tan()))
+			.or(tactile.map(|t| t.max_slope(detail.as_ref().map_or(1.0, |d| d.pixel_size)))),
+		layer_height: cli.layer_height,
+		thickness_offset: cli.thickness_offset,
+		part: cli.part.map(|p| match p {
+			PartArg::Image => Part::Image,
+			PartArg::Frame => Part::Frame,
+		}),
+		tiling,
+		header: header.clone(),
+		normal_averaging: match cli.normal_averaging {
+			NormalAveragingArg::Pair => NormalAveraging::Pair,
+			NormalAveragingArg::Four => NormalAveraging::Four,
+			NormalAveragingArg::EightWeighted => NormalAveraging::EightWeighted,
 		},
+		..Default::default()
 	};
 
-	fn meval_f32_wrapper(f: impl Fn(f64, f64, f64, f64) -> f64) -> impl Fn(f32, f32, f32, f32) -> f32 {
-		move |x: f32, y: f32, w: f32, h: f32| -> f32 { f(x as f64, y as f64, w as f64, h as f64) as f32 }
+	if let Some(path) = &cli.backlight_preview {
+		let saved = create_output_file(path).map(|mut file| simulate_backlight(&image, &options, &material).write_to(&mut file, ImageFormat::Png));
+		match saved {
+			Some(Ok(())) => {},
+			Some(Err(e)) => {
+				eprintln!("Error saving backlight preview to \"{}\": {}", path, e);
+				return ExitCode::FAILURE;
+			},
+			None => return ExitCode::FAILURE,
+		}
 	}
 
-	let lithophane = match generate_lithophane(
-		meval_f32_wrapper(x_expression),
-		meval_f32_wrapper(y_expression),
-		meval_f32_wrapper(z_expression),
-		image.into_luma8(),
-		0.5,
-		3.0,
-	) {
+	let build_volume = cli.bed.or(cli.printer.map(|p| {
+		match p {
+			PrinterArg::PrusaMk4 => Printer::PrusaMk4,
+			PrinterArg::PrusaMini => Printer::PrusaMini,
+			PrinterArg::BambuLabX1 => Printer::BambuLabX1,
+			PrinterArg::BambuLabA1Mini => Printer::BambuLabA1Mini,
+			PrinterArg::Ender3 => Printer::Ender3,
+			PrinterArg::Voron350 => Printer::Voron350,
+		}
+		.build_volume()
+	}));
+
+	// Resin layers and G-code are made straight from the thicknesses, which only works when the surface is flat
+	let flat_size = match (&expressions, cli.resin_layers.is_some() || cli.gcode.is_some()) {
+		(Some((x_expression, y_expression, z_expression)), true) => {
+			let size = surface_size(x_expression, y_expression, z_expression, image_width, image_height);
+			if size.z > FLATNESS {
+				eprintln!(
+					"--resin-layers and --gcode only work for flat lithophanes, but the surface is {:.1} mm deep",
+					size.z
+				);
+				return ExitCode::FAILURE;
+			}
+			Some((size.x, size.y))
+		},
+		_ => None,
+	};
+
+	if let (Some(path), Some(size)) = (&cli.resin_layers, flat_size) {
+		let layers = ResinLayers {
+			layer_height: cli.resin_layer_height,
+			pixel_size: cli.resin_pixel_size,
+		};
+		let layer_count =
+			ThicknessMap::new(&image, &options, size, layers.pixel_size).and_then(|map| Ok((map.layer_count(layers.layer_height)?, map)));
+		let (layer_count, map) = match layer_count {
+			Ok(counted) => counted,
+			Err(e) => {
+				eprintln!("Error making resin layers: {}", e);
+				return ExitCode::FAILURE;
+			},
+		};
+		for layer in 0..layer_count {
+			let layer_path = suffixed_path(path, &format!("layer{:04}", layer + 1));
+			let saved =
+				create_output_file(&layer_path).map(|mut file| map.layer_mask(layer, layers.layer_height).write_to(&mut file, ImageFormat::Png));
+			match saved {
+				Some(Ok(())) => {},
+				Some(Err(e)) => {
+					eprintln!("Error saving resin layer to \"{}\": {}", layer_path, e);
+					return ExitCode::FAILURE;
+				},
+				None => return ExitCode::FAILURE,
+			}
+		}
+	}
+
+	if let (Some(path), Some(size)) = (&cli.gcode, flat_size) {
+		let Some(bed) = build_volume else {
+			eprintln!("--gcode needs a --printer or --bed to put the lithophane in the middle of");
+			return ExitCode::FAILURE;
+		};
+		let default = PrintSettings::default();
+		let settings = GcodeSettings {
+			print: PrintSettings {
+				layer_height: cli.print_layer_height.or(cli.layer_height).unwrap_or(default.layer_height),
+				line_width: cli.line_width,
+				infill_speed: cli.infill_speed,
+				..default
+			},
+			bed,
+			nozzle_temperature: cli.nozzle_temperature,
+			bed_temperature: cli.bed_temperature,
+			..Default::default()
+		};
+		let gcode = match ThicknessMap::new(&image, &options, size, settings.print.line_width)
+			.map_err(GcodeError::from)
+			.and_then(|map| flat_lithophane_gcode(&map, &settings))
+		{
+			Ok(gcode) => gcode,
+			Err(e) => {
+				eprintln!("Error making G-code: {}", e);
+				return ExitCode::FAILURE;
+			},
+		};
+		let Some(mut file) = create_output_file(path) else {
+			return ExitCode::FAILURE;
+		};
+		if let Err(e) = file.write_all(gcode.as_bytes()) {
+			eprintln!("Error saving G-code to \"{}\": {}", path, e);
+			return ExitCode::FAILURE;
+		}
+	}
+
+	if let (Some(channels), Some((x_expression, y_expression, z_expression))) = (channels, &expressions) {
+		let models = match generate_rgb_stripes(
+			x_expression,
+			y_expression,
+			z_expression,
+			channels,
+			cli.rgb_stripes.unwrap_or_default(),
+			&options,
+		) {
+			Ok(m) => m,
+			Err(e) => {
+				eprintln!("Error generating lithophane: {}", e);
+				return ExitCode::FAILURE;
+			},
+		};
+		return save_named_models(&cli, format, ["red", "green", "blue"].map(String::from).into_iter().zip(models).collect());
+	}
+
+	if let (Some(base_depth), Some((x_expression, y_expression, z_expression))) = (cli.base_depth, &expressions) {
+		let models = match generate_base_and_relief(x_expression, y_expression, z_expression, image, base_depth, &options) {
+			Ok(m) => m,
+			Err(e) => {
+				eprintln!("Error generating lithophane: {}", e);
+				return ExitCode::FAILURE;
+			},
+		};
+		return save_named_models(&cli, format, ["base", "relief"].map(String::from).into_iter().zip(models).collect());
+	}
+
+	if let (Some((columns, rows)), Some((x_expression, y_expression, z_expression))) = (cli.puzzle, &expressions) {
+		if !cli.piece_brightness.is_empty() && cli.piece_brightness.len() != (columns * rows) as usize {
+			eprintln!(
+				"Expected one --piece-brightness for each of the {} pieces, found {}",
+				columns * rows,
+				cli.piece_brightness.len()
+			);
+			return ExitCode::FAILURE;
+		}
+		if cli.piece_brightness.iter().any(|&b| b <= 0.0) {
+			eprintln!("Every --piece-brightness has to be more than 0");
+			return ExitCode::FAILURE;
+		}
+		let puzzle = Puzzle {
+			columns,
+			rows,
+			seed: cli.puzzle_seed,
+			clearance: cli.puzzle_clearance / detail.as_ref().map_or(1.0, |d| d.pixel_size),
+			thickness_offsets: brightness_compensation(&cli.piece_brightness, &material),
+			..Default::default()
+		};
+		let pieces = match generate_puzzle_pieces(x_expression, y_expression, z_expression, image, &puzzle, &options) {
+			Ok(p) => p,
+			Err(e) => {
+				eprintln!("Error generating lithophane: {}", e);
+				return ExitCode::FAILURE;
+			},
+		};
+		let names = (0..rows).flat_map(|row| (0..columns).map(move |column| format!("{}-{}", row + 1, column + 1)));
+		return save_named_models(&cli, format, names.zip(pieces).collect());
+	}
+
+	if let Some(shadow_box) = &shadow_box {
+		let layers = match shadow_box.generate(&image) {
+			Ok(l) => l,
+			Err(e) => {
+				eprintln!("Error generating shadow box: {}", e);
+				return ExitCode::FAILURE;
+			},
+		};
+		let named = layers.into_iter().enumerate().map(|(i, mut layer)| {
+			layer.header = header.clone();
+			(format!("layer{}", i + 1), layer)
+		});
+		return save_named_models(&cli, format, named.collect());
+	}
+
+	let low_poly = cli.low_poly.map(|triangles| LowPoly {
+		triangles,
+		compactness: cli.low_poly_compactness,
+	});
+	let generated = match (expressions, &clock_face, &low_poly) {
+		(Some((x_expression, y_expression, z_expression)), _, Some(low_poly)) => low_poly
+			.generate(x_expression, y_expression, z_expression, &image, options.white_depth, options.black_depth)
+			.map(|mut lithophane| {
+				lithophane.header = header.clone();
+				lithophane
+			})
+			.map_err(LithophaneError::from),
+		(Some((x_expression, y_expression, z_expression)), _, None) if cli.thickness_stats => {
+			generate_lithophane_with_thickness_stats(x_expression, y_expression, z_expression, image, &options).map(|(lithophane, stats)| {
+				print_thickness_stats(stats.as_ref());
+				lithophane
+			})
+		},
+		(Some((x_expression, y_expression, z_expression)), _, None) => generate_lithophane(x_expression, y_expression, z_expression, image, &options),
+		(None, Some(clock), _) => {
+			let LithophaneOptions {
+				white_depth, black_depth, ..
+			} = LithophaneOptions::default();
+			clock.generate(image, white_depth, black_depth).map(|mut face| {
+				face.header = header.clone();
+				face
+			})
+		},
+		(None, None, _) => unreachable!("only clock faces are generated without expressions"),
+	};
+	let mut lithophane = match generated {
 		Ok(l) => l,
 		Err(e) => {
 			eprintln!("Error generating lithophane: {}", e);
@@ -76,10 +1826,374 @@ fn main() -> ExitCode {
 		},
 	};
 
-	if let Err(e) = output_file.write_all(&lithophane.as_binary()) {
-		eprintln!("Error saving lithophane to \"{}\": {}", cli.output, e);
-		return ExitCode::FAILURE;
+	if let Some(tilt) = cli.stand {
+		stand_up(&mut lithophane, tilt);
+	}
+
+	if let (Some(build_volume), Some((min, max))) = (build_volume, bounding_box(&lithophane)) {
+		let factor = build_volume.scale_to_fit(max - min);
+		if factor < 1.0 {
+			if cli.scale_to_fit {
+				scale(&mut lithophane, factor);
+				eprintln!("Scaled the lithophane to {:.1}% to fit the build volume", factor * 100.0);
+			} else {
+				eprintln!(
+					"Warning: the lithophane ({:.1} × {:.1} × {:.1} mm) doesn't fit in the build volume, use --scale-to-fit to shrink it",
+					max.x - min.x,
+					max.y - min.y,
+					max.z - min.z
+				);
+			}
+		}
+	}
+
+	if let (Some(detail), Some((min, max))) = (&detail, bounding_box(&lithophane)) {
+		eprintln!(
+			"The lithophane is {:.1} × {:.1} × {:.1} mm, with about {:.2} mm between vertices",
+			max.x - min.x,
+			max.y - min.y,
+			max.z - min.z,
+			vertex_spacing(detail.pixel_size, &cli)
+		);
+	}
+
+	let print_time = cli.estimate_print_time.then(|| {
+		let default = PrintSettings::default();
+		estimate_print_time(
+			&lithophane,
+			&PrintSettings {
+				layer_height: cli.print_layer_height.or(cli.layer_height).unwrap_or(default.layer_height),
+				line_width: cli.line_width,
+				perimeter_speed: cli.perimeter_speed,
+				infill_speed: cli.infill_speed,
+				..default
+			},
+		)
+	});
+	if let Some(seconds) = print_time {
+		let minutes = (seconds / 60.0).round() as u32;
+		eprintln!("Estimated print time: about {} h {} min", minutes / 60, minutes % 60);
+	}
+
+	if let Some(precision) = cli.precision.or(cli.canonical.then_some(CANONICAL_PRECISION)) {
+		let removed = quantize(&mut lithophane, precision);
+		if removed > 0 {
+			eprintln!("Removed {} triangles that collapsed when rounding coordinates", removed);
+		}
+	}
+
+	let mut lithophane = if cli.repair {
+		let (repaired, report) = repair(&lithophane, cli.weld_tolerance, 16);
+		eprintln!(
+			"Repaired mesh: merged {} vertices, removed {} degenerate and {} duplicate triangles, filled {} holes, flipped {} triangles",
+			report.merged_vertices,
+			report.removed_degenerate_triangles,
+			report.removed_duplicate_triangles,
+			report.filled_holes,
+			report.flipped_triangles
+		);
+		repaired
+	} else {
+		lithophane
+	};
+
+	if cli.canonical {
+		canonicalize(&mut lithophane);
+	}
+
+	let parts = match max_part_triangles {
+		Some(max) => split(&lithophane, max),
+		None => vec![lithophane],
+	};
+	// The path, triangle count and size of each file saved
+	let mut saved = Vec::with_capacity(parts.len());
+	for (i, part) in parts.iter().enumerate() {
+		let path = if parts.len() > 1 {
+			part_path(&cli.output, i + 1)
+		} else {
+			cli.output.clone()
+		};
+
+		let mut data = if format == OutputFormat::Obj {
+			let (mesh, merged_vertices) = IndexedMesh::welded(part, cli.weld_tolerance);
+			if merged_vertices > 0 {
+				eprintln!("Merged {} vertices within the weld tolerance", merged_vertices);
+			}
+			as_obj(&mesh, if cli.smooth { Shading::Smooth } else { Shading::Flat }).into_bytes()
+		} else {
+			as_binary(part)
+		};
+		if cli.output.ends_with(".gz") {
+			data = match compress_gzip(&data) {
+				Ok(s) => s,
+				Err(e) => {
+					eprintln!("Error compressing lithophane: {}", e);
+					return ExitCode::FAILURE;
+				},
+			};
+		}
+
+		let Some(mut file) = output_file.take().or_else(|| create_output_file(&path)) else {
+			return ExitCode::FAILURE;
+		};
+		if let Err(e) = file.write_all(&data) {
+			eprintln!("Error saving lithophane to \"{}\": {}", path, e);
+			return ExitCode::FAILURE;
+		}
+		// A lithophane that can't be cached is still saved, it just has to be generated again next time
+		if let Some(cache_path) = &cache_path {
+			if let Err(e) = cache_path.parent().map_or(Ok(()), std::fs::create_dir_all).and_then(|_| std::fs::write(cache_path, &data)) {
+				eprintln!("Warning: couldn't save the lithophane to the cache: {}", e);
+			}
+		}
+		saved.push((path, part.triangles.len(), data.len()));
+	}
+	if saved.len() > 1 {
+		eprintln!("Split the lithophane into {} files", saved.len());
+	}
+
+	if let Some(path) = &cli.manifest {
+		let manifest = Manifest {
+			input_hash,
+			parameters: vec![
+				("command_line".to_string(), std::env::args().collect::<Vec<_>>().join(" ")),
+				("x_expression".to_string(), x_source),
+				("y_expression".to_string(), y_source),
+				("z_expression".to_string(), z_source),
+			],
+			metadata: cli.metadata.clone(),
+			triangles: saved.iter().map(|(_, triangles, _)| triangles).sum(),
+			bytes: saved.iter().map(|(_, _, bytes)| bytes).sum(),
+			print_time,
+			warnings: warnings.iter().map(|w| w.to_string()).collect(),
+		};
+		if let Err(e) = std::fs::write(path, manifest.to_json()) {
+			eprintln!("Error saving manifest to \"{}\": {}", path, e);
+			return ExitCode::FAILURE;
+		}
+	}
+
+	if let Some(post_cmd) = &cli.post_cmd {
+		for (path, triangles, bytes) in &saved {
+			let mut stats = format!(
+				"{{\"path\":{},\"width\":{},\"height\":{},\"triangles\":{},\"bytes\":{}",
+				json_string(path),
+				image_width,
+				image_height,
+				triangles,
+				bytes
+			);
+			if let Some(detail) = &detail {
+				stats.push_str(&format!(
+					",\"pixel_size_mm\":{},\"smallest_feature_mm\":{},\"smallest_feature_pixels\":{},\"visible_detail\":{}",
+					detail.pixel_size, detail.smallest_feature, detail.smallest_feature_pixels, detail.visible_detail
+				));
+			}
+			if let Some(seconds) = print_time {
+				stats.push_str(&format!(",\"print_time_s\":{}", seconds));
+			}
+			stats.push('}');
+			match run_post_cmd(post_cmd, path, &stats) {
+				Ok(status) if status.success() => {},
+				Ok(status) => {
+					eprintln!("Post command \"{}\" failed ({})", post_cmd, status);
+					return ExitCode::FAILURE;
+				},
+				Err(e) => {
+					eprintln!("Error running post command \"{}\": {}", post_cmd, e);
+					return ExitCode::FAILURE;
+				},
+			}
+		}
 	}
 
 	ExitCode::SUCCESS
 }
+
+/// The average distance between neighbouring vertices on the surface in millimeters, from the average distance between pixels
+fn vertex_spacing(pixel_size: f32, cli: &Cli) -> f32 {
+	pixel_size * cli.sample_step.max(1) as f32 / cli.subdivisions.max(1) as f32
+}
+
+fn print_thickness_stats(stats: Option<&ThicknessStats>) {
+	let Some(stats) = stats else {
+		println!("Nothing is left inside the outline to measure the thickness of");
+		return;
+	};
+	println!("Thickness: {:.2} to {:.2} mm", stats.min, stats.max);
+	let percentiles = THICKNESS_PERCENTILES.iter().zip(stats.percentiles).map(|(p, t)| format!("{}%: {:.2} mm", p, t));
+	println!("Percentiles: {}", percentiles.collect::<Vec<_>>().join(", "));
+	let total = stats.histogram.iter().sum::<usize>();
+	let largest = stats.histogram.iter().copied().max().unwrap_or(0).max(1);
+	for (bin, &count) in stats.histogram.iter().enumerate() {
+		let (low, high) = stats.bin_range(bin);
+		let bar = "#".repeat(count * 40 / largest);
+		let range = format!("{:.2} - {:.2} mm", low, high);
+		println!("{:>16} {:<40} {:>5.1}%", range, bar, count as f32 / total as f32 * 100.0);
+	}
+}
+
+/// Create a new file to save output to, printing an error if it can't be created (eg because it already exists)
+fn create_output_file(path: &str) -> Option<File> {
+	match OpenOptions::new().create_new(true).write(true).open(path) {
+		Ok(f) => Some(f),
+		Err(e) => {
+			eprintln!("Error opening output file \"{}\": {}", path, e);
+			None
+		},
+	}
+}
+
+/// Read the --input file, or download it if it's a URL and the url-input feature is on
+fn read_input(input: &str) -> Result<Vec<u8>, String> {
+	#[cfg(feature = "url-input")]
+	if input.starts_with("http://") || input.starts_with("https://") {
+		use std::io::Read;
+
+		// Much bigger than any photo, so a wrong URL can't fill up the memory
+		const MAX_BYTES: u64 = 256 * 1024 * 1024;
+		let response = ureq::get(input).call().map_err(|e| e.to_string())?;
+		let mut bytes = Vec::new();
+		response.into_reader().take(MAX_BYTES + 1).read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+		if bytes.len() as u64 > MAX_BYTES {
+			return Err(format!("the download is bigger than {} MB", MAX_BYTES / 1024 / 1024));
+		}
+		return Ok(bytes);
+	}
+	std::fs::read(input).map_err(|e| e.to_string())
+}
+
+/// A description of the images and settings a lithophane is made with, which depends on the contents of the images used rather than their
+/// paths, and not on the order of the arguments
+///
+/// `definitions` is the preset, material and curve the names in the settings refer to, since the user can change them in their catalog.
+fn settings_key(cli: &Cli, input_hash: u64, definitions: &str) -> std::io::Result<String> {
+	let mut file_hashes = vec![input_hash];
+	for path in cli.overlay.iter().map(|o| &o.path).chain(&cli.depth_mask).chain(&cli.dodge_burn).chain(&cli.emboss) {
+		file_hashes.push(hash_input(&std::fs::read(path)?));
+	}
+	// Where the files are doesn't change the lithophane, so the paths are left out
+	let settings = Cli {
+		input: String::new(),
+		output: String::new(),
+		overlay: cli
+			.overlay
+			.iter()
+			.map(|o| OverlayArg {
+				path: String::new(),
+				..o.clone()
+			})
+			.collect(),
+		depth_mask: cli.depth_mask.as_ref().map(|_| String::new()),
+		dodge_burn: cli.dodge_burn.as_ref().map(|_| String::new()),
+		emboss: cli.emboss.as_ref().map(|_| String::new()),
+		config_dir: None,
+		cache: None,
+		..cli.clone()
+	};
+	Ok(format!("{} {:x?} {:?} {}", env!("CARGO_PKG_VERSION"), file_hashes, settings, definitions))
+}
+
+/// The name of the file in the --cache directory for a lithophane with this `settings_key`
+fn cache_file_name(cli: &Cli, settings_key: &str, format: OutputFormat) -> String {
+	let extension = if format == OutputFormat::Obj { "obj" } else { "stl" };
+	format!(
+		"{:016x}.{}{}",
+		hash_input(settings_key.as_bytes()),
+		extension,
+		if cli.output.ends_with(".gz") { ".gz" } else { "" }
+	)
+}
+
+/// The path to save part `number` of a split lithophane to, eg "out.part2.stl.gz" for "out.stl.gz"
+fn part_path(output: &str, number: usize) -> String {
+	suffixed_path(output, &format!("part{}", number))
+}
+
+/// Add a suffix to the output path before the extension, eg "out.red.stl.gz" for "out.stl.gz"
+fn suffixed_path(output: &str, suffix: &str) -> String {
+	let (name, gz) = match output.strip_suffix(".gz") {
+		Some(name) => (name, ".gz"),
+		None => (output, ""),
+	};
+	match name.rfind('.').filter(|&i| !name[i..].contains(['/', '\\'])) {
+		Some(i) => format!("{}.{}{}{}", &name[..i], suffix, &name[i..], gz),
+		None => format!("{}.{}{}", name, suffix, gz),
+	}
+}
+
+/// Save models that are made of several parts (eg the channels from `--rgb-stripes`), as named objects in one OBJ file or as a separate STL
+/// file for each part
+fn save_named_models(cli: &Cli, format: OutputFormat, mut models: Vec<(String, StlModel)>) -> ExitCode {
+	if cli.canonical {
+		for (_, model) in &mut models {
+			quantize(model, CANONICAL_PRECISION);
+			canonicalize(model);
+		}
+	}
+	let named = models.into_iter();
+	let files = if format == OutputFormat::Obj {
+		let (names, meshes): (Vec<_>, Vec<_>) = named.map(|(name, model)| (name, IndexedMesh::welded(&model, cli.weld_tolerance).0)).unzip();
+		let objects = names.iter().map(String::as_str).zip(meshes).collect::<Vec<_>>();
+		vec![(
+			cli.output.clone(),
+			as_obj_objects(&objects, if cli.smooth { Shading::Smooth } else { Shading::Flat }).into_bytes(),
+		)]
+	} else {
+		named.map(|(name, model)| (suffixed_path(&cli.output, &name), as_binary(&model))).collect()
+	};
+
+	for (path, mut data) in files {
+		if cli.output.ends_with(".gz") {
+			data = match compress_gzip(&data) {
+				Ok(s) => s,
+				Err(e) => {
+					eprintln!("Error compressing lithophane: {}", e);
+					return ExitCode::FAILURE;
+				},
+			};
+		}
+		let Some(mut file) = create_output_file(&path) else {
+			return ExitCode::FAILURE;
+		};
+		if let Err(e) = file.write_all(&data) {
+			eprintln!("Error saving lithophane to \"{}\": {}", path, e);
+			return ExitCode::FAILURE;
+		}
+	}
+	ExitCode::SUCCESS
+}
+
+/// Run a command with the system shell, passing it `path` as an argument and `stats` on standard input
+fn run_post_cmd(command: &str, path: &str, stats: &str) -> std::io::Result<ExitStatus> {
+	// The path is passed in an environment variable instead of being pasted into the command so it doesn't need escaping for the shell
+	let mut child = shell_command(command).env("LITHOPHANE_OUTPUT", path).stdin(Stdio::piped()).spawn()?;
+
+	// The command might not read its input at all, which is fine
+	if let Some(mut stdin) = child.stdin.take() {
+		let _ = stdin.write_all(stats.as_bytes());
+	}
+
+	child.wait()
+}
+
+/// A command that runs `command` with `sh`, followed by the path in `LITHOPHANE_OUTPUT`
+#[cfg(not(windows))]
+fn shell_command(command: &str) -> Command {
+	let mut shell = Command::new("sh");
+	shell.arg("-c").arg(format!("{} \"$LITHOPHANE_OUTPUT\"", command));
+	shell
+}
+
+/// A command that runs `command` with `cmd`, followed by the path in `LITHOPHANE_OUTPUT`
+///
+/// cmd doesn't follow the quoting rules `arg` escapes for, so the command line is passed as it is, with /S so cmd only strips the outer
+/// quotes. The variable is expanded inside quotes and paths can't contain quotes on Windows, so characters like & and % in the path are
+/// taken literally.
+#[cfg(windows)]
+fn shell_command(command: &str) -> Command {
+	use std::os::windows::process::CommandExt;
+	let mut shell = Command::new("cmd");
+	shell.raw_arg(format!("/S /C \"{} \"%LITHOPHANE_OUTPUT%\"\"", command));
+	shell
+}
@@ -0,0 +1,74 @@
+use std::fmt::Write;
+
+/// A record of how a model was generated, saved next to it so it can be archived and generated again later
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Manifest {
+	/// `hash_input` of the input image file
+	pub input_hash: u64,
+	/// The settings the model was generated with, as names and values
+	pub parameters: Vec<(String, String)>,
+	/// Information about what the model is for (eg the customer and order ID), passed through unchanged so prints can be traced back to
+	/// their orders
+	pub metadata: Vec<(String, String)>,
+	pub triangles: usize,
+	/// The size of the saved model in bytes
+	pub bytes: usize,
+	/// `printer::estimate_print_time` for the model in seconds, if it was estimated
+	pub print_time: Option<f32>,
+	pub warnings: Vec<String>,
+}
+
+impl Manifest {
+	/// The manifest as a JSON object, along with the version of this crate
+	pub fn to_json(&self) -> String {
+		let mut json = String::new();
+		json.push_str("{\n");
+		writeln!(json, "\t\"version\": {},", json_string(env!("CARGO_PKG_VERSION"))).unwrap();
+		writeln!(json, "\t\"input_hash\": \"{:016x}\",", self.input_hash).unwrap();
+		write_object(&mut json, "parameters", &self.parameters);
+		write_object(&mut json, "metadata", &self.metadata);
+		writeln!(json, "\t\"triangles\": {},", self.triangles).unwrap();
+		writeln!(json, "\t\"bytes\": {},", self.bytes).unwrap();
+		if let Some(print_time) = self.print_time {
+			writeln!(json, "\t\"print_time_s\": {},", print_time).unwrap();
+		}
+		let warnings = self.warnings.iter().map(|w| json_string(w)).collect::<Vec<_>>();
+		writeln!(json, "\t\"warnings\": [{}]", warnings.join(", ")).unwrap();
+		json.push_str("}\n");
+		json
+	}
+}
+
+/// Write a field of a JSON object whose value is an object of strings, followed by a comma
+fn write_object(json: &mut String, name: &str, fields: &[(String, String)]) {
+	write!(json, "\t{}: {{", json_string(name)).unwrap();
+	for (i, (name, value)) in fields.iter().enumerate() {
+		let separator = if i == 0 { "" } else { "," };
+		write!(json, "{}\n\t\t{}: {}", separator, json_string(name), json_string(value)).unwrap();
+	}
+	json.push_str(if fields.is_empty() { "},\n" } else { "\n\t},\n" });
+}
+
+/// A 64 bit FNV-1a hash of an input file, to tell whether a model was generated from the same file
+///
+/// This isn't a cryptographic hash, it's only meant to catch accidentally using a different or edited file.
+pub fn hash_input(bytes: &[u8]) -> u64 {
+	bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+/// Quote and escape a string for JSON
+pub fn json_string(s: &str) -> String {
+	let mut quoted = String::with_capacity(s.len() + 2);
+	quoted.push('"');
+	for c in s.chars() {
+		match c {
+			'"' => quoted.push_str("\\\""),
+			'\\' => quoted.push_str("\\\\"),
+			'\n' => quoted.push_str("\\n"),
+			c if (c as u32) < 0x20 => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+			c => quoted.push(c),
+		}
+	}
+	quoted.push('"');
+	quoted
+}
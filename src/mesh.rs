@@ -0,0 +1,481 @@
+use std::{
+	cmp::Ordering,
+	collections::{HashMap, HashSet},
+};
+
+use pk_stl::{
+	geometry::{Triangle, Vec3},
+	StlModel,
+};
+
+use crate::lithophane::cross_product;
+
+/// A vertex position that can be used as a hash key, so vertices are only considered equal if they're exactly equal
+type VertexKey = [u32; 3];
+
+fn vertex_key(v: Vec3) -> VertexKey {
+	v.as_arr().map(f32::to_bits)
+}
+
+/// Find the edges of a mesh that aren't shared with another triangle going the opposite direction, which are exactly the edges
+/// around holes (or any other reason the mesh isn't watertight). Each edge is returned in the direction its triangle uses it.
+pub fn boundary_edges(model: &StlModel) -> Vec<[Vec3; 2]> {
+	// The number of times each directed edge is used, minus the number of times it's used in the opposite direction
+	let mut edge_counts: HashMap<(VertexKey, VertexKey), (i32, [Vec3; 2])> = HashMap::new();
+	for triangle in &model.triangles {
+		for i in 0..3 {
+			let (a, b) = (triangle.vertices[i], triangle.vertices[(i + 1) % 3]);
+			let (a_key, b_key) = (vertex_key(a), vertex_key(b));
+			if let Some((count, _)) = edge_counts.get_mut(&(b_key, a_key)).filter(|(count, _)| *count > 0) {
+				*count -= 1;
+			} else {
+				edge_counts.entry((a_key, b_key)).or_insert((0, [a, b])).0 += 1;
+			}
+		}
+	}
+
+	edge_counts.into_values().flat_map(|(count, edge)| (0..count).map(move |_| edge)).collect()
+}
+
+/// A mesh with shared vertices, where each face is a triangle of indices into the vertices
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedMesh {
+	pub vertices: Vec<Vec3>,
+	pub faces: Vec<[usize; 3]>,
+}
+
+impl IndexedMesh {
+	/// Build an indexed mesh from a model's triangles, merging any vertices closer together than `tolerance`
+	/// With a tolerance of 0, only exactly equal vertices are merged.
+	pub fn from_model(model: &StlModel, tolerance: f32) -> Self {
+		let mut vertices = Vec::new();
+		let mut faces = Vec::with_capacity(model.triangles.len());
+
+		if tolerance <= 0.0 {
+			let mut indices = HashMap::new();
+			for triangle in &model.triangles {
+				faces.push(triangle.vertices.map(|v| {
+					*indices.entry(vertex_key(v)).or_insert_with(|| {
+						vertices.push(v);
+						vertices.len() - 1
+					})
+				}));
+			}
+			return Self { vertices, faces };
+		}
+
+		// Put vertices in a grid of cells the size of the tolerance, so only the surrounding cells need to be checked for close vertices
+		let cell = |v: Vec3| v.as_arr().map(|c| (c / tolerance).floor() as i64);
+		let mut grid: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+		for triangle in &model.triangles {
+			faces.push(triangle.vertices.map(|v| {
+				let [cx, cy, cz] = cell(v);
+				for x in cx - 1..=cx + 1 {
+					for y in cy - 1..=cy + 1 {
+						for z in cz - 1..=cz + 1 {
+							for &i in grid.get(&[x, y, z]).into_iter().flatten() {
+								let d = vertices[i] - v;
+								if d.x * d.x + d.y * d.y + d.z * d.z <= tolerance * tolerance {
+									return i;
+								}
+							}
+						}
+					}
+				}
+				vertices.push(v);
+				grid.entry([cx, cy, cz]).or_default().push(vertices.len() - 1);
+				vertices.len() - 1
+			}));
+		}
+		Self { vertices, faces }
+	}
+
+	/// Build an indexed mesh like `from_model`, also returning how many vertices were merged because they were within the tolerance
+	/// (not counting vertices that were exactly equal)
+	pub fn welded(model: &StlModel, tolerance: f32) -> (Self, usize) {
+		let mesh = Self::from_model(model, tolerance);
+		let merged = if tolerance > 0.0 {
+			Self::from_model(model, 0.0).vertices.len() - mesh.vertices.len()
+		} else {
+			0
+		};
+		(mesh, merged)
+	}
+
+	/// Convert back to a model, calculating the normals from the counterclockwise order of the vertices
+	/// Faces with no area are left out since they don't have a normal.
+	pub fn to_model(&self, header: String) -> StlModel {
+		StlModel {
+			header,
+			triangles: self
+				.faces
+				.iter()
+				.filter_map(|face| {
+					let vertices = face.map(|i| self.vertices[i]);
+					face_normal(vertices).map(|normal| Triangle { normal, vertices })
+				})
+				.collect(),
+		}
+	}
+
+	/// The directed edges that aren't matched by an edge going the other direction, as pairs of vertex indices
+	pub fn boundary_edges(&self) -> Vec<(usize, usize)> {
+		let mut edge_counts: HashMap<(usize, usize), i32> = HashMap::new();
+		for face in &self.faces {
+			for i in 0..3 {
+				let (a, b) = (face[i], face[(i + 1) % 3]);
+				match edge_counts.get_mut(&(b, a)).filter(|count| **count > 0) {
+					Some(count) => *count -= 1,
+					None => *edge_counts.entry((a, b)).or_default() += 1,
+				}
+			}
+		}
+		edge_counts.into_iter().flat_map(|(edge, count)| (0..count).map(move |_| edge)).collect()
+	}
+
+	/// Make the winding order of all faces consistent with their neighbors, then make sure each connected piece has its normals pointing
+	/// outward (a positive volume). Returns the number of faces that were flipped.
+	pub fn fix_orientation(&mut self) -> usize {
+		let mut edge_faces: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+		for (f, face) in self.faces.iter().enumerate() {
+			for i in 0..3 {
+				let (a, b) = (face[i], face[(i + 1) % 3]);
+				edge_faces.entry((a.min(b), a.max(b))).or_default().push(f);
+			}
+		}
+		let uses_edge = |face: &[usize; 3], a: usize, b: usize| (0..3).any(|i| face[i] == a && face[(i + 1) % 3] == b);
+
+		let mut flipped = vec![false; self.faces.len()];
+		let mut visited = vec![false; self.faces.len()];
+		for start in 0..self.faces.len() {
+			if visited[start] {
+				continue;
+			}
+
+			// Flood fill from this face, flipping neighbors that use a shared edge in the same direction instead of the opposite one
+			visited[start] = true;
+			let mut component = vec![start];
+			let mut stack = vec![start];
+			while let Some(f) = stack.pop() {
+				let face = self.faces[f];
+				for i in 0..3 {
+					let (a, b) = (face[i], face[(i + 1) % 3]);
+					for &neighbor in &edge_faces[&(a.min(b), a.max(b))] {
+						if visited[neighbor] {
+							continue;
+						}
+						visited[neighbor] = true;
+						if uses_edge(&self.faces[neighbor], a, b) {
+							self.faces[neighbor].swap(1, 2);
+							flipped[neighbor] = !flipped[neighbor];
+						}
+						component.push(neighbor);
+						stack.push(neighbor);
+					}
+				}
+			}
+
+			let volume: f32 = component
+				.iter()
+				.map(|&f| {
+					let [a, b, c] = self.faces[f].map(|i| self.vertices[i]);
+					a.x * (b.y * c.z - b.z * c.y) - a.y * (b.x * c.z - b.z * c.x) + a.z * (b.x * c.y - b.y * c.x)
+				})
+				.sum();
+			if volume < 0.0 {
+				for &f in &component {
+					self.faces[f].swap(1, 2);
+					flipped[f] = !flipped[f];
+				}
+			}
+		}
+
+		flipped.into_iter().filter(|&f| f).count()
+	}
+}
+
+pub(crate) fn face_normal([a, b, c]: [Vec3; 3]) -> Option<Vec3> {
+	let (u, v) = (b - a, c - a);
+	let n = Vec3::new([u.y * v.z - v.y * u.z, u.z * v.x - v.z * u.x, u.x * v.y - v.x * u.y]);
+	let length = (n.x * n.x + n.y * n.y + n.z * n.z).sqrt();
+	(length > 0.0).then(|| n * (1.0 / length))
+}
+
+/// What was changed while repairing a mesh
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RepairReport {
+	pub merged_vertices: usize,
+	pub removed_degenerate_triangles: usize,
+	pub removed_duplicate_triangles: usize,
+	pub filled_holes: usize,
+	pub flipped_triangles: usize,
+}
+
+/// Try to make a borderline mesh printable: weld vertices closer than `weld_tolerance`, remove triangles with no area or that duplicate
+/// another triangle, fill holes with at most `max_hole_edges` edges, and fix the winding order of inconsistent or inside out triangles
+pub fn repair(model: &StlModel, weld_tolerance: f32, max_hole_edges: usize) -> (StlModel, RepairReport) {
+	let mut report = RepairReport::default();
+
+	let (mut mesh, merged_vertices) = IndexedMesh::welded(model, weld_tolerance);
+	report.merged_vertices = merged_vertices;
+
+	let face_count = mesh.faces.len();
+	mesh.faces.retain(|&[a, b, c]| a != b && b != c && c != a && face_normal([a, b, c].map(|i| mesh.vertices[i])).is_some());
+	report.removed_degenerate_triangles = face_count - mesh.faces.len();
+
+	let face_count = mesh.faces.len();
+	let mut seen = HashSet::new();
+	mesh.faces.retain(|face| {
+		let mut sorted = *face;
+		sorted.sort_unstable();
+		seen.insert(sorted)
+	});
+	report.removed_duplicate_triangles = face_count - mesh.faces.len();
+
+	// Inconsistent winding looks like holes, so this has to be fixed before looking for real holes
+	report.flipped_triangles = mesh.fix_orientation();
+
+	// Follow the boundary edges around each hole, then fill the small ones with a fan of triangles going the opposite direction
+	let mut next_boundary_vertex: HashMap<usize, usize> = mesh.boundary_edges().into_iter().collect();
+	while let Some(&start) = next_boundary_vertex.keys().next() {
+		let mut hole = vec![start];
+		let mut current = start;
+		let mut closed = false;
+		while let Some(next) = next_boundary_vertex.remove(&current) {
+			if next == start {
+				closed = true;
+				break;
+			}
+			hole.push(next);
+			current = next;
+		}
+		// Holes that don't loop back around (eg because they run into a vertex with more than one boundary edge) are left alone
+		if closed && hole.len() >= 3 && hole.len() <= max_hole_edges {
+			for i in 1..hole.len() - 1 {
+				mesh.faces.push([hole[0], hole[i + 1], hole[i]]);
+			}
+			report.filled_holes += 1;
+		}
+	}
+
+	(mesh.to_model(model.header.clone()), report)
+}
+
+/// Round every vertex coordinate to a multiple of `precision` (eg 0.001 mm), which makes files smaller when combined with welding or
+/// compression without making any printable difference. Triangles that collapse from the rounding are removed, and the number of removed
+/// triangles is returned.
+///
+/// A precision that isn't positive leaves the model as it is, since every triangle would collapse.
+pub fn quantize(model: &mut StlModel, precision: f32) -> usize {
+	if !(precision > 0.0 && precision.is_finite()) {
+		return 0;
+	}
+	let triangle_count = model.triangles.len();
+	model.triangles.retain_mut(|triangle| {
+		triangle.vertices = triangle.vertices.map(|v| Vec3::new(v.as_arr().map(|c| (c / precision).round() * precision)));
+		match face_normal(triangle.vertices) {
+			Some(normal) => {
+				triangle.normal = normal;
+				true
+			},
+			None => false,
+		}
+	});
+	triangle_count - model.triangles.len()
+}
+
+/// Put a model's triangles in a canonical order, so the same triangles are always saved as the same bytes, eg for golden file tests or
+/// caching by content hash
+///
+/// Each triangle starts at its smallest vertex (keeping the winding), the triangles are sorted, negative zeros become zeros and the
+/// normals are recalculated from the vertices. The coordinates can still differ in their last bits between platforms (whose `sin`, `cos`
+/// etc aren't exactly the same), so this should be combined with `quantize` for output that's identical everywhere.
+pub fn canonicalize(model: &mut StlModel) {
+	for triangle in &mut model.triangles {
+		let vertices = triangle.vertices.map(|v| Vec3::new(v.as_arr().map(|c| if c == 0.0 { 0.0 } else { c })));
+		let smallest = (0..3).min_by(|&a, &b| compare_vertices(vertices[a], vertices[b])).unwrap();
+		triangle.vertices = [0, 1, 2].map(|i| vertices[(smallest + i) % 3]);
+		triangle.normal = face_normal(triangle.vertices).unwrap_or(Vec3::new([0.0; 3]));
+	}
+	model
+		.triangles
+		.sort_by(|a, b| a.vertices.iter().zip(&b.vertices).map(|(&a, &b)| compare_vertices(a, b)).find(|o| o.is_ne()).unwrap_or(Ordering::Equal));
+}
+
+fn compare_vertices(a: Vec3, b: Vec3) -> Ordering {
+	a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)).then(a.z.total_cmp(&b.z))
+}
+
+/// Stand a model that's lying on the XY plane (like a flat lithophane made with the expressions `x`, `-y`, `0`) up on its bottom edge
+///
+/// The model is rotated around the x axis so that +y ends up pointing up, then leaned back by `tilt` degrees (so the top is further
+/// back than the bottom), and moved so that it's resting on z = 0.
+pub fn stand_up(model: &mut StlModel, tilt: f32) {
+	let (sin, cos) = (90.0 - tilt).to_radians().sin_cos();
+	let rotate = |v: Vec3| Vec3::new([v.x, v.y * cos - v.z * sin, v.y * sin + v.z * cos]);
+	for triangle in &mut model.triangles {
+		triangle.vertices = triangle.vertices.map(rotate);
+		triangle.normal = rotate(triangle.normal);
+	}
+
+	let lowest = model.triangles.iter().flat_map(|t| t.vertices).map(|v| v.z).fold(f32::INFINITY, f32::min);
+	for triangle in &mut model.triangles {
+		for v in &mut triangle.vertices {
+			v.z -= lowest;
+		}
+	}
+}
+
+/// The center of mass of a closed model, assuming it's solid and evenly dense, or `None` if it doesn't have any volume
+pub fn center_of_mass(model: &StlModel) -> Option<Vec3> {
+	// Add up the signed volumes and centroids of the tetrahedrons between the origin and each triangle
+	let (mut volume, mut moment) = (0.0f64, [0.0f64; 3]);
+	for triangle in &model.triangles {
+		let [a, b, c] = triangle.vertices.map(|v| v.as_arr().map(|c| c as f64));
+		let tetrahedron_volume = (a[0] * (b[1] * c[2] - b[2] * c[1]) - a[1] * (b[0] * c[2] - b[2] * c[0]) + a[2] * (b[0] * c[1] - b[1] * c[0])) / 6.0;
+		volume += tetrahedron_volume;
+		for axis in 0..3 {
+			moment[axis] += tetrahedron_volume * (a[axis] + b[axis] + c[axis]) / 4.0;
+		}
+	}
+
+	(volume.abs() > f64::EPSILON).then(|| Vec3::new(moment.map(|m| (m / volume) as f32)))
+}
+
+/// Whether a model will stay standing on the points within `tolerance` of its lowest z, instead of tipping over
+///
+/// This is true if the center of mass is over the area the model is resting on (the convex hull of those points).
+pub fn is_stable(model: &StlModel, tolerance: f32) -> bool {
+	let Some(center) = center_of_mass(model) else {
+		return false;
+	};
+
+	let lowest = model.triangles.iter().flat_map(|t| t.vertices).map(|v| v.z).fold(f32::INFINITY, f32::min);
+	let mut points = model.triangles.iter().flat_map(|t| t.vertices).filter(|v| v.z <= lowest + tolerance).map(|v| (v.x, v.y)).collect::<Vec<_>>();
+	points.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+	points.dedup();
+
+	// Find the convex hull with Andrew's monotone chain algorithm, going counterclockwise
+	let cross = |o: (f32, f32), a: (f32, f32), b: (f32, f32)| (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0);
+	let mut hull: Vec<(f32, f32)> = Vec::with_capacity(points.len() * 2);
+	for pass in 0..2 {
+		let start = hull.len();
+		let ordered: Box<dyn Iterator<Item = &(f32, f32)>> = if pass == 0 {
+			Box::new(points.iter())
+		} else {
+			Box::new(points.iter().rev())
+		};
+		for &p in ordered {
+			while hull.len() >= start + 2 && cross(hull[hull.len() - 2], hull[hull.len() - 1], p) <= 0.0 {
+				hull.pop();
+			}
+			hull.push(p);
+		}
+		hull.pop();
+	}
+	if hull.len() < 3 {
+		return false;
+	}
+
+	(0..hull.len()).all(|i| cross(hull[i], hull[(i + 1) % hull.len()], (center.x, center.y)) >= 0.0)
+}
+
+/// The lowest and highest corners of the box around a model, or `None` if it doesn't have any triangles
+pub fn bounding_box(model: &StlModel) -> Option<(Vec3, Vec3)> {
+	let mut vertices = model.triangles.iter().flat_map(|t| t.vertices);
+	let first = vertices.next()?;
+	Some(vertices.fold((first, first), |(min, max), v| {
+		(
+			Vec3::new([min.x.min(v.x), min.y.min(v.y), min.z.min(v.z)]),
+			Vec3::new([max.x.max(v.x), max.y.max(v.y), max.z.max(v.z)]),
+		)
+	}))
+}
+
+/// The area and perimeter of a closed model's cross section halfway up each `layer_height` thick layer, from its lowest point up, like
+/// a slicer would see it
+///
+/// The area counts holes (eg the inside of a tube) as negative, so it's the area that's actually printed, and the perimeter includes the
+/// edges of the holes.
+pub fn layer_cross_sections(model: &StlModel, layer_height: f32) -> Vec<(f32, f32)> {
+	let Some((min, max)) = bounding_box(model).filter(|_| layer_height > 0.0) else {
+		return Vec::new();
+	};
+	let layers = ((max.z - min.z) / layer_height).ceil().max(1.0) as usize;
+	let mut sections = vec![(0.0f64, 0.0f64); layers];
+	for triangle in &model.triangles {
+		let [a, b, c] = triangle.vertices;
+		let normal = cross_product(b - a, c - a);
+		let (low, high) = (a.z.min(b.z).min(c.z), a.z.max(b.z).max(c.z));
+		let first = ((low - min.z) / layer_height - 0.5).ceil().max(0.0) as usize;
+		let last = (((high - min.z) / layer_height - 0.5).floor().max(0.0) as usize).min(layers - 1);
+		for (layer, section) in sections.iter_mut().enumerate().take(last + 1).skip(first) {
+			let z = min.z + (layer as f32 + 0.5) * layer_height;
+			// Where the plane crosses the edges that go from below it to above it (or the other way around), which is either none of them or two
+			let mut crossings = [[a, b], [b, c], [c, a]].into_iter().filter(|[p, q]| (p.z > z) != (q.z > z)).map(|[p, q]| {
+				let t = (z - p.z) / (q.z - p.z);
+				(p.x + (q.x - p.x) * t, p.y + (q.y - p.y) * t)
+			});
+			let (Some(mut start), Some(mut end)) = (crossings.next(), crossings.next()) else {
+				continue;
+			};
+			// Go around the outside of the cross section counterclockwise (so the model is on the left), which makes the area of holes negative
+			if (end.1 - start.1) * normal.x - (end.0 - start.0) * normal.y < 0.0 {
+				(start, end) = (end, start);
+			}
+			section.0 += (start.0 * end.1 - end.0 * start.1) as f64 / 2.0;
+			section.1 += ((end.0 - start.0).powi(2) + (end.1 - start.1).powi(2)).sqrt() as f64;
+		}
+	}
+	sections.into_iter().map(|(area, perimeter)| (area as f32, perimeter as f32)).collect()
+}
+
+/// Scale a model by `factor` in every direction, keeping the lowest corner of its bounding box where it is
+pub fn scale(model: &mut StlModel, factor: f32) {
+	let Some((origin, _)) = bounding_box(model) else {
+		return;
+	};
+	for triangle in &mut model.triangles {
+		for v in &mut triangle.vertices {
+			*v = origin + (*v - origin) * factor;
+		}
+	}
+}
+
+/// Split a model into parts with at most `max_triangles` triangles each, eg for services that limit the size of uploaded files
+///
+/// The model is cut into strips across its longest side, so each part is a contiguous piece of it, and the same model is always split
+/// the same way. The parts aren't closed where they were cut.
+pub fn split(model: &StlModel, max_triangles: usize) -> Vec<StlModel> {
+	let max_triangles = max_triangles.max(1);
+	if model.triangles.len() <= max_triangles {
+		return vec![StlModel {
+			header: model.header.clone(),
+			triangles: model.triangles.clone(),
+		}];
+	}
+
+	let (min, max) = bounding_box(model).expect("the model has triangles");
+	let along_x = max.x - min.x >= max.y - min.y;
+	let position = |t: &Triangle| {
+		let [a, b, c] = t.vertices;
+		if along_x {
+			a.x + b.x + c.x
+		} else {
+			a.y + b.y + c.y
+		}
+	};
+	let mut triangles = model.triangles.clone();
+	// The sort is stable, so triangles in the same place stay in their original order
+	triangles.sort_by(|a, b| position(a).total_cmp(&position(b)));
+
+	let parts = model.triangles.len().div_ceil(max_triangles);
+	let part_size = model.triangles.len().div_ceil(parts);
+	triangles
+		.chunks(part_size)
+		.map(|chunk| StlModel {
+			header: model.header.clone(),
+			triangles: chunk.to_vec(),
+		})
+		.collect()
+}
@@ -0,0 +1,79 @@
+use image::{imageops, GrayImage};
+
+use crate::{outline::Shape, preprocessing::crop_to_aspect_ratio};
+
+/// A flat panel with a clip at the bottom that slides into the slot of a plug-in LED nightlight base
+///
+/// The clip is part of the same solid as the panel: the image is extended downwards and cut back to the clip with an outline, so it's
+/// generated by the normal lithophane generator. All sizes are in millimeters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NightlightPanel {
+	/// The size of the panel, not counting the clip
+	pub width: f32,
+	pub height: f32,
+	pub clip_width: f32,
+	/// How far the clip sticks out below the panel
+	pub clip_height: f32,
+	/// This is limited to the black depth, since the clip is made from black pixels at most
+	pub clip_thickness: f32,
+}
+
+impl Default for NightlightPanel {
+	fn default() -> Self {
+		Self {
+			width: 60.0,
+			height: 80.0,
+			clip_width: 20.0,
+			clip_height: 10.0,
+			clip_thickness: 2.4,
+		}
+	}
+}
+
+impl NightlightPanel {
+	/// Crop the middle of an image to the aspect ratio of the panel and add rows at the bottom for the clip
+	pub fn prepare_image(&self, image: &GrayImage, white_depth: f32, black_depth: f32) -> GrayImage {
+		let panel = crop_to_aspect_ratio(image, self.width / self.height);
+		let clip_rows = self.clip_rows(panel.width());
+
+		// Invert `lithophane::pixel_depth` to find the gray value that's the clip's thickness
+		let gray = 255.0 - (self.clip_thickness - white_depth) / (black_depth - white_depth) * 255.0;
+		let mut prepared = GrayImage::from_pixel(
+			panel.width(),
+			panel.height() + clip_rows,
+			image::Luma([gray.round().clamp(0.0, 255.0) as u8]),
+		);
+		imageops::replace(&mut prepared, &panel, 0, 0);
+		prepared
+	}
+
+	/// Expressions for the panel lying on the XY plane, `width` wide, for an image from `prepare_image`
+	pub fn expressions(&self) -> [String; 3] {
+		let scale = format!("{}/(w-1)", self.width);
+		[format!("x*{}", scale), format!("-y*{}", scale), "0".to_string()]
+	}
+
+	/// The outline of the panel and clip, for an image from `prepare_image`
+	pub fn boundary(&self, image_width: u32, image_height: u32) -> Shape {
+		let millimeters_per_pixel = self.width / (image_width - 1) as f32;
+		let (right, bottom) = ((image_width - 1) as f32, (image_height - 1) as f32);
+		let panel_bottom = bottom - self.clip_rows(image_width) as f32;
+		let clip_left = right / 2.0 - self.clip_width / millimeters_per_pixel / 2.0;
+		let clip_right = right / 2.0 + self.clip_width / millimeters_per_pixel / 2.0;
+		Shape::Polygon(vec![
+			(0.0, 0.0),
+			(0.0, panel_bottom),
+			(clip_left, panel_bottom),
+			(clip_left, bottom),
+			(clip_right, bottom),
+			(clip_right, panel_bottom),
+			(right, panel_bottom),
+			(right, 0.0),
+		])
+	}
+
+	/// The number of rows of pixels the clip takes up, for an image `image_width` pixels wide
+	fn clip_rows(&self, image_width: u32) -> u32 {
+		(self.clip_height / (self.width / (image_width - 1) as f32)).ceil().max(1.0) as u32
+	}
+}
@@ -0,0 +1,203 @@
+use image::GrayImage;
+
+use crate::label::{glyph, GLYPH_HEIGHT, GLYPH_WIDTH};
+
+/// A shape in image coordinates (the same x and y that are passed to the expressions)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+	Circle {
+		center_x: f32,
+		center_y: f32,
+		radius: f32,
+	},
+	Ellipse {
+		center_x: f32,
+		center_y: f32,
+		radius_x: f32,
+		radius_y: f32,
+	},
+	/// A closed polygon, given as a list of x,y points
+	Polygon(Vec<(f32, f32)>),
+	/// The parts of a grayscale image (with one pixel per image coordinate) that are darker than `threshold`, interpolated between pixels
+	/// so the edges are smooth
+	Mask {
+		image: GrayImage,
+		threshold: u8,
+	},
+	/// Another shape with its edges moved in by `distance` (or out, if it's negative), as far as its distance is accurate
+	Inset {
+		shape: Box<Shape>,
+		distance: f32,
+	},
+	/// Everywhere that's inside any of the shapes, eg a pendant and its loop
+	Union(Vec<Shape>),
+}
+
+impl Shape {
+	/// The signed distance from the edge of the shape, which is negative inside the shape and positive outside of it
+	pub fn distance(&self, x: f32, y: f32) -> f32 {
+		match *self {
+			Shape::Polygon(ref points) => {
+				let mut distance = f32::INFINITY;
+				let mut inside = false;
+				for (i, &(ax, ay)) in points.iter().enumerate() {
+					let (bx, by) = points[(i + 1) % points.len()];
+
+					// Distance to the closest point on this edge
+					let (ex, ey) = (bx - ax, by - ay);
+					let t = (((x - ax) * ex + (y - ay) * ey) / (ex * ex + ey * ey)).clamp(0.0, 1.0);
+					distance = distance.min(((x - ax - ex * t).powi(2) + (y - ay - ey * t).powi(2)).sqrt());
+
+					// Count how many edges a ray going right from the point crosses to determine if it's inside
+					if (ay > y) != (by > y) && x < ax + (y - ay) / (by - ay) * ex {
+						inside = !inside;
+					}
+				}
+				if inside {
+					-distance
+				} else {
+					distance
+				}
+			},
+			Shape::Circle { center_x, center_y, radius } => ((x - center_x).powi(2) + (y - center_y).powi(2)).sqrt() - radius,
+			// This is only an approximation of the distance, but the sign is always correct
+			Shape::Ellipse {
+				center_x,
+				center_y,
+				radius_x,
+				radius_y,
+			} => ((((x - center_x) / radius_x).powi(2) + ((y - center_y) / radius_y).powi(2)).sqrt() - 1.0) * radius_x.min(radius_y),
+			// This isn't a distance at all, just how far the gray value is past the threshold (as a fraction of the full range), but the sign
+			// is always correct
+			Shape::Mask { ref image, threshold } => {
+				let value = |x: i64, y: i64| {
+					image.get_pixel(x.clamp(0, image.width() as i64 - 1) as u32, y.clamp(0, image.height() as i64 - 1) as u32).0[0] as f32
+				};
+				let (x0, y0) = (x.floor(), y.floor());
+				let (tx, ty) = (x - x0, y - y0);
+				let (x0, y0) = (x0 as i64, y0 as i64);
+				let top = value(x0, y0) * (1.0 - tx) + value(x0 + 1, y0) * tx;
+				let bottom = value(x0, y0 + 1) * (1.0 - tx) + value(x0 + 1, y0 + 1) * tx;
+				(top * (1.0 - ty) + bottom * ty - threshold as f32) / 255.0
+			},
+			Shape::Inset { ref shape, distance } => shape.distance(x, y) + distance,
+			// Like `Inset` this is only accurate outside of the shapes, since the inside of where they overlap is further from the edge
+			Shape::Union(ref shapes) => shapes.iter().map(|s| s.distance(x, y)).fold(f32::INFINITY, f32::min),
+		}
+	}
+
+	/// A polygon from points in normalized image coordinates, where 0,0 is the top left of the image and 1,1 is the bottom right
+	pub fn polygon_in_image(image_width: u32, image_height: u32, points: &[(f32, f32)]) -> Self {
+		Shape::Polygon(points.iter().map(|&(x, y)| (x * (image_width - 1) as f32, y * (image_height - 1) as f32)).collect())
+	}
+
+	/// A rectangle from its top left corner and size in normalized image coordinates
+	pub fn rectangle_in_image(image_width: u32, image_height: u32, x: f32, y: f32, width: f32, height: f32) -> Self {
+		Self::polygon_in_image(
+			image_width,
+			image_height,
+			&[(x, y), (x + width, y), (x + width, y + height), (x, y + height)],
+		)
+	}
+
+	/// The largest circle that fits inside an image, with its center offset from the center of the image by `offset_x` and `offset_y` pixels
+	pub fn circle_in_image(image_width: u32, image_height: u32, offset_x: f32, offset_y: f32) -> Self {
+		let (center_x, center_y) = ((image_width - 1) as f32 / 2.0 + offset_x, (image_height - 1) as f32 / 2.0 + offset_y);
+		Shape::Circle {
+			center_x,
+			center_y,
+			radius: center_x.min(center_y).min((image_width - 1) as f32 - center_x).min((image_height - 1) as f32 - center_y),
+		}
+	}
+
+	/// The largest ellipse that fits inside an image, with its center offset from the center of the image by `offset_x` and `offset_y` pixels
+	pub fn ellipse_in_image(image_width: u32, image_height: u32, offset_x: f32, offset_y: f32) -> Self {
+		let (center_x, center_y) = ((image_width - 1) as f32 / 2.0 + offset_x, (image_height - 1) as f32 / 2.0 + offset_y);
+		Shape::Ellipse {
+			center_x,
+			center_y,
+			radius_x: center_x.min((image_width - 1) as f32 - center_x),
+			radius_y: center_y.min((image_height - 1) as f32 - center_y),
+		}
+	}
+}
+
+/// Which parts of the image end up in the lithophane
+///
+/// By default the whole image is used. Anything outside of the boundary or inside a hole is left out, and walls are generated along the
+/// edges that are left, following the actual shape instead of the pixel grid.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Outline {
+	/// If set, only the parts of the image inside this shape are used
+	pub boundary: Option<Shape>,
+	pub holes: Vec<Shape>,
+	/// The width of the ring around each hole that's forced to the full (black) depth so the hole is sturdier
+	pub hole_reinforcement: f32,
+}
+
+impl Outline {
+	/// Add evenly spaced holes along the top edge of the image so that the lithophane can be hung, eg on a light chain for a garland
+	///
+	/// The holes are centered horizontally, with `spacing` pixels between the centers of adjacent holes, and are placed far enough from
+	/// the top edge that the reinforcement ring fits inside the image.
+	pub fn with_garland_holes(mut self, image_width: u32, count: u32, spacing: f32, radius: f32, reinforcement: f32) -> Self {
+		let first_x = (image_width - 1) as f32 / 2.0 - spacing * (count as f32 - 1.0) / 2.0;
+		for i in 0..count {
+			self.holes.push(Shape::Circle {
+				center_x: first_x + spacing * i as f32,
+				center_y: radius + reinforcement * 2.0,
+				radius,
+			});
+		}
+		self.hole_reinforcement = reinforcement;
+		self
+	}
+
+	/// Add holes the shape of text in the built in 5×7 font of labels (so only characters allowed by `check_label_text`), reading correctly
+	/// from the front, eg for the digits of a display lit by LEDs behind the print
+	///
+	/// Each dot of the font is its own square hole, with bars `gap` pixels wide left between them so the insides of letters like O don't
+	/// fall out (nothing is cut if the gap is as wide as the dots). The text's top left corner is at `x`,`y` and its characters are
+	/// `height` pixels high.
+	pub fn with_text_window(mut self, text: &str, x: f32, y: f32, height: f32, gap: f32) -> Self {
+		let cell = height / GLYPH_HEIGHT as f32;
+		let size = cell - gap;
+		if size <= 0.0 {
+			return self;
+		}
+		for (i, c) in text.chars().enumerate() {
+			let Some(rows) = glyph(c) else {
+				continue;
+			};
+			for (row, bits) in rows.iter().enumerate() {
+				for column in (0..GLYPH_WIDTH).filter(|column| bits >> (GLYPH_WIDTH - 1 - column) & 1 == 1) {
+					let left = x + (i * (GLYPH_WIDTH + 1) + column) as f32 * cell + gap / 2.0;
+					let top = y + row as f32 * cell + gap / 2.0;
+					self.holes.push(Shape::Polygon(vec![
+						(left, top),
+						(left + size, top),
+						(left + size, top + size),
+						(left, top + size),
+					]));
+				}
+			}
+		}
+		self
+	}
+
+	/// The width of text added by `with_text_window` in pixels, when its characters are `height` pixels high
+	pub fn text_window_width(text: &str, height: f32) -> f32 {
+		let columns = (text.chars().count() * (GLYPH_WIDTH + 1)).saturating_sub(1);
+		columns as f32 * height / GLYPH_HEIGHT as f32
+	}
+
+	/// Whether a point in image coordinates is part of the lithophane
+	pub fn contains(&self, x: f32, y: f32) -> bool {
+		self.boundary.iter().all(|b| b.distance(x, y) <= 0.0) && self.holes.iter().all(|h| h.distance(x, y) > 0.0)
+	}
+
+	/// Whether a point in image coordinates is close enough to a hole that it should be reinforced
+	pub fn is_reinforced(&self, x: f32, y: f32) -> bool {
+		self.hole_reinforcement > 0.0 && self.holes.iter().any(|h| h.distance(x, y) <= self.hole_reinforcement)
+	}
+}
@@ -0,0 +1,234 @@
+use std::{
+	fmt::Write as _,
+	io::{self, Write},
+};
+
+use flate2::{write::GzEncoder, Compression};
+use pk_stl::{geometry::Vec3, StlModel};
+use thiserror::Error;
+
+use crate::mesh::{face_normal, IndexedMesh};
+
+/// The file formats models can be saved as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+	BinaryStl,
+	AsciiStl,
+	Obj,
+}
+
+/// Roughly predict the size of a file in bytes, given the number of triangles in the model
+/// Binary STL is exact, the others assume a grid-like mesh (about two triangles per vertex) and typical number lengths.
+pub fn estimated_size(format: OutputFormat, triangle_count: usize) -> usize {
+	match format {
+		OutputFormat::BinaryStl => 84 + triangle_count * 50,
+		OutputFormat::AsciiStl => 20 + triangle_count * 265,
+		OutputFormat::Obj => triangle_count * 95,
+	}
+}
+
+/// The most triangles a model can have for its file to be at most `bytes` long, going by [estimated_size]
+pub fn max_triangles_for_size(format: OutputFormat, bytes: usize) -> usize {
+	match format {
+		OutputFormat::BinaryStl => bytes.saturating_sub(84) / 50,
+		OutputFormat::AsciiStl => bytes.saturating_sub(20) / 265,
+		OutputFormat::Obj => bytes / 95,
+	}
+}
+
+#[derive(Error, Debug)]
+pub enum InvalidHeaderError {
+	#[error("the STL header is {0} bytes long, but it can't be longer than 80 bytes")]
+	TooLong(usize),
+	#[error("the STL header can't start with \"solid\", since some programs would think the file is an ASCII STL file")]
+	StartsWithSolid,
+}
+
+/// Check that text can be used as the header of a binary STL file
+///
+/// The header is 80 bytes, and it's padded with zeros if it's shorter. It's usually ignored, but binary STL files starting with "solid" are
+/// misread as ASCII STL files by some programs.
+pub fn check_stl_header(header: &str) -> Result<(), InvalidHeaderError> {
+	let header = header.trim().replace('\n', " ");
+	if header.len() > 80 {
+		Err(InvalidHeaderError::TooLong(header.len()))
+	} else if header.starts_with("solid") {
+		Err(InvalidHeaderError::StartsWithSolid)
+	} else {
+		Ok(())
+	}
+}
+
+/// An STL header listing metadata as "name=value" pairs separated by spaces, eg to tell which order a print is for
+///
+/// Pairs that don't fit in the 80 bytes of the header are left out, and so is a first pair that starts with "solid" (see
+/// `check_stl_header`).
+pub fn metadata_header(metadata: &[(String, String)]) -> String {
+	let mut header = String::new();
+	for (name, value) in metadata {
+		let pair = format!("{}={}", name, value).replace('\n', " ");
+		let separator = if header.is_empty() { "" } else { " " };
+		if header.len() + separator.len() + pair.len() <= 80 && !(header.is_empty() && pair.starts_with("solid")) {
+			header.push_str(separator);
+			header.push_str(&pair);
+		}
+	}
+	header
+}
+
+/// Compress generated output with gzip (eg to save a `.stl.gz` file), since lithophane models are often hundreds of megabytes
+pub fn compress_gzip(bytes: &[u8]) -> io::Result<Vec<u8>> {
+	let mut encoder = GzEncoder::new(Vec::with_capacity(bytes.len() / 3), Compression::default());
+	encoder.write_all(bytes)?;
+	encoder.finish()
+}
+
+/// Write a model as binary STL to `writer` as it goes, optionally with a color for each triangle (see `as_colored_binary`), so a large
+/// model doesn't need a second copy of itself in memory
+///
+/// Every number is turned into little endian bytes on its own, so the file comes out the same on big endian hosts and nothing depends on
+/// how the host lays out or aligns structs. Models with more triangles than the format can count are an error rather than a corrupt file.
+pub fn write_binary_stl(model: &StlModel, colors: Option<&[[u8; 3]]>, writer: &mut impl Write) -> io::Result<()> {
+	let count = u32::try_from(model.triangles.len())
+		.map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "binary STL files can't have more than 4294967295 triangles"))?;
+
+	let header = model.header.trim().replace('\n', " ");
+	let mut padded_header = [0; 80];
+	let length = header.len().min(padded_header.len());
+	padded_header[..length].copy_from_slice(&header.as_bytes()[..length]);
+	writer.write_all(&padded_header)?;
+	writer.write_all(&count.to_le_bytes())?;
+
+	for (i, triangle) in model.triangles.iter().enumerate() {
+		let mut record = [0; 50];
+		for (j, v) in [triangle.normal].iter().chain(&triangle.vertices).enumerate() {
+			for (k, value) in [v.x, v.y, v.z].into_iter().enumerate() {
+				let offset = j * 12 + k * 4;
+				record[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+			}
+		}
+		let attribute = match colors.and_then(|colors| colors.get(i)) {
+			Some(&[r, g, b]) => 1 << 15 | (r as u16 >> 3) << 10 | (g as u16 >> 3) << 5 | b as u16 >> 3,
+			None => 0,
+		};
+		record[48..].copy_from_slice(&attribute.to_le_bytes());
+		writer.write_all(&record)?;
+	}
+	Ok(())
+}
+
+/// Write a model as binary STL, with `write_binary_stl`
+pub fn as_binary(model: &StlModel) -> Vec<u8> {
+	let mut result = Vec::with_capacity(84 + model.triangles.len() * 50);
+	write_binary_stl(model, None, &mut result).expect("writing to a Vec doesn't fail, and a model too big to count wouldn't fit in memory");
+	result
+}
+
+/// Write a model as binary STL with a color for each triangle, stored in the attribute bytes like VisCAM and SolidView do
+///
+/// Each color channel only gets 5 bits, and bit 15 is set to mark the color as valid. Most slicers ignore these colors, but they're
+/// useful for inspecting things like depth in viewers that do support them. `colors` must have one color per triangle.
+pub fn as_colored_binary(model: &StlModel, colors: &[[u8; 3]]) -> Vec<u8> {
+	let mut result = Vec::with_capacity(84 + model.triangles.len() * 50);
+	write_binary_stl(model, Some(colors), &mut result).expect("writing to a Vec doesn't fail, and a model too big to count wouldn't fit in memory");
+	result
+}
+
+/// Map a value from 0 to 1 onto a blue-green-red heat map, for visualizing things like depth or analysis results as colors
+pub fn heat_map_color(value: f32) -> [u8; 3] {
+	let value = value.clamp(0.0, 1.0);
+	let (r, g, b) = if value < 0.5 {
+		(0.0, value * 2.0, 1.0 - value * 2.0)
+	} else {
+		(value * 2.0 - 1.0, 2.0 - value * 2.0, 0.0)
+	};
+	[(r * 255.0).round() as u8, (g * 255.0).round() as u8, (b * 255.0).round() as u8]
+}
+
+/// How normals are written for formats that support vertex normals
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Shading {
+	/// Every triangle uses its own normal, which shows the faceted surface exactly as it will be printed
+	#[default]
+	Flat,
+	/// Every vertex uses the average of the normals around it, which looks better for previews and renders
+	Smooth,
+}
+
+/// Convert a mesh to Wavefront OBJ format, with normals depending on the shading
+/// Faces that were collapsed by welding vertices together are left out.
+pub fn as_obj(mesh: &IndexedMesh, shading: Shading) -> String {
+	let mut result = String::new();
+	write_obj_mesh(&mut result, mesh, shading, 0, 0);
+	result
+}
+
+/// Convert several meshes to a single Wavefront OBJ file with a named object for each one, eg so a slicer can assign each one a
+/// different filament
+pub fn as_obj_objects(objects: &[(&str, IndexedMesh)], shading: Shading) -> String {
+	let mut result = String::new();
+	let (mut vertex_offset, mut normal_offset) = (0, 0);
+	for (name, mesh) in objects {
+		writeln!(result, "o {}", name).unwrap();
+		normal_offset += write_obj_mesh(&mut result, mesh, shading, vertex_offset, normal_offset);
+		vertex_offset += mesh.vertices.len();
+	}
+	result
+}
+
+/// Write a mesh's vertices, normals and faces, numbered after the ones already written, and return the number of normals written
+fn write_obj_mesh(result: &mut String, mesh: &IndexedMesh, shading: Shading, vertex_offset: usize, normal_offset: usize) -> usize {
+	let faces = mesh.faces.iter().filter(|&&[a, b, c]| a != b && b != c && c != a).collect::<Vec<_>>();
+
+	for v in &mesh.vertices {
+		writeln!(result, "v {} {} {}", v.x, v.y, v.z).unwrap();
+	}
+
+	match shading {
+		Shading::Flat => {
+			for (i, face) in faces.iter().enumerate() {
+				let normal = face_normal(face.map(|i| mesh.vertices[i])).unwrap_or(Vec3::new([0.0; 3]));
+				writeln!(result, "vn {} {} {}", normal.x, normal.y, normal.z).unwrap();
+				let (a, b, c, n) = (
+					face[0] + vertex_offset + 1,
+					face[1] + vertex_offset + 1,
+					face[2] + vertex_offset + 1,
+					i + normal_offset + 1,
+				);
+				writeln!(result, "f {}//{} {}//{} {}//{}", a, n, b, n, c, n).unwrap();
+			}
+			faces.len()
+		},
+		Shading::Smooth => {
+			// Weight each triangle's normal by its area (the length of the cross product) so tiny triangles don't skew the result
+			let mut normals = vec![Vec3::new([0.0; 3]); mesh.vertices.len()];
+			for face in &faces {
+				let [a, b, c] = face.map(|i| mesh.vertices[i]);
+				let (u, v) = (b - a, c - a);
+				let weighted_normal = Vec3::new([u.y * v.z - v.y * u.z, u.z * v.x - v.z * u.x, u.x * v.y - v.x * u.y]);
+				for &i in *face {
+					normals[i] = normals[i] + weighted_normal;
+				}
+			}
+			for n in &normals {
+				let length = (n.x * n.x + n.y * n.y + n.z * n.z).sqrt().max(f32::MIN_POSITIVE);
+				writeln!(result, "vn {} {} {}", n.x / length, n.y / length, n.z / length).unwrap();
+			}
+			for face in &faces {
+				let [a, b, c] = face.map(|i| i + 1);
+				writeln!(
+					result,
+					"f {}//{} {}//{} {}//{}",
+					a + vertex_offset,
+					a + normal_offset,
+					b + vertex_offset,
+					b + normal_offset,
+					c + vertex_offset,
+					c + normal_offset
+				)
+				.unwrap();
+			}
+			normals.len()
+		},
+	}
+}
@@ -0,0 +1,97 @@
+use image::{imageops, GrayImage};
+
+use crate::{
+	outline::{Outline, Shape},
+	preprocessing::{crop_to_aspect_ratio, downsample_preserving_detail},
+};
+
+/// A small round pendant or earring with a jump ring loop at the top
+///
+/// The loop is part of the same solid as the pendant: the image is extended upwards and cut back to the disc and loop with an outline,
+/// and the loop is the reinforced ring around its hole, so it's generated by the normal lithophane generator. All sizes are in
+/// millimeters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pendant {
+	/// The diameter of the disc, not counting the loop (about 20-40 mm)
+	pub diameter: f32,
+	/// The distance between vertices the image is shrunk to, since much finer detail than a nozzle can print is lost anyway
+	pub pixel_size: f32,
+	/// The diameter of the hole in the loop, which has to fit the jump ring's wire
+	pub hole_diameter: f32,
+	/// The width of the loop around the hole, which is made the full (black) depth
+	pub loop_width: f32,
+}
+
+impl Default for Pendant {
+	fn default() -> Self {
+		Self {
+			diameter: 30.0,
+			pixel_size: 0.2,
+			hole_diameter: 2.0,
+			loop_width: 1.5,
+		}
+	}
+}
+
+impl Pendant {
+	/// Crop the middle of an image to a square, shrink it to `pixel_size` (see `downsample_preserving_detail`) and add rows at the top for
+	/// the loop
+	pub fn prepare_image(&self, image: &GrayImage) -> GrayImage {
+		let mut disc = crop_to_aspect_ratio(image, 1.0);
+		let size = (self.diameter / self.pixel_size).round() as u32 + 1;
+		if disc.width() > size {
+			disc = downsample_preserving_detail(&disc, size, size);
+		}
+		let loop_rows = self.loop_rows(disc.width());
+
+		// The loop is all reinforced, so the gray value of the rows above doesn't matter
+		let mut prepared = GrayImage::new(disc.width(), disc.height() + loop_rows);
+		imageops::replace(&mut prepared, &disc, 0, loop_rows as i64);
+		prepared
+	}
+
+	/// Expressions for the pendant lying on the XY plane, `diameter` wide, for an image from `prepare_image`
+	pub fn expressions(&self) -> [String; 3] {
+		let scale = format!("{}/(w-1)", self.diameter);
+		[format!("x*{}", scale), format!("-y*{}", scale), "0".to_string()]
+	}
+
+	/// The outline of the disc and loop with the loop's hole, for an image from `prepare_image`
+	pub fn outline(&self, image_width: u32, image_height: u32) -> Outline {
+		let millimeters_per_pixel = self.diameter / (image_width - 1) as f32;
+		let (right, bottom) = ((image_width - 1) as f32, (image_height - 1) as f32);
+		let disc_top = self.loop_rows(image_width) as f32;
+		let hole_radius = self.hole_diameter / 2.0 / millimeters_per_pixel;
+		let loop_width = self.loop_width / millimeters_per_pixel;
+		// The hole just touches the top of the disc, so the loop overlaps it by the loop's width
+		let (center_x, hole_y) = (right / 2.0, disc_top - hole_radius);
+
+		let disc_radius = (bottom - disc_top) / 2.0;
+		Outline {
+			boundary: Some(Shape::Union(vec![
+				Shape::Circle {
+					center_x,
+					center_y: disc_top + disc_radius,
+					radius: disc_radius,
+				},
+				Shape::Circle {
+					center_x,
+					center_y: hole_y,
+					radius: hole_radius + loop_width,
+				},
+			])),
+			holes: vec![Shape::Circle {
+				center_x,
+				center_y: hole_y,
+				radius: hole_radius,
+			}],
+			hole_reinforcement: loop_width,
+		}
+	}
+
+	/// The number of rows of pixels above the disc the loop takes up, for an image `image_width` pixels wide
+	fn loop_rows(&self, image_width: u32) -> u32 {
+		let millimeters_per_pixel = self.diameter / (image_width - 1) as f32;
+		((self.hole_diameter + self.loop_width) / millimeters_per_pixel).ceil() as u32 + 1
+	}
+}
@@ -0,0 +1,129 @@
+use image::GrayImage;
+
+use crate::{
+	lithophane::FrameFit,
+	preprocessing::{crop_to_aspect_ratio, pad_to_aspect_ratio, stretch_to_aspect_ratio},
+};
+
+/// Standard photo print sizes, which frames are also sold for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhotoSize {
+	/// 2.5×3.5"
+	Wallet,
+	/// 4×6"
+	FourBySix,
+	/// 5×7"
+	FiveBySeven,
+	/// 8×10"
+	EightByTen,
+	/// 10×15 cm
+	TenByFifteen,
+	/// 13×18 cm
+	ThirteenByEighteen,
+	A5,
+	A4,
+}
+
+impl PhotoSize {
+	/// The size of the photo in millimeters, short side first
+	pub fn dimensions(self) -> (f32, f32) {
+		match self {
+			PhotoSize::Wallet => (63.5, 88.9),
+			PhotoSize::FourBySix => (101.6, 152.4),
+			PhotoSize::FiveBySeven => (127.0, 177.8),
+			PhotoSize::EightByTen => (203.2, 254.0),
+			PhotoSize::TenByFifteen => (100.0, 150.0),
+			PhotoSize::ThirteenByEighteen => (130.0, 180.0),
+			PhotoSize::A5 => (148.0, 210.0),
+			PhotoSize::A4 => (210.0, 297.0),
+		}
+	}
+
+	/// The width and height in millimeters, which is landscape if the image is wider than it is tall
+	pub fn oriented_dimensions(self, image_width: u32, image_height: u32) -> (f32, f32) {
+		let (short, long) = self.dimensions();
+		if image_width > image_height {
+			(long, short)
+		} else {
+			(short, long)
+		}
+	}
+
+	/// The width divided by the height, the same way round as the image
+	pub fn aspect_ratio(self, image_width: u32, image_height: u32) -> f32 {
+		let (width, height) = self.oriented_dimensions(image_width, image_height);
+		width / height
+	}
+
+	/// Crop, pad or stretch an image to the aspect ratio of this size
+	///
+	/// This is also the unrolled aspect ratio of a vase from `vase_pixel_size`, which goes around once in the width of the photo.
+	pub fn fit_image(self, image: &GrayImage, fit: AspectFit) -> GrayImage {
+		fit.apply(image, self.aspect_ratio(image.width(), image.height()))
+	}
+
+	/// Expressions for a flat lithophane lying on the XY plane that's exactly this size
+	///
+	/// These use the `w` and `h` variables, so they still work after the image is cropped or resized as long as it's still the same way round.
+	pub fn flat_expressions(self, image_width: u32, image_height: u32) -> [String; 3] {
+		let (width, height) = self.oriented_dimensions(image_width, image_height);
+		[format!("x*{}/(w-1)", width), format!("-y*{}/(h-1)", height), "0".to_string()]
+	}
+
+	/// The pixel size for `lithophane::generate_vase_lithophane` that makes the vase this size around and this tall, for an image that has
+	/// already been fitted to this size
+	pub fn vase_pixel_size(self, image_width: u32, image_height: u32) -> f32 {
+		let (width, _) = self.oriented_dimensions(image_width, image_height);
+		width / image_width as f32
+	}
+}
+
+/// How to change the aspect ratio of an image to match a size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AspectFit {
+	/// Cut off the sides or the top and bottom of the image
+	Crop,
+	/// Add a border of this gray value to the sides or the top and bottom of the image
+	Pad { gray: u8 },
+	/// Squash or stretch the whole image, which distorts it (eg faces look wider or thinner)
+	Stretch,
+}
+
+impl AspectFit {
+	/// Change an image's aspect ratio (width divided by height) to `aspect_ratio`, eg for the unrolled surface of a shape it's wrapped
+	/// around
+	pub fn apply(self, image: &GrayImage, aspect_ratio: f32) -> GrayImage {
+		match self {
+			AspectFit::Crop => crop_to_aspect_ratio(image, aspect_ratio),
+			AspectFit::Pad { gray } => pad_to_aspect_ratio(image, aspect_ratio, gray),
+			AspectFit::Stretch => stretch_to_aspect_ratio(image, aspect_ratio),
+		}
+	}
+}
+
+/// A flat lithophane that drops into a store-bought photo frame in place of the photo
+///
+/// The image should be fitted to the photo size with `PhotoSize::fit_image` and generated with `PhotoSize::flat_expressions`, so that the
+/// lithophane is exactly the size of the photo. This makes it thin enough under the frame's lip to fit into the rabbet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhotoFrameInsert {
+	pub size: PhotoSize,
+	/// How far the frame's lip covers the edges of the photo, in millimeters
+	pub lip: f32,
+	/// The space in the frame's rabbet for the photo, in millimeters, which is the most the lithophane can be under the lip
+	pub max_thickness: f32,
+}
+
+impl PhotoFrameInsert {
+	/// The thickness limit under the lip, for an image that has already been fitted to the photo size
+	pub fn frame_fit(&self, image_width: u32, image_height: u32) -> FrameFit {
+		let (width, height) = self.size.oriented_dimensions(image_width, image_height);
+		let millimeters_per_pixel = (width / (image_width - 1) as f32).max(height / (image_height - 1) as f32);
+		let border = self.lip / millimeters_per_pixel;
+		FrameFit {
+			border,
+			max_thickness: self.max_thickness,
+			transition: border / 2.0,
+		}
+	}
+}
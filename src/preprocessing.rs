@@ -0,0 +1,1110 @@
+use std::{borrow::Cow, str::FromStr};
+
+use image::{
+	imageops::{self, FilterType},
+	ColorType, DynamicImage, GrayAlphaImage, GrayImage,
+};
+use thiserror::Error;
+
+/// Shrink an image for very small lithophanes (eg pendants or earrings) while keeping as much of the relief contrast as possible
+///
+/// Naively shrinking an image averages away most of the fine detail at these sizes, so the result is sharpened with an unsharp mask scaled
+/// to how much the image was reduced, then the tones are stretched back out to the full range.
+pub fn downsample_preserving_detail(image: &GrayImage, width: u32, height: u32) -> GrayImage {
+	let resized = imageops::resize(image, width, height, FilterType::Lanczos3);
+
+	let reduction = (image.width() as f32 / width as f32).max(image.height() as f32 / height as f32);
+	let sharpened = if reduction > 1.0 {
+		imageops::unsharpen(&resized, reduction.sqrt().min(3.0), 2)
+	} else {
+		resized
+	};
+
+	stretch_contrast(&sharpened)
+}
+
+/// Linearly stretch the tones of an image so the darkest pixel is black and the lightest is white
+pub fn stretch_contrast(image: &GrayImage) -> GrayImage {
+	let min = image.pixels().map(|p| p.0[0]).min().unwrap_or(0);
+	let max = image.pixels().map(|p| p.0[0]).max().unwrap_or(255);
+	if min == max {
+		return image.clone();
+	}
+
+	let mut stretched = image.clone();
+	for p in stretched.pixels_mut() {
+		p.0[0] = ((p.0[0] - min) as f32 / (max - min) as f32 * 255.0).round() as u8;
+	}
+	stretched
+}
+
+/// Stretch the tones of several images with the same curve, so a set of lithophanes (eg the panels of one lamp) has matched brightness
+/// when lit
+///
+/// The range is taken from the histogram of all of the images together, from the `low` to the `high` percentile (eg 0.5 and 99.5), so a
+/// few very dark or bright pixels don't decide it. Values outside the range are clipped to black or white.
+pub fn stretch_contrast_together(images: &[GrayImage], low: f32, high: f32) -> Vec<GrayImage> {
+	let mut histogram = [0u64; 256];
+	for p in images.iter().flat_map(|image| image.pixels()) {
+		histogram[p.0[0] as usize] += 1;
+	}
+	let total: u64 = histogram.iter().sum();
+	let percentile = |percent: f32| {
+		let target = (total as f64 * percent.clamp(0.0, 100.0) as f64 / 100.0).ceil().max(1.0) as u64;
+		let mut count = 0;
+		histogram
+			.iter()
+			.position(|&n| {
+				count += n;
+				count >= target
+			})
+			.unwrap_or(255) as u8
+	};
+	let (min, max) = (percentile(low), percentile(high));
+	if min >= max {
+		return images.to_vec();
+	}
+
+	images
+		.iter()
+		.map(|image| {
+			let mut stretched = image.clone();
+			for p in stretched.pixels_mut() {
+				p.0[0] = ((p.0[0].clamp(min, max) - min) as f32 / (max - min) as f32 * 255.0).round() as u8;
+			}
+			stretched
+		})
+		.collect()
+}
+
+/// How much less the pixels outside the subject count than the ones inside in `stretch_contrast_for_subject`
+const SUBJECT_BACKGROUND_WEIGHT: f64 = 0.1;
+
+/// Spread the tones of an image by how much of the subject has them, so the subject gets most of the thickness range even if the
+/// background takes up most of the image (and would get most of it with `stretch_contrast`)
+///
+/// This is a histogram equalization where the pixels in a rectangle around the subject count ten times as much as the rest. The rectangle
+/// is in normalized coordinates (from 0 to 1), eg 0.25, 0.25, 0.5, 0.5 for the middle of the image. The background's tones are squeezed
+/// together rather than clipped, so it keeps some detail.
+pub fn stretch_contrast_for_subject(image: &GrayImage, x: f32, y: f32, width: f32, height: f32) -> GrayImage {
+	let (image_width, image_height) = (image.width() as f32, image.height() as f32);
+	let (left, top) = (x * image_width, y * image_height);
+	let (right, bottom) = (left + width * image_width, top + height * image_height);
+
+	let mut histogram = [0.0; 256];
+	for (px, py, p) in image.enumerate_pixels() {
+		let (px, py) = (px as f32 + 0.5, py as f32 + 0.5);
+		let in_subject = px >= left && px < right && py >= top && py < bottom;
+		histogram[p.0[0] as usize] += if in_subject { 1.0 } else { SUBJECT_BACKGROUND_WEIGHT };
+	}
+
+	// Each gray value is mapped to the middle of its share of the total weight, stretched so the darkest and lightest values used become
+	// black and white
+	let mut curve = [0.0; 256];
+	let mut total = 0.0;
+	for (value, &weight) in curve.iter_mut().zip(&histogram) {
+		*value = total + weight / 2.0;
+		total += weight;
+	}
+	let used = |&v: &usize| histogram[v] > 0.0;
+	let (Some(darkest), Some(lightest)) = ((0..256).find(used), (0..256).rev().find(used)) else {
+		return image.clone();
+	};
+	if darkest == lightest {
+		return image.clone();
+	}
+	let (low, high) = (curve[darkest], curve[lightest]);
+
+	let mut stretched = image.clone();
+	for p in stretched.pixels_mut() {
+		p.0[0] = ((curve[p.0[0] as usize] - low) / (high - low) * 255.0).round() as u8;
+	}
+	stretched
+}
+
+/// Rotate an image by an arbitrary angle in degrees (clockwise for positive angles), eg to straighten a slightly crooked scan
+///
+/// The result is cropped to the largest rectangle that fits inside the rotated image, so there are no empty corners, and pixels are
+/// interpolated bilinearly.
+pub fn rotate(image: &GrayImage, degrees: f32) -> GrayImage {
+	let (width, height) = (image.width() as f32, image.height() as f32);
+	let (sin, cos) = degrees.to_radians().sin_cos();
+	let (cropped_width, cropped_height) = largest_rotated_rectangle(width, height, sin.abs(), cos.abs());
+	// Allow for rounding errors, so eg rotating by 90° doesn't lose a row
+	let (cropped_width, cropped_height) = (
+		((cropped_width + 0.01).floor() as u32).max(1),
+		((cropped_height + 0.01).floor() as u32).max(1),
+	);
+
+	GrayImage::from_fn(cropped_width, cropped_height, |x, y| {
+		// Rotate the center of the pixel back into the original image, around the centers of both images
+		let (dx, dy) = (x as f32 + 0.5 - cropped_width as f32 / 2.0, y as f32 + 0.5 - cropped_height as f32 / 2.0);
+		image::Luma([sample_bilinear(
+			image,
+			width / 2.0 + dx * cos + dy * sin - 0.5,
+			height / 2.0 - dx * sin + dy * cos - 0.5,
+		)])
+	})
+}
+
+/// What `map_image` fills the parts of the result outside the image with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EdgeMode {
+	/// Repeat the pixels along the image's edges
+	#[default]
+	Clamp,
+	/// Tile the image
+	Repeat,
+	/// Tile the image, flipping every other copy so the copies join up seamlessly
+	Mirror,
+}
+
+/// Where an image goes on the surface a lithophane is made from, so the photo can be slid and zoomed without changing the shape
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImageMapping {
+	/// How far to move the image right and down, as fractions of its width and height
+	pub offset: (f32, f32),
+	/// How many times bigger to make the image, around its center
+	pub scale: f32,
+	pub edges: EdgeMode,
+}
+
+impl Default for ImageMapping {
+	fn default() -> Self {
+		Self {
+			offset: (0.0, 0.0),
+			scale: 1.0,
+			edges: EdgeMode::default(),
+		}
+	}
+}
+
+#[derive(Error, Debug, PartialEq)]
+#[error("the image scale has to be a positive number and the offset has to be finite, not {scale} and {offset:?}")]
+pub struct InvalidMappingError {
+	pub scale: f32,
+	pub offset: (f32, f32),
+}
+
+impl ImageMapping {
+	/// Check the scale is positive and everything is finite, since nothing sensible can be made otherwise
+	pub fn validate(&self) -> Result<(), InvalidMappingError> {
+		if self.scale > 0.0 && self.scale.is_finite() && self.offset.0.is_finite() && self.offset.1.is_finite() {
+			Ok(())
+		} else {
+			Err(InvalidMappingError {
+				scale: self.scale,
+				offset: self.offset,
+			})
+		}
+	}
+}
+
+/// Move and scale an image within the same size image (see `ImageMapping`), interpolating pixels bilinearly
+pub fn map_image(image: &GrayImage, mapping: &ImageMapping) -> Result<GrayImage, InvalidMappingError> {
+	mapping.validate()?;
+	let (width, height) = (image.width() as i64, image.height() as i64);
+	// The pixel a position outside the image takes its value from
+	let edge = |position: i64, length: i64| -> u32 {
+		(match mapping.edges {
+			EdgeMode::Clamp => position.clamp(0, length - 1),
+			EdgeMode::Repeat => position.rem_euclid(length),
+			EdgeMode::Mirror => {
+				let position = position.rem_euclid(2 * length);
+				if position < length {
+					position
+				} else {
+					2 * length - 1 - position
+				}
+			},
+		}) as u32
+	};
+	let value = |x: i64, y: i64| image.get_pixel(edge(x, width), edge(y, height)).0[0] as f32;
+
+	Ok(GrayImage::from_fn(image.width(), image.height(), |x, y| {
+		// Scale the center of the pixel around the center of the image, then move it
+		let position = |i: u32, length: i64, offset: f32| {
+			let length = length as f32;
+			let position = (i as f32 + 0.5 - length / 2.0) / mapping.scale + length / 2.0 - offset * length - 0.5;
+			// Bring positions far outside the image back near it, so a tiny scale or a big offset can't overflow, without changing which
+			// pixels they land on (both tilings repeat every two lengths)
+			match mapping.edges {
+				EdgeMode::Clamp => position.clamp(-1.0, length),
+				EdgeMode::Repeat | EdgeMode::Mirror => position.rem_euclid(2.0 * length),
+			}
+		};
+		let (source_x, source_y) = (position(x, width, mapping.offset.0), position(y, height, mapping.offset.1));
+		let (x0, y0) = (source_x.floor(), source_y.floor());
+		let (tx, ty) = (source_x - x0, source_y - y0);
+		let (x0, y0) = (x0 as i64, y0 as i64);
+		let top = value(x0, y0) * (1.0 - tx) + value(x0 + 1, y0) * tx;
+		let bottom = value(x0, y0 + 1) * (1.0 - tx) + value(x0 + 1, y0 + 1) * tx;
+		image::Luma([(top * (1.0 - ty) + bottom * ty).round().clamp(0.0, 255.0) as u8])
+	}))
+}
+
+/// Correct the perspective of a photo taken at an angle (eg a photo of a printed photo), by stretching a quadrilateral in the image into a
+/// rectangle
+///
+/// The corners are in pixels, in the order top left, top right, bottom right, bottom left. The size of the result is the length of the
+/// longer of each pair of opposite sides, so the most detailed part of the photo isn't shrunk.
+pub fn correct_perspective(image: &GrayImage, corners: [(f32, f32); 4]) -> GrayImage {
+	let [(x0, y0), (x1, y1), (x2, y2), (x3, y3)] = corners;
+	let distance = |(ax, ay): (f32, f32), (bx, by): (f32, f32)| (ax - bx).hypot(ay - by);
+	let width = (distance(corners[0], corners[1]).max(distance(corners[3], corners[2])).round() as u32 + 1).max(2);
+	let height = (distance(corners[0], corners[3]).max(distance(corners[1], corners[2])).round() as u32 + 1).max(2);
+
+	// The projective transform from the unit square to the quadrilateral (Heckbert, "Fundamentals of Texture Mapping and Image Warping")
+	let (dx1, dx2, dx3) = (x1 - x2, x3 - x2, x0 - x1 + x2 - x3);
+	let (dy1, dy2, dy3) = (y1 - y2, y3 - y2, y0 - y1 + y2 - y3);
+	let determinant = dx1 * dy2 - dx2 * dy1;
+	let (g, h) = if determinant.abs() < f32::EPSILON {
+		(0.0, 0.0)
+	} else {
+		((dx3 * dy2 - dx2 * dy3) / determinant, (dx1 * dy3 - dx3 * dy1) / determinant)
+	};
+	let (a, b, c) = (x1 - x0 + g * x1, x3 - x0 + h * x3, x0);
+	let (d, e, f) = (y1 - y0 + g * y1, y3 - y0 + h * y3, y0);
+
+	GrayImage::from_fn(width, height, |x, y| {
+		let (u, v) = (x as f32 / (width - 1) as f32, y as f32 / (height - 1) as f32);
+		let w = g * u + h * v + 1.0;
+		image::Luma([sample_bilinear(image, (a * u + b * v + c) / w, (d * u + e * v + f) / w)])
+	})
+}
+
+/// Interpolate the value of an image between pixels, extending the edge pixels outwards
+pub(crate) fn sample_bilinear(image: &GrayImage, x: f32, y: f32) -> u8 {
+	let value =
+		|x: i64, y: i64| image.get_pixel(x.clamp(0, image.width() as i64 - 1) as u32, y.clamp(0, image.height() as i64 - 1) as u32).0[0] as f32;
+	let (x0, y0) = (x.floor(), y.floor());
+	let (tx, ty) = (x - x0, y - y0);
+	let (x0, y0) = (x0 as i64, y0 as i64);
+	let top = value(x0, y0) * (1.0 - tx) + value(x0 + 1, y0) * tx;
+	let bottom = value(x0, y0 + 1) * (1.0 - tx) + value(x0 + 1, y0 + 1) * tx;
+	(top * (1.0 - ty) + bottom * ty).round().clamp(0.0, 255.0) as u8
+}
+
+/// The size of the largest axis aligned rectangle inside a `width` by `height` rectangle rotated by an angle, given the absolute values of
+/// its sine and cosine
+fn largest_rotated_rectangle(width: f32, height: f32, sin: f32, cos: f32) -> (f32, f32) {
+	let (long, short) = (width.max(height), width.min(height));
+	if short <= 2.0 * sin * cos * long || (sin - cos).abs() < 1e-6 {
+		// The rectangle is limited by the short side, so two of its corners touch the long sides of the rotated image
+		let half = short / 2.0;
+		if width >= height {
+			(half / sin, half / cos)
+		} else {
+			(half / cos, half / sin)
+		}
+	} else {
+		let cos_2a = cos * cos - sin * sin;
+		((width * cos - height * sin) / cos_2a, (height * cos - width * sin) / cos_2a)
+	}
+}
+
+/// Turn near-uniform regions touching the edge of the image pure white, so busy backgrounds don't end up in the lithophane
+///
+/// A pixel counts as near-uniform if the standard deviation of the gray values around it is less than `threshold`. Only near-uniform
+/// regions connected to the edge of the image are flattened so that flat areas inside the subject (eg cheeks) are left alone.
+pub fn flatten_background(image: &GrayImage, threshold: f32) -> GrayImage {
+	const RADIUS: i64 = 2;
+
+	let (width, height) = (image.width() as usize, image.height() as usize);
+
+	// Summed area tables of the values and squared values, with an extra row and column of zeros, to quickly find local variance
+	let mut sums = vec![0f64; (width + 1) * (height + 1)];
+	let mut squared_sums = vec![0f64; (width + 1) * (height + 1)];
+	for y in 0..height {
+		for x in 0..width {
+			let v = image.get_pixel(x as u32, y as u32).0[0] as f64;
+			let i = (y + 1) * (width + 1) + x + 1;
+			sums[i] = v + sums[i - 1] + sums[i - width - 1] - sums[i - width - 2];
+			squared_sums[i] = v * v + squared_sums[i - 1] + squared_sums[i - width - 1] - squared_sums[i - width - 2];
+		}
+	}
+	let is_uniform = |x: usize, y: usize| -> bool {
+		let x0 = (x as i64 - RADIUS).max(0) as usize;
+		let y0 = (y as i64 - RADIUS).max(0) as usize;
+		let x1 = (x + RADIUS as usize + 1).min(width);
+		let y1 = (y + RADIUS as usize + 1).min(height);
+		let area = |t: &[f64]| t[y1 * (width + 1) + x1] - t[y0 * (width + 1) + x1] - t[y1 * (width + 1) + x0] + t[y0 * (width + 1) + x0];
+		let count = ((x1 - x0) * (y1 - y0)) as f64;
+		let mean = area(&sums) / count;
+		let variance = (area(&squared_sums) / count - mean * mean).max(0.0);
+		variance.sqrt() < threshold as f64
+	};
+
+	// Flood fill the uniform regions starting from the edges of the image
+	let mut visited = vec![false; width * height];
+	let mut stack = (0..width).flat_map(|x| [(x, 0), (x, height - 1)]).chain((0..height).flat_map(|y| [(0, y), (width - 1, y)])).collect::<Vec<_>>();
+	let mut flattened = image.clone();
+	while let Some((x, y)) = stack.pop() {
+		if visited[y * width + x] || !is_uniform(x, y) {
+			continue;
+		}
+		visited[y * width + x] = true;
+		flattened.get_pixel_mut(x as u32, y as u32).0[0] = 255;
+
+		if x > 0 {
+			stack.push((x - 1, y));
+		}
+		if x + 1 < width {
+			stack.push((x + 1, y));
+		}
+		if y > 0 {
+			stack.push((x, y - 1));
+		}
+		if y + 1 < height {
+			stack.push((x, y + 1));
+		}
+	}
+
+	flattened
+}
+
+/// Shrink an image by averaging all of the source pixels covered by each destination pixel, weighted by how much of them is covered
+///
+/// This avoids the aliasing (sparkly noise on fine patterns like fabric or foliage) that picking the nearest pixel causes. If the image is
+/// being enlarged in either direction, this falls back to bilinear interpolation.
+pub fn resize_area_average(image: &GrayImage, width: u32, height: u32) -> GrayImage {
+	if width > image.width() || height > image.height() {
+		return imageops::resize(image, width, height, FilterType::Triangle);
+	}
+
+	let scale_x = image.width() as f64 / width as f64;
+	let scale_y = image.height() as f64 / height as f64;
+
+	/// The source pixels covered by destination pixel `i`, along with how much of each is covered
+	fn coverage(i: u32, scale: f64, source_length: u32) -> impl Iterator<Item = (u32, f64)> {
+		let start = i as f64 * scale;
+		let end = ((i + 1) as f64 * scale).min(source_length as f64);
+		(start.floor() as u32..end.ceil() as u32).map(move |s| (s, (end.min(s as f64 + 1.0) - start.max(s as f64)).max(0.0)))
+	}
+
+	GrayImage::from_fn(width, height, |x, y| {
+		let mut total = 0.0;
+		let mut total_weight = 0.0;
+		for (source_y, weight_y) in coverage(y, scale_y, image.height()) {
+			for (source_x, weight_x) in coverage(x, scale_x, image.width()) {
+				total += image.get_pixel(source_x, source_y).0[0] as f64 * weight_x * weight_y;
+				total_weight += weight_x * weight_y;
+			}
+		}
+		image::Luma([(total / total_weight).round() as u8])
+	})
+}
+
+/// Crop the middle of an image so that its width divided by its height is as close to `aspect_ratio` as possible
+pub fn crop_to_aspect_ratio(image: &GrayImage, aspect_ratio: f32) -> GrayImage {
+	let (width, height) = image.dimensions();
+	let (cropped_width, cropped_height) = if width as f32 / height as f32 > aspect_ratio {
+		(((height as f32 * aspect_ratio).round() as u32).clamp(1, width), height)
+	} else {
+		(width, ((width as f32 / aspect_ratio).round() as u32).clamp(1, height))
+	};
+	imageops::crop_imm(
+		image,
+		(width - cropped_width) / 2,
+		(height - cropped_height) / 2,
+		cropped_width,
+		cropped_height,
+	)
+	.to_image()
+}
+
+/// Resize an image so that its width divided by its height is as close to `aspect_ratio` as possible, keeping its area about the same
+/// This distorts the image, unlike cropping or padding.
+pub fn stretch_to_aspect_ratio(image: &GrayImage, aspect_ratio: f32) -> GrayImage {
+	let area = (image.width() * image.height()) as f32;
+	let width = ((area * aspect_ratio).sqrt().round() as u32).max(1);
+	let height = ((area / aspect_ratio).sqrt().round() as u32).max(1);
+	resize_area_average(image, width, height)
+}
+
+/// Add equal borders of `gray` to the sides or the top and bottom of an image so that its width divided by its height is as close to
+/// `aspect_ratio` as possible
+pub fn pad_to_aspect_ratio(image: &GrayImage, aspect_ratio: f32, gray: u8) -> GrayImage {
+	let (width, height) = image.dimensions();
+	let (padded_width, padded_height) = if width as f32 / height as f32 > aspect_ratio {
+		(width, ((width as f32 / aspect_ratio).round() as u32).max(height))
+	} else {
+		(((height as f32 * aspect_ratio).round() as u32).max(width), height)
+	};
+	let mut padded = GrayImage::from_pixel(padded_width, padded_height, image::Luma([gray]));
+	imageops::replace(
+		&mut padded,
+		image,
+		((padded_width - width) / 2) as i64,
+		((padded_height - height) / 2) as i64,
+	);
+	padded
+}
+
+/// Surround an image with a uniform border (like the matte around a framed photo) that's `width` pixels wide on every side
+pub fn add_matte(image: &GrayImage, width: u32, gray: u8) -> GrayImage {
+	let mut matted = GrayImage::from_pixel(image.width() + width * 2, image.height() + width * 2, image::Luma([gray]));
+	imageops::replace(&mut matted, image, width as i64, width as i64);
+	matted
+}
+
+/// How an overlay is combined with the image below it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlendMode {
+	/// Darkens the image, eg for a dark watermark or date stamp
+	#[default]
+	Multiply,
+	/// Lightens the image
+	Screen,
+	/// Averages the two images, for a double exposure
+	Average,
+}
+
+/// An image composited onto the input image before it's turned into depths
+#[derive(Debug, Clone, PartialEq)]
+pub struct Overlay {
+	/// The overlay is stretched to the size of the image, and transparent areas leave the image unchanged
+	pub image: GrayAlphaImage,
+	pub mode: BlendMode,
+	/// How strongly the overlay is applied, from 0 (not at all) to 1
+	pub opacity: f32,
+}
+
+/// Composite overlays onto an image in order, each one on top of the result of the ones before it
+pub fn composite(image: &GrayImage, overlays: &[Overlay]) -> GrayImage {
+	let mut result = image.clone();
+	for overlay in overlays {
+		let layer = if overlay.image.dimensions() == image.dimensions() {
+			overlay.image.clone()
+		} else {
+			imageops::resize(&overlay.image, image.width(), image.height(), FilterType::Triangle)
+		};
+
+		for (pixel, layer_pixel) in result.pixels_mut().zip(layer.pixels()) {
+			let below = pixel.0[0] as f32 / 255.0;
+			let [value, alpha] = layer_pixel.0;
+			let above = value as f32 / 255.0;
+			let blended = match overlay.mode {
+				BlendMode::Multiply => below * above,
+				BlendMode::Screen => 1.0 - (1.0 - below) * (1.0 - above),
+				BlendMode::Average => (below + above) / 2.0,
+			};
+			let opacity = overlay.opacity.clamp(0.0, 1.0) * alpha as f32 / 255.0;
+			pixel.0[0] = ((below + (blended - below) * opacity) * 255.0).round() as u8;
+		}
+	}
+	result
+}
+
+/// Locally brighten (dodge) or darken (burn) an image with a grayscale mask, eg to lift a face out of the shadows
+///
+/// Mid gray (128) in the mask leaves the image unchanged, white brightens it and black darkens it, by up to `strength` (from 0 to 1) of
+/// the way to white or black. Brightening moves values towards white and darkening towards black in proportion, so highlights and shadows
+/// are never clipped. The mask is stretched to the size of the image if it isn't already the same size.
+pub fn dodge_and_burn(image: &GrayImage, mask: &GrayImage, strength: f32) -> GrayImage {
+	let mask = if mask.dimensions() == image.dimensions() {
+		mask.clone()
+	} else {
+		imageops::resize(mask, image.width(), image.height(), FilterType::Triangle)
+	};
+
+	let mut result = image.clone();
+	for (pixel, mask_pixel) in result.pixels_mut().zip(mask.pixels()) {
+		let value = pixel.0[0] as f32 / 255.0;
+		let amount = ((mask_pixel.0[0] as f32 - 128.0) / 127.0).clamp(-1.0, 1.0) * strength.clamp(0.0, 1.0);
+		let adjusted = if amount > 0.0 {
+			value + (1.0 - value) * amount
+		} else {
+			value * (1.0 + amount)
+		};
+		pixel.0[0] = (adjusted * 255.0).round() as u8;
+	}
+	result
+}
+
+/// Remove noise (eg film grain or the speckle of a phone photo in low light) while keeping edges sharp, with a bilateral filter
+///
+/// Each pixel is averaged with the pixels around it like a gaussian blur with a standard deviation of `sigma` pixels, but pixels are
+/// counted less the more their gray value differs from its, with a standard deviation of `range` gray levels. Differences in tone smaller
+/// than the range (noise) are smoothed away while larger ones (the edges of eyes, lips and hair) are left alone.
+pub fn bilateral_filter(image: &GrayImage, sigma: f32, range: f32) -> GrayImage {
+	let (width, height) = (image.width() as i64, image.height() as i64);
+	let radius = (sigma * 2.0).ceil().max(1.0) as i64;
+	let spatial_weights = (-radius..=radius)
+		.flat_map(|dy| (-radius..=radius).map(move |dx| (dx, dy)))
+		.map(|(dx, dy)| (dx, dy, (-((dx * dx + dy * dy) as f32) / (2.0 * sigma * sigma)).exp()))
+		.collect::<Vec<_>>();
+	let range_weights: [f32; 256] = std::array::from_fn(|difference| (-((difference * difference) as f32) / (2.0 * range * range)).exp());
+
+	GrayImage::from_fn(image.width(), image.height(), |x, y| {
+		let center = image.get_pixel(x, y).0[0];
+		let (mut total, mut total_weight) = (0.0f32, 0.0f32);
+		for &(dx, dy, spatial_weight) in &spatial_weights {
+			let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+			if nx < 0 || ny < 0 || nx >= width || ny >= height {
+				continue;
+			}
+			let value = image.get_pixel(nx as u32, ny as u32).0[0];
+			let weight = spatial_weight * range_weights[value.abs_diff(center) as usize];
+			total += value as f32 * weight;
+			total_weight += weight;
+		}
+		image::Luma([(total / total_weight).round() as u8])
+	})
+}
+
+/// Turn an image into black line art on a white background by finding its edges (Canny edge detection), eg for sketch lithophanes or
+/// embossed signs
+///
+/// Pixels where the brightness changes by more than `high` gray levels are edges, along with pixels connected to them where it changes
+/// by more than `low`, so edges are continuous without picking up noise. Lines are `line_width` pixels wide. The image should usually be
+/// blurred a little first to keep noise and texture from being detected.
+pub fn edges(image: &GrayImage, low: f32, high: f32, line_width: u32) -> GrayImage {
+	let (width, height) = (image.width() as i64, image.height() as i64);
+	let value = |x: i64, y: i64| image.get_pixel(x.clamp(0, width - 1) as u32, y.clamp(0, height - 1) as u32).0[0] as f32;
+
+	// Sobel gradients, scaled so a step between two gray values has about the difference between them as its magnitude
+	let mut magnitudes = vec![0.0f32; (width * height) as usize];
+	let mut directions = vec![0u8; (width * height) as usize];
+	for y in 0..height {
+		for x in 0..width {
+			let gx = (value(x + 1, y - 1) + 2.0 * value(x + 1, y) + value(x + 1, y + 1)
+				- value(x - 1, y - 1)
+				- 2.0 * value(x - 1, y)
+				- value(x - 1, y + 1))
+				/ 4.0;
+			let gy = (value(x - 1, y + 1) + 2.0 * value(x, y + 1) + value(x + 1, y + 1)
+				- value(x - 1, y - 1)
+				- 2.0 * value(x, y - 1)
+				- value(x + 1, y - 1))
+				/ 4.0;
+			let i = (y * width + x) as usize;
+			magnitudes[i] = gx.hypot(gy);
+			// The direction of the gradient rounded to one of 4 lines through the pixel: horizontal, diagonal, vertical, other diagonal
+			directions[i] = ((gy.atan2(gx).to_degrees() + 180.0 + 22.5) / 45.0) as u8 % 4;
+		}
+	}
+
+	// Only keep pixels that are stronger than their neighbours across the edge, so the edges are one pixel wide
+	let magnitude = |x: i64, y: i64| {
+		if x < 0 || y < 0 || x >= width || y >= height {
+			0.0
+		} else {
+			magnitudes[(y * width + x) as usize]
+		}
+	};
+	let mut strong = Vec::new();
+	let mut candidates = vec![false; magnitudes.len()];
+	for y in 0..height {
+		for x in 0..width {
+			let i = (y * width + x) as usize;
+			let (dx, dy) = [(1, 0), (1, 1), (0, 1), (-1, 1)][directions[i] as usize];
+			if magnitudes[i] > low && magnitudes[i] >= magnitude(x + dx, y + dy) && magnitudes[i] >= magnitude(x - dx, y - dy) {
+				candidates[i] = true;
+				if magnitudes[i] > high {
+					strong.push((x, y));
+				}
+			}
+		}
+	}
+
+	// Follow weaker edges connected to the strong ones
+	let mut is_edge = vec![false; magnitudes.len()];
+	while let Some((x, y)) = strong.pop() {
+		let i = (y * width + x) as usize;
+		if is_edge[i] {
+			continue;
+		}
+		is_edge[i] = true;
+		for (nx, ny) in (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy))) {
+			if nx >= 0 && ny >= 0 && nx < width && ny < height && candidates[(ny * width + nx) as usize] {
+				strong.push((nx, ny));
+			}
+		}
+	}
+
+	// Thicken the lines by taking the darkest pixel in a square around each pixel, one axis at a time
+	let lines = GrayImage::from_fn(image.width(), image.height(), |x, y| {
+		image::Luma([if is_edge[(y * image.width() + x) as usize] { 0 } else { 255 }])
+	});
+	let radius = (line_width.max(1) - 1) as i64 / 2;
+	let darkest = |image: &GrayImage, dx: i64, dy: i64| {
+		GrayImage::from_fn(image.width(), image.height(), |x, y| {
+			let pixels = (-radius..=radius).map(|r| ((x as i64 + r * dx).clamp(0, width - 1), (y as i64 + r * dy).clamp(0, height - 1)));
+			image::Luma([pixels.map(|(x, y)| image.get_pixel(x as u32, y as u32).0[0]).min().unwrap_or(255)])
+		})
+	};
+	darkest(&darkest(&lines, 1, 0), 0, 1)
+}
+
+/// Split a color image into its red, green and blue channels, each as a grayscale image
+pub fn split_channels(image: &DynamicImage) -> [GrayImage; 3] {
+	let rgb = image.to_rgb8();
+	[0, 1, 2].map(|channel| GrayImage::from_fn(rgb.width(), rgb.height(), |x, y| image::Luma([rgb.get_pixel(x, y).0[channel]])))
+}
+
+/// How to remove a color cast (eg the orange of indoor tungsten light) before an image is turned into gray values
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WhiteBalance {
+	/// Assume the image averages out to gray, and scale the channels so their averages are the same
+	GrayWorld,
+	/// Assume the lightest parts of the image are white, and scale the channels so this percentile of each of them (eg 99, so a few
+	/// specular highlights don't decide it) is the same
+	Percentile(f32),
+}
+
+/// Remove a color cast by scaling the red, green and blue channels of an image separately, since a strong cast changes which colors come
+/// out light or dark in gray, keeping the image's bit depth
+///
+/// Grayscale images are returned unchanged, and fully transparent pixels are left out when working out how much to scale each channel.
+pub fn white_balance(image: DynamicImage, white_balance: WhiteBalance) -> DynamicImage {
+	let color = image.color();
+	if !color.has_color() {
+		return image;
+	}
+
+	let mut rgba = image.into_rgba32f();
+	let references = {
+		let visible = || rgba.pixels().filter(|p| p.0[3] > 0.0);
+		match white_balance {
+			WhiteBalance::GrayWorld => {
+				let (mut sums, mut count) = ([0.0f64; 3], 0usize);
+				for p in visible() {
+					for (sum, &value) in sums.iter_mut().zip(&p.0) {
+						*sum += value as f64;
+					}
+					count += 1;
+				}
+				sums.map(|sum| (sum / count.max(1) as f64) as f32)
+			},
+			WhiteBalance::Percentile(percentile) => [0, 1, 2].map(|channel| {
+				let mut values = visible().map(|p| p.0[channel]).collect::<Vec<_>>();
+				if values.is_empty() {
+					return 0.0;
+				}
+				let i = ((percentile / 100.0).clamp(0.0, 1.0) * (values.len() - 1) as f32).round() as usize;
+				*values.select_nth_unstable_by(i, f32::total_cmp).1
+			}),
+		}
+	};
+
+	// Gray world keeps the average brightness, and the percentile keeps the lightest channel as it is, so nothing is scaled past white
+	// that wasn't already
+	let target = match white_balance {
+		WhiteBalance::GrayWorld => references.iter().sum::<f32>() / 3.0,
+		WhiteBalance::Percentile(_) => references.iter().copied().fold(0.0, f32::max),
+	};
+	let gains = references.map(|reference| if reference > 0.0 { target / reference } else { 1.0 });
+	for p in rgba.pixels_mut() {
+		for (value, gain) in p.0.iter_mut().zip(gains) {
+			*value *= gain;
+		}
+	}
+
+	let balanced = DynamicImage::ImageRgba32F(rgba);
+	match color {
+		ColorType::Rgb8 => DynamicImage::ImageRgb8(balanced.into_rgb8()),
+		ColorType::Rgba8 => DynamicImage::ImageRgba8(balanced.into_rgba8()),
+		ColorType::Rgb16 => DynamicImage::ImageRgb16(balanced.into_rgb16()),
+		ColorType::Rgba16 => DynamicImage::ImageRgba16(balanced.into_rgba16()),
+		ColorType::Rgb32F => DynamicImage::ImageRgb32F(balanced.into_rgb32f()),
+		_ => balanced,
+	}
+}
+
+/// How to squash the brightness of high dynamic range images (renders, HDR photography) into the range of a normal grayscale image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToneMapping {
+	#[default]
+	Reinhard,
+	/// An approximation of the ACES filmic curve, which has more contrast than Reinhard
+	Aces,
+}
+
+/// How gray values are computed from the colors of an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Grayscale {
+	/// Rec. 709 luma, the weighted sum of the gamma encoded red, green and blue values that most image editors use
+	#[default]
+	Luma,
+	/// CIELAB L*, the perceptual lightness of the color computed from its linear luminance
+	///
+	/// Saturated colors have about the same luma as much lighter or darker grays, so this keeps eg red and blue areas from turning into
+	/// similar gray values when they don't look equally bright.
+	Lightness,
+}
+
+/// Convert an image to grayscale, tone mapping it first if it's a high dynamic range (floating point) image, eg Radiance HDR or OpenEXR
+///
+/// Other images are converted directly, since their values are already in the right range.
+pub fn to_grayscale(image: DynamicImage, tone_mapping: ToneMapping, grayscale: Grayscale) -> GrayImage {
+	let hdr_image = match (image, grayscale) {
+		(DynamicImage::ImageRgb32F(i), _) => i,
+		(image @ DynamicImage::ImageRgba32F(_), _) => image.into_rgb32f(),
+		(image, Grayscale::Luma) => return image.into_luma8(),
+		(image, Grayscale::Lightness) => return lightness(&image),
+	};
+
+	GrayImage::from_fn(hdr_image.width(), hdr_image.height(), |x, y| {
+		let [r, g, b] = hdr_image.get_pixel(x, y).0;
+		let luminance = (0.2126 * r + 0.7152 * g + 0.0722 * b).max(0.0);
+		let mapped = match tone_mapping {
+			ToneMapping::Reinhard => luminance / (1.0 + luminance),
+			ToneMapping::Aces => (luminance * (2.51 * luminance + 0.03)) / (luminance * (2.43 * luminance + 0.59) + 0.14),
+		}
+		.clamp(0.0, 1.0);
+		let encoded = match grayscale {
+			// The tone mapped value is still linear, so encode it as sRGB like any other 8 bit image
+			Grayscale::Luma if mapped <= 0.0031308 => mapped * 12.92,
+			Grayscale::Luma => 1.055 * mapped.powf(1.0 / 2.4) - 0.055,
+			Grayscale::Lightness => luminance_to_lightness(mapped),
+		};
+		image::Luma([(encoded * 255.0).round() as u8])
+	})
+}
+
+/// Convert an 8 or 16 bit sRGB image to CIELAB L*, scaled so 0 is black and 255 is white
+///
+/// 8 bit grayscale and RGB images are read in place through a lookup table, rather than first being copied into a 16 bit RGB image, which
+/// would take six bytes for every pixel of a large scan.
+fn lightness(image: &DynamicImage) -> GrayImage {
+	let decode = |value: u16| {
+		let value = value as f32 / u16::MAX as f32;
+		if value <= 0.04045 {
+			value / 12.92
+		} else {
+			((value + 0.055) / 1.055).powf(2.4)
+		}
+	};
+	let encode = |[r, g, b]: [f32; 3]| {
+		let luminance = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+		image::Luma([(luminance_to_lightness(luminance).clamp(0.0, 1.0) * 255.0).round() as u8])
+	};
+
+	// Widen 8 bit values the same way converting to 16 bits does, so the results are the same either way
+	let decoded_u8 = (0..=u8::MAX).map(|v| decode(v as u16 * 257)).collect::<Vec<_>>();
+	match image {
+		DynamicImage::ImageLuma8(gray) => {
+			let lookup = decoded_u8.iter().map(|&v| encode([v; 3])).collect::<Vec<_>>();
+			GrayImage::from_fn(gray.width(), gray.height(), |x, y| lookup[gray.get_pixel(x, y).0[0] as usize])
+		},
+		DynamicImage::ImageRgb8(rgb) => GrayImage::from_fn(rgb.width(), rgb.height(), |x, y| {
+			encode(rgb.get_pixel(x, y).0.map(|v| decoded_u8[v as usize]))
+		}),
+		_ => {
+			let rgb = image.to_rgb16();
+			GrayImage::from_fn(rgb.width(), rgb.height(), |x, y| encode(rgb.get_pixel(x, y).0.map(decode)))
+		},
+	}
+}
+
+/// CIELAB L* from linear luminance, both from 0 to 1 (rather than 0 to 100 for L*)
+fn luminance_to_lightness(luminance: f32) -> f32 {
+	const EPSILON: f32 = 216.0 / 24389.0;
+	const KAPPA: f32 = 24389.0 / 27.0;
+	if luminance > EPSILON {
+		1.16 * luminance.cbrt() - 0.16
+	} else {
+		KAPPA * luminance / 100.0
+	}
+}
+
+/// Separate gamma curves for the shadows and the highlights, to choose how much of the lithophane's thickness goes to detail in each
+///
+/// Gray values below `threshold` (from 0 to 1) are the shadows, which are mapped to the range from black to `split`, and the rest are
+/// the highlights, mapped from `split` to white. Each band then gets its own gamma, where values over 1 brighten it like `Filter::Gamma`.
+/// For example a `split` above the `threshold` gives the shadows more of the depth range, which brings out detail in dark areas.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SplitToneCurve {
+	pub threshold: f32,
+	pub split: f32,
+	pub shadow_gamma: f32,
+	pub highlight_gamma: f32,
+}
+
+impl SplitToneCurve {
+	fn map(&self, value: f32) -> f32 {
+		let threshold = self.threshold.clamp(0.0, 1.0);
+		let split = self.split.clamp(0.0, 1.0);
+		if value < threshold {
+			split * (value / threshold).powf(1.0 / self.shadow_gamma)
+		} else if threshold < 1.0 {
+			split + (1.0 - split) * ((value - threshold) / (1.0 - threshold)).powf(1.0 / self.highlight_gamma)
+		} else {
+			1.0
+		}
+	}
+}
+
+impl ImageFilter for SplitToneCurve {
+	fn apply(&self, image: &GrayImage) -> GrayImage {
+		let lookup: Vec<u8> = (0..=255).map(|v| (self.map(v as f32 / 255.0) * 255.0).round() as u8).collect();
+		let mut adjusted = image.clone();
+		for p in adjusted.pixels_mut() {
+			p.0[0] = lookup[p.0[0] as usize];
+		}
+		adjusted
+	}
+}
+
+/// A preprocessing step that turns one grayscale image into another
+///
+/// This is implemented for closures, so custom steps can be added to a `Pipeline` without a new type.
+pub trait ImageFilter {
+	fn apply(&self, image: &GrayImage) -> GrayImage;
+}
+
+impl<F: Fn(&GrayImage) -> GrayImage> ImageFilter for F {
+	fn apply(&self, image: &GrayImage) -> GrayImage {
+		self(image)
+	}
+}
+
+/// The built in preprocessing steps, which can also be parsed from text (eg "gamma:1.5") for describing a pipeline in a config file or on
+/// the command line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+	/// "stretch-contrast", see `stretch_contrast`
+	StretchContrast,
+	/// "flatten-background:THRESHOLD", see `flatten_background`
+	FlattenBackground { threshold: f32 },
+	/// "resize:WIDTH:HEIGHT", see `resize_area_average`
+	Resize { width: u32, height: u32 },
+	/// "gamma:GAMMA", where values over 1 brighten the midtones (and make the lithophane thinner there) and values under 1 darken them
+	Gamma(f32),
+	/// "blur:SIGMA", a gaussian blur, to hide noise and JPEG artifacts
+	Blur(f32),
+	/// "denoise:SIGMA:RANGE", see `bilateral_filter`, which smooths noise without blurring edges like a gaussian blur does
+	Denoise { sigma: f32, range: f32 },
+	/// "crop:X:Y:WIDTH:HEIGHT", in pixels, which is clamped to the image
+	Crop { x: u32, y: u32, width: u32, height: u32 },
+	/// "matte:WIDTH:GRAY", see `add_matte`
+	Matte { width: u32, gray: u8 },
+	/// "rotate:DEGREES", see `rotate`
+	Rotate(f32),
+	/// "split-tone:THRESHOLD:SPLIT:SHADOW_GAMMA:HIGHLIGHT_GAMMA", with the threshold and split from 0 to 1, see `SplitToneCurve`
+	SplitTone(SplitToneCurve),
+	/// "edges:LOW:HIGH:LINE_WIDTH", see `edges`
+	Edges { low: f32, high: f32, line_width: u32 },
+	/// "subject-stretch" for a subject in the middle of the image, or "subject-stretch:X:Y:WIDTH:HEIGHT" in normalized coordinates, see
+	/// `stretch_contrast_for_subject`
+	SubjectStretch { x: f32, y: f32, width: f32, height: f32 },
+}
+
+impl ImageFilter for Filter {
+	fn apply(&self, image: &GrayImage) -> GrayImage {
+		match *self {
+			Filter::StretchContrast => stretch_contrast(image),
+			Filter::FlattenBackground { threshold } => flatten_background(image, threshold),
+			Filter::Resize { width, height } => resize_area_average(image, width, height),
+			Filter::Gamma(gamma) => {
+				let mut adjusted = image.clone();
+				for p in adjusted.pixels_mut() {
+					p.0[0] = ((p.0[0] as f32 / 255.0).powf(1.0 / gamma) * 255.0).round() as u8;
+				}
+				adjusted
+			},
+			Filter::Blur(sigma) => imageops::blur(image, sigma),
+			Filter::Denoise { sigma, range } => bilateral_filter(image, sigma, range),
+			Filter::Crop { x, y, width, height } => imageops::crop_imm(image, x, y, width, height).to_image(),
+			Filter::Matte { width, gray } => add_matte(image, width, gray),
+			Filter::Rotate(degrees) => rotate(image, degrees),
+			Filter::SplitTone(curve) => curve.apply(image),
+			Filter::Edges { low, high, line_width } => edges(image, low, high, line_width),
+			Filter::SubjectStretch { x, y, width, height } => stretch_contrast_for_subject(image, x, y, width, height),
+		}
+	}
+}
+
+#[derive(Error, Debug)]
+#[error(
+	"invalid filter \"{0}\", expected eg stretch-contrast, flatten-background:12, resize:300:200, gamma:1.5, blur:1, denoise:2:20, \
+	 crop:0:0:300:200, matte:20:255, rotate:1.5, split-tone:0.5:0.6:1.2:0.9, edges:10:30:2, subject-stretch:0.3:0.1:0.4:0.6"
+)]
+pub struct ParseFilterError(String);
+
+impl FromStr for Filter {
+	type Err = ParseFilterError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let error = || ParseFilterError(s.to_string());
+		let mut parts = s.trim().split(':');
+		let name = parts.next().ok_or_else(error)?;
+		let args = parts.map(|a| a.trim().parse::<f32>().map_err(|_| error())).collect::<Result<Vec<_>, _>>()?;
+		let positive = |v: f32| if v > 0.0 { Ok(v) } else { Err(error()) };
+
+		match (name, &args[..]) {
+			("stretch-contrast", []) => Ok(Filter::StretchContrast),
+			("flatten-background", &[threshold]) => Ok(Filter::FlattenBackground { threshold }),
+			("resize", &[width, height]) => Ok(Filter::Resize {
+				width: positive(width)? as u32,
+				height: positive(height)? as u32,
+			}),
+			("gamma", &[gamma]) => Ok(Filter::Gamma(positive(gamma)?)),
+			("blur", &[sigma]) => Ok(Filter::Blur(positive(sigma)?)),
+			("denoise", &[sigma, range]) => Ok(Filter::Denoise {
+				sigma: positive(sigma)?,
+				range: positive(range)?,
+			}),
+			("crop", &[x, y, width, height]) => Ok(Filter::Crop {
+				x: x.max(0.0) as u32,
+				y: y.max(0.0) as u32,
+				width: positive(width)? as u32,
+				height: positive(height)? as u32,
+			}),
+			("matte", &[width, gray]) if (0.0..=255.0).contains(&gray) => Ok(Filter::Matte {
+				width: width.max(0.0) as u32,
+				gray: gray as u8,
+			}),
+			("rotate", &[degrees]) => Ok(Filter::Rotate(degrees)),
+			("split-tone", &[threshold, split, shadow_gamma, highlight_gamma])
+				if (0.0..=1.0).contains(&threshold) && (0.0..=1.0).contains(&split) =>
+			{
+				Ok(Filter::SplitTone(SplitToneCurve {
+					threshold,
+					split,
+					shadow_gamma: positive(shadow_gamma)?,
+					highlight_gamma: positive(highlight_gamma)?,
+				}))
+			},
+			("edges", &[low, high, line_width]) if low >= 0.0 && high >= low => Ok(Filter::Edges {
+				low,
+				high,
+				line_width: positive(line_width)? as u32,
+			}),
+			("subject-stretch", []) => Ok(Filter::SubjectStretch {
+				x: 0.25,
+				y: 0.25,
+				width: 0.5,
+				height: 0.5,
+			}),
+			("subject-stretch", &[x, y, width, height]) => Ok(Filter::SubjectStretch {
+				x,
+				y,
+				width: positive(width)?,
+				height: positive(height)?,
+			}),
+			_ => Err(error()),
+		}
+	}
+}
+
+/// A list of preprocessing steps that are applied in order
+#[derive(Default)]
+pub struct Pipeline {
+	filters: Vec<Box<dyn ImageFilter>>,
+}
+
+impl Pipeline {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Add a step to the end of the pipeline
+	pub fn then(mut self, filter: impl ImageFilter + 'static) -> Self {
+		self.filters.push(Box::new(filter));
+		self
+	}
+}
+
+impl ImageFilter for Pipeline {
+	fn apply(&self, image: &GrayImage) -> GrayImage {
+		// Only the filters' results are owned, so the input isn't copied just to be replaced by the first filter's result
+		let mut filtered = Cow::Borrowed(image);
+		for filter in &self.filters {
+			filtered = Cow::Owned(filter.apply(&filtered));
+		}
+		filtered.into_owned()
+	}
+}
+
+impl FromIterator<Filter> for Pipeline {
+	fn from_iter<T: IntoIterator<Item = Filter>>(iter: T) -> Self {
+		iter.into_iter().fold(Self::new(), Pipeline::then)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use image::Luma;
+
+	use super::*;
+
+	/// An image that steps from black to `right` gray at x = 10, with the step only `lower_right` high below y = 10
+	fn step(right: u8, lower_right: u8) -> GrayImage {
+		GrayImage::from_fn(20, 20, |x, y| {
+			Luma([match (x < 10, y < 10) {
+				(true, _) => 0,
+				(false, true) => right,
+				(false, false) => lower_right,
+			}])
+		})
+	}
+
+	/// The columns with an edge in each row
+	fn edge_columns(lines: &GrayImage) -> Vec<Vec<u32>> {
+		(0..lines.height()).map(|y| (0..lines.width()).filter(|&x| lines.get_pixel(x, y).0[0] == 0).collect()).collect()
+	}
+
+	#[test]
+	fn edges_find_steps() {
+		for row in edge_columns(&edges(&step(200, 200), 20.0, 60.0, 1)) {
+			assert_eq!(row, vec![9, 10]);
+		}
+		let flat = GrayImage::from_pixel(20, 20, Luma([128]));
+		assert!(edges(&flat, 20.0, 60.0, 1).pixels().all(|p| p.0[0] == 255));
+	}
+
+	#[test]
+	fn edges_only_keep_weak_edges_connected_to_strong_ones() {
+		// Too weak to start an edge by itself
+		assert!(edge_columns(&edges(&step(40, 40), 20.0, 60.0, 1)).iter().all(|row| row.is_empty()));
+		// Strong at the top, which carries on down the weak part (rows 9 and 10 also have the edge between the top and bottom halves)
+		let rows = edge_columns(&edges(&step(200, 40), 20.0, 60.0, 1));
+		assert!(rows[..9].iter().chain(&rows[11..]).all(|row| *row == vec![9, 10]));
+		// Below even the low threshold at the bottom
+		let rows = edge_columns(&edges(&step(200, 10), 20.0, 60.0, 1));
+		assert!(rows[..9].iter().all(|row| *row == vec![9, 10]));
+		assert!(rows[11..].iter().all(|row| row.is_empty()));
+	}
+
+	#[test]
+	fn edges_are_as_wide_as_the_line_width() {
+		for row in edge_columns(&edges(&step(200, 200), 20.0, 60.0, 3)) {
+			assert_eq!(row, vec![8, 9, 10, 11]);
+		}
+	}
+
+	#[test]
+	fn map_image_keeps_positions_in_range() {
+		let image = GrayImage::from_fn(8, 6, |x, y| Luma([(x * 30 + y) as u8]));
+		for edges in [EdgeMode::Clamp, EdgeMode::Repeat, EdgeMode::Mirror] {
+			let identity = ImageMapping { edges, ..Default::default() };
+			assert_eq!(map_image(&image, &identity).unwrap(), image);
+			for (offset, scale) in [((0.0, 0.0), 1e-30), ((1e30, -1e30), 1.0), ((0.5, 0.5), 1e30)] {
+				assert!(map_image(&image, &ImageMapping { offset, scale, edges }).is_ok());
+			}
+			for (offset, scale) in [((0.0, 0.0), 0.0), ((0.0, 0.0), -1.0), ((f32::NAN, 0.0), 1.0), ((0.0, 0.0), f32::INFINITY)] {
+				assert!(map_image(&image, &ImageMapping { offset, scale, edges }).is_err());
+			}
+		}
+	}
+
+	#[cfg(feature = "openexr")]
+	#[test]
+	fn tone_maps_openexr_images() {
+		let hdr = DynamicImage::ImageRgb32F(image::Rgb32FImage::from_fn(4, 1, |x, _| image::Rgb([x as f32 * 4.0; 3])));
+		let mut bytes = std::io::Cursor::new(Vec::new());
+		hdr.write_to(&mut bytes, image::ImageOutputFormat::OpenExr).unwrap();
+		let decoded = crate::input::decode_frame(bytes.get_ref(), 0).unwrap();
+
+		let tone_mapped = |image| to_grayscale(image, ToneMapping::Reinhard, Grayscale::Luma);
+		let gray = tone_mapped(decoded);
+		assert_eq!(gray, tone_mapped(hdr));
+		assert!(
+			gray.pixels().map(|p| p.0[0]).collect::<Vec<_>>().windows(2).all(|w| w[0] < w[1]),
+			"{:?}",
+			gray
+		);
+	}
+}
@@ -0,0 +1,636 @@
+use std::path::{Path, PathBuf};
+
+use image::GrayImage;
+use thiserror::Error;
+
+use crate::{backlight::Material, preprocessing::ImageFilter};
+
+/// The name of the directory in the user's config directory that `Catalog::load_dir` reads for `config_dir`
+pub const CONFIG_DIR_NAME: &str = "lithophane";
+
+/// The expressions for a shape, like the ones given on the command line
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShapePreset {
+	pub x_expression: String,
+	pub y_expression: String,
+	pub z_expression: String,
+}
+
+/// A tone curve through points from an input gray value to an output gray value, both from 0 to 255, with straight lines between the
+/// points and flat lines before the first one and after the last one
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferCurve {
+	pub points: Vec<(f32, f32)>,
+}
+
+impl TransferCurve {
+	fn map(&self, value: f32) -> f32 {
+		let after = self.points.partition_point(|&(input, _)| input <= value);
+		match (after.checked_sub(1).map(|i| self.points[i]), self.points.get(after)) {
+			(Some((x0, y0)), Some(&(x1, y1))) => y0 + (y1 - y0) * (value - x0) / (x1 - x0),
+			(Some((_, y)), None) | (None, Some(&(_, y))) => y,
+			(None, None) => value,
+		}
+	}
+
+	/// The output gray value for each input gray value
+	pub fn lookup_table(&self) -> Vec<u8> {
+		(0..=255).map(|v| self.map(v as f32).round().clamp(0.0, 255.0) as u8).collect()
+	}
+}
+
+impl ImageFilter for TransferCurve {
+	fn apply(&self, image: &GrayImage) -> GrayImage {
+		let lookup = self.lookup_table();
+		let mut adjusted = image.clone();
+		for p in adjusted.pixels_mut() {
+			p.0[0] = lookup[p.0[0] as usize];
+		}
+		adjusted
+	}
+}
+
+#[derive(Error, Debug)]
+pub enum CatalogError {
+	#[error("error reading \"{}\": {source}", path.display())]
+	Io { path: PathBuf, source: std::io::Error },
+	#[error("{}: {source}", path.display())]
+	InFile { path: PathBuf, source: Box<CatalogError> },
+	#[error("line {line}: {message}")]
+	Syntax { line: usize, message: String },
+	#[error("unknown kind of definition \"{0}\", expected preset, material or curve")]
+	UnknownKind(String),
+	#[error("{kind} \"{name}\" is missing \"{key}\"")]
+	MissingKey { kind: &'static str, name: String, key: &'static str },
+	#[error("{kind} \"{name}\" has an invalid \"{key}\": {message}")]
+	InvalidValue {
+		kind: &'static str,
+		name: String,
+		key: String,
+		message: &'static str,
+	},
+}
+
+/// Named shapes, materials and tone curves, the built in ones and any the user defines
+///
+/// Definitions are read from TOML files (`parse_toml`, `load_dir`) or a JSON object (`parse_json`) with the same layout, for example:
+///
+/// ```toml
+/// [preset.arch]
+/// x = "x"
+/// y = "-y"
+/// z = "(x - w / 2) * (x - w / 2) / w"
+///
+/// [material.white-petg]
+/// attenuation = 0.9
+/// color = [1.0, 0.98, 0.92]
+///
+/// [curve.soft-shadows]
+/// points = [[0, 20], [128, 140], [255, 255]]
+/// ```
+///
+/// A material's color is optional and defaults to the default material's. Only the parts of TOML needed for this are understood: tables
+/// named `kind.name`, with the name optionally quoted, and keys set to numbers, strings or arrays on a single line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Catalog {
+	pub presets: Vec<(String, ShapePreset)>,
+	pub materials: Vec<(String, Material)>,
+	pub curves: Vec<(String, TransferCurve)>,
+}
+
+impl Default for Catalog {
+	/// The built in definitions
+	fn default() -> Self {
+		Self {
+			presets: vec![(
+				"flat".to_string(),
+				ShapePreset {
+					x_expression: "x".to_string(),
+					y_expression: "-y".to_string(),
+					z_expression: "0".to_string(),
+				},
+			)],
+			materials: vec![("white-pla".to_string(), Material::default())],
+			curves: vec![(
+				"linear".to_string(),
+				TransferCurve {
+					points: vec![(0.0, 0.0), (255.0, 255.0)],
+				},
+			)],
+		}
+	}
+}
+
+impl Catalog {
+	/// A catalog with nothing in it, to parse definitions into
+	pub fn empty() -> Self {
+		Self {
+			presets: Vec::new(),
+			materials: Vec::new(),
+			curves: Vec::new(),
+		}
+	}
+
+	pub fn parse_toml(source: &str) -> Result<Self, CatalogError> {
+		let mut tables: Vec<Table> = Vec::new();
+		for (index, line) in source.lines().enumerate() {
+			let syntax = |message: &str| CatalogError::Syntax {
+				line: index + 1,
+				message: message.to_string(),
+			};
+			let mut reader = Reader::new(line);
+			reader.skip_space();
+			if reader.at_end_of_line() {
+				continue;
+			}
+			if reader.eat('[') {
+				let kind = reader.key().map_err(syntax)?;
+				if !reader.eat('.') {
+					return Err(syntax("expected a table named kind.name, eg [material.petg]"));
+				}
+				let name = reader.key().map_err(syntax)?;
+				if !reader.eat(']') || !reader.at_end_of_line() {
+					return Err(syntax("expected ] at the end of the table name"));
+				}
+				tables.push((kind, name, Vec::new()));
+			} else {
+				let key = reader.key().map_err(syntax)?;
+				if !reader.eat('=') {
+					return Err(syntax("expected = after the key"));
+				}
+				let value = reader.value(false).map_err(syntax)?;
+				if !reader.at_end_of_line() {
+					return Err(syntax("unexpected text after the value"));
+				}
+				match tables.last_mut() {
+					Some((_, _, entries)) => entries.push((key, value)),
+					None => return Err(syntax("keys have to be in a table, eg [material.petg]")),
+				}
+			}
+		}
+		Self::from_tables(tables)
+	}
+
+	/// Parse definitions from a JSON object with an object for each kind of definition, eg `{"material": {"petg": {"attenuation": 0.9}}}`
+	pub fn parse_json(source: &str) -> Result<Self, CatalogError> {
+		let root = parse_json_value(source)?;
+		let not_object = |line| CatalogError::Syntax {
+			line,
+			message: "expected an object of definitions".to_string(),
+		};
+		let Value::Object(kinds) = root else { return Err(not_object(1)) };
+		let mut tables = Vec::new();
+		for (kind, definitions) in kinds {
+			let Value::Object(definitions) = definitions else {
+				return Err(not_object(1));
+			};
+			for (name, entries) in definitions {
+				let Value::Object(entries) = entries else { return Err(not_object(1)) };
+				tables.push((kind.clone(), name, entries));
+			}
+		}
+		Self::from_tables(tables)
+	}
+
+	/// Read every `.toml` file in a directory, in order of their names, so a definition in a later file replaces one with the same name in
+	/// an earlier file. A directory that doesn't exist is treated as empty.
+	pub fn load_dir(path: &Path) -> Result<Self, CatalogError> {
+		let io = |source| CatalogError::Io {
+			path: path.to_path_buf(),
+			source,
+		};
+		let mut files = match std::fs::read_dir(path) {
+			Ok(entries) => entries.map(|e| e.map(|e| e.path())).collect::<Result<Vec<_>, _>>().map_err(io)?,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+			Err(e) => return Err(io(e)),
+		};
+		files.retain(|f| f.extension().is_some_and(|e| e == "toml") && f.is_file());
+		files.sort();
+
+		let mut catalog = Self::empty();
+		for file in files {
+			let source = std::fs::read_to_string(&file).map_err(|source| CatalogError::Io { path: file.clone(), source })?;
+			let definitions = Self::parse_toml(&source).map_err(|e| CatalogError::InFile {
+				path: file.clone(),
+				source: Box::new(e),
+			})?;
+			catalog.extend(definitions);
+		}
+		Ok(catalog)
+	}
+
+	/// Add the definitions from `other`, replacing any with the same kind and name
+	pub fn extend(&mut self, other: Self) {
+		fn merge<T>(into: &mut Vec<(String, T)>, from: Vec<(String, T)>) {
+			for (name, definition) in from {
+				into.retain(|(n, _)| *n != name);
+				into.push((name, definition));
+			}
+		}
+		merge(&mut self.presets, other.presets);
+		merge(&mut self.materials, other.materials);
+		merge(&mut self.curves, other.curves);
+	}
+
+	pub fn preset(&self, name: &str) -> Option<&ShapePreset> {
+		self.presets.iter().find(|(n, _)| n == name).map(|(_, p)| p)
+	}
+
+	pub fn material(&self, name: &str) -> Option<&Material> {
+		self.materials.iter().find(|(n, _)| n == name).map(|(_, m)| m)
+	}
+
+	pub fn curve(&self, name: &str) -> Option<&TransferCurve> {
+		self.curves.iter().find(|(n, _)| n == name).map(|(_, c)| c)
+	}
+
+	fn from_tables(tables: Vec<Table>) -> Result<Self, CatalogError> {
+		let mut catalog = Self::empty();
+		for (kind, name, entries) in tables {
+			match kind.as_str() {
+				"preset" => {
+					let definition = Definition::new("preset", &name, &entries);
+					let [x_expression, y_expression, z_expression] =
+						["x", "y", "z"].map(|key| definition.required(key).and_then(|(key, v)| definition.string(key, v)));
+					let preset = ShapePreset {
+						x_expression: x_expression?,
+						y_expression: y_expression?,
+						z_expression: z_expression?,
+					};
+					catalog.extend(Self {
+						presets: vec![(name, preset)],
+						..Self::empty()
+					});
+				},
+				"material" => {
+					let definition = Definition::new("material", &name, &entries);
+					let default = Material::default();
+					let (key, attenuation) = definition.required("attenuation")?;
+					let attenuation = definition.number(key, attenuation).and_then(|a| definition.check(key, a >= 0.0, a, "it can't be negative"))?;
+					let color = match definition.get("color") {
+						Some(color) => {
+							let channels = definition.numbers("color", color)?;
+							let channels = definition.check("color", channels.len() == 3, channels, "it needs a red, green and blue value")?;
+							let in_range = channels.iter().all(|c| (0.0..=1.0).contains(c));
+							let channels = definition.check("color", in_range, channels, "the values have to be from 0 to 1")?;
+							[channels[0], channels[1], channels[2]]
+						},
+						None => default.color,
+					};
+					catalog.extend(Self {
+						materials: vec![(name, Material { attenuation, color })],
+						..Self::empty()
+					});
+				},
+				"curve" => {
+					let definition = Definition::new("curve", &name, &entries);
+					let (key, points) = definition.required("points")?;
+					let Value::Array(points) = points else {
+						return Err(definition.invalid(key, "expected an array of [input, output] pairs"));
+					};
+					let points = points
+						.iter()
+						.map(|p| match definition.numbers(key, p)?[..] {
+							[input, output] if (0.0..=255.0).contains(&input) && (0.0..=255.0).contains(&output) => Ok((input, output)),
+							_ => Err(definition.invalid(key, "each point has to be an [input, output] pair from 0 to 255")),
+						})
+						.collect::<Result<Vec<_>, _>>()?;
+					let increasing = !points.is_empty() && points.windows(2).all(|w| w[0].0 < w[1].0);
+					let points = definition.check(key, increasing, points, "the inputs have to go up from one point to the next")?;
+					catalog.extend(Self {
+						curves: vec![(name, TransferCurve { points })],
+						..Self::empty()
+					});
+				},
+				_ => return Err(CatalogError::UnknownKind(kind)),
+			}
+		}
+		Ok(catalog)
+	}
+}
+
+/// Where the user's definitions are kept, `lithophane` in `$XDG_CONFIG_HOME` or else in `~/.config`
+pub fn config_dir() -> Option<PathBuf> {
+	let non_empty = |name| std::env::var_os(name).filter(|v| !v.is_empty()).map(PathBuf::from);
+	let config = non_empty("XDG_CONFIG_HOME").or_else(|| non_empty("HOME").map(|home| home.join(".config")))?;
+	Some(config.join(CONFIG_DIR_NAME))
+}
+
+/// The built in definitions with the user's from `config_dir` added
+pub fn load_user_catalog() -> Result<Catalog, CatalogError> {
+	let mut catalog = Catalog::default();
+	if let Some(dir) = config_dir() {
+		catalog.extend(Catalog::load_dir(&dir)?);
+	}
+	Ok(catalog)
+}
+
+/// Read a whole JSON document, which is also used for saved settings (see `settings::Settings::parse_json`)
+pub(crate) fn parse_json_value(source: &str) -> Result<Value, CatalogError> {
+	let mut reader = Reader::new(source);
+	let syntax = |reader: &Reader, message: &str| CatalogError::Syntax {
+		line: source[..reader.position].matches('\n').count() + 1,
+		message: message.to_string(),
+	};
+	let root = reader.value(true).map_err(|message| syntax(&reader, message))?;
+	reader.skip_space();
+	if reader.position < source.len() {
+		return Err(syntax(&reader, "unexpected text after the object"));
+	}
+	Ok(root)
+}
+
+/// The kind and name of a definition, and its keys
+type Table = (String, String, Vec<(String, Value)>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+	Number(f32),
+	String(String),
+	Array(Vec<Value>),
+	Object(Vec<(String, Value)>),
+}
+
+/// The keys of one definition, for looking them up with errors that say which definition they're from
+struct Definition<'a> {
+	kind: &'static str,
+	name: &'a str,
+	entries: &'a [(String, Value)],
+}
+
+impl<'a> Definition<'a> {
+	fn new(kind: &'static str, name: &'a str, entries: &'a [(String, Value)]) -> Self {
+		Self { kind, name, entries }
+	}
+
+	fn get(&self, key: &str) -> Option<&'a Value> {
+		self.entries.iter().rev().find(|(k, _)| k == key).map(|(_, v)| v)
+	}
+
+	fn required(&self, key: &'static str) -> Result<(&'static str, &'a Value), CatalogError> {
+		self.get(key).map(|v| (key, v)).ok_or_else(|| CatalogError::MissingKey {
+			kind: self.kind,
+			name: self.name.to_string(),
+			key,
+		})
+	}
+
+	fn invalid(&self, key: &str, message: &'static str) -> CatalogError {
+		CatalogError::InvalidValue {
+			kind: self.kind,
+			name: self.name.to_string(),
+			key: key.to_string(),
+			message,
+		}
+	}
+
+	fn number(&self, key: &str, value: &Value) -> Result<f32, CatalogError> {
+		match *value {
+			Value::Number(n) => Ok(n),
+			_ => Err(self.invalid(key, "expected a number")),
+		}
+	}
+
+	fn string(&self, key: &str, value: &Value) -> Result<String, CatalogError> {
+		match value {
+			Value::String(s) => Ok(s.clone()),
+			_ => Err(self.invalid(key, "expected a string")),
+		}
+	}
+
+	fn numbers(&self, key: &str, value: &Value) -> Result<Vec<f32>, CatalogError> {
+		match value {
+			Value::Array(values) => values.iter().map(|v| self.number(key, v)).collect(),
+			_ => Err(self.invalid(key, "expected an array of numbers")),
+		}
+	}
+
+	fn check<T>(&self, key: &str, valid: bool, value: T, message: &'static str) -> Result<T, CatalogError> {
+		if valid {
+			Ok(value)
+		} else {
+			Err(self.invalid(key, message))
+		}
+	}
+}
+
+/// How deeply arrays and objects can be nested, so a catalog from someone else can't overflow the stack
+const MAX_NESTING: usize = 32;
+
+/// Reads the values TOML and JSON have in common (numbers, strings and arrays), and JSON objects
+struct Reader<'a> {
+	source: &'a str,
+	position: usize,
+	/// How many arrays and objects the reader is inside
+	depth: usize,
+}
+
+impl<'a> Reader<'a> {
+	fn new(source: &'a str) -> Self {
+		Self {
+			source,
+			position: 0,
+			depth: 0,
+		}
+	}
+
+	fn rest(&self) -> &'a str {
+		&self.source[self.position..]
+	}
+
+	fn skip_space(&mut self) {
+		let rest = self.rest();
+		self.position += rest.len() - rest.trim_start().len();
+	}
+
+	/// Whether there's nothing left on the line but a comment
+	fn at_end_of_line(&mut self) -> bool {
+		self.skip_space();
+		self.rest().is_empty() || self.rest().starts_with('#')
+	}
+
+	fn eat(&mut self, c: char) -> bool {
+		self.skip_space();
+		let found = self.rest().starts_with(c);
+		if found {
+			self.position += c.len_utf8();
+		}
+		found
+	}
+
+	/// A bare TOML key (letters, digits, - and _) or a quoted one
+	fn key(&mut self) -> Result<String, &'static str> {
+		self.skip_space();
+		if self.rest().starts_with('"') {
+			return self.string();
+		}
+		let length = self.rest().find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_')).unwrap_or(self.rest().len());
+		if length == 0 {
+			return Err("expected a name");
+		}
+		let key = &self.rest()[..length];
+		self.position += length;
+		Ok(key.to_string())
+	}
+
+	fn string(&mut self) -> Result<String, &'static str> {
+		if !self.eat('"') {
+			return Err("expected a string");
+		}
+		let mut string = String::new();
+		let mut chars = self.rest().char_indices();
+		while let Some((i, c)) = chars.next() {
+			match c {
+				'"' => {
+					self.position += i + 1;
+					return Ok(string);
+				},
+				'\\' => match chars.next() {
+					Some((_, '"')) => string.push('"'),
+					Some((_, '\\')) => string.push('\\'),
+					Some((_, '/')) => string.push('/'),
+					Some((_, 'n')) => string.push('\n'),
+					Some((_, 't')) => string.push('\t'),
+					_ => return Err("unsupported escape in a string"),
+				},
+				c => string.push(c),
+			}
+		}
+		Err("the string doesn't end")
+	}
+
+	fn value(&mut self, objects: bool) -> Result<Value, &'static str> {
+		self.skip_space();
+		match self.rest().chars().next() {
+			Some('"') => self.string().map(Value::String),
+			Some('[' | '{') if self.depth >= MAX_NESTING => Err("the arrays or objects are nested too deeply"),
+			Some('[') => {
+				self.eat('[');
+				self.depth += 1;
+				let mut values = Vec::new();
+				while !self.eat(']') {
+					values.push(self.value(objects)?);
+					if !self.eat(',') && !self.rest().starts_with(']') {
+						return Err("expected , or ] in an array");
+					}
+				}
+				self.depth -= 1;
+				Ok(Value::Array(values))
+			},
+			Some('{') if objects => {
+				self.eat('{');
+				self.depth += 1;
+				let mut entries = Vec::new();
+				while !self.eat('}') {
+					let key = self.string()?;
+					if !self.eat(':') {
+						return Err("expected : after a key");
+					}
+					entries.push((key, self.value(objects)?));
+					if !self.eat(',') && !self.rest().starts_with('}') {
+						return Err("expected , or } in an object");
+					}
+				}
+				self.depth -= 1;
+				Ok(Value::Object(entries))
+			},
+			Some(_) => {
+				let length = self.rest().find(|c: char| !(c.is_ascii_alphanumeric() || "+-._".contains(c))).unwrap_or(self.rest().len());
+				let number = self.rest()[..length].replace('_', "").parse::<f32>().ok().filter(|n| n.is_finite());
+				let number = number.ok_or("expected a number, string or array")?;
+				self.position += length;
+				Ok(Value::Number(number))
+			},
+			None => Err("expected a value"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn reads_toml_with_quoted_names_comments_and_escapes() {
+		let catalog = Catalog::parse_toml(
+			r#"
+# a comment on its own line
+[preset."my arch"] # and one after a table
+x = "x" # and one after a value
+"y" = "-y"
+z = "\"quoted\" \\ and\ttab"
+"#,
+		)
+		.unwrap();
+		let preset = catalog.preset("my arch").unwrap();
+		assert_eq!(preset.x_expression, "x");
+		assert_eq!(preset.y_expression, "-y");
+		assert_eq!(preset.z_expression, "\"quoted\" \\ and\ttab");
+	}
+
+	#[test]
+	fn reads_materials_and_curves() {
+		let catalog = Catalog::parse_toml(
+			"[material.petg]\nattenuation = 0.9\ncolor = [1.0, 0.98, 0.92]\n\n[curve.soft]\npoints = [[0, 20], [128, 140], [255, 255]]\n",
+		)
+		.unwrap();
+		assert_eq!(catalog.material("petg").unwrap().attenuation, 0.9);
+		assert_eq!(catalog.material("petg").unwrap().color, [1.0, 0.98, 0.92]);
+		assert_eq!(catalog.curve("soft").unwrap().points, vec![(0.0, 20.0), (128.0, 140.0), (255.0, 255.0)]);
+	}
+
+	#[test]
+	fn rejects_curves_that_dont_go_up() {
+		for points in ["[[0, 0], [128, 100], [128, 200]]", "[[0, 0], [200, 100], [100, 200]]", "[]"] {
+			let error = Catalog::parse_toml(&format!("[curve.bad]\npoints = {}", points)).unwrap_err();
+			assert!(matches!(error, CatalogError::InvalidValue { key, .. } if key == "points"), "{}", points);
+		}
+	}
+
+	#[test]
+	fn rejects_unknown_kinds() {
+		assert!(matches!(Catalog::parse_toml("[shape.arch]\nx = \"x\""), Err(CatalogError::UnknownKind(kind)) if kind == "shape"));
+		assert!(matches!(Catalog::parse_json(r#"{"shape": {"arch": {"x": "x"}}}"#), Err(CatalogError::UnknownKind(kind)) if kind == "shape"));
+	}
+
+	#[test]
+	fn reports_the_line_of_syntax_errors() {
+		assert!(matches!(
+			Catalog::parse_toml("[material.petg]\nattenuation 0.9"),
+			Err(CatalogError::Syntax { line: 2, .. })
+		));
+		assert!(matches!(
+			Catalog::parse_toml("attenuation = 0.9"),
+			Err(CatalogError::Syntax { line: 1, .. })
+		));
+		assert!(matches!(
+			Catalog::parse_json("{\n\"material\": {\n\"petg\": [}}"),
+			Err(CatalogError::Syntax { line: 3, .. })
+		));
+	}
+
+	#[test]
+	fn reads_nested_json() {
+		let catalog = Catalog::parse_json(
+			r#"{
+				"preset": {"flat-ish": {"x": "x", "y": "-y", "z": "0.1 * x"}},
+				"material": {"petg": {"attenuation": 0.9, "color": [1, 0.5, 0.25]}},
+				"curve": {"soft": {"points": [[0, 20], [255, 255]]}}
+			}"#,
+		)
+		.unwrap();
+		assert_eq!(catalog.preset("flat-ish").unwrap().z_expression, "0.1 * x");
+		assert_eq!(catalog.material("petg").unwrap().color, [1.0, 0.5, 0.25]);
+		assert_eq!(catalog.curve("soft").unwrap().points, vec![(0.0, 20.0), (255.0, 255.0)]);
+		assert!(Catalog::parse_json(r#"{"material": ["petg"]}"#).is_err());
+		assert!(Catalog::parse_json(r#"{"material": {}} extra"#).is_err());
+	}
+
+	#[test]
+	fn limits_how_deeply_json_is_nested() {
+		let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+		assert!(Reader::new(&nested(MAX_NESTING)).value(true).is_ok());
+		assert!(Reader::new(&nested(MAX_NESTING + 1)).value(true).is_err());
+		assert!(matches!(Catalog::parse_json(&"[".repeat(100_000)), Err(CatalogError::Syntax { .. })));
+	}
+}
@@ -0,0 +1,98 @@
+use pk_stl::{geometry::Vec3, StlModel};
+
+use crate::mesh::layer_cross_sections;
+
+/// The size of the space a printer can print in, in millimeters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BuildVolume {
+	/// Along x
+	pub width: f32,
+	/// Along y
+	pub depth: f32,
+	pub height: f32,
+}
+
+impl BuildVolume {
+	/// Whether a model with this bounding box size fits, either as it is or turned 90° on the bed
+	pub fn fits(&self, size: Vec3) -> bool {
+		self.scale_to_fit(size) >= 1.0
+	}
+
+	/// The largest factor a model with this bounding box size can be scaled by and still fit, either as it is or turned 90° on the bed
+	pub fn scale_to_fit(&self, size: Vec3) -> f32 {
+		let z = self.height / size.z;
+		let straight = (self.width / size.x).min(self.depth / size.y);
+		let turned = (self.width / size.y).min(self.depth / size.x);
+		straight.max(turned).min(z)
+	}
+}
+
+/// Common printers, to check models against their build volumes without looking them up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Printer {
+	PrusaMk4,
+	PrusaMini,
+	BambuLabX1,
+	BambuLabA1Mini,
+	Ender3,
+	Voron350,
+}
+
+impl Printer {
+	pub fn build_volume(self) -> BuildVolume {
+		let [width, depth, height] = match self {
+			Printer::PrusaMk4 => [250.0, 210.0, 220.0],
+			Printer::PrusaMini => [180.0, 180.0, 180.0],
+			Printer::BambuLabX1 => [256.0, 256.0, 256.0],
+			Printer::BambuLabA1Mini => [180.0, 180.0, 180.0],
+			Printer::Ender3 => [220.0, 220.0, 250.0],
+			Printer::Voron350 => [350.0, 350.0, 340.0],
+		};
+		BuildVolume { width, depth, height }
+	}
+}
+
+/// Slicer settings for `estimate_print_time`, in millimeters and millimeters per second
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrintSettings {
+	pub layer_height: f32,
+	/// The width of the extruded lines
+	pub line_width: f32,
+	/// How many lines go around the outside of each island (and each hole) in a layer
+	pub perimeters: u32,
+	pub perimeter_speed: f32,
+	/// The speed of the infill inside the perimeters, which is solid since lithophanes are printed with 100% infill
+	pub infill_speed: f32,
+	/// The time in seconds each layer takes on top of printing it, for layer changes and travel moves
+	pub layer_time: f32,
+}
+
+impl Default for PrintSettings {
+	fn default() -> Self {
+		Self {
+			layer_height: 0.2,
+			line_width: 0.45,
+			perimeters: 2,
+			perimeter_speed: 40.0,
+			infill_speed: 60.0,
+			layer_time: 1.0,
+		}
+	}
+}
+
+/// Roughly estimate how many seconds a closed model takes to print, from the area and perimeter of its cross section in each layer (see
+/// `mesh::layer_cross_sections`), without slicing it
+///
+/// This doesn't account for acceleration, minimum layer times or supports, so it's meant for comparing models (eg to quote them) rather
+/// than predicting the exact time a slicer would give.
+pub fn estimate_print_time(model: &StlModel, settings: &PrintSettings) -> f32 {
+	let line_width = settings.line_width.max(f32::EPSILON);
+	layer_cross_sections(model, settings.layer_height)
+		.into_iter()
+		.map(|(area, perimeter)| {
+			let perimeter_length = perimeter * settings.perimeters as f32;
+			let infill_length = (area - perimeter_length * line_width).max(0.0) / line_width;
+			perimeter_length / settings.perimeter_speed + infill_length / settings.infill_speed + settings.layer_time
+		})
+		.sum()
+}
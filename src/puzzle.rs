@@ -0,0 +1,149 @@
+use std::f32::consts::TAU;
+
+use crate::{lithophane::random, outline::Shape};
+
+/// Cutting a lithophane into a grid of interlocking jigsaw pieces, eg to print one bigger than the printer's bed, with seams that are
+/// less noticeable than straight cuts when it's lit
+///
+/// Each piece is the whole lithophane with its outline replaced by the piece's shape, so walls are generated along the cuts like along
+/// any other outline, and every piece is a closed solid.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Puzzle {
+	pub columns: u32,
+	pub rows: u32,
+	/// Which way each tab points and how far it's moved from the middle of its edge are random, but always the same for the same seed
+	pub seed: u32,
+	/// The width of the round head of each tab, as a fraction of the shorter side of a piece (up to about 0.3, so tabs on opposite
+	/// edges don't run into each other)
+	pub tab_size: f32,
+	/// The gap between neighbouring pieces in pixels, so they can be pushed together after printing
+	pub clearance: f32,
+	/// Added to the lithophane's thickness offset for each piece, row by row, eg from `backlight::brightness_compensation` when each
+	/// piece is lit by its own LED panel. Pieces past the end of the list don't get anything added.
+	pub thickness_offsets: Vec<f32>,
+}
+
+impl Default for Puzzle {
+	fn default() -> Self {
+		Self {
+			columns: 2,
+			rows: 2,
+			seed: 0,
+			tab_size: 0.2,
+			clearance: 1.0,
+			thickness_offsets: Vec::new(),
+		}
+	}
+}
+
+/// The number of straight segments each tab's round head is made of
+const TAB_HEAD_SEGMENTS: u32 = 16;
+
+impl Puzzle {
+	/// The shapes of the pieces in image coordinates, row by row from the top left
+	pub fn pieces(&self, image_width: u32, image_height: u32) -> Vec<Shape> {
+		let (columns, rows) = (self.columns.max(1), self.rows.max(1));
+		let (width, height) = ((image_width - 1) as f32, (image_height - 1) as f32);
+		let tab_size = (width / columns as f32).min(height / rows as f32) * self.tab_size;
+
+		// The outside edges are moved out past the image, so only the cuts between pieces are inset by the clearance
+		let margin = 1.0 + self.clearance;
+		let column_x = |column: u32| match column {
+			0 => -margin,
+			c if c == columns => width + margin,
+			c => c as f32 * width / columns as f32,
+		};
+		let row_y = |row: u32| match row {
+			0 => -margin,
+			r if r == rows => height + margin,
+			r => r as f32 * height / rows as f32,
+		};
+
+		// Each cut is generated once from left to right or top to bottom, so the pieces on either side of it get exactly the same points
+		let horizontal_cut = |column: u32, row: u32| {
+			tab_edge(
+				(column_x(column), row_y(row)),
+				(column_x(column + 1), row_y(row)),
+				tab_size,
+				self.seed,
+				[column, row * 2],
+			)
+		};
+		let vertical_cut = |column: u32, row: u32| {
+			tab_edge(
+				(column_x(column), row_y(row)),
+				(column_x(column), row_y(row + 1)),
+				tab_size,
+				self.seed,
+				[column, row * 2 + 1],
+			)
+		};
+
+		let mut pieces = Vec::with_capacity((columns * rows) as usize);
+		for row in 0..rows {
+			for column in 0..columns {
+				let (left, right, top, bottom) = (column_x(column), column_x(column + 1), row_y(row), row_y(row + 1));
+				let mut points = Vec::new();
+				// Top edge going right, right edge going down, bottom edge going left, then left edge going up
+				match row {
+					0 => points.push((left, top)),
+					_ => points.extend(horizontal_cut(column, row)),
+				}
+				match column + 1 {
+					c if c == columns => points.push((right, top)),
+					_ => points.extend(vertical_cut(column + 1, row)),
+				}
+				match row + 1 {
+					r if r == rows => points.push((right, bottom)),
+					_ => points.extend(reversed(horizontal_cut(column, row + 1), (right, bottom))),
+				}
+				match column {
+					0 => points.push((left, bottom)),
+					_ => points.extend(reversed(vertical_cut(column, row), (left, bottom))),
+				}
+
+				pieces.push(Shape::Inset {
+					shape: Box::new(Shape::Polygon(points)),
+					distance: self.clearance / 2.0,
+				});
+			}
+		}
+		pieces
+	}
+}
+
+/// The points along a cut from `a` towards `b` (not including `b`), with a tab sticking out to one side somewhere near the middle
+///
+/// The tab is a round head `tab_size` wide on a narrower neck, so the pieces can't be pulled apart once they're pushed together.
+fn tab_edge(a: (f32, f32), b: (f32, f32), tab_size: f32, seed: u32, [column, row]: [u32; 2]) -> Vec<(f32, f32)> {
+	let length = ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt();
+	let along = ((b.0 - a.0) / length, (b.1 - a.1) / length);
+	let side = if random(column, row, seed) < 0.5 { 1.0 } else { -1.0 };
+	let across = (-along.1 * side, along.0 * side);
+	let point = |u: f32, v: f32| (a.0 + along.0 * u + across.0 * v, a.1 + along.1 * u + across.1 * v);
+
+	let radius = tab_size / 2.0;
+	let neck = radius * 0.6;
+	let neck_height = radius * 0.4;
+	let center = length / 2.0 + (random(column, row, seed.wrapping_add(1)) - 0.5) * length * 0.2;
+	let head_center = neck_height + (radius * radius - neck * neck).sqrt();
+
+	let mut points = vec![a, point(center - neck, 0.0)];
+	// Go around the head from where it meets the left of the neck, over the far side, to where it meets the right of the neck
+	let start = (neck_height - head_center).atan2(-neck);
+	let end = (neck_height - head_center).atan2(neck);
+	let sweep = TAU - (end - start);
+	for i in 0..=TAB_HEAD_SEGMENTS {
+		let angle = start - sweep * i as f32 / TAB_HEAD_SEGMENTS as f32;
+		points.push(point(center + radius * angle.cos(), head_center + radius * angle.sin()));
+	}
+	points.push(point(center + neck, 0.0));
+	points
+}
+
+/// The same cut going the other way, from `b` towards `a` (not including `a`)
+fn reversed(mut points: Vec<(f32, f32)>, b: (f32, f32)) -> Vec<(f32, f32)> {
+	points[0] = b;
+	points[1..].reverse();
+	points
+}
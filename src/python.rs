@@ -0,0 +1,131 @@
+// The impls pyo3 0.20's macros generate are inside functions, which newer compilers warn about
+#![allow(non_local_definitions)]
+
+use image::GrayImage;
+use pyo3::{exceptions::PyValueError, prelude::*};
+
+use crate::{
+	expression::{self, AngleUnit},
+	lithophane::{self, LithophaneOptions},
+	mesh::IndexedMesh,
+};
+
+/// The settings of `LithophaneOptions` that have a simple Python form, given as keyword arguments that default to the same values
+#[pyclass(name = "LithophaneOptions")]
+#[derive(Debug, Clone)]
+pub struct PyLithophaneOptions {
+	#[pyo3(get, set)]
+	pub white_depth: f32,
+	#[pyo3(get, set)]
+	pub black_depth: f32,
+	#[pyo3(get, set)]
+	pub subdivisions: u32,
+	#[pyo3(get, set)]
+	pub sample_step: u32,
+	#[pyo3(get, set)]
+	pub backing_margin: f32,
+	#[pyo3(get, set)]
+	pub wall_draft: f32,
+	#[pyo3(get, set)]
+	pub max_slope: Option<f32>,
+	#[pyo3(get, set)]
+	pub layer_height: Option<f32>,
+	#[pyo3(get, set)]
+	pub thickness_offset: f32,
+}
+
+#[pymethods]
+impl PyLithophaneOptions {
+	#[new]
+	#[pyo3(signature = (
+		white_depth = None, black_depth = None, subdivisions = None, sample_step = None, backing_margin = None, wall_draft = None,
+		max_slope = None, layer_height = None, thickness_offset = None,
+	))]
+	#[allow(clippy::too_many_arguments)]
+	fn new(
+		white_depth: Option<f32>,
+		black_depth: Option<f32>,
+		subdivisions: Option<u32>,
+		sample_step: Option<u32>,
+		backing_margin: Option<f32>,
+		wall_draft: Option<f32>,
+		max_slope: Option<f32>,
+		layer_height: Option<f32>,
+		thickness_offset: Option<f32>,
+	) -> Self {
+		let defaults = LithophaneOptions::default();
+		Self {
+			white_depth: white_depth.unwrap_or(defaults.white_depth),
+			black_depth: black_depth.unwrap_or(defaults.black_depth),
+			subdivisions: subdivisions.unwrap_or(defaults.subdivisions),
+			sample_step: sample_step.unwrap_or(defaults.sample_step),
+			backing_margin: backing_margin.unwrap_or(defaults.backing_margin),
+			wall_draft: wall_draft.unwrap_or(defaults.wall_draft),
+			max_slope: max_slope.or(defaults.max_slope),
+			layer_height: layer_height.or(defaults.layer_height),
+			thickness_offset: thickness_offset.unwrap_or(defaults.thickness_offset),
+		}
+	}
+
+	fn __repr__(&self) -> String {
+		format!("{:?}", self).replacen("PyLithophaneOptions", "LithophaneOptions", 1)
+	}
+}
+
+impl From<&PyLithophaneOptions> for LithophaneOptions {
+	fn from(options: &PyLithophaneOptions) -> Self {
+		Self {
+			white_depth: options.white_depth,
+			black_depth: options.black_depth,
+			subdivisions: options.subdivisions,
+			sample_step: options.sample_step,
+			backing_margin: options.backing_margin,
+			wall_draft: options.wall_draft,
+			max_slope: options.max_slope,
+			layer_height: options.layer_height,
+			thickness_offset: options.thickness_offset,
+			..Default::default()
+		}
+	}
+}
+
+/// The vertices of a mesh, and its triangles as indices into them
+type Mesh = (Vec<[f32; 3]>, Vec<[usize; 3]>);
+
+/// Generate a lithophane from a grayscale image given as rows of gray values (eg a 2D uint8 numpy array), returning the vertices and
+/// the triangles as indices into them, which is what trimesh and numpy-stl take
+///
+/// The expressions are compiled and the lithophane is generated without holding the GIL, so other Python threads keep running.
+#[pyfunction]
+#[pyo3(signature = (x_expression, y_expression, z_expression, image, options = None))]
+fn generate_lithophane(
+	py: Python,
+	x_expression: &str,
+	y_expression: &str,
+	z_expression: &str,
+	image: Vec<Vec<u8>>,
+	options: Option<PyLithophaneOptions>,
+) -> PyResult<Mesh> {
+	let width = image.first().map_or(0, Vec::len);
+	let same_length = image.iter().all(|row| row.len() == width);
+	let image = GrayImage::from_raw(width as u32, image.len() as u32, image.concat())
+		.filter(|_| same_length)
+		.ok_or_else(|| PyValueError::new_err("the rows of the image have to be the same length"))?;
+	let options = options.as_ref().map_or_else(LithophaneOptions::default, LithophaneOptions::from);
+
+	// The compiled expressions aren't Send, so they're compiled on the thread that uses them
+	let model = py.allow_threads(|| {
+		let sources = [x_expression, y_expression, z_expression];
+		let [x_fn, y_fn, z_fn] = expression::compile_expressions(sources, AngleUnit::Radians, None).map_err(|e| e.to_string())?.map(|e| e.bind());
+		lithophane::generate_lithophane(x_fn, y_fn, z_fn, image, &options).map_err(|e| e.to_string())
+	});
+	let mesh = IndexedMesh::from_model(&model.map_err(PyValueError::new_err)?, 0.0);
+	Ok((mesh.vertices.into_iter().map(|v| v.as_arr()).collect(), mesh.faces))
+}
+
+#[pymodule]
+fn lithophane_generator(_py: Python, module: &PyModule) -> PyResult<()> {
+	module.add_class::<PyLithophaneOptions>()?;
+	module.add_function(wrap_pyfunction!(generate_lithophane, module)?)?;
+	Ok(())
+}
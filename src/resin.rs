@@ -0,0 +1,128 @@
+use image::{GrayImage, Luma};
+use thiserror::Error;
+
+use crate::lithophane::{pixel_depth, LithophaneOptions};
+
+/// How a flat lithophane is sliced into layer masks for an mSLA resin printer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResinLayers {
+	/// The thickness of each layer, in millimeters
+	pub layer_height: f32,
+	/// The size of one pixel of the printer's screen, in millimeters
+	pub pixel_size: f32,
+}
+
+impl Default for ResinLayers {
+	/// Typical for a 4K screen on a small printer
+	fn default() -> Self {
+		Self {
+			layer_height: 0.05,
+			pixel_size: 0.05,
+		}
+	}
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ResinError {
+	#[error("the pixel size has to be a positive number of millimeters, not {0}")]
+	InvalidPixelSize(f32),
+	#[error("the layer height has to be a positive number of millimeters, not {0}")]
+	InvalidLayerHeight(f32),
+	#[error("the lithophane would be {width} × {height} pixels, which is too many")]
+	TooBig { width: f32, height: f32 },
+}
+
+/// The most pixels a `ThicknessMap` can have, which is about 400 MB of thicknesses
+const MAX_MAP_PIXELS: f32 = 100_000_000.0;
+
+/// How thick a flat lithophane is at each pixel of a resin printer's screen
+///
+/// Resin printers cure a whole layer at once from a mask image, so a flat lithophane can go straight from its thicknesses to masks without
+/// making a mesh and slicing it. The thickness is interpolated between image pixels at the screen's resolution, so gradients come out as
+/// smooth as the layers allow instead of following the facets of a mesh.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThicknessMap {
+	pub width: u32,
+	pub height: u32,
+	/// The thickness at each screen pixel in millimeters, row by row
+	pub thickness: Vec<f32>,
+}
+
+impl ThicknessMap {
+	/// Work out the thicknesses for a lithophane `size` millimeters wide and tall, which the image is stretched over from the middle of its
+	/// first pixel to the middle of its last (like the mesh's points are), at `pixel_size` millimeters per pixel of the map
+	///
+	/// This includes contours and the thickness offset. The frame, skirt, depth mask, label and back texture aren't included, only the image
+	/// itself.
+	pub fn new(image: &GrayImage, options: &LithophaneOptions, (width, height): (f32, f32), pixel_size: f32) -> Result<Self, ResinError> {
+		if !(pixel_size > 0.0 && pixel_size.is_finite()) {
+			return Err(ResinError::InvalidPixelSize(pixel_size));
+		}
+		let (pixels_x, pixels_y) = ((width / pixel_size).ceil().max(1.0), (height / pixel_size).ceil().max(1.0));
+		let pixels = pixels_x * pixels_y;
+		if pixels.is_nan() || pixels > MAX_MAP_PIXELS {
+			return Err(ResinError::TooBig {
+				width: pixels_x,
+				height: pixels_y,
+			});
+		}
+
+		// There are only 256 gray values, so work out the thickness for each once
+		let thicknesses: Vec<f32> = (0..=u8::MAX)
+			.map(|gray_value| {
+				let depth = pixel_depth(gray_value, options.white_depth, options.black_depth);
+				options.contours.map_or(depth, |c| c.apply(depth, options.white_depth, options.black_depth)) + options.thickness_offset
+			})
+			.collect();
+		let thickness_at = |x: i64, y: i64| {
+			let x = x.clamp(0, image.width() as i64 - 1) as u32;
+			let y = y.clamp(0, image.height() as i64 - 1) as u32;
+			thicknesses[image.get_pixel(x, y).0[0] as usize]
+		};
+
+		let (map_width, map_height) = (pixels_x as u32, pixels_y as u32);
+		// Image pixels per millimeter, going through the middles of the screen's pixels
+		let scale_x = if width > 0.0 { (image.width() - 1) as f32 / width } else { 0.0 };
+		let scale_y = if height > 0.0 { (image.height() - 1) as f32 / height } else { 0.0 };
+
+		let mut thickness = Vec::with_capacity((map_width * map_height) as usize);
+		for screen_y in 0..map_height {
+			let y = ((screen_y as f32 + 0.5) * pixel_size * scale_y).min((image.height() - 1) as f32);
+			for screen_x in 0..map_width {
+				let x = ((screen_x as f32 + 0.5) * pixel_size * scale_x).min((image.width() - 1) as f32);
+				let (x0, y0) = (x.floor(), y.floor());
+				let (tx, ty) = (x - x0, y - y0);
+				let (x0, y0) = (x0 as i64, y0 as i64);
+				let top = thickness_at(x0, y0) * (1.0 - tx) + thickness_at(x0 + 1, y0) * tx;
+				let bottom = thickness_at(x0, y0 + 1) * (1.0 - tx) + thickness_at(x0 + 1, y0 + 1) * tx;
+				thickness.push(top * (1.0 - ty) + bottom * ty);
+			}
+		}
+
+		Ok(Self {
+			width: map_width,
+			height: map_height,
+			thickness,
+		})
+	}
+
+	/// How many layers the thickest point needs
+	pub fn layer_count(&self, layer_height: f32) -> Result<usize, ResinError> {
+		if !(layer_height > 0.0 && layer_height.is_finite()) {
+			return Err(ResinError::InvalidLayerHeight(layer_height));
+		}
+		let thickest = self.thickness.iter().copied().fold(0.0, f32::max);
+		Ok((thickest / layer_height).round() as usize)
+	}
+
+	/// The mask for layer `layer` (counting from 0 at the build plate), white where resin is cured and black elsewhere
+	///
+	/// A pixel is cured in every layer whose middle is below its thickness, which rounds each thickness to the nearest layer.
+	pub fn layer_mask(&self, layer: usize, layer_height: f32) -> GrayImage {
+		let middle = (layer as f32 + 0.5) * layer_height;
+		GrayImage::from_fn(self.width, self.height, |x, y| {
+			let thickness = self.thickness[(y * self.width + x) as usize];
+			Luma([if thickness > middle { 255 } else { 0 }])
+		})
+	}
+}
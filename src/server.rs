@@ -0,0 +1,252 @@
+use std::{
+	io::{self, BufRead, BufReader, Read, Write},
+	net::{TcpListener, TcpStream},
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc,
+	},
+	thread,
+	time::Duration,
+};
+
+use crate::{
+	expression::AngleUnit,
+	jobs::{JobQueue, JobRequest, JobStatus, SubmitError},
+	manifest::json_string,
+};
+
+/// The largest request body (the image) the server accepts, in bytes
+pub const MAX_BODY_SIZE: usize = 64 * 1024 * 1024;
+
+/// The longest a request line or header can be, in bytes
+const MAX_LINE_LENGTH: u64 = 16 * 1024;
+
+/// Limits that keep slow or numerous clients from tying up the server
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ServerLimits {
+	/// The most connections handled at once, with any more turned away with 503 Service Unavailable
+	pub max_connections: usize,
+	/// How long a client can go without sending or receiving anything before its connection is closed
+	pub timeout: Duration,
+}
+
+impl Default for ServerLimits {
+	fn default() -> Self {
+		Self {
+			max_connections: 64,
+			timeout: Duration::from_secs(30),
+		}
+	}
+}
+
+/// Serve a small HTTP API for generating lithophanes on `listener` (eg bound to "0.0.0.0:8080"), with the work done by `queue`, forever
+///
+/// Errors accepting connections (eg running out of file descriptors) are passed to `on_error` and the server carries on, since they're
+/// usually temporary.
+///
+/// - `POST /jobs?x=...&y=...&z=...` with the image file as the body starts a job, answering `{"id": 1}`. The other settings that can go in
+///   the query are `white_depth`, `black_depth`, `frame`, `degrees=1` for trig functions in degrees and `compress=1` to gzip the STL.
+///   When the queue is full (see `JobLimits::max_queued`) it answers 503 Service Unavailable instead.
+/// - `GET /jobs/ID` answers with the job's status, eg `{"status": "running", "progress": 0.25}`, `{"status": "queued", "position": 2}`,
+///   `{"status": "done"}` or `{"status": "failed", "error": "..."}`.
+/// - `GET /jobs/ID/result` downloads the finished STL.
+/// - `GET /jobs/ID/manifest` answers with the finished job's manifest (see `Manifest::to_json`).
+/// - `DELETE /jobs/ID` cancels the job and forgets it and its result.
+///
+/// Each connection gets its own thread and handles one request, and there's no authentication or TLS, so this is meant for a trusted
+/// network (eg a print farm's LAN) rather than the internet. Expressions are still checked against the queue's `Sandbox`.
+pub fn serve(listener: TcpListener, queue: JobQueue, limits: ServerLimits, on_error: impl Fn(io::Error)) -> ! {
+	let connections = Arc::new(AtomicUsize::new(0));
+	loop {
+		let mut stream = match listener.accept() {
+			Ok((stream, _)) => stream,
+			Err(e) => {
+				on_error(e);
+				// Errors like running out of file descriptors come back straight away, so wait a little for some to be freed
+				thread::sleep(Duration::from_millis(100));
+				continue;
+			},
+		};
+		// Errors writing a response are ignored throughout, since there's no one to tell when a client has gone away
+		let _ = stream.set_read_timeout(Some(limits.timeout)).and_then(|_| stream.set_write_timeout(Some(limits.timeout)));
+		if connections.fetch_add(1, Ordering::SeqCst) >= limits.max_connections {
+			connections.fetch_sub(1, Ordering::SeqCst);
+			let _ = write_response(&mut stream, error("503 Service Unavailable", "the server is busy, try again later"));
+			continue;
+		}
+
+		let (queue, connections) = (queue.clone(), connections.clone());
+		thread::spawn(move || {
+			let _ = handle_connection(stream, &queue);
+			connections.fetch_sub(1, Ordering::SeqCst);
+		});
+	}
+}
+
+/// An HTTP response: the status code and reason, the content type and the body
+type Response = (&'static str, &'static str, Vec<u8>);
+
+fn json(status: &'static str, body: String) -> Response {
+	(status, "application/json", body.into_bytes())
+}
+
+fn error(status: &'static str, message: &str) -> Response {
+	json(status, format!("{{\"error\": {}}}", json_string(message)))
+}
+
+fn handle_connection(mut stream: TcpStream, queue: &JobQueue) -> io::Result<()> {
+	let mut reader = BufReader::new(stream.try_clone()?);
+	let response = match read_request(&mut reader) {
+		Ok((method, target, body)) => respond(queue, &method, &target, body),
+		Err(response) => response,
+	};
+	write_response(&mut stream, response)
+}
+
+fn write_response(stream: &mut TcpStream, (status, content_type, body): Response) -> io::Result<()> {
+	write!(
+		stream,
+		"HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+		status,
+		content_type,
+		body.len()
+	)?;
+	stream.write_all(&body)?;
+	stream.flush()
+}
+
+/// Read the method, target and body of a request, or the response to send if it's not one the server can handle
+fn read_request(reader: &mut impl BufRead) -> Result<(String, String, Vec<u8>), Response> {
+	let bad_request = |message: &str| error("400 Bad Request", message);
+	let read_line = |reader: &mut dyn BufRead| {
+		let mut line = String::new();
+		match reader.take(MAX_LINE_LENGTH).read_line(&mut line) {
+			Ok(_) if line.ends_with('\n') => Ok(line.trim_end().to_string()),
+			_ => Err(bad_request("the request is cut off or its lines are too long")),
+		}
+	};
+
+	let request_line = read_line(reader)?;
+	let mut parts = request_line.split(' ');
+	let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+		return Err(bad_request("the request line is invalid"));
+	};
+
+	let mut content_length = 0;
+	loop {
+		let header = read_line(reader)?;
+		if header.is_empty() {
+			break;
+		}
+		if let Some((name, value)) = header.split_once(':') {
+			if name.trim().eq_ignore_ascii_case("content-length") {
+				content_length = value.trim().parse().map_err(|_| bad_request("the Content-Length is invalid"))?;
+			} else if name.trim().eq_ignore_ascii_case("transfer-encoding") {
+				return Err(error("411 Length Required", "the body needs a Content-Length"));
+			}
+		}
+	}
+	if content_length > MAX_BODY_SIZE {
+		return Err(error("413 Payload Too Large", "the image is too big"));
+	}
+
+	// The body grows as it arrives rather than being allocated up front, so a Content-Length alone can't take up any memory
+	let mut body = Vec::new();
+	match reader.take(content_length as u64).read_to_end(&mut body) {
+		Ok(length) if length == content_length => {},
+		_ => return Err(bad_request("the body is shorter than its Content-Length")),
+	}
+	Ok((method.to_string(), target.to_string(), body))
+}
+
+fn respond(queue: &JobQueue, method: &str, target: &str, body: Vec<u8>) -> Response {
+	let (path, query) = target.split_once('?').unwrap_or((target, ""));
+	let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+	let job = |id: &str| id.parse::<u64>().ok().filter(|&id| queue.status(id).is_some());
+
+	match (method, &segments[..]) {
+		("POST", ["jobs"]) => match job_request(query, body) {
+			Ok(request) => match queue.submit(request) {
+				Ok(id) => json("202 Accepted", format!("{{\"id\": {}}}", id)),
+				Err(e @ SubmitError::QueueFull(_)) => error("503 Service Unavailable", &format!("{}, try again later", e)),
+				Err(e @ SubmitError::Store(_)) => error("500 Internal Server Error", &e.to_string()),
+			},
+			Err(message) => error("400 Bad Request", &message),
+		},
+		("GET", ["jobs", id]) => match job(id).and_then(|id| queue.status(id)) {
+			Some(JobStatus::Queued { position }) => json("200 OK", format!("{{\"status\": \"queued\", \"position\": {}}}", position)),
+			Some(JobStatus::Running { progress }) => json("200 OK", format!("{{\"status\": \"running\", \"progress\": {:.3}}}", progress)),
+			Some(JobStatus::Done) => json("200 OK", "{\"status\": \"done\"}".to_string()),
+			Some(JobStatus::Failed(e)) => json("200 OK", format!("{{\"status\": \"failed\", \"error\": {}}}", json_string(&e))),
+			None => error("404 Not Found", "there's no job with that id"),
+		},
+		("GET", ["jobs", id, "result"]) => match job(id) {
+			Some(id) => match queue.result(id) {
+				Some(stl) => ("200 OK", "model/stl", stl.to_vec()),
+				None => error("409 Conflict", "the job hasn't finished"),
+			},
+			None => error("404 Not Found", "there's no job with that id"),
+		},
+		("GET", ["jobs", id, "manifest"]) => match job(id) {
+			Some(id) => match queue.manifest(id) {
+				Some(manifest) => ("200 OK", "application/json", manifest.as_bytes().to_vec()),
+				None => error("409 Conflict", "the job hasn't finished"),
+			},
+			None => error("404 Not Found", "there's no job with that id"),
+		},
+		("DELETE", ["jobs", id]) => match job(id) {
+			Some(id) => {
+				queue.remove(id);
+				json("200 OK", "{}".to_string())
+			},
+			None => error("404 Not Found", "there's no job with that id"),
+		},
+		(_, ["jobs"] | ["jobs", _] | ["jobs", _, "result" | "manifest"]) => error("405 Method Not Allowed", "that method isn't supported there"),
+		_ => error("404 Not Found", "there's nothing there"),
+	}
+}
+
+/// Make a job from the settings in a query string and the image in the body
+fn job_request(query: &str, image: Vec<u8>) -> Result<JobRequest, String> {
+	let mut request = JobRequest { image, ..Default::default() };
+	for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+		let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+		let value = percent_decode(value).ok_or_else(|| format!("the value of \"{}\" isn't encoded properly", key))?;
+		let number = |value: &str| value.parse::<f32>().ok().filter(|v| v.is_finite()).ok_or_else(|| format!("\"{}\" has to be a number", key));
+		match key {
+			"x" => request.x_expression = value,
+			"y" => request.y_expression = value,
+			"z" => request.z_expression = value,
+			"white_depth" => request.white_depth = number(&value)?,
+			"black_depth" => request.black_depth = number(&value)?,
+			"frame" => request.frame = value.parse().map_err(|_| "\"frame\" has to be a whole number".to_string())?,
+			"degrees" => request.angle_unit = if value == "1" { AngleUnit::Degrees } else { AngleUnit::Radians },
+			"compress" => request.compress = value == "1",
+			_ => return Err(format!("unknown setting \"{}\"", key)),
+		}
+	}
+	if request.image.is_empty() {
+		return Err("the body has to be the image".to_string());
+	}
+	if [&request.x_expression, &request.y_expression, &request.z_expression].iter().any(|e| e.is_empty()) {
+		return Err("x, y and z expressions are needed".to_string());
+	}
+	Ok(request)
+}
+
+/// Decode a value from a query string, where `+` is a space and `%XX` is a byte in hex
+fn percent_decode(value: &str) -> Option<String> {
+	let mut bytes = Vec::with_capacity(value.len());
+	let mut input = value.bytes();
+	while let Some(b) = input.next() {
+		bytes.push(match b {
+			b'+' => b' ',
+			b'%' => {
+				let hex = [input.next()?, input.next()?];
+				u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?
+			},
+			b => b,
+		});
+	}
+	String::from_utf8(bytes).ok()
+}
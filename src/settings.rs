@@ -0,0 +1,205 @@
+use std::fmt::Write;
+
+use thiserror::Error;
+
+use crate::{
+	lithophane::LithophaneOptions,
+	manifest::json_string,
+	presets::{parse_json_value, CatalogError, Value},
+};
+
+/// Everything the user has set up for a lithophane, saved as JSON so a project can be shared as a link or autosaved, and loaded again
+///
+/// The names of the preset, curve and material refer to the catalog (see `presets::Catalog`), so a project only loads the same way where
+/// the same definitions exist.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+	/// The preset the expressions came from, if any
+	pub preset: Option<String>,
+	pub x_expression: String,
+	pub y_expression: String,
+	pub z_expression: String,
+	pub white_depth: f32,
+	pub black_depth: f32,
+	/// The frame of an animated image
+	pub frame: usize,
+	/// The step of the preview in pixels
+	pub step: u32,
+	/// The tone curve the image is mapped through, if any
+	pub curve: Option<String>,
+	pub material: Option<String>,
+	/// The settings of the generators that don't use expressions, eg `pixel_size` or `hole_diameter`, in the order they were given
+	pub shape: Vec<(String, f32)>,
+}
+
+impl Default for Settings {
+	fn default() -> Self {
+		let LithophaneOptions {
+			white_depth, black_depth, ..
+		} = LithophaneOptions::default();
+		Self {
+			preset: None,
+			x_expression: "x".to_string(),
+			y_expression: "-y".to_string(),
+			z_expression: "0".to_string(),
+			white_depth,
+			black_depth,
+			frame: 0,
+			step: 1,
+			curve: None,
+			material: None,
+			shape: Vec::new(),
+		}
+	}
+}
+
+#[derive(Error, Debug)]
+pub enum SettingsError {
+	#[error(transparent)]
+	Syntax(#[from] CatalogError),
+	#[error("expected an object of settings")]
+	NotObject,
+	#[error("the setting \"{key}\" is invalid: {message}")]
+	InvalidValue { key: String, message: &'static str },
+	#[error("the setting \"{0}\" has to be a finite number to be saved")]
+	NotFinite(String),
+}
+
+impl Settings {
+	/// The settings as a JSON object, along with the version of this crate, leaving out the preset, curve and material if they aren't set
+	pub fn to_json(&self) -> Result<String, SettingsError> {
+		let numbers = [("white_depth", self.white_depth), ("black_depth", self.black_depth)].into_iter();
+		let shape = self.shape.iter().map(|(name, value)| (name.as_str(), *value));
+		if let Some((name, _)) = numbers.chain(shape).find(|(_, value)| !value.is_finite()) {
+			return Err(SettingsError::NotFinite(name.to_string()));
+		}
+
+		let mut json = String::new();
+		json.push_str("{\n");
+		writeln!(json, "\t\"version\": {},", json_string(env!("CARGO_PKG_VERSION"))).unwrap();
+		if let Some(preset) = &self.preset {
+			writeln!(json, "\t\"preset\": {},", json_string(preset)).unwrap();
+		}
+		writeln!(json, "\t\"x\": {},", json_string(&self.x_expression)).unwrap();
+		writeln!(json, "\t\"y\": {},", json_string(&self.y_expression)).unwrap();
+		writeln!(json, "\t\"z\": {},", json_string(&self.z_expression)).unwrap();
+		writeln!(json, "\t\"white_depth\": {},", self.white_depth).unwrap();
+		writeln!(json, "\t\"black_depth\": {},", self.black_depth).unwrap();
+		writeln!(json, "\t\"frame\": {},", self.frame).unwrap();
+		writeln!(json, "\t\"step\": {},", self.step).unwrap();
+		if let Some(curve) = &self.curve {
+			writeln!(json, "\t\"curve\": {},", json_string(curve)).unwrap();
+		}
+		if let Some(material) = &self.material {
+			writeln!(json, "\t\"material\": {},", json_string(material)).unwrap();
+		}
+		let shape = self.shape.iter().map(|(name, value)| format!("{}: {}", json_string(name), value)).collect::<Vec<_>>();
+		writeln!(json, "\t\"shape\": {{{}}}", shape.join(", ")).unwrap();
+		json.push_str("}\n");
+		Ok(json)
+	}
+
+	/// Read settings saved by `to_json`, with the same parser as `Catalog::parse_json`
+	///
+	/// Settings that are left out keep their defaults and ones that aren't known are ignored, so projects saved by other versions still
+	/// load.
+	pub fn parse_json(source: &str) -> Result<Self, SettingsError> {
+		let Value::Object(entries) = parse_json_value(source)? else {
+			return Err(SettingsError::NotObject);
+		};
+		let invalid = |key: &str, message| SettingsError::InvalidValue {
+			key: key.to_string(),
+			message,
+		};
+		let string = |key: &str, value: Value| match value {
+			Value::String(s) => Ok(s),
+			_ => Err(invalid(key, "expected a string")),
+		};
+		let number = |key: &str, value: &Value| match *value {
+			Value::Number(n) => Ok(n),
+			_ => Err(invalid(key, "expected a number")),
+		};
+		let whole_number = |key: &str, value: &Value| {
+			let n = number(key, value)?;
+			if n >= 0.0 && n.fract() == 0.0 {
+				Ok(n as u32)
+			} else {
+				Err(invalid(key, "expected a whole number"))
+			}
+		};
+
+		let mut settings = Self::default();
+		for (key, value) in entries {
+			match key.as_str() {
+				"preset" => settings.preset = Some(string(&key, value)?),
+				"x" => settings.x_expression = string(&key, value)?,
+				"y" => settings.y_expression = string(&key, value)?,
+				"z" => settings.z_expression = string(&key, value)?,
+				"white_depth" => settings.white_depth = number(&key, &value)?,
+				"black_depth" => settings.black_depth = number(&key, &value)?,
+				"frame" => settings.frame = whole_number(&key, &value)? as usize,
+				"step" => settings.step = whole_number(&key, &value)?.max(1),
+				"curve" => settings.curve = Some(string(&key, value)?),
+				"material" => settings.material = Some(string(&key, value)?),
+				"shape" => {
+					let Value::Object(shape) = value else {
+						return Err(invalid(&key, "expected an object of numbers"));
+					};
+					settings.shape = shape.iter().map(|(name, value)| number(name, value).map(|n| (name.clone(), n))).collect::<Result<_, _>>()?;
+				},
+				_ => {},
+			}
+		}
+		Ok(settings)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn round_trips_through_json() {
+		let settings = Settings {
+			preset: Some("my \"arch\"".to_string()),
+			z_expression: "(x - w / 2) * (x - w / 2) / w".to_string(),
+			white_depth: 0.6,
+			black_depth: 2.4,
+			frame: 3,
+			step: 4,
+			curve: Some("soft".to_string()),
+			shape: vec![("pixel_size".to_string(), 0.2), ("hole_diameter".to_string(), 8.0)],
+			..Default::default()
+		};
+		assert_eq!(Settings::parse_json(&settings.to_json().unwrap()).unwrap(), settings);
+		assert_eq!(
+			Settings::parse_json(&Settings::default().to_json().unwrap()).unwrap(),
+			Settings::default()
+		);
+	}
+
+	#[test]
+	fn defaults_missing_settings_and_ignores_unknown_ones() {
+		let settings = Settings::parse_json(r#"{"x": "x * 2", "from_the_future": [1, 2]}"#).unwrap();
+		assert_eq!(settings.x_expression, "x * 2");
+		assert_eq!(settings.black_depth, Settings::default().black_depth);
+	}
+
+	#[test]
+	fn rejects_invalid_settings() {
+		assert!(matches!(Settings::parse_json("[]"), Err(SettingsError::NotObject)));
+		assert!(matches!(Settings::parse_json(r#"{"frame": 1.5}"#), Err(SettingsError::InvalidValue { key, .. }) if key == "frame"));
+		assert!(
+			matches!(Settings::parse_json(r#"{"shape": {"pixel_size": "big"}}"#), Err(SettingsError::InvalidValue { key, .. }) if key == "pixel_size")
+		);
+		assert!(matches!(
+			Settings::parse_json("{\n\"x\": }"),
+			Err(SettingsError::Syntax(CatalogError::Syntax { line: 2, .. }))
+		));
+		let settings = Settings {
+			white_depth: f32::NAN,
+			..Default::default()
+		};
+		assert!(matches!(settings.to_json(), Err(SettingsError::NotFinite(key)) if key == "white_depth"));
+	}
+}
@@ -0,0 +1,143 @@
+use image::GrayImage;
+use pk_stl::StlModel;
+
+use crate::{
+	lithophane::{generate_lithophane, LithophaneError, LithophaneOptions},
+	outline::{Outline, Shape},
+};
+
+/// A layered shadow box scene, where the image is split into bands of gray values that each become a flat layer, to be stacked with gaps
+/// between them and lit from behind
+///
+/// The back layer is solid, and each layer in front of it only keeps the parts of the image that are darker than the layer behind it, so
+/// darker areas have more layers in front of the light. Every layer has a frame around it, with pegs on the front that go through holes in
+/// the next layer and hold it `spacing` in front. Parts of a layer that aren't connected to its frame fall out, so simple, high contrast
+/// (or blurred) images work best.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowBox {
+	/// The width of the layers in millimeters, with the height following from the image's aspect ratio
+	pub width: f32,
+	pub layers: u32,
+	pub layer_thickness: f32,
+	/// The gap between neighbouring layers in millimeters
+	pub spacing: f32,
+	/// The width of the frame around each layer in millimeters, which needs to be at least twice the peg diameter
+	pub border: f32,
+	/// The diameter of the pins that go through the next layer, in millimeters
+	/// They stand on spacers twice as wide, which the next layer rests on.
+	pub peg_diameter: f32,
+	/// How much wider the holes are than the pins, so they fit without sanding
+	pub clearance: f32,
+}
+
+impl Default for ShadowBox {
+	fn default() -> Self {
+		Self {
+			width: 100.0,
+			layers: 4,
+			layer_thickness: 1.2,
+			spacing: 5.0,
+			border: 8.0,
+			peg_diameter: 3.0,
+			clearance: 0.3,
+		}
+	}
+}
+
+impl ShadowBox {
+	/// Generate the layers from back to front
+	pub fn generate(&self, image: &GrayImage) -> Result<Vec<StlModel>, LithophaneError> {
+		let (width, height) = image.dimensions();
+		let pixel_size = self.width / (width - 1) as f32;
+		let border = self.border / pixel_size;
+		let (right, bottom) = ((width - 1) as f32, (height - 1) as f32);
+		let in_frame = |x: u32, y: u32| (x as f32).min(y as f32).min(right - x as f32).min(bottom - y as f32) < border;
+
+		// The pegs alternate between the corners and the middles of the sides of the frame, so the holes for the pegs of the layer behind
+		// aren't where the pegs for the layer in front go
+		let inset = border / 2.0;
+		let peg_positions = [
+			[
+				(inset, inset),
+				(right - inset, inset),
+				(right - inset, bottom - inset),
+				(inset, bottom - inset),
+			],
+			[
+				(right / 2.0, inset),
+				(right - inset, bottom / 2.0),
+				(right / 2.0, bottom - inset),
+				(inset, bottom / 2.0),
+			],
+		];
+
+		// The pegs are raised from the front of the layer like the pixels of a lithophane, with the pins at the full depth and the spacers
+		// around them part of the way
+		let white_depth = self.layer_thickness;
+		let black_depth = self.layer_thickness + self.spacing + self.layer_thickness;
+		let spacer_gray = (255.0 - self.spacing / (black_depth - white_depth) * 255.0).round() as u8;
+		let (pin_radius, spacer_radius) = (self.peg_diameter / 2.0 / pixel_size, self.peg_diameter / pixel_size);
+
+		let position = |axis: usize| {
+			move |x: f32, y: f32, _: f32, _: f32| -> f32 {
+				match axis {
+					0 => x * pixel_size,
+					1 => -y * pixel_size,
+					_ => 0.0,
+				}
+			}
+		};
+
+		let layers = self.layers.max(1);
+		(0..layers)
+			.map(|layer| {
+				let pegs = &peg_positions[layer as usize % 2];
+				let relief = GrayImage::from_fn(width, height, |x, y| {
+					// The front layer doesn't have any pegs
+					let distance = if layer + 1 == layers {
+						f32::INFINITY
+					} else {
+						pegs.iter().map(|&(px, py)| (x as f32 - px).hypot(y as f32 - py)).fold(f32::INFINITY, f32::min)
+					};
+					image::Luma([if distance <= pin_radius {
+						0
+					} else if distance <= spacer_radius {
+						spacer_gray
+					} else {
+						255
+					}])
+				});
+
+				let outline = if layer == 0 {
+					Outline::default()
+				} else {
+					let threshold = (255 * (layers - layer) / layers) as u8;
+					let mask = GrayImage::from_fn(width, height, |x, y| {
+						image::Luma([if in_frame(x, y) { 0 } else { image.get_pixel(x, y).0[0] }])
+					});
+					let hole_radius = (self.peg_diameter + self.clearance) / 2.0 / pixel_size;
+					Outline {
+						boundary: Some(Shape::Mask { image: mask, threshold }),
+						holes: peg_positions[(layer as usize - 1) % 2]
+							.iter()
+							.map(|&(center_x, center_y)| Shape::Circle {
+								center_x,
+								center_y,
+								radius: hole_radius,
+							})
+							.collect(),
+						hole_reinforcement: 0.0,
+					}
+				};
+
+				let options = LithophaneOptions {
+					white_depth,
+					black_depth,
+					outline,
+					..Default::default()
+				};
+				generate_lithophane(position(0), position(1), position(2), relief, &options)
+			})
+			.collect()
+	}
+}
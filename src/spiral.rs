@@ -0,0 +1,68 @@
+use image::GrayImage;
+
+/// A lampshade with the image wound around it in a helical band, like a label wound around a tube
+///
+/// The band is laid out on the unrolled wall of a cylinder (see `unroll`), which is then generated with
+/// `lithophane::generate_vase_lithophane`. Each turn of the band sits right on top of the previous one (with an optional separator
+/// between them), so the wall is one solid and doesn't need walls along the edges of the band. The wedges below the first turn and above
+/// the last one are filled in so the lampshade has flat ends to stand on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpiralLampshade {
+	/// The number of times the band goes around, which can be fractional
+	pub turns: f32,
+	/// The height in pixels of a strip between the turns, eg a thick dark line to set them apart
+	pub separator_height: u32,
+	/// The gray value of the separator, where black is the thickest
+	pub separator_gray: u8,
+	/// The gray value of the wedges at the top and bottom
+	pub end_gray: u8,
+}
+
+impl Default for SpiralLampshade {
+	fn default() -> Self {
+		Self {
+			turns: 3.0,
+			separator_height: 2,
+			separator_gray: 0,
+			end_gray: 255,
+		}
+	}
+}
+
+impl SpiralLampshade {
+	/// The number of pixels around the lampshade, which is the image width divided by the number of turns
+	pub fn circumference(&self, image_width: u32) -> u32 {
+		((image_width as f32 / self.turns).round() as u32).max(3)
+	}
+
+	/// Lay the image out along the band, returning the unrolled wall of the cylinder
+	///
+	/// Rows are the same height as in the image and columns are (almost exactly) the same width, so pass the same pixel size to
+	/// `generate_vase_lithophane` that the image would have had flat.
+	pub fn unroll(&self, image: &GrayImage) -> GrayImage {
+		let circumference = self.circumference(image.width());
+		let pitch = image.height() + self.separator_height;
+		let height = (pitch as f32 * (self.turns + 1.0)).ceil() as u32;
+
+		GrayImage::from_fn(circumference, height, |x, y| {
+			// Measure up from the bottom, where the band starts, and in turns around the cylinder
+			let around = x as f32 / circumference as f32;
+			let up = (height - 1 - y) as f32;
+			// The turn of the band whose strip covers this pixel
+			let turn = ((up - pitch as f32 * around) / pitch as f32).floor();
+			let position = around + turn;
+			let offset = up - pitch as f32 * position;
+
+			let gray = if position < 0.0 || position > self.turns {
+				self.end_gray
+			} else if offset >= image.height() as f32 {
+				self.separator_gray
+			} else {
+				let image_x = ((position * circumference as f32).round() as u32).min(image.width() - 1);
+				let image_y = (image.height() - 1).saturating_sub(offset as u32);
+				image.get_pixel(image_x, image_y).0[0]
+			};
+			image::Luma([gray])
+		})
+	}
+}
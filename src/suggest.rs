@@ -0,0 +1,283 @@
+use std::{f32::consts::PI, fmt::Write};
+
+use image::GrayImage;
+
+use crate::{manifest::json_string, photo_frame::PhotoSize};
+
+/// The shape of an image, by its width divided by its height
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+	/// Taller than it is wide
+	Portrait,
+	/// Within 10% of square
+	Square,
+	/// Wider than it is tall, up to twice as wide
+	Landscape,
+	/// More than twice as wide as it is tall
+	Panorama,
+}
+
+impl Orientation {
+	pub fn of(width: u32, height: u32) -> Self {
+		let aspect_ratio = width as f32 / height as f32;
+		if aspect_ratio < 1.0 / 1.1 {
+			Orientation::Portrait
+		} else if aspect_ratio <= 1.1 {
+			Orientation::Square
+		} else if aspect_ratio <= 2.0 {
+			Orientation::Landscape
+		} else {
+			Orientation::Panorama
+		}
+	}
+
+	pub fn name(self) -> &'static str {
+		match self {
+			Orientation::Portrait => "portrait",
+			Orientation::Square => "square",
+			Orientation::Landscape => "landscape",
+			Orientation::Panorama => "panorama",
+		}
+	}
+}
+
+/// A shape that can be made from expressions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SuggestedShape {
+	Flat,
+	/// Curved sideways around a vertical axis through this many radians, which stands up on its own
+	Arc {
+		angle: f32,
+	},
+	/// Wrapped all the way around a vertical axis, eg for a lamp shade
+	Cylinder,
+}
+
+impl SuggestedShape {
+	pub fn name(self) -> &'static str {
+		match self {
+			SuggestedShape::Flat => "flat",
+			SuggestedShape::Arc { .. } => "arc",
+			SuggestedShape::Cylinder => "cylinder",
+		}
+	}
+}
+
+/// A shape and size that suit an image, from `suggest_shapes`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShapeSuggestion {
+	/// What the image was taken to be, which the shape was chosen for
+	pub orientation: Orientation,
+	pub shape: SuggestedShape,
+	/// The size of the surface in millimeters, measured around the curve for curved shapes, which keeps the image's aspect ratio
+	pub width: f32,
+	pub height: f32,
+	/// The print size with the closest aspect ratio, for a frame to put a flat lithophane in
+	pub photo_size: Option<PhotoSize>,
+	/// Why the shape suits the image, to show the user
+	pub reason: &'static str,
+	/// The x, y and z expressions that make the shape at this size
+	pub expressions: [String; 3],
+}
+
+impl ShapeSuggestion {
+	fn new(
+		orientation: Orientation,
+		shape: SuggestedShape,
+		(width, height): (f32, f32),
+		photo_size: Option<PhotoSize>,
+		reason: &'static str,
+	) -> Self {
+		let y = format!("-y*{}/(h-1)", height);
+		let expressions = match shape {
+			SuggestedShape::Flat => [format!("x*{}/(w-1)", width), y, "0".to_string()],
+			SuggestedShape::Arc { angle } => {
+				let (radius, theta) = (width / angle, format!("(x/(w-1)-0.5)*{}", angle));
+				[format!("{}*sin({})", radius, theta), y, format!("{}*(1-cos({}))", radius, theta)]
+			},
+			// The angle only goes up to one column short of all the way around, since the first and last columns can't be in the same place
+			SuggestedShape::Cylinder => {
+				let (radius, theta) = (width / (2.0 * PI), format!("x/w*{}", 2.0 * PI));
+				[format!("{}*sin({})", radius, theta), y, format!("{}*(1-cos({}))", radius, theta)]
+			},
+		};
+		Self {
+			orientation,
+			shape,
+			width,
+			height,
+			photo_size,
+			reason,
+			expressions,
+		}
+	}
+
+	/// The suggestion as a JSON object
+	pub fn to_json(&self) -> String {
+		let mut json = String::new();
+		write!(
+			json,
+			"{{\"orientation\": {}, \"shape\": {}, ",
+			json_string(self.orientation.name()),
+			json_string(self.shape.name())
+		)
+		.unwrap();
+		if let SuggestedShape::Arc { angle } = self.shape {
+			write!(json, "\"angle_degrees\": {:.0}, ", angle.to_degrees()).unwrap();
+		}
+		write!(json, "\"width\": {:.1}, \"height\": {:.1}, ", self.width, self.height).unwrap();
+		if let Some(size) = self.photo_size {
+			let (short, long) = size.dimensions();
+			write!(json, "\"photo_size\": [{}, {}], ", short, long).unwrap();
+		}
+		let expressions: Vec<String> = self.expressions.iter().map(|e| json_string(e)).collect();
+		write!(
+			json,
+			"\"reason\": {}, \"expressions\": [{}]}}",
+			json_string(self.reason),
+			expressions.join(", ")
+		)
+		.unwrap();
+		json
+	}
+}
+
+/// How long the longer side of a portrait, square or landscape lithophane is, in millimeters, which fits most printers and picture frames
+const FLAT_LONG_SIDE: f32 = 150.0;
+
+/// How tall a panorama is, in millimeters, unless it would then be longer than `PANORAMA_MAX_LENGTH`
+const PANORAMA_HEIGHT: f32 = 80.0;
+
+/// The longest a panorama is around its curve, in millimeters
+const PANORAMA_MAX_LENGTH: f32 = 450.0;
+
+/// How many degrees a panorama that doesn't wrap around is curved through
+const PANORAMA_ARC: f32 = 120.0;
+
+/// How many degrees other images are curved through when they're curved, which is enough for them to stand up
+const GENTLE_ARC: f32 = 60.0;
+
+/// Suggest shapes and sizes for an image, best first, from its proportions and whether its left and right edges join up (as a 360°
+/// panorama's do)
+///
+/// The sizes keep the image's aspect ratio, so the image doesn't need cropping. These are only starting points, since they can't know what
+/// the lithophane is for.
+pub fn suggest_shapes(image: &GrayImage) -> Vec<ShapeSuggestion> {
+	let (image_width, image_height) = image.dimensions();
+	let aspect_ratio = image_width as f32 / image_height as f32;
+	let photo_size = closest_photo_size(image_width, image_height);
+	let flat_size = if aspect_ratio >= 1.0 {
+		(FLAT_LONG_SIDE, FLAT_LONG_SIDE / aspect_ratio)
+	} else {
+		(FLAT_LONG_SIDE * aspect_ratio, FLAT_LONG_SIDE)
+	};
+	let gentle_arc = SuggestedShape::Arc {
+		angle: GENTLE_ARC.to_radians(),
+	};
+
+	let orientation = Orientation::of(image_width, image_height);
+	match orientation {
+		Orientation::Portrait => vec![
+			ShapeSuggestion::new(
+				orientation,
+				SuggestedShape::Flat,
+				flat_size,
+				Some(photo_size),
+				"portraits fit a standard picture frame",
+			),
+			ShapeSuggestion::new(orientation, gentle_arc, flat_size, None, "a slight curve stands up without a stand"),
+		],
+		Orientation::Square => vec![
+			ShapeSuggestion::new(
+				orientation,
+				SuggestedShape::Flat,
+				flat_size,
+				Some(photo_size),
+				"square images suit a flat panel or a window hanger",
+			),
+			ShapeSuggestion::new(orientation, gentle_arc, flat_size, None, "a slight curve stands up without a stand"),
+		],
+		Orientation::Landscape => vec![
+			ShapeSuggestion::new(
+				orientation,
+				gentle_arc,
+				flat_size,
+				None,
+				"a slight curve stands up on a shelf and spreads the light",
+			),
+			ShapeSuggestion::new(
+				orientation,
+				SuggestedShape::Flat,
+				flat_size,
+				Some(photo_size),
+				"landscapes fit a standard picture frame",
+			),
+		],
+		Orientation::Panorama => {
+			let length = (PANORAMA_HEIGHT * aspect_ratio).min(PANORAMA_MAX_LENGTH);
+			let size = (length, length / aspect_ratio);
+			let wraps = wraps_around(image);
+			let cylinder = ShapeSuggestion::new(
+				orientation,
+				SuggestedShape::Cylinder,
+				size,
+				None,
+				if wraps {
+					"the edges join up, so it can go all the way around"
+				} else {
+					"it can go all the way around, with a seam where the edges meet"
+				},
+			);
+			let arc = ShapeSuggestion::new(
+				orientation,
+				SuggestedShape::Arc {
+					angle: PANORAMA_ARC.to_radians(),
+				},
+				size,
+				None,
+				"wide images curve around the viewer",
+			);
+			if wraps {
+				vec![cylinder, arc]
+			} else {
+				vec![arc, cylinder]
+			}
+		},
+	}
+}
+
+/// The print size whose aspect ratio is closest to the image's
+fn closest_photo_size(image_width: u32, image_height: u32) -> PhotoSize {
+	let aspect_ratio = image_width as f32 / image_height as f32;
+	let difference = |size: PhotoSize| {
+		let (width, height) = size.oriented_dimensions(image_width, image_height);
+		(width / height / aspect_ratio).ln().abs()
+	};
+	[
+		PhotoSize::Wallet,
+		PhotoSize::FourBySix,
+		PhotoSize::FiveBySeven,
+		PhotoSize::EightByTen,
+		PhotoSize::TenByFifteen,
+		PhotoSize::ThirteenByEighteen,
+		PhotoSize::A5,
+		PhotoSize::A4,
+	]
+	.into_iter()
+	.min_by(|&a, &b| difference(a).total_cmp(&difference(b)))
+	.unwrap()
+}
+
+/// Whether the left and right edges of an image are about as similar as neighbouring columns usually are, like a 360° panorama's
+fn wraps_around(image: &GrayImage) -> bool {
+	let (width, height) = image.dimensions();
+	if width < 3 {
+		return false;
+	}
+	let column_difference = |a: u32, b: u32| {
+		(0..height).map(|y| (image.get_pixel(a, y).0[0] as f32 - image.get_pixel(b, y).0[0] as f32).abs()).sum::<f32>() / height as f32
+	};
+	let typical = (0..width - 1).map(|x| column_difference(x, x + 1)).sum::<f32>() / (width - 1) as f32;
+	// The small allowance keeps noise from deciding it for images that are almost the same all the way across
+	column_difference(0, width - 1) <= typical * 2.0 + 2.0
+}
@@ -0,0 +1,110 @@
+use image::{imageops, GrayImage, Luma};
+
+/// A tactile graphic, for reading with the fingertips instead of holding up to the light, eg a picture or map for someone who's blind
+///
+/// The tones are simplified into a few heights far enough apart to tell apart by touch, details too small to feel are smoothed away, and
+/// the walls between heights lean back so their edges aren't sharp. Darker areas are raised, like the thick parts of a lithophane, so
+/// lines and shapes should be dark on a light background. All sizes are in millimeters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tactile {
+	/// The number of heights, including the base
+	pub levels: u32,
+	/// How far the highest level stands above the base
+	pub relief: f32,
+	/// The thickness under the lowest level
+	pub base: f32,
+	/// Areas narrower than this are merged into their surroundings, since a fingertip can't make them out
+	pub smallest_feature: f32,
+	/// How many degrees the walls between levels lean away from vertical
+	pub wall_angle: f32,
+}
+
+impl Default for Tactile {
+	/// Four levels 1 mm apart, which is about as fine as most readers can tell apart
+	fn default() -> Self {
+		Self {
+			levels: 4,
+			relief: 3.0,
+			base: 1.5,
+			smallest_feature: 2.0,
+			wall_angle: 30.0,
+		}
+	}
+}
+
+/// Levels closer together than this many millimeters are hard to tell apart by touch
+pub const MIN_TACTILE_STEP: f32 = 0.5;
+
+impl Tactile {
+	/// The white and black depths for `LithophaneOptions`
+	pub fn depths(&self) -> (f32, f32) {
+		(self.base, self.base + self.relief)
+	}
+
+	/// The height between neighbouring levels
+	pub fn step(&self) -> f32 {
+		self.relief / (self.levels.max(2) - 1) as f32
+	}
+
+	/// The `max_slope` for `LithophaneOptions` that leans the walls by `wall_angle`, for pixels `pixel_size` millimeters apart
+	pub fn max_slope(&self, pixel_size: f32) -> f32 {
+		// A wall leaning by the angle covers its height times the tangent of the angle horizontally
+		pixel_size / self.wall_angle.max(1.0).to_radians().tan()
+	}
+
+	/// Turn an image into evenly spaced gray values, one for each level, without any areas smaller than `smallest_feature`, for pixels
+	/// `pixel_size` millimeters apart
+	///
+	/// The image is blurred a little so noise doesn't break up the bands, then each pixel takes whichever level is most common around it,
+	/// which removes specks and thin slivers where one band meets another.
+	pub fn simplify(&self, image: &GrayImage, pixel_size: f32) -> GrayImage {
+		let steps = self.levels.max(2) - 1;
+		let radius = (self.smallest_feature / pixel_size / 2.0).round() as i64;
+		let blurred = if radius > 0 {
+			imageops::blur(image, radius as f32 / 2.0)
+		} else {
+			image.clone()
+		};
+		let band = |gray: u8| ((gray as f32 / 255.0 * steps as f32).round() as u32).min(steps);
+		if radius == 0 {
+			return GrayImage::from_fn(image.width(), image.height(), |x, y| {
+				level_gray(band(blurred.get_pixel(x, y).0[0]), steps)
+			});
+		}
+
+		// A summed area table for each level makes counting them around each pixel take the same time whatever the radius
+		let (width, height) = (image.width() as i64, image.height() as i64);
+		let tables: Vec<Vec<u32>> = (0..=steps)
+			.map(|level| {
+				let mut table = vec![0; ((width + 1) * (height + 1)) as usize];
+				for y in 0..height {
+					let mut row = 0;
+					for x in 0..width {
+						row += (band(blurred.get_pixel(x as u32, y as u32).0[0]) == level) as u32;
+						table[((y + 1) * (width + 1) + x + 1) as usize] = table[(y * (width + 1) + x + 1) as usize] + row;
+					}
+				}
+				table
+			})
+			.collect();
+		let count = |table: &[u32], x: i64, y: i64| {
+			let (left, right) = ((x - radius).max(0), (x + radius + 1).min(width));
+			let (top, bottom) = ((y - radius).max(0), (y + radius + 1).min(height));
+			let at = |x: i64, y: i64| table[(y * (width + 1) + x) as usize];
+			at(right, bottom) + at(left, top) - at(left, bottom) - at(right, top)
+		};
+
+		GrayImage::from_fn(image.width(), image.height(), |x, y| {
+			let own = band(blurred.get_pixel(x, y).0[0]);
+			let counts: Vec<u32> = tables.iter().map(|table| count(table, x as i64, y as i64)).collect();
+			// Ties go to the pixel's own level, so edges between two equal areas stay where they were
+			let most = (0..=steps).max_by_key(|&level| (counts[level as usize], level == own)).unwrap_or(own);
+			level_gray(most, steps)
+		})
+	}
+}
+
+/// The gray value of a level, which `lithophane::pixel_depth` turns back into that level's depth (to within rounding)
+fn level_gray(level: u32, steps: u32) -> Luma<u8> {
+	Luma([(level as f32 / steps as f32 * 255.0).round() as u8])
+}
@@ -0,0 +1,261 @@
+use image::{GrayImage, ImageError, Luma};
+use thiserror::Error;
+
+/// A grid of elevations, eg a digital elevation model (DEM) of some terrain
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElevationGrid {
+	pub width: u32,
+	pub height: u32,
+	/// In meters, row by row from the top (usually north), with NaN where there's no data
+	pub elevations: Vec<f32>,
+	/// The distance between neighbouring points in meters, if the file says
+	pub cell_size: Option<f32>,
+}
+
+#[derive(Error, Debug)]
+pub enum TerrainError {
+	#[error("{0}")]
+	ImageError(#[from] ImageError),
+	#[error("invalid ASCII grid: {0}")]
+	InvalidAsciiGrid(String),
+	#[error("the elevations don't say how far apart their points are, so a cell size is needed")]
+	MissingCellSize,
+	#[error("there aren't any elevations")]
+	NoData,
+	#[cfg(feature = "tiff")]
+	#[error("invalid GeoTIFF: {0}")]
+	InvalidGeoTiff(#[from] tiff::TiffError),
+	#[cfg(feature = "tiff")]
+	#[error("the GeoTIFF has more than one band, so it isn't a grid of elevations")]
+	MultipleBands,
+}
+
+impl ElevationGrid {
+	/// Read an ESRI ASCII grid (`.asc`), or any image the `image` crate can decode, where each gray value (eg of a 16 bit grayscale PNG
+	/// heightmap) is `elevation_scale` meters. Images with fewer bits per channel are stretched to 16 bits first, like everything else
+	/// the `image` crate converts.
+	///
+	/// With the tiff feature, TIFFs are read as GeoTIFFs (see `from_geotiff`). Without it, GDAL can convert them to ASCII grids with
+	/// `gdal_translate -of AAIGrid`.
+	pub fn read(bytes: &[u8], elevation_scale: f32) -> Result<Self, TerrainError> {
+		let is_ascii_grid = bytes.iter().skip_while(|b| b.is_ascii_whitespace()).take(5).map(u8::to_ascii_lowercase).eq(*b"ncols");
+		if is_ascii_grid {
+			return Self::from_ascii_grid(&String::from_utf8_lossy(bytes));
+		}
+		#[cfg(feature = "tiff")]
+		if image::guess_format(bytes).is_ok_and(|format| format == image::ImageFormat::Tiff) {
+			return Self::from_geotiff(bytes, elevation_scale);
+		}
+
+		let image = image::load_from_memory(bytes)?.into_luma16();
+		Ok(Self {
+			width: image.width(),
+			height: image.height(),
+			elevations: image.pixels().map(|p| p.0[0] as f32 * elevation_scale).collect(),
+			cell_size: None,
+		})
+	}
+
+	/// Parse an ESRI ASCII grid, which is a header of `ncols`, `nrows`, `xllcorner`, `yllcorner`, `cellsize` and optionally
+	/// `nodata_value`, followed by the elevations row by row from the top
+	pub fn from_ascii_grid(text: &str) -> Result<Self, TerrainError> {
+		let invalid = |message: String| TerrainError::InvalidAsciiGrid(message);
+		let mut tokens = text.split_ascii_whitespace().peekable();
+
+		let (mut width, mut height, mut cell_size, mut no_data) = (None, None, None, None);
+		while let Some(key) = tokens.next_if(|t| t.starts_with(|c: char| c.is_ascii_alphabetic())) {
+			let value = tokens.next().ok_or_else(|| invalid(format!("{} doesn't have a value", key)))?;
+			let number = || value.parse::<f64>().map_err(|e| invalid(format!("invalid {} \"{}\": {}", key, value, e)));
+			match key.to_ascii_lowercase().as_str() {
+				"ncols" => width = Some(number()? as u32),
+				"nrows" => height = Some(number()? as u32),
+				"cellsize" => cell_size = Some(number()? as f32),
+				"nodata_value" => no_data = Some(number()? as f32),
+				// The position of the grid doesn't matter for its shape
+				"xllcorner" | "yllcorner" | "xllcenter" | "yllcenter" => {},
+				_ => return Err(invalid(format!("unknown header \"{}\"", key))),
+			}
+		}
+		let width = width.ok_or_else(|| invalid("ncols is missing".to_string()))?;
+		let height = height.ok_or_else(|| invalid("nrows is missing".to_string()))?;
+
+		let elevations = tokens
+			.map(|t| match t.parse::<f32>() {
+				Ok(v) if Some(v) == no_data => Ok(f32::NAN),
+				Ok(v) => Ok(v),
+				Err(e) => Err(invalid(format!("invalid elevation \"{}\": {}", t, e))),
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+		if elevations.len() != (width * height) as usize {
+			return Err(invalid(format!("expected {} × {} elevations, found {}", width, height, elevations.len())));
+		}
+
+		Ok(Self {
+			width,
+			height,
+			elevations,
+			cell_size,
+		})
+	}
+
+	/// Read a GeoTIFF (or any other single band TIFF) where each value is `elevation_scale` meters, taking the cell size from its
+	/// ModelPixelScale tag and the points with no data from its GDAL_NODATA tag
+	///
+	/// The values are used as they are, rather than stretched to 16 bits like images. Maps in latitude and longitude have their pixel
+	/// scale in degrees, so they're left without a cell size.
+	#[cfg(feature = "tiff")]
+	pub fn from_geotiff(bytes: &[u8], elevation_scale: f32) -> Result<Self, TerrainError> {
+		use tiff::{
+			decoder::{Decoder, DecodingResult},
+			tags::Tag,
+			ColorType,
+		};
+
+		// The GTModelTypeGeoKey, and its value for maps in latitude and longitude
+		const MODEL_TYPE_KEY: u16 = 1024;
+		const MODEL_TYPE_GEOGRAPHIC: u16 = 2;
+
+		let mut decoder = Decoder::new(std::io::Cursor::new(bytes))?;
+		let (width, height) = decoder.dimensions()?;
+		if !matches!(decoder.colortype()?, ColorType::Gray(_)) {
+			return Err(TerrainError::MultipleBands);
+		}
+		// GDAL saves the value it uses for points with no data as text
+		let no_data = decoder
+			.find_tag(Tag::GdalNodata)?
+			.and_then(|v| v.into_string().ok())
+			.and_then(|s| s.trim_matches(|c: char| c.is_whitespace() || c == '\0').parse::<f32>().ok());
+		// The geo key directory is a header of 4 numbers, then 4 numbers for each key: its ID, where its value is (0 for in the entry
+		// itself), the number of values and the value
+		let geographic =
+			decoder.find_tag(Tag::GeoKeyDirectoryTag)?.map(|v| v.into_u16_vec()).transpose()?.is_some_and(|keys| {
+				keys.get(4..).unwrap_or_default().chunks_exact(4).any(|key| key == [MODEL_TYPE_KEY, 0, 1, MODEL_TYPE_GEOGRAPHIC])
+			});
+		let cell_size = match decoder.find_tag(Tag::ModelPixelScaleTag)? {
+			Some(scale) if !geographic => scale.into_f64_vec()?.first().map(|&s| s as f32),
+			_ => None,
+		};
+
+		let values: Vec<f32> = match decoder.read_image()? {
+			DecodingResult::U8(v) => v.into_iter().map(f32::from).collect(),
+			DecodingResult::U16(v) => v.into_iter().map(f32::from).collect(),
+			DecodingResult::U32(v) => v.into_iter().map(|v| v as f32).collect(),
+			DecodingResult::U64(v) => v.into_iter().map(|v| v as f32).collect(),
+			DecodingResult::I8(v) => v.into_iter().map(f32::from).collect(),
+			DecodingResult::I16(v) => v.into_iter().map(f32::from).collect(),
+			DecodingResult::I32(v) => v.into_iter().map(|v| v as f32).collect(),
+			DecodingResult::I64(v) => v.into_iter().map(|v| v as f32).collect(),
+			DecodingResult::F32(v) => v,
+			DecodingResult::F64(v) => v.into_iter().map(|v| v as f32).collect(),
+		};
+		Ok(Self {
+			width,
+			height,
+			elevations: values.into_iter().map(|v| if Some(v) == no_data { f32::NAN } else { v * elevation_scale }).collect(),
+			cell_size,
+		})
+	}
+
+	/// The lowest and highest elevations, leaving out points with no data
+	pub fn range(&self) -> Option<(f32, f32)> {
+		let known = self.elevations.iter().copied().filter(|e| !e.is_nan());
+		known.fold(None, |range, e| {
+			Some(range.map_or((e, e), |(low, high): (f32, f32)| (low.min(e), high.max(e))))
+		})
+	}
+}
+
+/// A terrain relief made from an elevation grid, where the thickness follows the elevation at the same scale as the map (times the
+/// exaggeration), so mountains come out as steep as they really are
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Terrain {
+	/// The width of the relief in millimeters, with the depth following from the grid's aspect ratio
+	pub width: f32,
+	/// The distance between neighbouring points in meters, instead of the one in the elevation grid (which images don't have)
+	pub cell_size: Option<f32>,
+	/// How many times higher the relief is than it would be at the map's scale, since real terrain usually looks very flat when it's small
+	pub exaggeration: f32,
+	/// The thickness at the lowest point, in millimeters
+	pub base: f32,
+}
+
+impl Default for Terrain {
+	fn default() -> Self {
+		Self {
+			width: 100.0,
+			cell_size: None,
+			exaggeration: 1.0,
+			base: 2.0,
+		}
+	}
+}
+
+impl Terrain {
+	/// Turn elevations into a grayscale image, where the highest point is black and the lowest is white (points with no data are treated
+	/// as the lowest), and the white and black depths that make the relief the right height, so the usual lithophane pipeline can be used
+	/// to generate it
+	///
+	/// The image only has 256 gray values, so the height is rounded to 1/255th of the relief.
+	pub fn prepare(&self, grid: &ElevationGrid) -> Result<(GrayImage, f32, f32), TerrainError> {
+		let cell_size = self.cell_size.or(grid.cell_size).ok_or(TerrainError::MissingCellSize)?;
+		let (low, high) = grid.range().ok_or(TerrainError::NoData)?;
+
+		let millimeters_per_meter = self.width / ((grid.width.max(2) - 1) as f32 * cell_size);
+		let relief = (high - low) * millimeters_per_meter * self.exaggeration;
+		let image = GrayImage::from_fn(grid.width, grid.height, |x, y| {
+			let elevation = grid.elevations[(y * grid.width + x) as usize];
+			let height = if elevation.is_nan() || high == low {
+				0.0
+			} else {
+				(elevation - low) / (high - low)
+			};
+			Luma([255 - (height * 255.0).round() as u8])
+		});
+		Ok((image, self.base, self.base + relief))
+	}
+
+	/// The expressions for a flat relief this wide, for an image made by `prepare`
+	pub fn flat_expressions(&self, image_width: u32, image_height: u32) -> [String; 3] {
+		let height = self.width * (image_height - 1) as f32 / (image_width - 1) as f32;
+		[format!("x*{}/(w-1)", self.width), format!("-y*{}/(h-1)", height), "0".to_string()]
+	}
+}
+
+#[cfg(all(test, feature = "tiff"))]
+mod tests {
+	use tiff::{
+		encoder::{colortype, TiffEncoder},
+		tags::Tag,
+	};
+
+	use super::*;
+
+	#[test]
+	fn reads_geotiff_elevations_and_cell_size() {
+		let mut bytes = std::io::Cursor::new(Vec::new());
+		let mut encoder = TiffEncoder::new(&mut bytes).unwrap();
+		let mut image = encoder.new_image::<colortype::Gray32Float>(3, 2).unwrap();
+		image.encoder().write_tag(Tag::ModelPixelScaleTag, &[30.0f64, 30.0, 0.0][..]).unwrap();
+		image.encoder().write_tag(Tag::GdalNodata, "-9999").unwrap();
+		image.write_data(&[100.5, 120.0, -9999.0, 90.0, 250.25, 110.0]).unwrap();
+
+		let grid = ElevationGrid::read(bytes.get_ref(), 2.0).unwrap();
+		assert_eq!((grid.width, grid.height, grid.cell_size), (3, 2, Some(30.0)));
+		assert!(grid.elevations[2].is_nan());
+		assert_eq!(grid.range(), Some((180.0, 500.5)));
+	}
+
+	#[test]
+	fn leaves_out_the_cell_size_of_maps_in_degrees() {
+		let mut bytes = std::io::Cursor::new(Vec::new());
+		let mut encoder = TiffEncoder::new(&mut bytes).unwrap();
+		let mut image = encoder.new_image::<colortype::GrayI16>(2, 2).unwrap();
+		image.encoder().write_tag(Tag::ModelPixelScaleTag, &[0.0003f64, 0.0003, 0.0][..]).unwrap();
+		image.encoder().write_tag(Tag::GeoKeyDirectoryTag, &[1u16, 1, 0, 1, 1024, 0, 1, 2][..]).unwrap();
+		image.write_data(&[-5, 10, 20, 30]).unwrap();
+
+		let grid = ElevationGrid::read(bytes.get_ref(), 1.0).unwrap();
+		assert_eq!(grid.cell_size, None);
+		assert_eq!(grid.elevations, vec![-5.0, 10.0, 20.0, 30.0]);
+	}
+}
@@ -0,0 +1,338 @@
+use std::fmt;
+
+use image::GrayImage;
+
+use crate::{
+	detail::{DetailReport, ThinFeature},
+	lithophane::{StretchedRegion, MAX_STRETCH, MIN_IMAGE_SIZE},
+	photo_frame::AspectFit,
+	preprocessing::resize_area_average,
+	tactile::{Tactile, MIN_TACTILE_STEP},
+};
+
+/// Problems that won't stop a lithophane from being generated, but that the user should probably know about before printing it
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+	/// This fraction of the pixels are pure black, so they'll all be at the maximum thickness with no detail between them
+	CrushedShadows { fraction: f32 },
+	/// This fraction of the pixels are pure white, so they'll all be at the minimum thickness with no detail between them
+	BlownHighlights { fraction: f32 },
+	/// The image didn't match the shape's aspect ratio, so this fraction of its width or height was cut off
+	Cropped { fraction: f32 },
+	/// The image didn't match the shape's aspect ratio, so a border this fraction of its width or height was added
+	Padded { fraction: f32 },
+	/// The image didn't match the shape's aspect ratio, so it was made this many times wider relative to its height
+	Stretched { factor: f32 },
+	/// Only this fraction of the image's detail will be visible, since the smallest printable feature is this many millimeters
+	LostDetail { visible: f32, smallest_feature: f32, pixel_size: f32 },
+	/// The image's shorter side only has this many pixels, fewer than the minimum from `QualityChecks`
+	LowResolution { pixels: u32, min_pixels: u32 },
+	/// The image's sharpness (see `sharpness`) is lower than the minimum from `QualityChecks`, so it's probably blurry
+	Blurry { sharpness: f32, min_sharpness: f32 },
+	/// The image's average gray value is this low, so it's probably underexposed
+	Underexposed { brightness: f32 },
+	/// The image's average gray value is this high, so it's probably overexposed
+	Overexposed { brightness: f32 },
+	/// These dark features are narrower than the smallest printable feature (in millimeters), so they'll print thinner than they should
+	/// or not at all
+	ThinFeatures { features: Vec<ThinFeature>, smallest_feature: f32 },
+	/// The expressions stretch the pixels in this region much more in one direction than the other, which causes visible banding
+	StretchedPixels { region: StretchedRegion },
+	/// The image was only this many pixels tall or wide, so its only row or column was repeated to make a thin bar
+	ExtendedToStrip { width: u32, height: u32 },
+	/// The images at these positions in a set (counting from 0) look the same, with this many bits different between their
+	/// `perceptual_hash`es
+	DuplicateImages { first: usize, second: usize, distance: u32 },
+	/// The levels of a tactile graphic are only this many millimeters apart, so they'll be hard to tell apart by touch
+	TactileLevels { step: f32 },
+}
+
+impl fmt::Display for Warning {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Warning::CrushedShadows { fraction } => write!(
+				f,
+				"{:.1}% of the image is pure black, which will all be printed at the maximum thickness without any detail",
+				fraction * 100.0
+			),
+			Warning::BlownHighlights { fraction } => write!(
+				f,
+				"{:.1}% of the image is pure white, which will all be printed at the minimum thickness without any detail",
+				fraction * 100.0
+			),
+			Warning::Cropped { fraction } => write!(f, "{:.1}% of the image was cropped off to fit the shape", fraction * 100.0),
+			Warning::Padded { fraction } => write!(f, "the image was padded by {:.1}% to fit the shape", fraction * 100.0),
+			Warning::Stretched { factor } if *factor >= 1.0 => {
+				write!(
+					f,
+					"the image was stretched to {:.1}% of its width relative to its height to fit the shape",
+					factor * 100.0
+				)
+			},
+			Warning::Stretched { factor } => {
+				write!(
+					f,
+					"the image was squashed to {:.1}% of its width relative to its height to fit the shape",
+					factor * 100.0
+				)
+			},
+			Warning::LostDetail {
+				visible,
+				smallest_feature,
+				pixel_size,
+			} => write!(
+				f,
+				"only about {:.0}% of the image's detail will be visible, since features smaller than {} mm ({:.1} pixels at {:.3} mm per pixel) \
+				 can't be printed, so consider making the lithophane larger",
+				visible * 100.0,
+				smallest_feature,
+				smallest_feature / pixel_size,
+				pixel_size
+			),
+			Warning::LowResolution { pixels, min_pixels } => {
+				write!(
+					f,
+					"the image is only {} pixels on its shorter side, less than the minimum of {}",
+					pixels, min_pixels
+				)
+			},
+			Warning::Blurry { sharpness, min_sharpness } => {
+				write!(
+					f,
+					"the image looks blurry, with a sharpness of {:.1} (the minimum is {})",
+					sharpness, min_sharpness
+				)
+			},
+			Warning::Underexposed { brightness } => {
+				write!(f, "the image looks underexposed, with an average gray value of {:.0}", brightness)
+			},
+			Warning::Overexposed { brightness } => {
+				write!(f, "the image looks overexposed, with an average gray value of {:.0}", brightness)
+			},
+			Warning::ThinFeatures { features, smallest_feature } => {
+				write!(
+					f,
+					"{} dark features are thinner than {} mm and won't print properly, including",
+					features.len(),
+					smallest_feature
+				)?;
+				for (i, feature) in features.iter().take(THIN_FEATURES_LISTED).enumerate() {
+					let separator = if i == 0 { "" } else { "," };
+					write!(
+						f,
+						"{} a {}×{} pixel one at {},{}",
+						separator, feature.width, feature.height, feature.x, feature.y
+					)?;
+				}
+				write!(f, ", so they should be made thicker before printing")
+			},
+			Warning::StretchedPixels { region } => write!(
+				f,
+				"the expressions stretch the pixels {}{:.1} times more in one direction than the other in {:.0}% of the image (within the {}×{} \
+				 pixels at {},{}), which can show up as banding, so check that the expressions scale x and y evenly",
+				if region.ratio >= MAX_STRETCH { "over " } else { "up to " },
+				region.ratio,
+				region.fraction * 100.0,
+				region.width,
+				region.height,
+				region.x,
+				region.y
+			),
+			Warning::ExtendedToStrip { width, height } => write!(
+				f,
+				"the image is only {}×{} pixels, so its only {} was repeated to make the lithophane a thin bar",
+				width,
+				height,
+				if *height == 1 { "row" } else { "column" }
+			),
+			Warning::DuplicateImages { first, second, distance } => write!(
+				f,
+				"images {} and {} look {}the same, so one of them might have been included by mistake",
+				first + 1,
+				second + 1,
+				if *distance == 0 { "" } else { "almost " }
+			),
+			Warning::TactileLevels { step } => write!(
+				f,
+				"the tactile levels are only {:.2} mm apart, which is hard to feel, so use fewer levels or more relief",
+				step
+			),
+		}
+	}
+}
+
+/// The fraction of clipped pixels at which a warning is given
+const CLIPPING_WARNING_FRACTION: f32 = 0.02;
+
+/// Check the image that will be turned into a lithophane (after any preprocessing) for pure black and white areas
+///
+/// Since gray values are mapped linearly to depths, these are exactly the pixels that end up at the minimum and maximum thickness.
+pub fn clipping_warnings(image: &GrayImage) -> Vec<Warning> {
+	let pixel_count = (image.width() * image.height()).max(1) as f32;
+	let black_fraction = image.pixels().filter(|p| p.0[0] == 0).count() as f32 / pixel_count;
+	let white_fraction = image.pixels().filter(|p| p.0[0] == 255).count() as f32 / pixel_count;
+
+	let mut warnings = Vec::new();
+	if black_fraction >= CLIPPING_WARNING_FRACTION {
+		warnings.push(Warning::CrushedShadows { fraction: black_fraction });
+	}
+	if white_fraction >= CLIPPING_WARNING_FRACTION {
+		warnings.push(Warning::BlownHighlights { fraction: white_fraction });
+	}
+	warnings
+}
+
+/// How far an image's aspect ratio can be from the shape's before fitting it gets a warning
+const ASPECT_WARNING_TOLERANCE: f32 = 0.01;
+
+/// Check how much an image will be changed by fitting it to a shape's aspect ratio (width divided by height, eg of the unrolled surface
+/// of a cylinder), since otherwise it happens silently
+pub fn aspect_warning(image_width: u32, image_height: u32, aspect_ratio: f32, fit: AspectFit) -> Option<Warning> {
+	// How many times wider the shape is than the image, relative to their heights
+	let factor = aspect_ratio / (image_width as f32 / image_height as f32);
+	if (factor - 1.0).abs() < ASPECT_WARNING_TOLERANCE {
+		return None;
+	}
+	let change = 1.0 - factor.min(1.0 / factor);
+	Some(match fit {
+		AspectFit::Crop => Warning::Cropped { fraction: change },
+		AspectFit::Pad { .. } => Warning::Padded {
+			fraction: factor.max(1.0 / factor) - 1.0,
+		},
+		AspectFit::Stretch => Warning::Stretched { factor },
+	})
+}
+
+/// How many of the thin features are listed in the warning
+const THIN_FEATURES_LISTED: usize = 3;
+
+/// Report dark features that are too thin to print (from `detail::thin_features`), if there are any
+pub fn thin_feature_warning(features: Vec<ThinFeature>, smallest_feature: f32) -> Option<Warning> {
+	(!features.is_empty()).then_some(Warning::ThinFeatures { features, smallest_feature })
+}
+
+/// How many times longer one side of a pixel can be than the other on the surface before `stretched_pixels_warning` warns about it
+pub const STRETCHED_PIXEL_RATIO: f32 = 2.0;
+
+/// Report a region where the expressions stretch the pixels unevenly (from `lithophane::stretched_region`), if there is one
+pub fn stretched_pixels_warning(region: Option<StretchedRegion>) -> Option<Warning> {
+	region.map(|region| Warning::StretchedPixels { region })
+}
+
+/// Check whether the image is so thin that `extend_to_strip` will turn it into a thin bar
+pub fn strip_warning(width: u32, height: u32) -> Option<Warning> {
+	(width > 0 && height > 0 && (width < MIN_IMAGE_SIZE || height < MIN_IMAGE_SIZE)).then_some(Warning::ExtendedToStrip { width, height })
+}
+
+/// Check whether the levels of a tactile graphic are far enough apart to tell apart by touch
+pub fn tactile_warning(tactile: &Tactile) -> Option<Warning> {
+	let step = tactile.step();
+	(step < MIN_TACTILE_STEP).then_some(Warning::TactileLevels { step })
+}
+
+/// The fraction of visible detail below which a warning is given
+const DETAIL_WARNING_FRACTION: f32 = 0.5;
+
+/// Check whether much of the image's detail will be too small to print
+pub fn detail_warning(report: &DetailReport) -> Option<Warning> {
+	(report.visible_detail < DETAIL_WARNING_FRACTION).then_some(Warning::LostDetail {
+		visible: report.visible_detail,
+		smallest_feature: report.smallest_feature,
+		pixel_size: report.pixel_size,
+	})
+}
+
+/// Limits for `quality_warnings`, eg to reject images that won't make a good lithophane before spending time and filament on them when
+/// generating many at once
+///
+/// Every check is off by default.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct QualityChecks {
+	/// The fewest pixels the shorter side of the image can have
+	pub min_resolution: Option<u32>,
+	/// The lowest sharpness (see `sharpness`) an image can have before it's considered blurry
+	pub min_sharpness: Option<f32>,
+	/// The lowest and highest average gray value an image can have, outside of which it's considered under or overexposed
+	pub brightness: Option<(u8, u8)>,
+}
+
+/// Check the image (before any preprocessing) against the limits in `checks`
+pub fn quality_warnings(image: &GrayImage, checks: &QualityChecks) -> Vec<Warning> {
+	let mut warnings = Vec::new();
+	if let Some(min_pixels) = checks.min_resolution {
+		let pixels = image.width().min(image.height());
+		if pixels < min_pixels {
+			warnings.push(Warning::LowResolution { pixels, min_pixels });
+		}
+	}
+	if let Some(min_sharpness) = checks.min_sharpness {
+		let sharpness = sharpness(image);
+		if sharpness < min_sharpness {
+			warnings.push(Warning::Blurry { sharpness, min_sharpness });
+		}
+	}
+	if let Some((low, high)) = checks.brightness {
+		let brightness = image.pixels().map(|p| p.0[0] as f64).sum::<f64>() as f32 / (image.width() * image.height()).max(1) as f32;
+		if brightness < low as f32 {
+			warnings.push(Warning::Underexposed { brightness });
+		} else if brightness > high as f32 {
+			warnings.push(Warning::Overexposed { brightness });
+		}
+	}
+	warnings
+}
+
+/// How sharp an image is, as the variance of its Laplacian (each pixel's value times 4, minus its four neighbours), which is low when
+/// there are no sharp edges
+///
+/// For photos, values below about 100 usually mean the image is blurry, though images that are mostly smooth gradients are low too.
+pub fn sharpness(image: &GrayImage) -> f32 {
+	let (width, height) = image.dimensions();
+	if width < 3 || height < 3 {
+		return 0.0;
+	}
+	let value = |x: u32, y: u32| image.get_pixel(x, y).0[0] as f64;
+	let (mut sum, mut sum_of_squares) = (0.0, 0.0);
+	for y in 1..height - 1 {
+		for x in 1..width - 1 {
+			let laplacian = 4.0 * value(x, y) - value(x - 1, y) - value(x + 1, y) - value(x, y - 1) - value(x, y + 1);
+			sum += laplacian;
+			sum_of_squares += laplacian * laplacian;
+		}
+	}
+	let count = ((width - 2) * (height - 2)) as f64;
+	(sum_of_squares / count - (sum / count).powi(2)) as f32
+}
+
+/// A fingerprint of what an image looks like, which barely changes when it's resized, recompressed or slightly adjusted
+///
+/// This is a difference hash: the image is shrunk to 9×8 pixels and each bit says whether a pixel is darker than the one to its right.
+/// The number of bits that differ between two hashes (see `u64::count_ones` of their XOR) says how different the images look.
+pub fn perceptual_hash(image: &GrayImage) -> u64 {
+	if image.width() == 0 || image.height() == 0 {
+		return 0;
+	}
+	let small = resize_area_average(image, 9, 8);
+	(0..8).flat_map(|y| (0..8).map(move |x| (x, y))).fold(0, |hash, (x, y)| {
+		(hash << 1) | (small.get_pixel(x, y).0[0] < small.get_pixel(x + 1, y).0[0]) as u64
+	})
+}
+
+/// How many bits can differ between the `perceptual_hash`es of two images for `duplicate_warnings` to count them as the same
+pub const DUPLICATE_HASH_DISTANCE: u32 = 6;
+
+/// Check a set of images that will be made into one product (eg the panels of a lamp or the photos of a collage) for ones that look the
+/// same, since a duplicate in an order is usually a mistake
+///
+/// Each image is only reported once, along with the first image it looks like.
+pub fn duplicate_warnings(images: &[GrayImage]) -> Vec<Warning> {
+	let hashes: Vec<u64> = images.iter().map(perceptual_hash).collect();
+	(1..hashes.len())
+		.filter_map(|second| {
+			(0..second).find_map(|first| {
+				let distance = (hashes[first] ^ hashes[second]).count_ones();
+				(distance <= DUPLICATE_HASH_DISTANCE).then_some(Warning::DuplicateImages { first, second, distance })
+			})
+		})
+		.collect()
+}